@@ -1,4 +1,14 @@
-use std::ops;
+//! Reading a signal between its frames.
+//!
+//! Every [`Interpolator`] reads the frames around a fractional position, from 1 for
+//! [`Nearest`] to a few dozen for [`WindowedSinc`]. The frames come from a function so the
+//! caller decides what lies past the signal ends or across a loop.
+
+use std::{f32::consts::PI, fmt, ops, str::FromStr};
+
+use anyhow::bail;
+
+use crate::audio::frame::Frame;
 
 #[inline]
 pub fn linear<I, O>(f1: I, f2: I, mut a: f32) -> O
@@ -12,10 +22,226 @@ where
     f1 * (1.0 - a) + f2 * a
 }
 
+pub trait Interpolator {
+    /// Frame at `position`, `frame` gives the frame at any index. `step` is how far the position
+    /// moves per frame read, past 1 the signal is sped up and what would alias is filtered out
+    /// by the interpolators which can
+    fn interpolate<const SIZE: usize>(
+        &self,
+        frame: impl Fn(isize) -> Frame<SIZE>,
+        position: f32,
+        step: f32,
+    ) -> Frame<SIZE>;
+}
+
+/// Index of the frame at or before `position` and how far past it `position` is
+fn split(position: f32) -> (isize, f32) {
+    let index = position.floor();
+    (index as isize, position - index)
+}
+
+/// Steps between frames, keeps the aliasing of chip sounds
+#[derive(Debug, Clone, Copy)]
+pub struct Nearest;
+
+impl Interpolator for Nearest {
+    fn interpolate<const SIZE: usize>(
+        &self,
+        frame: impl Fn(isize) -> Frame<SIZE>,
+        position: f32,
+        _step: f32,
+    ) -> Frame<SIZE> {
+        frame(position.round() as isize)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Linear;
+
+impl Interpolator for Linear {
+    fn interpolate<const SIZE: usize>(
+        &self,
+        frame: impl Fn(isize) -> Frame<SIZE>,
+        position: f32,
+        _step: f32,
+    ) -> Frame<SIZE> {
+        let (index, fraction) = split(position);
+        linear(frame(index), frame(index + 1), fraction)
+    }
+}
+
+/// Catmull-Rom spline through the 4 frames around the position
+#[derive(Debug, Clone, Copy)]
+pub struct CubicHermite;
+
+impl Interpolator for CubicHermite {
+    fn interpolate<const SIZE: usize>(
+        &self,
+        frame: impl Fn(isize) -> Frame<SIZE>,
+        position: f32,
+        _step: f32,
+    ) -> Frame<SIZE> {
+        let (index, t) = split(position);
+        let [p0, p1, p2, p3] = [-1, 0, 1, 2].map(|offset| frame(index + offset));
+        let c1 = (p2 - p0) * 0.5;
+        let c2 = p0 - p1 * 2.5 + p2 * 2.0 - p3 * 0.5;
+        let c3 = (p3 - p0) * 0.5 + (p1 - p2) * 1.5;
+        ((c3 * t + c2) * t + c1) * t + p1
+    }
+}
+
+/// Sped up signals are filtered with a lower cutoff over more frames, up to this factor
+const MAX_SINC_WIDENING: f32 = 4.0;
+
+/// Sinc under a Blackman window, reads `2 * radius` frames
+#[derive(Debug, Clone, Copy)]
+pub struct WindowedSinc {
+    radius: usize,
+}
+
+impl WindowedSinc {
+    pub const fn new(radius: usize) -> Self {
+        Self { radius }
+    }
+
+    /// Frames the kernel spans on each side of the position
+    fn kernel_reach(&self, step: f32) -> f32 {
+        let cutoff = 1.0 / step.max(1.0);
+        self.radius as f32 * (1.0 / cutoff).min(MAX_SINC_WIDENING)
+    }
+
+    /// Frames read on each side: from the frame at or before the position minus `reach - 1`, up
+    /// to that frame plus `reach`
+    pub fn reach(&self, step: f32) -> isize {
+        self.kernel_reach(step).ceil() as isize
+    }
+}
+
+impl Interpolator for WindowedSinc {
+    fn interpolate<const SIZE: usize>(
+        &self,
+        frame: impl Fn(isize) -> Frame<SIZE>,
+        position: f32,
+        step: f32,
+    ) -> Frame<SIZE> {
+        let (index, fraction) = split(position);
+        let cutoff = 1.0 / step.max(1.0);
+        let reach = self.kernel_reach(step);
+
+        let mut sum = Frame::default();
+        let mut weight_sum = 0.0;
+        for offset in 1 - self.reach(step)..=self.reach(step) {
+            let x = offset as f32 - fraction;
+            if x.abs() >= reach {
+                continue;
+            }
+            let weight = cutoff * sinc(cutoff * x) * blackman(x / reach);
+            sum += frame(index + offset) * weight;
+            weight_sum += weight;
+        }
+        // The truncated kernel does not quite sum to 1, this keeps the level of a constant signal
+        sum * (1.0 / weight_sum)
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// Window over -1 to 1
+fn blackman(x: f32) -> f32 {
+    0.42 + 0.5 * (PI * x).cos() + 0.08 * (2.0 * PI * x).cos()
+}
+
+/// Interpolation of a sample instrument
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InterpolationMode {
+    Nearest,
+    #[default]
+    Linear,
+    Cubic,
+    Sinc,
+}
+
+impl InterpolationMode {
+    pub const SINC: WindowedSinc = WindowedSinc::new(8);
+}
+
+impl Interpolator for InterpolationMode {
+    fn interpolate<const SIZE: usize>(
+        &self,
+        frame: impl Fn(isize) -> Frame<SIZE>,
+        position: f32,
+        step: f32,
+    ) -> Frame<SIZE> {
+        match self {
+            Self::Nearest => Nearest.interpolate(frame, position, step),
+            Self::Linear => Linear.interpolate(frame, position, step),
+            Self::Cubic => CubicHermite.interpolate(frame, position, step),
+            Self::Sinc => Self::SINC.interpolate(frame, position, step),
+        }
+    }
+}
+
+impl fmt::Display for InterpolationMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Nearest => "nearest",
+            Self::Linear => "linear",
+            Self::Cubic => "cubic",
+            Self::Sinc => "sinc",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl FromStr for InterpolationMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s.trim().to_lowercase().as_str() {
+            "nearest" => Self::Nearest,
+            "linear" => Self::Linear,
+            "cubic" => Self::Cubic,
+            "sinc" => Self::Sinc,
+            _ => bail!("Unknown interpolation {s:?}, expected nearest, linear, cubic or sinc"),
+        })
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use joy_vector::Vector;
+
     use super::*;
 
+    /// Mono signal of `len` frames, silent outside
+    fn signal(len: usize, value: impl Fn(f32) -> f32) -> impl Fn(isize) -> Frame<1> {
+        move |index| {
+            if (0..len as isize).contains(&index) {
+                Vector([value(index as f32)])
+            } else {
+                Frame::default()
+            }
+        }
+    }
+
+    /// Largest error reading `expected` between the frames of a sine away from the signal ends
+    fn sine_error(interpolator: &impl Interpolator, cycle_len: f32) -> f32 {
+        let sine = |x: f32| (2.0 * PI * x / cycle_len).sin();
+        let frame = signal(1000, sine);
+        (0..400)
+            .map(|index| {
+                let position = 300.0 + index as f32 * 0.37;
+                (interpolator.interpolate(&frame, position, 1.0).0[0] - sine(position)).abs()
+            })
+            .fold(0.0, f32::max)
+    }
+
     #[test]
     #[should_panic(expected = "a should be between 0 and 1")]
     fn test_a_gt_1() {
@@ -67,4 +293,92 @@ mod test {
         let res = linear(start, end, 0.75);
         assert_eq!(2.75, res);
     }
+
+    #[test]
+    fn test_interpolators_go_through_the_frames() {
+        let frame = signal(8, |x| x * x - 3.0 * x);
+        for index in 0..8 {
+            let expected = frame(index).0[0];
+            let position = index as f32;
+            for mode in [
+                InterpolationMode::Nearest,
+                InterpolationMode::Linear,
+                InterpolationMode::Cubic,
+                InterpolationMode::Sinc,
+            ] {
+                let value = mode.interpolate(&frame, position, 1.0).0[0];
+                approx::assert_relative_eq!(expected, value, epsilon = 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn test_nearest_holds_the_closest_frame() {
+        let frame = signal(4, |x| x);
+        approx::assert_relative_eq!(1.0, Nearest.interpolate(&frame, 1.4, 1.0).0[0]);
+        approx::assert_relative_eq!(2.0, Nearest.interpolate(&frame, 1.6, 1.0).0[0]);
+    }
+
+    #[test]
+    fn test_cubic_is_exact_on_lines() {
+        let frame = signal(10, |x| 0.5 * x - 1.0);
+        approx::assert_relative_eq!(
+            0.5 * 4.3 - 1.0,
+            CubicHermite.interpolate(&frame, 4.3, 1.0).0[0],
+            epsilon = 1e-5
+        );
+    }
+
+    #[test]
+    fn test_higher_orders_are_closer_to_a_sine() {
+        // 8 frames per cycle, a high pitched sample
+        let linear = sine_error(&Linear, 8.0);
+        let cubic = sine_error(&CubicHermite, 8.0);
+        let sinc = sine_error(&InterpolationMode::SINC, 8.0);
+        assert!(cubic < linear / 2.0, "{cubic} {linear}");
+        assert!(sinc < 0.01, "{sinc}");
+        assert!(sine_error(&Nearest, 8.0) > linear);
+    }
+
+    #[test]
+    fn test_sinc_filters_what_would_alias() {
+        // 2.5 frames per cycle, read every 2 frames it would fold down
+        let frame = signal(1000, |x| (2.0 * PI * x / 2.5).sin());
+        let peak = |interpolator: &dyn Fn(f32) -> f32| {
+            (0..200)
+                .map(|index| interpolator(300.0 + index as f32 * 2.0).abs())
+                .fold(0.0, f32::max)
+        };
+        let filtered =
+            peak(&|position| InterpolationMode::SINC.interpolate(&frame, position, 2.0).0[0]);
+        let unfiltered = peak(&|position| Linear.interpolate(&frame, position, 2.0).0[0]);
+        assert!(filtered < 0.05, "{filtered}");
+        assert!(unfiltered > 0.5, "{unfiltered}");
+    }
+
+    #[test]
+    fn test_sinc_keeps_a_constant_level() {
+        let frame = signal(100, |_| 0.75);
+        for step in [1.0, 1.5, 3.0, 10.0] {
+            approx::assert_relative_eq!(
+                0.75,
+                InterpolationMode::SINC.interpolate(&frame, 50.3, step).0[0],
+                epsilon = 1e-4
+            );
+        }
+    }
+
+    #[test]
+    fn test_modes_round_trip_through_text() {
+        for mode in [
+            InterpolationMode::Nearest,
+            InterpolationMode::Linear,
+            InterpolationMode::Cubic,
+            InterpolationMode::Sinc,
+        ] {
+            assert_eq!(mode, mode.to_string().parse().unwrap());
+        }
+        assert_eq!(InterpolationMode::Cubic, " Cubic ".parse().unwrap());
+        assert!("bilinear".parse::<InterpolationMode>().is_err());
+    }
 }
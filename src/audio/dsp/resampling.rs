@@ -1,29 +1,284 @@
-use std::time::Duration;
+//! Changing the frame rate of a signal.
+//!
+//! The frames are read through a [`WindowedSinc`] which, going down in frame rate, filters out
+//! what the new rate can't hold. The [`Resampler`] takes its input in chunks, [`resample`] takes
+//! a whole signal. Output positions are computed from the count of output frames, never
+//! accumulated, so long signals don't drift.
 
-use crate::audio::signal;
+use std::{collections::VecDeque, fmt, str::FromStr};
 
-#[allow(dead_code)]
-pub fn linear<const FRAME_SIZE: usize>(
-    src: signal::Ref<FRAME_SIZE>,
-    target_sample_rate: f32,
-) -> signal::Owned<FRAME_SIZE> {
-    let target_frame_rate = target_sample_rate.round();
+use anyhow::bail;
 
-    if src.frame_rate == target_frame_rate {
-        return src.clone();
+use crate::audio::{frame::Frame, signal};
+
+use super::interpolation::{Interpolator, WindowedSinc};
+
+/// Presets trading speed for a sharper filter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Quality {
+    Fast,
+    #[default]
+    Good,
+    Best,
+}
+
+impl Quality {
+    fn interpolator(self) -> WindowedSinc {
+        match self {
+            Quality::Fast => WindowedSinc::new(8),
+            Quality::Good => WindowedSinc::new(16),
+            Quality::Best => WindowedSinc::new(32),
+        }
+    }
+}
+
+impl fmt::Display for Quality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Quality::Fast => "fast",
+            Quality::Good => "good",
+            Quality::Best => "best",
+        };
+        write!(f, "{name}")
     }
+}
+
+impl FromStr for Quality {
+    type Err = anyhow::Error;
 
-    let src_duration = src.duration();
-    let target_frame_count = (src_duration.as_secs_f32() * target_frame_rate) as usize;
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s.trim().to_lowercase().as_str() {
+            "fast" => Quality::Fast,
+            "good" => Quality::Good,
+            "best" => Quality::Best,
+            _ => bail!("Unknown resampling quality {s:?}, expected fast, good or best"),
+        })
+    }
+}
 
-    let mut current_duration = Duration::ZERO;
-    let period = Duration::from_secs_f32(1.0 / target_frame_rate);
-    let mut frames = Vec::with_capacity(target_frame_count);
+/// Streaming resampler, every output frame is given once the input frames it reads are there
+pub struct Resampler<const SIZE: usize> {
+    interpolator: WindowedSinc,
+    /// Input frames per output frame
+    step: f64,
+    /// Input frames the next output frames still read, the first one is input frame `first_index`
+    pending: VecDeque<Frame<SIZE>>,
+    first_index: u64,
+    output_count: u64,
+}
+
+impl<const SIZE: usize> Resampler<SIZE> {
+    pub fn new(from_frame_rate: f32, to_frame_rate: f32, quality: Quality) -> Self {
+        Self {
+            interpolator: quality.interpolator(),
+            step: from_frame_rate as f64 / to_frame_rate as f64,
+            pending: VecDeque::new(),
+            first_index: 0,
+            output_count: 0,
+        }
+    }
 
-    while current_duration < src_duration {
-        frames.push(src.lerp_frame_at_duration(current_duration).unwrap());
-        current_duration += period;
+    /// Resamples `input`, which follows the input given before
+    pub fn process(&mut self, input: &[Frame<SIZE>], output: &mut Vec<Frame<SIZE>>) {
+        self.pending.extend(input);
+        let reach = self.interpolator.reach(self.step as f32) as i64;
+        self.resample_while(output, |index, input_end| index + reach < input_end);
     }
 
-    signal::Owned::from_frames(frames, target_frame_rate)
+    /// Gives the output frames left once the input ended, the input is silent past its end
+    pub fn flush(&mut self, output: &mut Vec<Frame<SIZE>>) {
+        self.resample_while(output, |index, input_end| index < input_end);
+    }
+
+    /// Gives output frames while `can_resample` accepts the input frame at or before the output
+    /// position and the end of the input given so far
+    fn resample_while(
+        &mut self,
+        output: &mut Vec<Frame<SIZE>>,
+        can_resample: impl Fn(i64, i64) -> bool,
+    ) {
+        let step = self.step as f32;
+        let reach = self.interpolator.reach(step) as i64;
+        loop {
+            let position = self.output_count as f64 * self.step;
+            let input_end = (self.first_index + self.pending.len() as u64) as i64;
+            if !can_resample(position.floor() as i64, input_end) {
+                break;
+            }
+
+            // Relative to the pending frames so the position keeps its precision as an f32
+            let pending = &self.pending;
+            let frame = |index: isize| {
+                usize::try_from(index)
+                    .ok()
+                    .and_then(|index| pending.get(index))
+                    .copied()
+                    .unwrap_or_default()
+            };
+            let relative_position = (position - self.first_index as f64) as f32;
+            output.push(
+                self.interpolator
+                    .interpolate(frame, relative_position, step),
+            );
+            self.output_count += 1;
+
+            let next_position = self.output_count as f64 * self.step;
+            let first_read = (next_position.floor() as i64 + 1 - reach).max(0) as u64;
+            while self.first_index < first_read && self.pending.pop_front().is_some() {
+                self.first_index += 1;
+            }
+        }
+    }
+}
+
+/// Whole `signal` at `frame_rate`, its duration is kept
+pub fn resample<const SIZE: usize>(
+    signal: signal::Ref<SIZE>,
+    frame_rate: f32,
+    quality: Quality,
+) -> signal::Owned<SIZE> {
+    if signal.frame_rate == frame_rate {
+        return signal.clone();
+    }
+
+    let mut resampler = Resampler::new(signal.frame_rate, frame_rate, quality);
+    let frame_count = (signal.frame_count() as f64 * frame_rate as f64 / signal.frame_rate as f64)
+        .ceil() as usize;
+    let mut frames = Vec::with_capacity(frame_count);
+    resampler.process(&signal, &mut frames);
+    resampler.flush(&mut frames);
+    signal::Owned::from_frames(frames, frame_rate)
+}
+
+#[cfg(test)]
+mod test {
+    use std::f64::consts::PI;
+
+    use joy_vector::Vector;
+
+    use super::*;
+
+    const SWEEP_DURATION: f64 = 1.0;
+    const SWEEP_START_FREQ: f64 = 20.0;
+    const SWEEP_END_FREQ: f64 = 20000.0;
+
+    /// Exponential sine sweep from 20 Hz to 20 kHz in one second
+    fn sweep_phase(time: f64) -> f64 {
+        let rate = (SWEEP_END_FREQ / SWEEP_START_FREQ).ln() / SWEEP_DURATION;
+        2.0 * PI * SWEEP_START_FREQ * ((rate * time).exp() - 1.0) / rate
+    }
+
+    fn sweep_freq(time: f64) -> f64 {
+        SWEEP_START_FREQ * (SWEEP_END_FREQ / SWEEP_START_FREQ).powf(time / SWEEP_DURATION)
+    }
+
+    fn sweep(frame_rate: f32) -> signal::Owned<1> {
+        let frame_count = (SWEEP_DURATION * frame_rate as f64) as usize;
+        signal::Owned::from_frames(
+            (0..frame_count)
+                .map(|index| Vector([sweep_phase(index as f64 / frame_rate as f64).sin() as f32]))
+                .collect(),
+            frame_rate,
+        )
+    }
+
+    /// Largest difference with the ideal sweep, and largest level, over the frames where the
+    /// sweep is in `freqs`. The first and last 10 ms are left out, the sweep starts and stops
+    /// there
+    fn compare_to_sweep(signal: &signal::Owned<1>, freqs: (f64, f64)) -> (f32, f32) {
+        let margin = (0.01 * signal.frame_rate) as usize;
+        signal
+            .iter()
+            .enumerate()
+            .skip(margin)
+            .take(signal.len() - 2 * margin)
+            .filter(|(index, _)| {
+                let freq = sweep_freq(*index as f64 / signal.frame_rate as f64);
+                (freqs.0..freqs.1).contains(&freq)
+            })
+            .map(|(index, Vector([value]))| {
+                let expected = sweep_phase(index as f64 / signal.frame_rate as f64).sin() as f32;
+                ((value - expected).abs(), value.abs())
+            })
+            .fold((0.0, 0.0), |(error, peak), (frame_error, level)| {
+                (error.max(frame_error), peak.max(level))
+            })
+    }
+
+    #[test]
+    fn test_upsampled_sweep_matches_the_ideal_sweep() {
+        let resampled = resample(sweep(44100.0).as_ref(), 48000.0, Quality::Good);
+        approx::assert_relative_eq!(48000.0, resampled.frame_rate);
+        assert_eq!(48000, resampled.len());
+
+        let (error, _) = compare_to_sweep(&resampled, (0.0, 16000.0));
+        assert!(error < 0.002, "{error}");
+    }
+
+    #[test]
+    fn test_downsampled_sweep_loses_what_would_alias() {
+        let resampled = resample(sweep(48000.0).as_ref(), 22050.0, Quality::Good);
+        assert_eq!(22050, resampled.len());
+
+        let (error, _) = compare_to_sweep(&resampled, (0.0, 8000.0));
+        assert!(error < 0.002, "{error}");
+        // Past the new Nyquist frequency and the filter transition, the sweep would fold back
+        let (_, level) = compare_to_sweep(&resampled, (14000.0, SWEEP_END_FREQ));
+        assert!(level < 0.002, "{level}");
+    }
+
+    #[test]
+    fn test_better_quality_is_closer() {
+        let source = sweep(44100.0);
+        let error = |quality| {
+            compare_to_sweep(&resample(source.as_ref(), 96000.0, quality), (0.0, 18000.0)).0
+        };
+        let (fast, good, best) = (
+            error(Quality::Fast),
+            error(Quality::Good),
+            error(Quality::Best),
+        );
+        assert!(best < good && good < fast, "{fast} {good} {best}");
+    }
+
+    #[test]
+    fn test_streaming_matches_the_whole_signal() {
+        let source = sweep(44100.0);
+        let whole = resample(source.as_ref(), 32000.0, Quality::Fast);
+
+        let mut resampler = Resampler::new(44100.0, 32000.0, Quality::Fast);
+        let mut frames = Vec::new();
+        for chunk in source.chunks(333) {
+            resampler.process(chunk, &mut frames);
+        }
+        resampler.flush(&mut frames);
+        signal::test_utils::assert_signal_eq(whole, signal::Owned::from_frames(frames, 32000.0));
+    }
+
+    #[test]
+    fn test_long_signals_dont_drift() {
+        // Ten seconds at 44.1 kHz give exactly ten seconds at 48 kHz
+        let source = signal::Owned::<1>::from_frames(vec![Vector([0.5]); 441000], 44100.0);
+        let resampled = resample(source.as_ref(), 48000.0, Quality::Fast);
+        assert_eq!(480000, resampled.len());
+        let last = resampled[resampled.len() - 1000].0[0];
+        approx::assert_relative_eq!(0.5, last, epsilon = 1e-4);
+    }
+
+    #[test]
+    fn test_same_frame_rate_is_a_copy() {
+        let source = sweep(44100.0);
+        signal::test_utils::assert_signal_eq(
+            source.clone(),
+            resample(source.as_ref(), 44100.0, Quality::Best),
+        );
+    }
+
+    #[test]
+    fn test_qualities_round_trip_through_text() {
+        for quality in [Quality::Fast, Quality::Good, Quality::Best] {
+            assert_eq!(quality, quality.to_string().parse().unwrap());
+        }
+        assert!("perfect".parse::<Quality>().is_err());
+    }
 }
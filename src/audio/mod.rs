@@ -10,6 +10,7 @@ pub mod device;
 pub mod dsp;
 pub mod frame;
 pub mod player;
+pub mod render;
 pub mod signal;
 pub mod synthesis;
 
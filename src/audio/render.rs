@@ -0,0 +1,358 @@
+//! Offline rendering of a song, without any audio device.
+//!
+//! The song is played through the same [`model::Command`]s the audio callback sends, one block
+//! of frames at a time, so the output matches what the player would produce at that frame rate
+//! and block size.
+
+use anyhow::{anyhow, ensure};
+
+use crate::model::{self, Song};
+
+use super::{signal, Volume};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Settings {
+    pub frame_rate: f32,
+    pub block_frame_count: usize,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            frame_rate: 44100.0,
+            block_frame_count: 1024,
+        }
+    }
+}
+
+/// Master mix of a song along with the contribution of every channel, all sample-aligned and of
+/// the same length
+#[derive(Debug)]
+pub struct Stems {
+    pub master: signal::stereo::Owned,
+    pub channels: Vec<signal::stereo::Owned>,
+}
+
+/// Renders the song from its beginning until the playback stops. `on_progress` is called after
+/// every block with the played ratio of the song, from 0 to 1.
+pub fn render<OnProgressFn>(
+    song: Song,
+    settings: Settings,
+    on_progress: OnProgressFn,
+) -> anyhow::Result<signal::stereo::Owned>
+where
+    OnProgressFn: FnMut(f32),
+{
+    let mut frames = Vec::new();
+    play(song, settings, false, on_progress, |state| {
+        frames.extend_from_slice(&state.output_samples()?);
+        Ok(())
+    })?;
+
+    Ok(signal::stereo::Owned::from_frames(
+        frames,
+        settings.frame_rate,
+    ))
+}
+
+/// Renders the song like [`render`], capturing every channel on its own along the way. The global
+/// volume only applies to the channels when `with_global_volume` is set, the master mix always
+/// has it.
+pub fn render_stems<OnProgressFn>(
+    song: Song,
+    settings: Settings,
+    with_global_volume: bool,
+    on_progress: OnProgressFn,
+) -> anyhow::Result<Stems>
+where
+    OnProgressFn: FnMut(f32),
+{
+    let channel_volume = if with_global_volume {
+        song.global_volume
+    } else {
+        Volume::MAX
+    };
+    let mut master = Vec::new();
+    let mut channels = vec![Vec::new(); song.patterns.channel_count as usize];
+    play(song, settings, true, on_progress, |state| {
+        master.extend_from_slice(&state.output_samples()?);
+        for (index, frames) in channels.iter_mut().enumerate() {
+            frames.extend(
+                state
+                    .channel_output_samples(index)?
+                    .iter()
+                    .map(|frame| *frame * channel_volume),
+            );
+        }
+        Ok(())
+    })?;
+
+    Ok(Stems {
+        master: signal::stereo::Owned::from_frames(master, settings.frame_rate),
+        channels: channels
+            .into_iter()
+            .map(|frames| signal::stereo::Owned::from_frames(frames, settings.frame_rate))
+            .collect(),
+    })
+}
+
+/// Plays the song without any device, `on_step` reads the state after every performed step
+fn play<OnProgressFn, OnStepFn>(
+    song: Song,
+    settings: Settings,
+    capture_channel_outputs: bool,
+    mut on_progress: OnProgressFn,
+    mut on_step: OnStepFn,
+) -> anyhow::Result<()>
+where
+    OnProgressFn: FnMut(f32),
+    OnStepFn: FnMut(&model::State) -> anyhow::Result<()>,
+{
+    ensure!(
+        settings.frame_rate.is_normal() && settings.frame_rate > 0.0,
+        "Invalid frame rate {}",
+        settings.frame_rate
+    );
+    ensure!(settings.block_frame_count > 0, "Block size can't be empty");
+
+    let mut state = model::State::default();
+    let channel_len = song.patterns.channel_len as usize;
+    let line_count = (song.patterns.order().len() * channel_len) as f32;
+    state.handle_command(model::Command::LoadSong(Box::new(song)));
+    state.handle_command(model::Command::CaptureChannelOutputs(
+        capture_channel_outputs,
+    ));
+    state.handle_command(model::Command::InitializeAudio {
+        frame_rate: settings.frame_rate,
+    });
+    state.handle_command(model::Command::UpdatePlaybackSampleCount(
+        settings.block_frame_count * 2,
+    ));
+    state.handle_command(model::Command::StartSongPlaybackFromBeginning);
+
+    while state.is_song_playing() {
+        state.handle_command(model::Command::PerformPlaybacksStep);
+        on_step(&state)?;
+
+        let played_line_count = state
+            .currently_played_order()
+            .zip(state.currently_played_line())
+            .map(|(order, line)| order * channel_len + line)
+            .ok_or_else(|| anyhow!("Uninitialized state"))?;
+        on_progress((played_line_count as f32 / line_count).min(1.0));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use joy_vector::Vector;
+
+    use crate::{
+        format::import,
+        model::{
+            instrument::{Instrument, Instruments, Kind},
+            pattern::{Pattern, Patterns},
+            tempo::Tempo,
+        },
+    };
+
+    use super::*;
+
+    const CHANNEL_LEN: i32 = 8;
+    const LINE_PER_SECOND: f32 = 10.0;
+
+    fn song(lines: &[(usize, usize, &str)]) -> Song {
+        let channel_count = 2;
+        let mut pattern = Pattern::new(channel_count, CHANNEL_LEN);
+        for (channel, row, line) in lines {
+            pattern.lines[channel * CHANNEL_LEN as usize + row] = line.parse().unwrap();
+        }
+        let patterns = Patterns::from_patterns(channel_count, CHANNEL_LEN, vec![pattern]).unwrap();
+        let mut instruments = Instruments::empty();
+        instruments.set(0, Instrument::from(Kind::Sine)).unwrap();
+        instruments.set(1, Instrument::from(Kind::Square)).unwrap();
+        import::song(
+            patterns,
+            instruments,
+            Tempo::from_line_per_second(LINE_PER_SECOND, 4, 6).unwrap(),
+        )
+    }
+
+    fn settings(frame_rate: f32, block_frame_count: usize) -> Settings {
+        Settings {
+            frame_rate,
+            block_frame_count,
+        }
+    }
+
+    fn expected_frame_count(frame_rate: f32) -> usize {
+        (CHANNEL_LEN as f32 / LINE_PER_SECOND * frame_rate).round() as usize
+    }
+
+    fn is_silent(frames: &[Vector<f32, 2>]) -> bool {
+        frames.iter().all(|frame| frame.0 == [0.0, 0.0])
+    }
+
+    #[test]
+    fn test_render_lasts_the_whole_song() {
+        for (frame_rate, block_frame_count) in [(44100.0, 1024), (48000.0, 512), (8000.0, 333)] {
+            let signal =
+                render(song(&[]), settings(frame_rate, block_frame_count), |_| {}).unwrap();
+            approx::assert_relative_eq!(frame_rate, signal.frame_rate);
+            let frame_count = signal.len() as i64;
+            let expected = expected_frame_count(frame_rate) as i64;
+            assert!(
+                (frame_count - expected).abs() <= 1,
+                "{frame_count} frame(s) rendered at {frame_rate}Hz, {expected} expected"
+            );
+        }
+    }
+
+    #[test]
+    fn test_notes_are_rendered_on_their_line() {
+        let frame_rate = 8000.0;
+        let signal = render(
+            song(&[
+                (0, 2, "A-5 .. 00"),
+                (0, 4, "CUT .. .."),
+                (1, 5, "C-4 .. 01"),
+            ]),
+            settings(frame_rate, 256),
+            |_| {},
+        )
+        .unwrap();
+
+        let line_frame_count = (frame_rate / LINE_PER_SECOND) as usize;
+        let line = |index: usize| {
+            &signal[index * line_frame_count + 1..(index + 1) * line_frame_count - 1]
+        };
+        assert!(is_silent(line(0)));
+        assert!(is_silent(line(1)));
+        assert!(!is_silent(line(2)));
+        assert!(!is_silent(line(3)));
+        assert!(is_silent(line(4)));
+        assert!(!is_silent(line(5)));
+        assert!(!is_silent(line(7)));
+    }
+
+    #[test]
+    fn test_playback_follows_the_order() {
+        let frame_rate = 8000.0;
+        let silent = Pattern::new(1, CHANNEL_LEN);
+        let mut playing = Pattern::new(1, CHANNEL_LEN);
+        playing.lines[0] = "A-5 .. 00".parse().unwrap();
+        playing.lines[CHANNEL_LEN as usize - 1] = "CUT .. ..".parse().unwrap();
+        let mut patterns = Patterns::from_patterns(1, CHANNEL_LEN, vec![silent, playing]).unwrap();
+        patterns.set_order(vec![1, 0, 1]).unwrap();
+        let mut song = song(&[]);
+        song.patterns = patterns;
+
+        let signal = render(song, settings(frame_rate, 256), |_| {}).unwrap();
+
+        let pattern_frame_count = expected_frame_count(frame_rate);
+        assert!((signal.len() as i64 - 3 * pattern_frame_count as i64).abs() <= 1);
+        let pattern = |index: usize| {
+            let start = index * pattern_frame_count;
+            &signal[start + 1..start + pattern_frame_count / 2]
+        };
+        assert!(!is_silent(pattern(0)));
+        assert!(is_silent(pattern(1)));
+        assert!(!is_silent(pattern(2)));
+    }
+
+    #[test]
+    fn test_block_size_does_not_change_the_output() {
+        let song = song(&[(0, 0, "A-5 80 00"), (1, 3, "C-4 .. 01")]);
+        let reference = render(song.clone(), settings(8000.0, 4096), |_| {}).unwrap();
+        let signal = render(song, settings(8000.0, 100), |_| {}).unwrap();
+
+        assert_frames_eq(&reference, &signal);
+    }
+
+    #[test]
+    fn test_progress_reaches_the_end() {
+        let mut progress = Vec::new();
+        render(song(&[]), settings(8000.0, 256), |ratio| {
+            progress.push(ratio)
+        })
+        .unwrap();
+
+        assert!(progress.windows(2).all(|pair| pair[0] <= pair[1]));
+        approx::assert_relative_eq!(1.0, *progress.last().unwrap());
+    }
+
+    fn assert_frames_eq(expected: &[Vector<f32, 2>], actual: &[Vector<f32, 2>]) {
+        assert_eq!(expected.len(), actual.len());
+        for (index, (expected, actual)) in expected.iter().zip(actual).enumerate() {
+            assert!(
+                (*expected - *actual).norm2() < 0.001,
+                "frame {index} differs: {:?} != {:?}",
+                expected.as_slice(),
+                actual.as_slice()
+            );
+        }
+    }
+
+    fn stems_song() -> Song {
+        let mut song = song(&[(0, 0, "A-5 80 00"), (1, 3, "C-4 .. 01")]);
+        song.global_volume = Volume::new_unchecked(0.5);
+        song
+    }
+
+    #[test]
+    fn test_stems_sum_up_to_the_master_mix() {
+        let stems = render_stems(stems_song(), settings(8000.0, 256), true, |_| {}).unwrap();
+
+        assert_eq!(2, stems.channels.len());
+        let sum = stems.channels[0]
+            .iter()
+            .zip(stems.channels[1].iter())
+            .map(|(left, right)| *left + *right)
+            .collect::<Vec<_>>();
+        assert_frames_eq(&stems.master, &sum);
+        assert!(!is_silent(&stems.channels[0]));
+        assert!(is_silent(&stems.channels[1][..2 * 800]));
+        assert!(!is_silent(&stems.channels[1]));
+    }
+
+    #[test]
+    fn test_stems_master_matches_the_render() {
+        let signal = render(stems_song(), settings(8000.0, 256), |_| {}).unwrap();
+        let stems = render_stems(stems_song(), settings(8000.0, 256), false, |_| {}).unwrap();
+
+        assert_frames_eq(&signal, &stems.master);
+    }
+
+    #[test]
+    fn test_stems_without_global_volume() {
+        let with = render_stems(stems_song(), settings(8000.0, 256), true, |_| {}).unwrap();
+        let without = render_stems(stems_song(), settings(8000.0, 256), false, |_| {}).unwrap();
+
+        for (with, without) in with.channels.iter().zip(&without.channels) {
+            let expected = without.iter().map(|frame| *frame * 0.5).collect::<Vec<_>>();
+            assert_frames_eq(&expected, with);
+        }
+    }
+
+    #[test]
+    fn test_silenced_channels_are_not_mixed() {
+        let mut muted = stems_song();
+        muted.mute_solo.set_muted(1, true);
+        let mut soloed = stems_song();
+        soloed.mute_solo.set_soloed(0, true);
+        let stems = render_stems(stems_song(), settings(8000.0, 256), true, |_| {}).unwrap();
+
+        for song in [muted, soloed] {
+            let signal = render(song, settings(8000.0, 256), |_| {}).unwrap();
+            assert_frames_eq(&stems.channels[0], &signal);
+        }
+    }
+
+    #[test]
+    fn test_invalid_settings_are_an_error() {
+        assert!(render(song(&[]), settings(0.0, 256), |_| {}).is_err());
+        assert!(render(song(&[]), settings(8000.0, 0), |_| {}).is_err());
+    }
+}
@@ -8,8 +8,6 @@ use std::{
 use anyhow::ensure;
 use joy_vector::Vector;
 
-use crate::audio::dsp;
-
 use super::frame::Frame;
 
 #[derive(Clone)]
@@ -245,25 +243,6 @@ impl<const FRAME_SIZE: usize> Ref<'_, FRAME_SIZE> {
         (index as usize, index.fract())
     }
 
-    pub fn lerp_frame_at_duration(&self, duration: Duration) -> Option<Frame<FRAME_SIZE>> {
-        let (frame_index, rem) = self.frame_index_from_duration(duration);
-        if frame_index >= self.frames.len() {
-            return None;
-        }
-
-        if frame_index == self.frames.len() - 1 {
-            if let [.., last_frame] = self.frames {
-                return Some(*last_frame);
-            }
-        }
-
-        Some(dsp::interpolation::linear(
-            &self.frames[frame_index],
-            &self.frames[frame_index + 1],
-            rem,
-        ))
-    }
-
     pub fn sub_signal(&self, range: RangeTo<usize>) -> Ref<FRAME_SIZE> {
         Ref {
             frames: &self.frames[range],
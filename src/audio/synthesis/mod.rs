@@ -1,9 +1,24 @@
+//! Oscillators of the synthesized instruments.
+//!
+//! The square, sawtooth and pulse waves jump, their naive versions alias as soon as their
+//! harmonics go past the Nyquist frequency. They are smoothed around their jumps with PolyBLEP
+//! (polynomial band-limited step) and the triangle around its corners with PolyBLAMP (its
+//! integral). Every waveform goes from -1 to 1 around 0, none adds a DC offset to the mix.
+//!
+//! Phases are in radians for the sine, in cycles for the other waves and count frames for the
+//! noises.
+
 use joy_vector::vector;
 
 use crate::utils::math::TWO_PI;
 
 use super::{frame::StereoFrame, Pan, Volume};
 
+/// The noises repeat after this many frames, it keeps the frame count exact in a `f32`
+const NOISE_PERIOD: f32 = (1 << 20) as f32;
+/// Octaves of white noise summed by the pink noise
+const PINK_NOISE_ROW_COUNT: u32 = 10;
+
 pub fn sine_wave(
     freq: f32,
     volume: Volume,
@@ -18,10 +33,7 @@ pub fn sine_wave(
         *phase -= TWO_PI;
     }
 
-    let left_volume = pan.left_volume() * volume;
-    let right_volume = pan.right_volume() * volume;
-
-    vector!(sample * left_volume.value(), sample * right_volume.value(),)
+    stereo(sample, volume, pan)
 }
 
 pub fn square_wave(
@@ -31,20 +43,25 @@ pub fn square_wave(
     phase: &mut f32,
     frame_rate: f32,
 ) -> StereoFrame {
-    let freq_period = 1.0 / freq;
-    let half_freq_period = freq_period / 2.0;
-
-    let sample = if *phase < half_freq_period { 1.0 } else { -1.0 };
-
-    *phase += 1.0 / frame_rate;
-    if *phase > freq_period {
-        *phase -= freq_period;
-    }
-
-    let left_volume = volume * pan.left_volume();
-    let right_volume = volume * pan.right_volume();
+    pulse_wave(0.5, freq, volume, pan, phase, frame_rate)
+}
 
-    vector!(sample * left_volume.value(), sample * right_volume.value(),)
+/// Square wave spending `width` (0 to 1) of its cycle up, centered and scaled so its mean is 0
+/// and it stays within [-1, 1]
+pub fn pulse_wave(
+    width: f32,
+    freq: f32,
+    volume: Volume,
+    pan: Pan,
+    phase: &mut f32,
+    frame_rate: f32,
+) -> StereoFrame {
+    let (t, dt) = advance(phase, freq, frame_rate);
+    let naive = if t < width { 1.0 } else { -1.0 };
+    // Up at the start of the cycle, down at `width`
+    let sample = naive + poly_blep(t, dt) - poly_blep((t + 1.0 - width).fract(), dt);
+    let mean = 2.0 * width - 1.0;
+    stereo((sample - mean) / (1.0 + mean.abs()), volume, pan)
 }
 
 pub fn sawtooth_wave(
@@ -54,17 +71,269 @@ pub fn sawtooth_wave(
     phase: &mut f32,
     frame_rate: f32,
 ) -> StereoFrame {
-    let freq_period = 1.0 / freq;
+    let (t, dt) = advance(phase, freq, frame_rate);
+    stereo(2.0 * t - 1.0 - poly_blep(t, dt), volume, pan)
+}
 
-    let sample = *phase / freq_period;
+pub fn triangle_wave(
+    freq: f32,
+    volume: Volume,
+    pan: Pan,
+    phase: &mut f32,
+    frame_rate: f32,
+) -> StereoFrame {
+    let (t, dt) = advance(phase, freq, frame_rate);
+    let naive = 1.0 - 4.0 * (t - 0.5).abs();
+    // The slope goes from -4 to 4 per cycle at the start, and back at the middle
+    let sample = naive + 8.0 * dt * (poly_blamp(t, dt) - poly_blamp((t + 0.5).fract(), dt));
+    stereo(sample, volume, pan)
+}
 
-    *phase += 1.0 / frame_rate;
-    if *phase > freq_period {
-        *phase -= freq_period;
-    }
+/// Uniform noise, every frequency has the same power
+pub fn white_noise(volume: Volume, pan: Pan, phase: &mut f32) -> StereoFrame {
+    let frame = advance_noise(phase);
+    stereo(noise_value(frame, 0), volume, pan)
+}
+
+/// Noise losing 3 dB per octave, summing white noises held for 1, 2, 4... frames (Voss-McCartney)
+pub fn pink_noise(volume: Volume, pan: Pan, phase: &mut f32) -> StereoFrame {
+    let frame = advance_noise(phase);
+    let sum = (0..PINK_NOISE_ROW_COUNT)
+        .map(|row| noise_value(frame >> row, row))
+        .sum::<f32>();
+    stereo(sum / PINK_NOISE_ROW_COUNT as f32, volume, pan)
+}
 
+fn stereo(sample: f32, volume: Volume, pan: Pan) -> StereoFrame {
     let left_volume = volume * pan.left_volume();
     let right_volume = volume * pan.right_volume();
 
-    vector!(sample * left_volume.value(), sample * right_volume.value(),)
+    vector!(sample * left_volume.value(), sample * right_volume.value())
+}
+
+/// Position in the cycle and cycles per frame, before `phase` moves to the next frame
+fn advance(phase: &mut f32, freq: f32, frame_rate: f32) -> (f32, f32) {
+    let t = *phase;
+    let dt = freq / frame_rate;
+    *phase = (*phase + dt).fract();
+    (t, dt)
+}
+
+fn advance_noise(phase: &mut f32) -> u32 {
+    let frame = *phase as u32;
+    *phase = (*phase + 1.0) % NOISE_PERIOD;
+    frame
+}
+
+/// Difference between a band-limited and a naive upward step of 2 at `t` = 0, `dt` away from it
+fn poly_blep(t: f32, dt: f32) -> f32 {
+    if t < dt {
+        let x = t / dt;
+        2.0 * x - x * x - 1.0
+    } else if t > 1.0 - dt {
+        let x = (t - 1.0) / dt;
+        x * x + 2.0 * x + 1.0
+    } else {
+        0.0
+    }
+}
+
+/// Integral of [`poly_blep`], for a slope increasing by 1 per frame at `t` = 0
+fn poly_blamp(t: f32, dt: f32) -> f32 {
+    if t < dt {
+        let x = t / dt - 1.0;
+        -x * x * x / 3.0
+    } else if t > 1.0 - dt {
+        let x = (t - 1.0) / dt + 1.0;
+        x * x * x / 3.0
+    } else {
+        0.0
+    }
+}
+
+/// Hashes `frame` to a value in [-1, 1], a different `seed` gives an unrelated noise
+fn noise_value(frame: u32, seed: u32) -> f32 {
+    let mut hash = frame ^ seed.wrapping_mul(0x9E37_79B9);
+    hash = (hash ^ (hash >> 16)).wrapping_mul(0x7FEB_352D);
+    hash = (hash ^ (hash >> 15)).wrapping_mul(0x846C_A68B);
+    hash ^= hash >> 16;
+    hash as f32 / u32::MAX as f32 * 2.0 - 1.0
+}
+
+#[cfg(test)]
+mod test {
+    use std::f64::consts::TAU;
+
+    use super::*;
+
+    const FRAME_RATE: f32 = 48000.0;
+
+    type Wave = fn(f32, Volume, Pan, &mut f32, f32) -> StereoFrame;
+
+    /// One second of the left channel, so spectrum bins are 1 Hz apart
+    fn render(mut oscillator: impl FnMut(&mut f32) -> StereoFrame) -> Vec<f32> {
+        let mut phase = 0.0;
+        (0..FRAME_RATE as usize)
+            .map(|_| oscillator(&mut phase).0[0])
+            .collect()
+    }
+
+    fn render_wave(wave: Wave, freq: f32) -> Vec<f32> {
+        render(|phase| wave(freq, Volume::MAX, Pan::DEFAULT, phase, FRAME_RATE))
+    }
+
+    fn mean(samples: &[f32]) -> f32 {
+        samples.iter().sum::<f32>() / samples.len() as f32
+    }
+
+    /// Amplitude of the `freq` Hz sinusoid in one second of samples
+    fn amplitude(samples: &[f32], freq: u32) -> f32 {
+        let (re, im) = samples
+            .iter()
+            .enumerate()
+            .fold((0.0, 0.0), |(re, im), (index, sample)| {
+                let angle = TAU * freq as f64 * index as f64 / samples.len() as f64;
+                (
+                    re + *sample as f64 * angle.cos(),
+                    im + *sample as f64 * angle.sin(),
+                )
+            });
+        (2.0 * re.hypot(im) / samples.len() as f64) as f32
+    }
+
+    /// Mean power of the bins from `low` to `high` Hz
+    fn band_power(samples: &[f32], low: u32, high: u32) -> f32 {
+        let step = ((high - low) / 64).max(1);
+        let bins = (low..high).step_by(step as usize);
+        let count = bins.len();
+        bins.map(|freq| amplitude(samples, freq).powi(2))
+            .sum::<f32>()
+            / count as f32
+    }
+
+    /// Highest amplitude of the harmonics of `freq` folded back under 16 kHz, relative to the
+    /// fundamental. PolyBLEP barely helps with the ones folded close to the Nyquist frequency.
+    fn aliasing(samples: &[f32], freq: u32) -> f32 {
+        let frame_rate = FRAME_RATE as u32;
+        let aliases = (2..)
+            .map(|harmonic| harmonic * freq)
+            .take_while(|freq| *freq < frame_rate)
+            .map(|freq| frame_rate - freq)
+            .filter(|alias| *alias < 16000)
+            .map(|alias| amplitude(samples, alias));
+        aliases.fold(0.0, f32::max) / amplitude(samples, freq)
+    }
+
+    #[test]
+    fn test_waves_have_no_dc_offset() {
+        for (name, samples) in [
+            ("sine", render_wave(sine_wave, 440.0)),
+            ("square", render_wave(square_wave, 440.0)),
+            ("sawtooth", render_wave(sawtooth_wave, 440.0)),
+            ("triangle", render_wave(triangle_wave, 440.0)),
+            (
+                "pulse",
+                render(|phase| {
+                    pulse_wave(0.1, 440.0, Volume::MAX, Pan::DEFAULT, phase, FRAME_RATE)
+                }),
+            ),
+            (
+                "white noise",
+                render(|phase| white_noise(Volume::MAX, Pan::DEFAULT, phase)),
+            ),
+            (
+                "pink noise",
+                render(|phase| pink_noise(Volume::MAX, Pan::DEFAULT, phase)),
+            ),
+        ] {
+            assert!(mean(&samples).abs() < 0.02, "{name}: {}", mean(&samples));
+            assert!(
+                samples.iter().all(|sample| sample.abs() <= 1.0),
+                "{name} goes out of [-1, 1]"
+            );
+        }
+    }
+
+    #[test]
+    fn test_square_has_odd_harmonics() {
+        let samples = render_wave(square_wave, 1000.0);
+        let fundamental = 4.0 / std::f32::consts::PI;
+        approx::assert_relative_eq!(fundamental, amplitude(&samples, 1000), max_relative = 0.01);
+        approx::assert_abs_diff_eq!(0.0, amplitude(&samples, 2000), epsilon = 0.01);
+        approx::assert_relative_eq!(
+            fundamental / 3.0,
+            amplitude(&samples, 3000),
+            max_relative = 0.02
+        );
+    }
+
+    #[test]
+    fn test_sawtooth_has_every_harmonic() {
+        let samples = render_wave(sawtooth_wave, 1000.0);
+        let fundamental = 2.0 / std::f32::consts::PI;
+        approx::assert_relative_eq!(fundamental, amplitude(&samples, 1000), max_relative = 0.01);
+        approx::assert_relative_eq!(
+            fundamental / 2.0,
+            amplitude(&samples, 2000),
+            max_relative = 0.02
+        );
+    }
+
+    #[test]
+    fn test_triangle_harmonics_fall_with_their_square() {
+        let samples = render_wave(triangle_wave, 1000.0);
+        let fundamental = 8.0 / std::f32::consts::PI.powi(2);
+        approx::assert_relative_eq!(fundamental, amplitude(&samples, 1000), max_relative = 0.01);
+        approx::assert_abs_diff_eq!(0.0, amplitude(&samples, 2000), epsilon = 0.01);
+        // Smoothing the corners slightly dulls the harmonics
+        approx::assert_relative_eq!(
+            fundamental / 9.0,
+            amplitude(&samples, 3000),
+            max_relative = 0.05
+        );
+    }
+
+    #[test]
+    fn test_quarter_pulse_misses_every_fourth_harmonic() {
+        let samples =
+            render(|phase| pulse_wave(0.25, 1000.0, Volume::MAX, Pan::DEFAULT, phase, FRAME_RATE));
+        assert!(amplitude(&samples, 1000) > 0.5);
+        approx::assert_abs_diff_eq!(0.0, amplitude(&samples, 4000), epsilon = 0.01);
+    }
+
+    #[test]
+    fn test_high_notes_barely_alias() {
+        // Its harmonics 11 to 15 fold back between 14989 and 2985 Hz
+        let freq = 3001;
+        for (name, wave) in [
+            ("square", square_wave as Wave),
+            ("sawtooth", sawtooth_wave),
+            ("triangle", triangle_wave),
+        ] {
+            let aliasing = aliasing(&render_wave(wave, freq as f32), freq);
+            assert!(aliasing < 0.02, "{name}: {aliasing}");
+        }
+
+        // The naive sawtooth aliases 4 times more
+        let naive = render(|phase| {
+            let (t, _) = advance(phase, freq as f32, FRAME_RATE);
+            vector!(2.0 * t - 1.0, 2.0 * t - 1.0)
+        });
+        assert!(aliasing(&naive, freq) > 0.08);
+    }
+
+    #[test]
+    fn test_white_noise_is_flat() {
+        let samples = render(|phase| white_noise(Volume::MAX, Pan::DEFAULT, phase));
+        let ratio = band_power(&samples, 100, 1000) / band_power(&samples, 10000, 20000);
+        assert!((0.5..2.0).contains(&ratio), "{ratio}");
+    }
+
+    #[test]
+    fn test_pink_noise_falls_with_the_frequency() {
+        let samples = render(|phase| pink_noise(Volume::MAX, Pan::DEFAULT, phase));
+        // 5 octaves apart, about 15 dB
+        let ratio = band_power(&samples, 200, 400) / band_power(&samples, 6400, 12800);
+        assert!((10.0..100.0).contains(&ratio), "{ratio}");
+    }
 }
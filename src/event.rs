@@ -1,11 +1,17 @@
+use std::path::PathBuf;
+
 use winit::{event::KeyEvent, keyboard::ModifiersState};
 
 use crate::{
     audio::device::{ConfiguredDevice, Devices},
+    format::wav,
     keybindings::InputContext,
     model::{
         self,
         pattern::{HexDigit, NoteFieldValue, NoteName, OctaveValue},
+        playback::song::PlaybackStart,
+        selection::PasteMode,
+        transform::Interpolation,
     },
     utils::Direction,
     view::screen::Screen,
@@ -15,12 +21,17 @@ use crate::{
 #[derive(Debug)]
 pub enum Event {
     KeyPressed(ModifiersState, KeyEvent),
+    /// Only sent while jamming
+    KeyReleased(KeyEvent),
     State(model::Command),
     AudioCallback(model::Command),
     Panic(anyhow::Error),
     Action(Action),
     AsyncAction(AsyncAction),
-    Resize { width: u16, height: u16 },
+    Resize {
+        width: u16,
+        height: u16,
+    },
     Composite(Vec<Event>),
     StartLoading,
     LoadingDone(AsyncAction),
@@ -31,12 +42,30 @@ pub enum Event {
     RequestRedraw,
     Text(Text),
     ChangeScreen(Screen),
+    SaveSong(PathBuf),
+    OpenSong(PathBuf),
+    ExportPatternText(PathBuf),
+    ImportPatternText(PathBuf),
+    ExportXm(PathBuf),
+    ExportMidi(PathBuf),
+    ChooseStemsGlobalVolume(PathBuf),
+    ChooseWavSampleFormat(PathBuf, RenderTarget),
+    RenderWav(PathBuf, wav::SampleFormat, RenderTarget),
+    RenderProgress(f32),
     ExitApp,
 }
 
 #[derive(Debug)]
 pub enum AsyncAction {
     GetDevices(Devices),
+    LoadSong(PathBuf, anyhow::Result<Box<model::Song>>),
+    RenderWav(PathBuf, RenderTarget, anyhow::Result<()>),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum RenderTarget {
+    Song,
+    Stems { with_global_volume: bool },
 }
 
 #[derive(Debug, Clone)]
@@ -47,6 +76,12 @@ pub enum Action {
     Confirm,
     Cancel,
     TogglePlay,
+    StartPlayback(PlaybackStart),
+    ToggleFollowPlaying,
+    ToggleRecord,
+    ToggleRecordQuantize,
+    ToggleRecordPolyphony,
+    ToggleJam,
     ToggleFullscreen,
     RequestChangeScreenToDeviceSelection,
     RequestChangeScreenToSongEditor,
@@ -63,12 +98,48 @@ pub enum Action {
         octave_modifier: i32,
     },
     SetNoteCut,
+    SetNoteOff,
     ClearField,
     SetOctaveField(OctaveValue),
     SetHexField(HexDigit),
     CreateNewPattern,
+    DuplicatePattern,
+    DeletePattern,
     GoToNextPattern,
     GoToPreviousPattern,
+    ChangeCurrentOrder {
+        increment: i32,
+    },
+    InsertOrderEntry,
+    RemoveOrderEntry,
+    Undo,
+    Redo,
+    ExtendSelection(Direction),
+    Copy,
+    Cut,
+    Paste(PasteMode),
+    Transpose {
+        semitones: i32,
+    },
+    InterpolateVelocity(Interpolation),
+    HumanizeVelocity,
+    ChangeTempo,
+    ChangeEnvelope,
+    ChangeSampleLoops,
+    ChangeInterpolation,
+    ChangeResampleQuality,
+    ToggleResampleOnDeviceChange,
+    ToggleMute,
+    ToggleSolo,
+    SaveSong,
+    SaveSongAs,
+    OpenSong,
+    ExportPatternText,
+    ImportPatternText,
+    ExportXm,
+    ExportMidi,
+    RenderWav,
+    RenderStems,
     Text(Text),
 }
 
@@ -0,0 +1,196 @@
+use anyhow::bail;
+
+pub struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data, position: 0 }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.position
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn seek(&mut self, position: usize) -> anyhow::Result<()> {
+        if position > self.data.len() {
+            bail!(
+                "Unexpected end of data: tried to seek to offset {position} but data is {} byte(s) long",
+                self.data.len()
+            );
+        }
+        self.position = position;
+        Ok(())
+    }
+
+    pub fn bytes(&mut self, count: usize) -> anyhow::Result<&'a [u8]> {
+        if count > self.remaining() {
+            bail!(
+                "Unexpected end of data: tried to read {count} byte(s) at offset {} but only {} remain",
+                self.position,
+                self.remaining()
+            );
+        }
+        let bytes = &self.data[self.position..self.position + count];
+        self.position += count;
+        Ok(bytes)
+    }
+
+    pub fn array<const N: usize>(&mut self) -> anyhow::Result<[u8; N]> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    pub fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    pub fn i8(&mut self) -> anyhow::Result<i8> {
+        Ok(self.u8()? as i8)
+    }
+
+    pub fn u16_le(&mut self) -> anyhow::Result<u16> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub fn u16_be(&mut self) -> anyhow::Result<u16> {
+        Ok(u16::from_be_bytes(self.array()?))
+    }
+
+    pub fn u32_le(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub fn u32_be(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_be_bytes(self.array()?))
+    }
+
+    pub fn i32_le(&mut self) -> anyhow::Result<i32> {
+        Ok(i32::from_le_bytes(self.array()?))
+    }
+
+    pub fn f32_le(&mut self) -> anyhow::Result<f32> {
+        Ok(f32::from_le_bytes(self.array()?))
+    }
+
+    /// Length prefixed (u32 little endian) UTF-8 string
+    pub fn string(&mut self) -> anyhow::Result<String> {
+        let len = self.u32_le()? as usize;
+        Ok(String::from_utf8(self.bytes(len)?.to_vec())?)
+    }
+
+    /// Fixed size, zero padded string as found in tracker modules.
+    /// Anything after the first zero byte is ignored and invalid UTF-8 is replaced.
+    pub fn fixed_string(&mut self, len: usize) -> anyhow::Result<String> {
+        let bytes = self.bytes(len)?;
+        let end = bytes.iter().position(|b| *b == 0).unwrap_or(len);
+        Ok(String::from_utf8_lossy(&bytes[..end]).trim_end().to_owned())
+    }
+}
+
+#[derive(Default)]
+pub struct Writer {
+    data: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Writer {
+        Writer::default()
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn u16_le(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn u16_be(&mut self, value: u16) {
+        self.bytes(&value.to_be_bytes());
+    }
+
+    pub fn u32_le(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn u32_be(&mut self, value: u32) {
+        self.bytes(&value.to_be_bytes());
+    }
+
+    pub fn i32_le(&mut self, value: i32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn f32_le(&mut self, value: f32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    /// Length prefixed (u32 little endian) UTF-8 string
+    pub fn string(&mut self, value: &str) {
+        self.u32_le(value.len() as u32);
+        self.bytes(value.as_bytes());
+    }
+
+    /// Fixed size, zero padded string as found in tracker modules, truncated if too long
+    pub fn fixed_string(&mut self, value: &str, len: usize) {
+        let bytes = &value.as_bytes()[..value.len().min(len)];
+        self.bytes(bytes);
+        self.bytes(&vec![0; len - bytes.len()]);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_written_values_are_read_back() {
+        let mut writer = Writer::new();
+        writer.u8(0xAB);
+        writer.u16_le(0x1234);
+        writer.u32_le(0xDEADBEEF);
+        writer.f32_le(-0.5);
+        writer.string("tracky");
+        writer.fixed_string("Bass", 8);
+        writer.fixed_string("Too long", 3);
+
+        let bytes = writer.into_bytes();
+        let mut reader = Reader::new(&bytes);
+        assert_eq!(0xAB, reader.u8().unwrap());
+        assert_eq!(0x1234, reader.u16_le().unwrap());
+        assert_eq!(0xDEADBEEF, reader.u32_le().unwrap());
+        assert_eq!(-0.5, reader.f32_le().unwrap());
+        assert_eq!("tracky", reader.string().unwrap());
+        assert_eq!("Bass", reader.fixed_string(8).unwrap());
+        assert_eq!("Too", reader.fixed_string(3).unwrap());
+        assert_eq!(0, reader.remaining());
+    }
+
+    #[test]
+    fn test_fixed_string_stops_at_first_zero() {
+        let mut reader = Reader::new(b"Bass  \0garbage\0\0end");
+        assert_eq!("Bass", reader.fixed_string(16).unwrap());
+        assert_eq!(b"end", reader.bytes(3).unwrap());
+    }
+
+    #[test]
+    fn test_reading_past_the_end_is_an_error() {
+        let mut reader = Reader::new(&[1, 2, 3]);
+        assert!(reader.u32_le().is_err());
+        assert_eq!(3, reader.remaining());
+    }
+}
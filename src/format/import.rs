@@ -0,0 +1,476 @@
+//! Pieces shared by the importers of foreign formats (tracker modules, MIDI files...).
+//!
+//! Importers never fail on something tracky can't represent yet, they drop it and record a
+//! [`Warning`] in the [`Report`] returned alongside the song.
+
+use std::fmt;
+
+use anyhow::ensure;
+use log::{info, warn};
+
+use joy_vector::vector;
+
+use crate::{
+    audio::{dsp::interpolation::InterpolationMode, signal, Decibels, Volume},
+    model::{
+        channel::MuteSolo,
+        effect::Effect,
+        instrument::{Instrument, Instruments, Kind, MAX_SLOT_COUNT},
+        pattern::{
+            u8_to_hex_digit_pair, EffectFieldValue, HexDigit, OctaveValue, Pattern, PatternLine,
+            Patterns,
+        },
+        sample_loop::{LoopMode, SampleLoop, SampleLoops},
+        tempo::Tempo,
+        Song,
+    },
+};
+
+/// Where in the imported patterns a warning comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    pub pattern: usize,
+    pub row: usize,
+    pub channel: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub location: Option<Location>,
+    pub message: String,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Location {
+            pattern,
+            row,
+            channel,
+        } = self;
+        write!(f, "pattern {pattern} row {row:03} channel {channel}")
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(location) = self.location {
+            write!(f, "{location}: ")?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub warnings: Vec<Warning>,
+}
+
+impl Report {
+    pub fn warn<S: Into<String>>(&mut self, message: S) {
+        self.warnings.push(Warning {
+            location: None,
+            message: message.into(),
+        });
+    }
+
+    pub fn warn_at<S: Into<String>>(&mut self, location: Location, message: S) {
+        self.warnings.push(Warning {
+            location: Some(location),
+            message: message.into(),
+        });
+    }
+
+    pub fn log(&self, source: &str) {
+        if self.warnings.is_empty() {
+            info!("Imported {source} without loss");
+            return;
+        }
+        warn!("Imported {source} with {} warning(s):", self.warnings.len());
+        for warning in self.warnings.iter() {
+            warn!("  {warning}");
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Import {
+    pub song: Song,
+    pub report: Report,
+}
+
+/// Song with the settings tracky would use for a new song
+pub fn song(patterns: Patterns, instruments: Instruments, tempo: Tempo) -> Song {
+    Song {
+        mute_solo: MuteSolo::new(patterns.channel_count as usize),
+        patterns,
+        instruments,
+        global_octave: OctaveValue::default(),
+        global_volume: Decibels::DEFAULT.volume(),
+        tempo,
+    }
+}
+
+/// Tracker modules express tempo with ticks: a row lasts `speed` ticks of `2.5 / tempo` seconds.
+/// They conventionally highlight a beat every 4 rows, so the BPM is the tempo at speed 6.
+pub fn tempo(speed: u8, tempo: u8) -> anyhow::Result<Tempo> {
+    const ROWS_PER_BEAT: u32 = 4;
+    Tempo::new(
+        tempo as f32 * 6.0 / speed as f32,
+        ROWS_PER_BEAT,
+        speed as u32,
+    )
+}
+
+/// Linear tracker volume (0..=max) to a tracky velocity
+pub fn velocity(volume: u8, max: u8) -> u8 {
+    ((volume.min(max) as u32 * 0xFF + max as u32 / 2) / max as u32) as u8
+}
+
+/// Linear tracker volume (0..=max) to an instrument volume
+pub fn volume(volume: u8, max: u8) -> Volume {
+    Volume::new_clamped(volume as f32 / max as f32)
+}
+
+/// Effect column value of a ProTracker numbered effect, `None` if tracky doesn't run it.
+/// Trackers reuse the previous parameter when it is zero, tracky doesn't remember them so these
+/// are not supported either.
+pub fn effect(command: u8, param: u8) -> Option<EffectFieldValue> {
+    if param == 0 {
+        return None;
+    }
+    let value = EffectFieldValue {
+        command: HexDigit::new(command)?,
+        parameter: u8_to_hex_digit_pair(param),
+    };
+    Effect::from_field_value(&value).map(|_| value)
+}
+
+/// Effect column value of a Scream Tracker lettered effect (`A` is 1), which Impulse Tracker
+/// kept. Fine slides and retriggers changing the volume have no ProTracker counterpart.
+pub fn lettered_effect(letter: u8, param: u8) -> Option<EffectFieldValue> {
+    let (x, y) = (param >> 4, param & 0xF);
+    let (command, param) = match letter {
+        // Dxy
+        4 if x == 0 || y == 0 => (0xA, param),
+        // Exx, Fxx
+        5 if x < 0xE => (0x2, param),
+        6 if x < 0xE => (0x1, param),
+        // Gxx, Hxy, Jxy, Oxx
+        7 => (0x3, param),
+        8 => (0x4, param),
+        10 => (0x0, param),
+        15 => (0x9, param),
+        // Qxy, x = 0 or 8 leaves the volume alone
+        17 if (x == 0 || x == 8) && y != 0 => (0xE, 0x90 | y),
+        // SDx
+        19 if x == 0xD => (0xE, param),
+        _ => return None,
+    };
+    effect(command, param)
+}
+
+/// Patterns the song order plays, in module order, along with the order renumbered to index
+/// them. The other patterns are never played so they are not imported.
+pub fn used_patterns(
+    order: &[u8],
+    pattern_count: usize,
+    report: &mut Report,
+) -> (Vec<usize>, Vec<u8>) {
+    let mut used = order
+        .iter()
+        .map(|pattern| *pattern as usize)
+        .collect::<Vec<_>>();
+    used.sort_unstable();
+    used.dedup();
+    if used.len() < pattern_count {
+        report.warn(format!(
+            "{} pattern(s) out of the song order are dropped",
+            pattern_count - used.len()
+        ));
+    }
+    let order = order
+        .iter()
+        .map(|pattern| used.partition_point(|used| *used < *pattern as usize) as u8)
+        .collect();
+    (used, order)
+}
+
+/// Builds tracky patterns out of row major tracker cells.
+/// tracky patterns all have the same length, shorter patterns are padded to the longest one.
+pub fn patterns<C, F>(
+    cells: &[Vec<C>],
+    channel_count: usize,
+    report: &mut Report,
+    mut convert_cell: F,
+) -> anyhow::Result<Patterns>
+where
+    F: FnMut(&C, Location, &mut Report) -> PatternLine,
+{
+    ensure!(!cells.is_empty(), "Module has no pattern");
+    let channel_len = cells
+        .iter()
+        .map(|cells| cells.len() / channel_count)
+        .max()
+        .unwrap();
+
+    let patterns = cells
+        .iter()
+        .enumerate()
+        .map(|(pattern_index, cells)| {
+            let row_count = cells.len() / channel_count;
+            if row_count < channel_len {
+                report.warn(format!(
+                    "Pattern {pattern_index} has {row_count} row(s), it is padded to {channel_len}"
+                ));
+            }
+            let mut pattern = Pattern::new(channel_count as i32, channel_len as i32);
+            for (cell_index, cell) in cells.iter().enumerate() {
+                let (row, channel) = (cell_index / channel_count, cell_index % channel_count);
+                let location = Location {
+                    pattern: pattern_index,
+                    row,
+                    channel,
+                };
+                pattern.lines[channel * channel_len + row] = convert_cell(cell, location, report);
+            }
+            pattern
+        })
+        .collect();
+
+    Patterns::from_patterns(channel_count as i32, channel_len as i32, patterns)
+}
+
+/// Tracker instrument numbers start at 1, tracky slots at 0
+pub fn set_instrument(
+    instruments: &mut Instruments,
+    index: usize,
+    name: &str,
+    instrument: Instrument,
+) -> anyhow::Result<()> {
+    ensure!(
+        index < MAX_SLOT_COUNT as usize,
+        "Instrument {} ({name:?}) does not fit in tracky's {MAX_SLOT_COUNT} instrument slots",
+        index + 1
+    );
+    instruments.set(index as u8, instrument)
+}
+
+/// Raw PCM sample data, little endian when 16 bit
+pub fn pcm_samples(bytes: &[u8], is_16_bit: bool, is_signed: bool) -> Vec<f32> {
+    if is_16_bit {
+        bytes
+            .chunks_exact(2)
+            .map(|bytes| {
+                let value = u16::from_le_bytes([bytes[0], bytes[1]]);
+                let value = if is_signed {
+                    value as i16
+                } else {
+                    (value ^ 0x8000) as i16
+                };
+                value as f32 / 32768.0
+            })
+            .collect()
+    } else {
+        bytes
+            .iter()
+            .map(|byte| {
+                let value = if is_signed { *byte } else { byte ^ 0x80 };
+                value as i8 as f32 / 128.0
+            })
+            .collect()
+    }
+}
+
+/// Tracker samples are mono, tracky instruments are stereo
+pub fn mono_sample<I>(name: String, samples: I, frame_rate: f32, loops: SampleLoops) -> Kind
+where
+    I: Iterator<Item = f32>,
+{
+    Kind::Sample {
+        name,
+        signal: signal::stereo::Owned::from_frames(
+            samples.map(|sample| vector!(sample, sample)).collect(),
+            frame_rate,
+        ),
+        loops,
+        interpolation: InterpolationMode::default(),
+    }
+}
+
+/// Tracker loop from `start` to `end` (excluded) cut to the `frame_count` frames of its sample,
+/// `what` names it in the warnings
+pub fn sample_loop(
+    mode: LoopMode,
+    start: usize,
+    end: usize,
+    frame_count: usize,
+    what: &str,
+    report: &mut Report,
+) -> Option<SampleLoop> {
+    if end > frame_count {
+        report.warn(format!(
+            "{what} ({start}..{end}) goes past the {frame_count} frame(s) of the sample, it is cut"
+        ));
+    }
+    let sample_loop = SampleLoop::new(mode, start, end.min(frame_count)).ok();
+    if sample_loop.is_none() {
+        report.warn(format!("{what} ({start}..{end}) is empty, it is ignored"));
+    }
+    sample_loop
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_default_tracker_timing() {
+        let tempo = tempo(6, 125).unwrap();
+        approx::assert_relative_eq!(125.0 / 15.0, tempo.line_per_second());
+        approx::assert_relative_eq!(125.0, tempo.bpm);
+        assert_eq!(6, tempo.ticks_per_row);
+    }
+
+    #[test]
+    fn test_velocity_covers_the_whole_range() {
+        assert_eq!(0x00, velocity(0, 64));
+        assert_eq!(0x80, velocity(32, 64));
+        assert_eq!(0xFF, velocity(64, 64));
+        assert_eq!(0xFF, velocity(80, 64));
+    }
+
+    #[test]
+    fn test_effects_tracky_runs_are_kept() {
+        let effect = |command, param| effect(command, param).map(|value| value.to_string());
+        assert_eq!(Some("437".to_string()), effect(0x4, 0x37));
+        assert_eq!(Some("ED2".to_string()), effect(0xE, 0xD2));
+        assert_eq!(None, effect(0xB, 0x01));
+        assert_eq!(None, effect(0xE, 0xC2));
+        // Parameter memory
+        assert_eq!(None, effect(0x3, 0x00));
+    }
+
+    #[test]
+    fn test_lettered_effects() {
+        let effect = |letter: char, param| {
+            lettered_effect(letter as u8 - b'A' + 1, param).map(|value| value.to_string())
+        };
+        for (letter, param, expected) in [
+            ('D', 0x0F, Some("A0F")),
+            ('D', 0xF0, Some("AF0")),
+            ('D', 0xF2, None),
+            ('D', 0x12, None),
+            ('E', 0x20, Some("220")),
+            ('E', 0xF1, None),
+            ('F', 0x20, Some("120")),
+            ('G', 0x10, Some("310")),
+            ('G', 0x00, None),
+            ('H', 0x48, Some("448")),
+            ('J', 0x47, Some("047")),
+            ('O', 0x10, Some("910")),
+            ('Q', 0x03, Some("E93")),
+            ('Q', 0x83, Some("E93")),
+            ('Q', 0x13, None),
+            ('S', 0xD2, Some("ED2")),
+            ('S', 0x12, None),
+        ] {
+            assert_eq!(
+                expected.map(ToString::to_string),
+                effect(letter, param),
+                "{letter}{param:02X}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_unused_patterns_are_dropped() {
+        let mut report = Report::default();
+        assert_eq!(
+            (vec![1, 3], vec![1, 0, 1]),
+            used_patterns(&[3, 1, 3], 4, &mut report)
+        );
+        assert_eq!(
+            "2 pattern(s) out of the song order are dropped",
+            report.warnings[0].to_string()
+        );
+    }
+
+    #[test]
+    fn test_pcm_samples() {
+        assert_eq!(vec![0.5, -1.0], pcm_samples(&[0x40, 0x80], false, true));
+        assert_eq!(vec![0.5, -1.0], pcm_samples(&[0xC0, 0x00], false, false));
+        assert_eq!(
+            vec![0.5, -1.0],
+            pcm_samples(&[0x00, 0x40, 0x00, 0x80], true, true)
+        );
+        assert_eq!(
+            vec![0.5, -1.0],
+            pcm_samples(&[0x00, 0xC0, 0x00, 0x00], true, false)
+        );
+    }
+
+    #[test]
+    fn test_short_patterns_are_padded() {
+        let mut report = Report::default();
+        let cells = vec![vec![1u8; 2 * 4], vec![2u8; 2 * 2]];
+        let patterns = patterns(&cells, 2, &mut report, |_, _, _| {
+            "C-5 .. ..".parse().unwrap()
+        })
+        .unwrap();
+        assert_eq!(4, patterns.channel_len);
+        assert_eq!(2, patterns.pattern_count);
+        assert_eq!(
+            PatternLine::default(),
+            patterns.patterns()[1].lines[2],
+            "padded row"
+        );
+        assert_eq!(
+            "Pattern 1 has 2 row(s), it is padded to 4",
+            report.warnings[0].to_string()
+        );
+    }
+
+    #[test]
+    fn test_warning_display() {
+        let mut report = Report::default();
+        report.warn("global");
+        report.warn_at(
+            Location {
+                pattern: 2,
+                row: 5,
+                channel: 1,
+            },
+            "local",
+        );
+        assert_eq!("global", report.warnings[0].to_string());
+        assert_eq!(
+            "pattern 2 row 005 channel 1: local",
+            report.warnings[1].to_string()
+        );
+    }
+
+    #[test]
+    fn test_sample_loops_are_cut_to_the_sample() {
+        let mut report = Report::default();
+        assert_eq!(
+            Some(SampleLoop::new(LoopMode::Forward, 2, 8).unwrap()),
+            sample_loop(LoopMode::Forward, 2, 10, 8, "Loop", &mut report)
+        );
+        assert_eq!(
+            None,
+            sample_loop(LoopMode::PingPong, 9, 12, 8, "Sustain loop", &mut report)
+        );
+        assert_eq!(
+            vec![
+                "Loop (2..10) goes past the 8 frame(s) of the sample, it is cut",
+                "Sustain loop (9..12) goes past the 8 frame(s) of the sample, it is cut",
+                "Sustain loop (9..12) is empty, it is ignored",
+            ],
+            report
+                .warnings
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,1296 @@
+//! Impulse Tracker `.it` importer.
+//!
+//! Little endian layout:
+//! ```text
+//! header            "IMPM", name: 26 bytes, highlight: u16, order_count: u16,
+//!                   instrument_count: u16, sample_count: u16, pattern_count: u16,
+//!                   created with: u16, compatible with: u16, flags: u16, special: u16,
+//!                   global volume: u8, mix volume: u8, speed: u8, tempo: u8, separation: u8,
+//!                   pitch wheel depth: u8, message length: u16, message offset: u32, 4 reserved
+//! channels          64 * pan: u8 (0..=64, 100 surround, +128 disabled), 64 * volume: u8 (0..=64)
+//! order             order_count * u8 (254 marker, 255 end of song)
+//! offsets           instrument_count * u32, sample_count * u32, pattern_count * u32
+//! instrument        "IMPI", ..., note/sample keyboard at 0x40: 120 * (note: u8, sample: u8),
+//!                   envelopes at 0x130 (new format only)
+//! sample            "IMPS", file name: 12 bytes, 0, global volume: u8, flags: u8, volume: u8,
+//!                   name: 26 bytes, convert: u8, pan: u8, length: u32, loop: 2 * u32,
+//!                   c5speed: u32, sustain loop: 2 * u32, data offset: u32, vibrato: 4 bytes
+//! pattern           packed size: u16, row_count: u16, 4 reserved bytes, packed data
+//! ```
+//!
+//! IT's C-5 plays a sample at its c5speed, notes are kept as is.
+
+use std::{fs, path::Path};
+
+use anyhow::{bail, ensure, Context};
+
+use crate::model::{
+    instrument::{Instrument, Instruments},
+    midi::{midi_value_to_note, MidiValue},
+    pattern::{u8_to_hex_digit_pair, Field, NoteFieldValue, PatternLine},
+    sample_loop::{LoopMode, SampleLoops},
+    Song,
+};
+
+use super::{
+    binary::Reader,
+    import::{self, Import, Location, Report},
+};
+
+pub const EXTENSION: &str = "it";
+
+const ID: &[u8; 4] = b"IMPM";
+const INSTRUMENT_ID: &[u8; 4] = b"IMPI";
+const SAMPLE_ID: &[u8; 4] = b"IMPS";
+const CHANNEL_COUNT: usize = 64;
+const MAX_ROW_COUNT: usize = 200;
+const MAX_PATTERN_COUNT: usize = 200;
+
+const ORDER_MARKER: u8 = 254;
+const ORDER_END: u8 = 255;
+
+const FLAG_USE_INSTRUMENTS: u16 = 0b100;
+const NEW_INSTRUMENT_FORMAT: u16 = 0x200;
+const KEYBOARD_OFFSET: usize = 0x40;
+const KEYBOARD_C5: usize = 60;
+const OLD_ENVELOPE_FLAGS_OFFSET: usize = 0x11;
+const NEW_ENVELOPES_OFFSET: usize = 0x130;
+const ENVELOPE_SIZE: usize = 82;
+const ENVELOPE_ENABLED: u8 = 0b1;
+
+const SAMPLE_PRESENT: u8 = 0b1;
+const SAMPLE_16_BIT: u8 = 0b10;
+const SAMPLE_STEREO: u8 = 0b100;
+const SAMPLE_COMPRESSED: u8 = 0b1000;
+const SAMPLE_LOOP: u8 = 0b1_0000;
+const SAMPLE_SUSTAIN_LOOP: u8 = 0b10_0000;
+const SAMPLE_PING_PONG_LOOP: u8 = 0b100_0000;
+const SAMPLE_PING_PONG_SUSTAIN_LOOP: u8 = 0b1000_0000;
+const CONVERT_SIGNED: u8 = 0b1;
+const CONVERT_IT215: u8 = 0b100;
+
+const MAX_VOLUME: u8 = 64;
+const PAN_CENTER: u8 = 32;
+const PAN_DISABLED: u8 = 0x80;
+const NOTE_CUT: u8 = 254;
+const NOTE_OFF: u8 = 255;
+const LAST_NOTE: u8 = 119;
+const VOLUME_COLUMN_SET_VOLUME: std::ops::RangeInclusive<u8> = 0..=64;
+
+const EFFECT_SET_SPEED: u8 = 1;
+const EFFECT_SET_TEMPO: u8 = 20;
+
+#[derive(Clone, Copy, Default)]
+struct Cell {
+    note: Option<u8>,
+    instrument: u8,
+    volume: Option<u8>,
+    effect: u8,
+    param: u8,
+}
+
+struct Sample {
+    name: String,
+    volume: u8,
+    global_volume: u8,
+    flags: u8,
+    c5speed: u32,
+    /// Start and end (excluded) frames
+    loop_points: (u32, u32),
+    sustain_loop_points: (u32, u32),
+    data: Vec<f32>,
+}
+
+struct ItInstrument {
+    name: String,
+    global_volume: u8,
+    /// Sample number (starting at 1) played by every note
+    keyboard: Vec<u8>,
+    volume_envelope: bool,
+    panning_envelope: bool,
+}
+
+struct Module {
+    speed: u8,
+    tempo: u8,
+    uses_instruments: bool,
+    channel_pans: Vec<u8>,
+    channel_volumes: Vec<u8>,
+    order: Vec<u8>,
+    /// Row major cells of every pattern, for the 64 IT channels
+    patterns: Vec<Vec<Cell>>,
+    instruments: Vec<ItInstrument>,
+    samples: Vec<Option<Sample>>,
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Import> {
+    let data = fs::read(path.as_ref())
+        .with_context(|| format!("Could not open module {:?}", path.as_ref()))?;
+    read(&data).with_context(|| format!("Invalid module {:?}", path.as_ref()))
+}
+
+pub fn read(data: &[u8]) -> anyhow::Result<Import> {
+    let mut report = Report::default();
+    let module = parse(data, &mut report)?;
+    let song = convert(module, &mut report)?;
+    Ok(Import { song, report })
+}
+
+fn parse(data: &[u8], report: &mut Report) -> anyhow::Result<Module> {
+    let mut reader = Reader::new(data);
+    ensure!(reader.bytes(ID.len())? == ID, "Not an IT module");
+    let _name = reader.fixed_string(26)?;
+    let _highlight = reader.u16_le()?;
+    let order_count = reader.u16_le()? as usize;
+    let instrument_count = reader.u16_le()? as usize;
+    let sample_count = reader.u16_le()? as usize;
+    let pattern_count = reader.u16_le()? as usize;
+    let _created_with = reader.u16_le()?;
+    let compatible_with = reader.u16_le()?;
+    let flags = reader.u16_le()?;
+    let _special = reader.u16_le()?;
+    let _global_volume = reader.u8()?;
+    let _mix_volume = reader.u8()?;
+    let speed = reader.u8()?;
+    let tempo = reader.u8()?;
+    reader.bytes(2 + 2 + 4 + 4)?;
+    let channel_pans = reader.bytes(CHANNEL_COUNT)?.to_vec();
+    let channel_volumes = reader.bytes(CHANNEL_COUNT)?.to_vec();
+
+    let order = reader
+        .bytes(order_count)?
+        .iter()
+        .copied()
+        .take_while(|pattern| *pattern != ORDER_END)
+        .filter(|pattern| *pattern != ORDER_MARKER)
+        .collect::<Vec<_>>();
+    ensure!(!order.is_empty(), "Song order is empty");
+    ensure!(
+        pattern_count <= MAX_PATTERN_COUNT,
+        "Invalid pattern count {pattern_count}"
+    );
+    ensure!(
+        order
+            .iter()
+            .all(|pattern| (*pattern as usize) < pattern_count),
+        "Song order {order:?} references missing patterns (pattern count is {pattern_count})"
+    );
+    let (used_patterns, order) = import::used_patterns(&order, pattern_count, report);
+
+    let mut offsets = |count: usize| {
+        (0..count)
+            .map(|_| Ok(reader.u32_le()? as usize))
+            .collect::<anyhow::Result<Vec<_>>>()
+    };
+    let instrument_offsets = offsets(instrument_count)?;
+    let sample_offsets = offsets(sample_count)?;
+    let pattern_offsets = offsets(pattern_count)?;
+
+    let uses_instruments = flags & FLAG_USE_INSTRUMENTS != 0;
+    let instruments = if uses_instruments {
+        instrument_offsets
+            .into_iter()
+            .enumerate()
+            .map(|(index, offset)| {
+                parse_instrument(data, offset, compatible_with >= NEW_INSTRUMENT_FORMAT)
+                    .with_context(|| format!("Could not read instrument {}", index + 1))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?
+    } else {
+        Vec::new()
+    };
+
+    let samples = sample_offsets
+        .into_iter()
+        .enumerate()
+        .map(|(index, offset)| {
+            parse_sample(data, offset)
+                .with_context(|| format!("Could not read sample {}", index + 1))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let patterns = used_patterns
+        .into_iter()
+        .map(|index| {
+            parse_pattern(data, pattern_offsets[index])
+                .with_context(|| format!("Could not read pattern {index}"))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok(Module {
+        speed: speed.max(1),
+        tempo: tempo.max(32),
+        uses_instruments,
+        channel_pans,
+        channel_volumes,
+        order,
+        patterns,
+        instruments,
+        samples,
+    })
+}
+
+fn parse_instrument(
+    data: &[u8],
+    offset: usize,
+    is_new_format: bool,
+) -> anyhow::Result<ItInstrument> {
+    let mut reader = Reader::new(data);
+    reader.seek(offset)?;
+    ensure!(
+        reader.bytes(4)? == INSTRUMENT_ID,
+        "Invalid instrument header"
+    );
+
+    let (global_volume, volume_envelope, panning_envelope) = if is_new_format {
+        reader.seek(offset + 0x18)?;
+        let global_volume = reader.u8()?;
+        reader.seek(offset + NEW_ENVELOPES_OFFSET)?;
+        let volume_flags = reader.u8()?;
+        reader.seek(offset + NEW_ENVELOPES_OFFSET + ENVELOPE_SIZE)?;
+        let panning_flags = reader.u8()?;
+        (
+            // 0..=128
+            global_volume.min(128) / 2,
+            volume_flags & ENVELOPE_ENABLED != 0,
+            panning_flags & ENVELOPE_ENABLED != 0,
+        )
+    } else {
+        reader.seek(offset + OLD_ENVELOPE_FLAGS_OFFSET)?;
+        let volume_flags = reader.u8()?;
+        (MAX_VOLUME, volume_flags & ENVELOPE_ENABLED != 0, false)
+    };
+
+    reader.seek(offset + 0x20)?;
+    let name = reader.fixed_string(26)?;
+    reader.seek(offset + KEYBOARD_OFFSET)?;
+    let keyboard = reader
+        .bytes(120 * 2)?
+        .chunks_exact(2)
+        .map(|note_sample| note_sample[1])
+        .collect();
+
+    Ok(ItInstrument {
+        name,
+        global_volume,
+        keyboard,
+        volume_envelope,
+        panning_envelope,
+    })
+}
+
+fn parse_sample(data: &[u8], offset: usize) -> anyhow::Result<Option<Sample>> {
+    let mut reader = Reader::new(data);
+    reader.seek(offset)?;
+    ensure!(reader.bytes(4)? == SAMPLE_ID, "Invalid sample header");
+    let _file_name = reader.fixed_string(12)?;
+    reader.u8()?;
+    let global_volume = reader.u8()?.min(MAX_VOLUME);
+    let flags = reader.u8()?;
+    let volume = reader.u8()?.min(MAX_VOLUME);
+    let name = reader.fixed_string(26)?;
+    let convert = reader.u8()?;
+    let _pan = reader.u8()?;
+    let length = reader.u32_le()? as usize;
+    let loop_points = (reader.u32_le()?, reader.u32_le()?);
+    let c5speed = reader.u32_le()?;
+    let sustain_loop_points = (reader.u32_le()?, reader.u32_le()?);
+    let data_offset = reader.u32_le()? as usize;
+
+    if flags & SAMPLE_PRESENT == 0 || length == 0 {
+        return Ok(None);
+    }
+
+    let is_16_bit = flags & SAMPLE_16_BIT != 0;
+    reader.seek(data_offset)?;
+    // Stereo samples store the whole left channel first, only that one is read
+    let data = if flags & SAMPLE_COMPRESSED != 0 {
+        let is_it215 = convert & CONVERT_IT215 != 0;
+        if is_16_bit {
+            decompress::<16>(&mut reader, length, is_it215)?
+        } else {
+            decompress::<8>(&mut reader, length, is_it215)?
+        }
+    } else {
+        let bytes = reader
+            .bytes(length * if is_16_bit { 2 } else { 1 })
+            .context("Sample data is truncated")?;
+        import::pcm_samples(bytes, is_16_bit, convert & CONVERT_SIGNED != 0)
+    };
+
+    Ok(Some(Sample {
+        name,
+        volume,
+        global_volume,
+        flags,
+        c5speed,
+        loop_points,
+        sustain_loop_points,
+        data,
+    }))
+}
+
+/// Reads bits least significant first
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, count: u32) -> anyhow::Result<u32> {
+        let mut value = 0;
+        for bit in 0..count {
+            let Some(byte) = self.data.get(self.position / 8) else {
+                bail!("Compressed block is truncated");
+            };
+            value |= ((*byte as u32 >> (self.position % 8)) & 1) << bit;
+            self.position += 1;
+        }
+        Ok(value)
+    }
+}
+
+/// IT 2.14 / 2.15 sample decompression, for `BITS` = 8 or 16.
+///
+/// Samples are stored in blocks of 0x8000 bytes once decompressed, each one prefixed by its
+/// compressed size. Values are deltas (deltas of deltas for IT 2.15) written with a bit width
+/// that changes along the way:
+/// - below 7 bits, the value `1 << (width - 1)` is followed by the new width on 3 (or 4) bits
+/// - up to `BITS` bits, values just above the maximum announce the new width
+/// - at `BITS + 1` bits, the top bit set means the low byte is the new width
+fn decompress<const BITS: u32>(
+    reader: &mut Reader,
+    length: usize,
+    is_it215: bool,
+) -> anyhow::Result<Vec<f32>> {
+    let block_length = 0x8000 / (BITS as usize / 8);
+    let width_bits = if BITS == 8 { 3 } else { 4 };
+    let sign_shift = 32 - BITS;
+
+    // Every sample takes at least a bit, a corrupt length can't allocate more than the file holds
+    let mut samples = Vec::with_capacity(length.min(reader.remaining().saturating_mul(8)));
+    while samples.len() < length {
+        let compressed_size = reader.u16_le()? as usize;
+        let mut bits = BitReader {
+            data: reader.bytes(compressed_size)?,
+            position: 0,
+        };
+        let block_end = (samples.len() + block_length).min(length);
+        let mut width = BITS + 1;
+        let (mut d1, mut d2) = (0i32, 0i32);
+
+        while samples.len() < block_end {
+            let value = bits.bits(width)?;
+            if width < 7 {
+                if value == 1 << (width - 1) {
+                    let new_width = bits.bits(width_bits)? + 1;
+                    width = if new_width < width {
+                        new_width
+                    } else {
+                        new_width + 1
+                    };
+                    continue;
+                }
+            } else if width <= BITS {
+                let border = (((1u32 << BITS) - 1) >> (BITS + 1 - width)) - BITS / 2;
+                if value > border && value <= border + BITS {
+                    let new_width = value - border;
+                    width = if new_width < width {
+                        new_width
+                    } else {
+                        new_width + 1
+                    };
+                    continue;
+                }
+            } else if width == BITS + 1 {
+                if value & (1 << BITS) != 0 {
+                    width = (value + 1) & 0xFF;
+                    ensure!(
+                        (1..=BITS + 1).contains(&width),
+                        "Invalid bit width {width} in compressed sample"
+                    );
+                    continue;
+                }
+            } else {
+                bail!("Invalid bit width {width} in compressed sample");
+            }
+
+            // Sign extend the value from its width to BITS
+            let value = if width < BITS {
+                ((value << (32 - width)) as i32) >> (32 - width)
+            } else {
+                ((value << sign_shift) as i32) >> sign_shift
+            };
+            d1 = ((d1 + value) << sign_shift) >> sign_shift;
+            d2 = ((d2 + d1) << sign_shift) >> sign_shift;
+            let sample = if is_it215 { d2 } else { d1 };
+            samples.push(sample as f32 / (1 << (BITS - 1)) as f32);
+        }
+    }
+
+    Ok(samples)
+}
+
+fn parse_pattern(data: &[u8], offset: usize) -> anyhow::Result<Vec<Cell>> {
+    // An empty pattern has no data
+    if offset == 0 {
+        return Ok(vec![Cell::default(); 64 * CHANNEL_COUNT]);
+    }
+
+    let mut reader = Reader::new(data);
+    reader.seek(offset)?;
+    let packed_size = reader.u16_le()? as usize;
+    let row_count = reader.u16_le()? as usize;
+    reader.bytes(4)?;
+    ensure!(
+        (1..=MAX_ROW_COUNT).contains(&row_count),
+        "Invalid row count {row_count}"
+    );
+
+    let mut data = Reader::new(reader.bytes(packed_size)?);
+    let mut cells = vec![Cell::default(); row_count * CHANNEL_COUNT];
+    // Masks and values are remembered per channel to be reused by later cells
+    let mut last_masks = [0u8; CHANNEL_COUNT];
+    let mut last_cells = [Cell::default(); CHANNEL_COUNT];
+    for row in 0..row_count {
+        loop {
+            let channel_variable = data.u8()?;
+            if channel_variable == 0 {
+                break;
+            }
+            let channel = (channel_variable as usize - 1) & (CHANNEL_COUNT - 1);
+            if channel_variable & 0x80 != 0 {
+                last_masks[channel] = data.u8()?;
+            }
+            let mask = last_masks[channel];
+            let last = &mut last_cells[channel];
+            let cell = &mut cells[row * CHANNEL_COUNT + channel];
+
+            if mask & 0b1 != 0 {
+                last.note = Some(data.u8()?);
+            }
+            if mask & 0b10 != 0 {
+                last.instrument = data.u8()?;
+            }
+            if mask & 0b100 != 0 {
+                last.volume = Some(data.u8()?);
+            }
+            if mask & 0b1000 != 0 {
+                last.effect = data.u8()?;
+                last.param = data.u8()?;
+            }
+            if mask & 0b1_0001 != 0 {
+                cell.note = last.note;
+            }
+            if mask & 0b10_0010 != 0 {
+                cell.instrument = last.instrument;
+            }
+            if mask & 0b100_0100 != 0 {
+                cell.volume = last.volume;
+            }
+            if mask & 0b1000_1000 != 0 {
+                (cell.effect, cell.param) = (last.effect, last.param);
+            }
+        }
+    }
+
+    Ok(cells)
+}
+
+fn note_to_tracky(note: u8) -> Option<NoteFieldValue> {
+    match note {
+        NOTE_CUT => Some(NoteFieldValue::Cut),
+        NOTE_OFF => Some(NoteFieldValue::Off),
+        0..=LAST_NOTE => {
+            let (note, octave) =
+                midi_value_to_note(MidiValue::new_unchecked(MidiValue::MIN_VALUE + note as i32));
+            Some(NoteFieldValue::Note(note, octave))
+        }
+        _ => None,
+    }
+}
+
+fn effect_name(effect: u8, param: u8) -> String {
+    let effect = match effect {
+        1..=26 => (b'A' + effect - 1) as char,
+        _ => '?',
+    };
+    format!("{effect}{param:02X}")
+}
+
+fn convert(module: Module, report: &mut Report) -> anyhow::Result<Song> {
+    let Module {
+        mut speed,
+        mut tempo,
+        uses_instruments,
+        channel_pans,
+        channel_volumes,
+        order,
+        patterns,
+        instruments,
+        samples,
+    } = module;
+
+    // Only the channels that are used are kept
+    let channel_count = patterns
+        .iter()
+        .flat_map(|cells| cells.chunks_exact(CHANNEL_COUNT))
+        .filter_map(|row| {
+            row.iter()
+                .rposition(|cell| cell.note.is_some() || cell.instrument != 0)
+        })
+        .max()
+        .map_or(1, |last_channel| last_channel + 1);
+
+    for (channel, &pan) in channel_pans[..channel_count].iter().enumerate() {
+        if pan & PAN_DISABLED != 0 {
+            report.warn(format!(
+                "Channel {channel} is disabled in the module but imported as a regular channel"
+            ));
+        } else if pan != PAN_CENTER {
+            report.warn(format!(
+                "Default panning {pan} of channel {channel} ignored, channel panning is not supported"
+            ));
+        }
+    }
+
+    // Initial speed and tempo can be overridden on the first row played
+    let first_pattern = order[0] as usize;
+    for cell in patterns[first_pattern][..channel_count].iter() {
+        match (cell.effect, cell.param) {
+            (EFFECT_SET_SPEED, param @ 1..) => speed = param,
+            (EFFECT_SET_TEMPO, param @ 0x20..) => tempo = param,
+            _ => {}
+        }
+    }
+
+    let cells = patterns
+        .iter()
+        .map(|cells| {
+            cells
+                .chunks_exact(CHANNEL_COUNT)
+                .flat_map(|row| row[..channel_count].iter().copied())
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let mut patterns =
+        import::patterns(&cells, channel_count, report, |cell, location, report| {
+            let mut line = convert_cell(cell, location, location.pattern == first_pattern, report);
+            // Channel volume is folded in the velocities of the channel
+            let channel_volume = channel_volumes[location.channel].min(MAX_VOLUME) as u32;
+            if let Some(velocity) = line.velocity.get_u8() {
+                let velocity = (velocity as u32 * channel_volume / MAX_VOLUME as u32) as u8;
+                line.velocity = Field::new(u8_to_hex_digit_pair(velocity));
+            }
+            line
+        })?;
+    patterns.set_order(order.iter().map(|pattern| *pattern as usize).collect())?;
+
+    let mut slots = Instruments::empty();
+    if uses_instruments {
+        for (index, instrument) in instruments.into_iter().enumerate() {
+            let number = index + 1;
+            // A tracky instrument plays a single sample, keep the one C-5 is mapped to
+            let sample_number = instrument.keyboard[KEYBOARD_C5] as usize;
+            let Some(Some(sample)) = samples.get(sample_number.wrapping_sub(1)) else {
+                continue;
+            };
+            let mut mapped_samples = instrument.keyboard.clone();
+            mapped_samples.sort_unstable();
+            mapped_samples.dedup();
+            mapped_samples.retain(|sample_number| *sample_number != 0);
+            if mapped_samples.len() > 1 {
+                report.warn(format!(
+                    "Instrument {number} maps {} samples, only sample {sample_number} (mapped to C-5) is imported",
+                    mapped_samples.len()
+                ));
+            }
+            if instrument.volume_envelope {
+                report.warn(format!(
+                    "Volume envelope of instrument {number} ignored, point envelopes can't be mapped to tracky's ADSR envelopes"
+                ));
+            }
+            if instrument.panning_envelope {
+                report.warn(format!(
+                    "Panning envelope of instrument {number} ignored, tracky has no panning envelope"
+                ));
+            }
+            let name = [&instrument.name, &sample.name]
+                .into_iter()
+                .find(|name| !name.is_empty())
+                .cloned()
+                .unwrap_or_else(|| format!("Instrument {number}"));
+            let mut converted = convert_sample(sample_number, sample, name.clone(), report);
+            converted.volume = import::volume(
+                (converted.volume.value() * instrument.global_volume as f32) as u8,
+                MAX_VOLUME,
+            );
+            import::set_instrument(&mut slots, index, &name, converted)?;
+        }
+    } else {
+        for (index, sample) in samples.iter().enumerate() {
+            let Some(sample) = sample else {
+                continue;
+            };
+            let number = index + 1;
+            let name = if sample.name.is_empty() {
+                format!("Sample {number}")
+            } else {
+                sample.name.clone()
+            };
+            let instrument = convert_sample(number, sample, name.clone(), report);
+            import::set_instrument(&mut slots, index, &name, instrument)?;
+        }
+    }
+
+    Ok(import::song(patterns, slots, import::tempo(speed, tempo)?))
+}
+
+fn convert_sample(number: usize, sample: &Sample, name: String, report: &mut Report) -> Instrument {
+    let mut sample_loop = |flag, ping_pong_flag, (start, end): (u32, u32), what: &str| {
+        if sample.flags & flag == 0 {
+            return None;
+        }
+        let mode = if sample.flags & ping_pong_flag != 0 {
+            LoopMode::PingPong
+        } else {
+            LoopMode::Forward
+        };
+        import::sample_loop(
+            mode,
+            start as usize,
+            end as usize,
+            sample.data.len(),
+            &format!("{what} of sample {number}"),
+            report,
+        )
+    };
+    let loops = SampleLoops {
+        normal: sample_loop(
+            SAMPLE_LOOP,
+            SAMPLE_PING_PONG_LOOP,
+            sample.loop_points,
+            "Loop",
+        ),
+        sustain: sample_loop(
+            SAMPLE_SUSTAIN_LOOP,
+            SAMPLE_PING_PONG_SUSTAIN_LOOP,
+            sample.sustain_loop_points,
+            "Sustain loop",
+        ),
+    };
+    if sample.flags & SAMPLE_STEREO != 0 {
+        report.warn(format!(
+            "Sample {number} is stereo, only its left channel is imported"
+        ));
+    }
+
+    let mut instrument = Instrument::from(import::mono_sample(
+        name,
+        sample.data.iter().copied(),
+        sample.c5speed as f32,
+        loops,
+    ));
+    instrument.volume = import::volume(
+        (sample.volume as u32 * sample.global_volume as u32 / MAX_VOLUME as u32) as u8,
+        MAX_VOLUME,
+    );
+    instrument
+}
+
+fn convert_cell(
+    cell: &Cell,
+    location: Location,
+    is_first_pattern: bool,
+    report: &mut Report,
+) -> PatternLine {
+    let mut line = PatternLine::default();
+
+    if let Some(note) = cell.note {
+        match note_to_tracky(note) {
+            Some(note) => line.note = Field::new(note),
+            None => report.warn_at(location, format!("Unsupported note fade ({note})")),
+        }
+    }
+
+    if cell.instrument != 0 {
+        line.instrument = Field::new(u8_to_hex_digit_pair(cell.instrument - 1));
+        // Triggering an instrument resets the channel to the sample volume
+        line.velocity = Field::new(u8_to_hex_digit_pair(0xFF));
+    }
+
+    match cell.volume {
+        None => {}
+        Some(volume) if VOLUME_COLUMN_SET_VOLUME.contains(&volume) => {
+            line.velocity = Field::new(u8_to_hex_digit_pair(import::velocity(volume, MAX_VOLUME)))
+        }
+        Some(volume) => report.warn_at(
+            location,
+            format!("Unsupported volume column command {volume}"),
+        ),
+    }
+
+    match (cell.effect, cell.param) {
+        (0, _) => {}
+        (EFFECT_SET_SPEED | EFFECT_SET_TEMPO, _) if is_first_pattern && location.row == 0 => {}
+        (effect, param) => match import::lettered_effect(effect, param) {
+            Some(effect) => line.effect = Field::new(effect),
+            None => report.warn_at(
+                location,
+                format!("Unsupported effect {}", effect_name(effect, param)),
+            ),
+        },
+    }
+
+    line
+}
+
+#[cfg(test)]
+mod test {
+    use joy_vector::Vector;
+
+    use crate::model::{
+        instrument::Kind,
+        pattern::{HexDigit, NoteName, OctaveValue, Patterns},
+    };
+
+    use super::{super::binary::Writer, *};
+
+    /// Writes bits least significant first
+    #[derive(Default)]
+    struct BitWriter {
+        bytes: Vec<u8>,
+        position: usize,
+    }
+
+    impl BitWriter {
+        fn bits(&mut self, value: u32, count: u32) {
+            for bit in 0..count {
+                if self.position.is_multiple_of(8) {
+                    self.bytes.push(0);
+                }
+                *self.bytes.last_mut().unwrap() |=
+                    (((value >> bit) & 1) as u8) << (self.position % 8);
+                self.position += 1;
+            }
+        }
+    }
+
+    fn compressed_block(write: impl FnOnce(&mut BitWriter)) -> Vec<u8> {
+        let mut writer = BitWriter::default();
+        write(&mut writer);
+        let mut block = (writer.bytes.len() as u16).to_le_bytes().to_vec();
+        block.extend(writer.bytes);
+        block
+    }
+
+    struct TestSample {
+        name: &'static str,
+        volume: u8,
+        flags: u8,
+        c5speed: u32,
+        loop_points: (u32, u32),
+        sustain_loop_points: (u32, u32),
+        /// Signed 8 bit
+        data: Vec<i8>,
+    }
+
+    struct TestInstrument {
+        name: &'static str,
+        volume_envelope: bool,
+        /// Sample number for each of the 120 notes
+        keyboard: Vec<u8>,
+    }
+
+    /// Volume column left empty
+    const NO_VOLUME: u8 = 255;
+
+    /// (row, channel, note, instrument, volume, (effect, param)), stored without packing
+    type TestCell = (usize, usize, u8, u8, u8, (u8, u8));
+
+    struct TestModule {
+        uses_instruments: bool,
+        channel_pans: Vec<u8>,
+        channel_volumes: Vec<u8>,
+        order: Vec<u8>,
+        instruments: Vec<TestInstrument>,
+        samples: Vec<TestSample>,
+        patterns: Vec<(usize, Vec<TestCell>)>,
+    }
+
+    impl Default for TestModule {
+        fn default() -> Self {
+            TestModule {
+                uses_instruments: false,
+                channel_pans: vec![PAN_CENTER; CHANNEL_COUNT],
+                channel_volumes: vec![MAX_VOLUME; CHANNEL_COUNT],
+                order: vec![0, ORDER_END],
+                instruments: Vec::new(),
+                samples: Vec::new(),
+                patterns: vec![(64, Vec::new())],
+            }
+        }
+    }
+
+    impl TestModule {
+        fn build(&self) -> Vec<u8> {
+            let mut writer = Writer::new();
+            writer.bytes(ID);
+            writer.bytes(&[0; 26]);
+            writer.u16_le(0x0410);
+            writer.u16_le(self.order.len() as u16);
+            writer.u16_le(self.instruments.len() as u16);
+            writer.u16_le(self.samples.len() as u16);
+            writer.u16_le(self.patterns.len() as u16);
+            writer.u16_le(0x0214);
+            writer.u16_le(0x0214);
+            writer.u16_le(if self.uses_instruments {
+                0b1101
+            } else {
+                0b1001
+            });
+            writer.u16_le(0);
+            writer.bytes(&[128, 48, 6, 125, 128, 0]);
+            writer.bytes(&[0; 2 + 4 + 4]);
+            writer.bytes(&self.channel_pans);
+            writer.bytes(&self.channel_volumes);
+            writer.bytes(&self.order);
+
+            let offsets_position = 0xC0 + self.order.len();
+            let offset_count = self.instruments.len() + self.samples.len() + self.patterns.len();
+            let mut offset = offsets_position + 4 * offset_count;
+            let mut offsets = Vec::new();
+            let mut blocks = Vec::new();
+
+            for instrument in self.instruments.iter() {
+                offsets.push(offset as u32);
+                let mut block = vec![0; NEW_ENVELOPES_OFFSET + 3 * ENVELOPE_SIZE];
+                block[..4].copy_from_slice(INSTRUMENT_ID);
+                block[0x18] = 128;
+                block[0x20..0x20 + instrument.name.len()]
+                    .copy_from_slice(instrument.name.as_bytes());
+                for (note, sample) in instrument.keyboard.iter().enumerate() {
+                    block[KEYBOARD_OFFSET + 2 * note] = note as u8;
+                    block[KEYBOARD_OFFSET + 2 * note + 1] = *sample;
+                }
+                block[NEW_ENVELOPES_OFFSET] = instrument.volume_envelope as u8;
+                offset += block.len();
+                blocks.push(block);
+            }
+
+            for sample in self.samples.iter() {
+                offsets.push(offset as u32);
+                let mut header = Writer::new();
+                header.bytes(SAMPLE_ID);
+                header.bytes(&[0; 13]);
+                header.u8(MAX_VOLUME);
+                header.u8(sample.flags);
+                header.u8(sample.volume);
+                let mut name = [0; 26];
+                name[..sample.name.len()].copy_from_slice(sample.name.as_bytes());
+                header.bytes(&name);
+                header.u8(CONVERT_SIGNED);
+                header.u8(PAN_CENTER);
+                header.u32_le(sample.data.len() as u32);
+                header.u32_le(sample.loop_points.0);
+                header.u32_le(sample.loop_points.1);
+                header.u32_le(sample.c5speed);
+                header.u32_le(sample.sustain_loop_points.0);
+                header.u32_le(sample.sustain_loop_points.1);
+                header.u32_le((offset + 0x50) as u32);
+                header.bytes(&[0; 4]);
+                let mut block = header.into_bytes();
+                block.extend(sample.data.iter().map(|value| *value as u8));
+                offset += block.len();
+                blocks.push(block);
+            }
+
+            for (row_count, cells) in self.patterns.iter() {
+                offsets.push(offset as u32);
+                let mut data = Vec::new();
+                for row in 0..*row_count {
+                    for (_, channel, note, instrument, volume, (effect, param)) in
+                        cells.iter().filter(|cell| cell.0 == row)
+                    {
+                        data.push((*channel as u8 + 1) | 0x80);
+                        if *volume == NO_VOLUME {
+                            data.extend([0b1011, *note, *instrument, *effect, *param]);
+                        } else {
+                            data.extend([0b1111, *note, *instrument, *volume, *effect, *param]);
+                        }
+                    }
+                    data.push(0);
+                }
+                let mut block = Writer::new();
+                block.u16_le(data.len() as u16);
+                block.u16_le(*row_count as u16);
+                block.bytes(&[0; 4]);
+                block.bytes(&data);
+                let block = block.into_bytes();
+                offset += block.len();
+                blocks.push(block);
+            }
+
+            for offset in offsets {
+                writer.u32_le(offset);
+            }
+            let mut data = writer.into_bytes();
+            data.extend(blocks.into_iter().flatten());
+            data
+        }
+    }
+
+    fn line_at(patterns: &Patterns, pattern: usize, channel: usize, row: usize) -> &PatternLine {
+        &patterns.patterns()[pattern].lines[channel * patterns.channel_len as usize + row]
+    }
+
+    fn warnings(report: &Report) -> Vec<String> {
+        report.warnings.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_8_bit_decompression() {
+        let mut data = compressed_block(|bits| {
+            // Width 9: raw deltas
+            bits.bits(10, 9);
+            bits.bits((-20i32) as u32 & 0xFF, 9);
+            // Switch to width 4
+            bits.bits(0x100 | 3, 9);
+            bits.bits(3, 4);
+            bits.bits((-2i32) as u32 & 0xF, 4);
+            // Back to width 8 from method 1, then a method 2 switch to width 9
+            bits.bits(1 << 3, 4);
+            bits.bits(8 - 1 - 1, 3);
+            bits.bits(100, 8);
+            bits.bits((0xFF >> 1) - 4 + 8, 8);
+            bits.bits(0x80, 9);
+        });
+        data.extend([0xAA]);
+
+        let mut reader = Reader::new(&data);
+        let samples = decompress::<8>(&mut reader, 6, false).unwrap();
+        let expected = [10i8, -10, -7, -9, 91, -37]
+            .map(|value| value as f32 / 128.0)
+            .to_vec();
+        assert_eq!(expected, samples);
+        assert_eq!(1, reader.remaining());
+    }
+
+    #[test]
+    fn test_it215_integrates_twice() {
+        let data = compressed_block(|bits| {
+            for _ in 0..3 {
+                bits.bits(1, 9);
+            }
+        });
+        let samples = decompress::<8>(&mut Reader::new(&data), 3, true).unwrap();
+        assert_eq!(vec![1.0 / 128.0, 3.0 / 128.0, 6.0 / 128.0], samples);
+    }
+
+    #[test]
+    fn test_corrupt_length_is_an_error() {
+        let data = compressed_block(|bits| bits.bits(1, 9));
+        assert!(decompress::<8>(&mut Reader::new(&data), usize::MAX, false).is_err());
+    }
+
+    #[test]
+    fn test_16_bit_decompression() {
+        let data = compressed_block(|bits| {
+            bits.bits(0x1000, 17);
+            // Switch to width 6
+            bits.bits(0x10000 | 5, 17);
+            bits.bits((-3i32) as u32 & 0x3F, 6);
+            // Method 1 switch to width 16
+            bits.bits(1 << 5, 6);
+            bits.bits(16 - 1 - 1, 4);
+            bits.bits(0x9000, 16);
+        });
+        let samples = decompress::<16>(&mut Reader::new(&data), 3, false).unwrap();
+        let expected = [0x1000i16, 0x0FFD, -0x6003]
+            .map(|value| value as f32 / 32768.0)
+            .to_vec();
+        assert_eq!(expected, samples);
+    }
+
+    #[test]
+    fn test_decompression_spans_several_blocks() {
+        let block = compressed_block(|bits| {
+            for _ in 0..0x8000 {
+                bits.bits(1, 9);
+            }
+        });
+        let last_block = compressed_block(|bits| bits.bits(5, 9));
+        let data = [block, last_block].concat();
+
+        let samples = decompress::<8>(&mut Reader::new(&data), 0x8001, false).unwrap();
+        assert_eq!(0x8001, samples.len());
+        // Deltas restart from 0 in every block
+        assert_eq!(1.0 / 128.0, samples[0]);
+        assert_eq!(0.0, samples[0x7FFF]);
+        assert_eq!(5.0 / 128.0, samples[0x8000]);
+    }
+
+    #[test]
+    fn test_truncated_compressed_data_is_an_error() {
+        let data = compressed_block(|bits| bits.bits(1, 9));
+        assert!(decompress::<8>(&mut Reader::new(&data), 2, false).is_err());
+    }
+
+    #[test]
+    fn test_patterns_are_imported() {
+        let module = TestModule {
+            order: vec![0, ORDER_MARKER, 1, ORDER_END],
+            samples: vec![TestSample {
+                name: "",
+                volume: 64,
+                flags: SAMPLE_PRESENT,
+                c5speed: 8363,
+                loop_points: (0, 0),
+                sustain_loop_points: (0, 0),
+                data: vec![1, 2],
+            }],
+            patterns: vec![
+                (
+                    32,
+                    vec![
+                        (0, 0, 60, 1, 32, (EFFECT_SET_SPEED, 3)),
+                        (4, 2, NOTE_OFF, 0, NO_VOLUME, (0, 0)),
+                        (31, 1, 61, 0, 0xC1, (4, 0xF2)),
+                    ],
+                ),
+                (
+                    64,
+                    vec![
+                        (1, 1, 200, 0, NO_VOLUME, (0, 0)),
+                        (2, 0, 60, 0, NO_VOLUME, (19, 0xD2)),
+                    ],
+                ),
+            ],
+            ..Default::default()
+        };
+
+        let import = read(&module.build()).unwrap();
+        let patterns = &import.song.patterns;
+        assert_eq!(3, patterns.channel_count);
+        assert_eq!(64, patterns.channel_len);
+        assert_eq!(2, patterns.pattern_count);
+        // Markers are skipped
+        assert_eq!(&[0, 1], patterns.order());
+        approx::assert_relative_eq!(125.0 / 7.5, import.song.tempo.line_per_second());
+
+        assert_eq!(
+            &PatternLine {
+                note: Field::new(NoteFieldValue::Note(NoteName::C, OctaveValue::OCTAVE_5)),
+                velocity: Field::new((HexDigit::HEX_8, HexDigit::HEX_0)),
+                instrument: Field::new((HexDigit::HEX_0, HexDigit::HEX_0)),
+                effect: Field::empty(),
+            },
+            line_at(patterns, 0, 0, 0)
+        );
+        assert_eq!(
+            Field::new(NoteFieldValue::Off),
+            line_at(patterns, 0, 2, 4).note
+        );
+        assert_eq!(
+            Field::new(NoteFieldValue::Note(
+                NoteName::CSharp,
+                OctaveValue::OCTAVE_5
+            )),
+            line_at(patterns, 0, 1, 31).note
+        );
+        assert_eq!(
+            "ED2",
+            line_at(patterns, 1, 0, 2)
+                .effect
+                .value()
+                .unwrap()
+                .to_string()
+        );
+        assert_eq!(
+            vec![
+                "Pattern 0 has 32 row(s), it is padded to 64",
+                "pattern 0 row 031 channel 1: Unsupported volume column command 193",
+                "pattern 0 row 031 channel 1: Unsupported effect DF2",
+                "pattern 1 row 001 channel 1: Unsupported note fade (200)",
+            ],
+            warnings(&import.report)
+        );
+    }
+
+    #[test]
+    fn test_packed_values_are_reused() {
+        let mut module = TestModule::default();
+        let mut data = Vec::new();
+        // Channel 0: note, instrument and volume, then "same note, same volume" from memory
+        data.extend([1 | 0x80, 0b0111, 48, 1, 10, 0]);
+        data.extend([1 | 0x80, 0b0101_0000, 0]);
+        data.extend([1, 0]);
+        module.patterns = vec![(3, Vec::new())];
+        let mut bytes = module.build();
+        // Replace the empty pattern with the hand packed one
+        let pattern_offset = bytes.len() - (8 + 3);
+        bytes.truncate(pattern_offset);
+        bytes.extend((data.len() as u16).to_le_bytes());
+        bytes.extend(3u16.to_le_bytes());
+        bytes.extend([0; 4]);
+        bytes.extend(data);
+
+        let patterns = read(&bytes).unwrap().song.patterns;
+        let note = Field::new(NoteFieldValue::Note(NoteName::C, OctaveValue::OCTAVE_4));
+        let velocity = Field::new(u8_to_hex_digit_pair(import::velocity(10, MAX_VOLUME)));
+        assert_eq!(note, line_at(&patterns, 0, 0, 0).note);
+        assert_eq!(note, line_at(&patterns, 0, 0, 1).note);
+        assert_eq!(velocity, line_at(&patterns, 0, 0, 1).velocity);
+        assert!(line_at(&patterns, 0, 0, 1).instrument.value().is_none());
+        assert_eq!(note, line_at(&patterns, 0, 0, 2).note);
+        assert_eq!(velocity, line_at(&patterns, 0, 0, 2).velocity);
+    }
+
+    #[test]
+    fn test_channel_volume_and_pan() {
+        let mut module = TestModule {
+            patterns: vec![(64, vec![(0, 1, 60, 0, 64, (0, 0))])],
+            ..Default::default()
+        };
+        module.channel_volumes[1] = 32;
+        module.channel_pans[0] = 0;
+        module.channel_pans[1] = PAN_DISABLED | PAN_CENTER;
+
+        let import = read(&module.build()).unwrap();
+        assert_eq!(
+            Field::new((HexDigit::HEX_7, HexDigit::HEX_F)),
+            line_at(&import.song.patterns, 0, 1, 0).velocity
+        );
+        assert_eq!(
+            vec![
+                "Default panning 0 of channel 0 ignored, channel panning is not supported",
+                "Channel 1 is disabled in the module but imported as a regular channel",
+            ],
+            warnings(&import.report)
+        );
+    }
+
+    #[test]
+    fn test_samples_are_imported() {
+        let module = TestModule {
+            samples: vec![
+                TestSample {
+                    name: "hat",
+                    volume: 32,
+                    flags: SAMPLE_PRESENT
+                        | SAMPLE_LOOP
+                        | SAMPLE_SUSTAIN_LOOP
+                        | SAMPLE_PING_PONG_SUSTAIN_LOOP,
+                    c5speed: 44100,
+                    loop_points: (0, 2),
+                    sustain_loop_points: (1, 3),
+                    data: vec![64, -64],
+                },
+                TestSample {
+                    name: "",
+                    volume: 64,
+                    flags: 0,
+                    c5speed: 8363,
+                    loop_points: (0, 0),
+                    sustain_loop_points: (0, 0),
+                    data: vec![],
+                },
+            ],
+            ..Default::default()
+        };
+
+        let import = read(&module.build()).unwrap();
+        let instruments = import.song.instruments.iter().collect::<Vec<_>>();
+        assert_eq!(1, instruments.len());
+        let (index, instrument) = instruments[0];
+        assert_eq!(0, index);
+        assert_eq!(0.5, instrument.volume.value());
+        let Kind::Sample {
+            name,
+            signal,
+            loops,
+            ..
+        } = instrument.source()
+        else {
+            panic!("Expected a sample");
+        };
+        assert_eq!("hat", name);
+        assert_eq!("forward 0 2; pingpong 1 2", loops.to_string());
+        assert_eq!(44100.0, signal.frame_rate);
+        assert_eq!(
+            vec![Vector([0.5, 0.5]), Vector([-0.5, -0.5])],
+            signal.to_vec()
+        );
+        assert_eq!(
+            vec![
+                "Sustain loop of sample 1 (1..3) goes past the 2 frame(s) of the sample, it is cut"
+            ],
+            warnings(&import.report)
+        );
+    }
+
+    #[test]
+    fn test_instruments_pick_the_sample_mapped_to_c5() {
+        let sample = |name| TestSample {
+            name,
+            volume: 64,
+            flags: SAMPLE_PRESENT,
+            c5speed: 8363,
+            loop_points: (0, 0),
+            sustain_loop_points: (0, 0),
+            data: vec![1],
+        };
+        let mut keyboard = vec![1; 120];
+        keyboard[KEYBOARD_C5..].fill(2);
+        let module = TestModule {
+            uses_instruments: true,
+            instruments: vec![
+                TestInstrument {
+                    name: "",
+                    volume_envelope: false,
+                    keyboard: vec![0; 120],
+                },
+                TestInstrument {
+                    name: "split",
+                    volume_envelope: true,
+                    keyboard,
+                },
+            ],
+            samples: vec![sample("low"), sample("high")],
+            ..Default::default()
+        };
+
+        let import = read(&module.build()).unwrap();
+        let instruments = import.song.instruments.iter().collect::<Vec<_>>();
+        assert_eq!(1, instruments.len());
+        let (index, instrument) = instruments[0];
+        assert_eq!(1, index);
+        assert_eq!(1.0, instrument.volume.value());
+        assert_eq!("split", instrument.source().to_string());
+        assert_eq!(
+            vec![
+                "Instrument 2 maps 2 samples, only sample 2 (mapped to C-5) is imported",
+                "Volume envelope of instrument 2 ignored, point envelopes can't be mapped to tracky's ADSR envelopes",
+            ],
+            warnings(&import.report)
+        );
+    }
+
+    #[test]
+    fn test_invalid_data_is_an_error() {
+        assert!(read(b"IMPM").is_err());
+        let mut data = TestModule::default().build();
+        data[0] = b'X';
+        assert!(read(&data).is_err());
+        let module = TestModule {
+            order: vec![1, ORDER_END],
+            ..Default::default()
+        };
+        assert!(read(&module.build()).is_err());
+        let module = TestModule {
+            patterns: vec![(64, Vec::new()); MAX_PATTERN_COUNT + 1],
+            ..Default::default()
+        };
+        assert!(read(&module.build()).is_err());
+    }
+}
@@ -0,0 +1,33 @@
+use std::path::Path;
+
+use crate::model::Song;
+
+pub mod binary;
+pub mod import;
+pub mod it;
+pub mod native;
+pub mod protracker;
+pub mod s3m;
+pub mod smf;
+pub mod text;
+pub mod wav;
+pub mod xm;
+
+/// Opens a native song, or imports a foreign one based on the file extension
+pub fn open_song<P: AsRef<Path>>(path: P) -> anyhow::Result<Song> {
+    let path = path.as_ref();
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+
+    let import = match extension.as_deref() {
+        Some(protracker::EXTENSION) => protracker::load(path)?,
+        Some(xm::EXTENSION) => xm::load(path)?,
+        Some(s3m::EXTENSION) => s3m::load(path)?,
+        Some(it::EXTENSION) => it::load(path)?,
+        Some(smf::EXTENSION) => smf::load(path, smf::DEFAULT_ROWS_PER_BEAT)?,
+        _ => return native::load(path),
+    };
+    import.report.log(&path.to_string_lossy());
+    Ok(import.song)
+}
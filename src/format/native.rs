@@ -0,0 +1,931 @@
+//! Native song file format (`.tracky`).
+//!
+//! Little endian binary layout:
+//! ```text
+//! magic             b"TRACKY"
+//! version           u16
+//! settings          global_octave: i32, global_volume: f32,
+//!                   then bpm: f32, rows_per_beat: u32, ticks_per_row: u32 (since version 3)
+//!                   or line_per_second: f32 (before version 3)
+//! patterns          channel_count: u32, channel_len: u32, pattern_count: u32,
+//!                   then every line of every pattern (channel major), the effect of the
+//!                   lines is only there since version 4
+//! order             order_len: u32, then order_len * pattern index: u32 (since version 2)
+//! instruments       slot_count: u32, then (slot index: u8, instrument) per filled slot
+//! mute / solo       channel_count * flags: u8, 1 for muted and 2 for soloed (since version 5)
+//! envelopes         per filled instrument slot in order, attack_ms: u32, decay_ms: u32,
+//!                   sustain: f32, release_ms: u32 (since version 6)
+//! sample loops      per filled sample slot in order, the loop then the sustain loop, each
+//!                   mode: u8 (0 for none, 1 forward, 2 ping-pong), start: u32, end: u32
+//!                   (since version 7)
+//! interpolations    per filled sample slot in order, mode: u8 (0 for nearest, 1 linear,
+//!                   2 cubic, 3 sinc) (since version 9)
+//! ```
+//!
+//! Notes are tagged 0 when empty, 1 for a note, 2 for a cut and 3 for a note-off. Instruments are
+//! tagged 0 for a sine, 1 for a square, 2 for a sawtooth and 3 for a sample, since version 8 also
+//! 4 for a triangle, 5 for a pulse followed by its width: f32, 6 for white and 7 for pink noise.
+
+use std::{fs, path::Path, time::Duration};
+
+use anyhow::{bail, ensure, Context};
+use joy_vector::Vector;
+
+use crate::{
+    audio::{dsp::interpolation::InterpolationMode, signal, Volume},
+    model::{
+        channel::MuteSolo,
+        envelope::Envelope,
+        instrument::{Instrument, Instruments, Kind},
+        pattern::{
+            u8_to_hex_digit_pair, EffectFieldValue, Field, HexDigit, NoteFieldValue, NoteName,
+            OctaveValue, Pattern, PatternLine, Patterns,
+        },
+        sample_loop::{LoopMode, SampleLoop, SampleLoops},
+        tempo::Tempo,
+        Song,
+    },
+};
+
+use super::binary::{Reader, Writer};
+
+pub const EXTENSION: &str = "tracky";
+
+const MAGIC: &[u8; 6] = b"TRACKY";
+const VERSION: u16 = 9;
+
+const NOTE_EMPTY: u8 = 0;
+const NOTE_NOTE: u8 = 1;
+const NOTE_CUT: u8 = 2;
+const NOTE_OFF: u8 = 3;
+
+const KIND_SINE: u8 = 0;
+const KIND_SQUARE: u8 = 1;
+const KIND_SAWTOOTH: u8 = 2;
+const KIND_SAMPLE: u8 = 3;
+const KIND_TRIANGLE: u8 = 4;
+const KIND_PULSE: u8 = 5;
+const KIND_WHITE_NOISE: u8 = 6;
+const KIND_PINK_NOISE: u8 = 7;
+
+const LOOP_NONE: u8 = 0;
+const LOOP_FORWARD: u8 = 1;
+const LOOP_PING_PONG: u8 = 2;
+
+const INTERPOLATION_NEAREST: u8 = 0;
+const INTERPOLATION_LINEAR: u8 = 1;
+const INTERPOLATION_CUBIC: u8 = 2;
+const INTERPOLATION_SINC: u8 = 3;
+
+pub fn save<P: AsRef<Path>>(song: &Song, path: P) -> anyhow::Result<()> {
+    fs::write(path.as_ref(), write(song))
+        .with_context(|| format!("Could not save song to {:?}", path.as_ref()))
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Song> {
+    let data = fs::read(path.as_ref())
+        .with_context(|| format!("Could not open song {:?}", path.as_ref()))?;
+    read(&data).with_context(|| format!("Invalid song file {:?}", path.as_ref()))
+}
+
+pub fn write(song: &Song) -> Vec<u8> {
+    let mut writer = Writer::new();
+    writer.bytes(MAGIC);
+    writer.u16_le(VERSION);
+
+    writer.i32_le(song.global_octave.value());
+    writer.f32_le(song.global_volume.value());
+    writer.f32_le(song.tempo.bpm);
+    writer.u32_le(song.tempo.rows_per_beat);
+    writer.u32_le(song.tempo.ticks_per_row);
+
+    write_patterns(&mut writer, &song.patterns);
+    write_order(&mut writer, song.patterns.order());
+    write_instruments(&mut writer, &song.instruments);
+    write_mute_solo(
+        &mut writer,
+        &song.mute_solo,
+        song.patterns.channel_count as usize,
+    );
+    write_envelopes(&mut writer, &song.instruments);
+    write_sample_loops(&mut writer, &song.instruments);
+    write_interpolations(&mut writer, &song.instruments);
+
+    writer.into_bytes()
+}
+
+pub fn read(data: &[u8]) -> anyhow::Result<Song> {
+    let mut reader = Reader::new(data);
+    ensure!(reader.bytes(MAGIC.len())? == MAGIC, "Not a tracky song");
+    let version = reader.u16_le()?;
+    ensure!(
+        (1..=VERSION).contains(&version),
+        "Unsupported song version {version} (latest supported is {VERSION})"
+    );
+
+    let global_octave = reader.i32_le()?;
+    ensure!(
+        (OctaveValue::MIN_VALUE..=OctaveValue::MAX_VALUE).contains(&global_octave),
+        "Invalid global octave {global_octave}"
+    );
+    let global_volume = Volume::new_clamped(reader.f32_le()?);
+    let tempo = if version >= 3 {
+        Tempo::new(reader.f32_le()?, reader.u32_le()?, reader.u32_le()?)?
+    } else {
+        let line_per_second = reader.f32_le()?;
+        ensure!(
+            line_per_second.is_normal() && line_per_second > 0.0,
+            "Invalid line per second {line_per_second}"
+        );
+        let default = Tempo::default();
+        Tempo::from_line_per_second(
+            line_per_second,
+            default.rows_per_beat,
+            default.ticks_per_row,
+        )?
+    };
+
+    let mut patterns = read_patterns(&mut reader, version).context("Could not read patterns")?;
+    // Songs saved before the order list play every pattern once
+    if version >= 2 {
+        let order = read_order(&mut reader).context("Could not read order")?;
+        patterns.set_order(order)?;
+    }
+    let mut instruments = read_instruments(&mut reader).context("Could not read instruments")?;
+    let channel_count = patterns.channel_count as usize;
+    let mute_solo = if version >= 5 {
+        read_mute_solo(&mut reader, channel_count).context("Could not read mute / solo")?
+    } else {
+        MuteSolo::new(channel_count)
+    };
+    // Older songs play their notes without envelope
+    if version >= 6 {
+        read_envelopes(&mut reader, &mut instruments).context("Could not read envelopes")?;
+    }
+    // Older songs play their samples once
+    if version >= 7 {
+        read_sample_loops(&mut reader, &mut instruments).context("Could not read sample loops")?;
+    }
+    // Older songs interpolate their samples linearly
+    if version >= 9 {
+        read_interpolations(&mut reader, &mut instruments)
+            .context("Could not read interpolations")?;
+    }
+
+    ensure!(
+        reader.remaining() == 0,
+        "{} unexpected trailing byte(s)",
+        reader.remaining()
+    );
+
+    Ok(Song {
+        patterns,
+        instruments,
+        global_octave: OctaveValue::new_unchecked(global_octave),
+        global_volume,
+        tempo,
+        mute_solo,
+    })
+}
+
+fn write_patterns(writer: &mut Writer, patterns: &Patterns) {
+    writer.u32_le(patterns.channel_count as u32);
+    writer.u32_le(patterns.channel_len as u32);
+    writer.u32_le(patterns.patterns().len() as u32);
+    for pattern in patterns.patterns() {
+        for line in pattern.lines.iter() {
+            write_line(writer, line);
+        }
+    }
+}
+
+fn write_order(writer: &mut Writer, order: &[usize]) {
+    writer.u32_le(order.len() as u32);
+    for pattern in order {
+        writer.u32_le(*pattern as u32);
+    }
+}
+
+fn read_order(reader: &mut Reader) -> anyhow::Result<Vec<usize>> {
+    let order_len = reader.u32_le()? as usize;
+    ensure!(
+        order_len.saturating_mul(4) <= reader.remaining(),
+        "Order section is truncated"
+    );
+    (0..order_len)
+        .map(|_| Ok(reader.u32_le()? as usize))
+        .collect()
+}
+
+fn read_patterns(reader: &mut Reader, version: u16) -> anyhow::Result<Patterns> {
+    let channel_count = reader.u32_le()?;
+    let channel_len = reader.u32_le()?;
+    let pattern_count = reader.u32_le()? as usize;
+    ensure!(
+        (1..=i32::MAX as u32).contains(&channel_count)
+            && (1..=i32::MAX as u32).contains(&channel_len),
+        "Invalid pattern dimensions: {channel_count} channel(s) of {channel_len} line(s)"
+    );
+    let line_count = (channel_count as usize)
+        .checked_mul(channel_len as usize)
+        .context("Patterns are too large")?;
+    // Every line takes at least 3 bytes, this protects against absurd allocations
+    ensure!(
+        line_count.saturating_mul(pattern_count).saturating_mul(3) <= reader.remaining(),
+        "Pattern section is truncated"
+    );
+    let (channel_count, channel_len) = (channel_count as i32, channel_len as i32);
+
+    let patterns = (0..pattern_count)
+        .map(|pattern_index| {
+            let lines = (0..line_count)
+                .map(|_| read_line(reader, version))
+                .collect::<anyhow::Result<Vec<_>>>()
+                .with_context(|| format!("In pattern {pattern_index}"))?;
+            Ok(Pattern { lines })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Patterns::from_patterns(channel_count, channel_len, patterns)
+}
+
+fn write_line(writer: &mut Writer, line: &PatternLine) {
+    match line.note.value() {
+        None => writer.u8(NOTE_EMPTY),
+        Some(NoteFieldValue::Note(note, octave)) => {
+            writer.u8(NOTE_NOTE);
+            writer.u8(note.ordinal() as u8);
+            writer.u8(octave.value() as u8);
+        }
+        Some(NoteFieldValue::Cut) => writer.u8(NOTE_CUT),
+        Some(NoteFieldValue::Off) => writer.u8(NOTE_OFF),
+    }
+    write_hex_field(writer, &line.velocity);
+    write_hex_field(writer, &line.instrument);
+    write_effect_field(writer, &line.effect);
+}
+
+fn read_line(reader: &mut Reader, version: u16) -> anyhow::Result<PatternLine> {
+    let note = match reader.u8()? {
+        NOTE_EMPTY => Field::empty(),
+        NOTE_NOTE => {
+            let note_index = reader.u8()?;
+            let Some(note) = NoteName::VARIANTS.get(note_index as usize) else {
+                bail!("Invalid note {note_index}");
+            };
+            let octave = reader.u8()? as i32;
+            ensure!(
+                (OctaveValue::MIN_VALUE..=OctaveValue::MAX_VALUE).contains(&octave),
+                "Invalid octave {octave}"
+            );
+            Field::new(NoteFieldValue::Note(
+                *note,
+                OctaveValue::new_unchecked(octave),
+            ))
+        }
+        NOTE_CUT => Field::new(NoteFieldValue::Cut),
+        NOTE_OFF => Field::new(NoteFieldValue::Off),
+        tag => bail!("Invalid note tag {tag}"),
+    };
+    let velocity = read_hex_field(reader)?;
+    let instrument = read_hex_field(reader)?;
+    let effect = if version >= 4 {
+        read_effect_field(reader)?
+    } else {
+        Field::empty()
+    };
+
+    Ok(PatternLine {
+        note,
+        velocity,
+        instrument,
+        effect,
+    })
+}
+
+fn write_hex_field(writer: &mut Writer, field: &Field<(HexDigit, HexDigit)>) {
+    match field.get_u8() {
+        Some(value) => {
+            writer.u8(1);
+            writer.u8(value);
+        }
+        None => writer.u8(0),
+    }
+}
+
+fn read_hex_field(reader: &mut Reader) -> anyhow::Result<Field<(HexDigit, HexDigit)>> {
+    match reader.u8()? {
+        0 => Ok(Field::empty()),
+        1 => Ok(Field::new(u8_to_hex_digit_pair(reader.u8()?))),
+        tag => bail!("Invalid hex field tag {tag}"),
+    }
+}
+
+fn write_effect_field(writer: &mut Writer, field: &Field<EffectFieldValue>) {
+    match field.value() {
+        Some(effect) => {
+            writer.u8(1);
+            writer.u8(effect.command.value());
+            writer.u8(effect.parameter_u8());
+        }
+        None => writer.u8(0),
+    }
+}
+
+fn read_effect_field(reader: &mut Reader) -> anyhow::Result<Field<EffectFieldValue>> {
+    match reader.u8()? {
+        0 => Ok(Field::empty()),
+        1 => {
+            let command = reader.u8()?;
+            let Some(command) = HexDigit::new(command) else {
+                bail!("Invalid effect command {command}");
+            };
+            Ok(Field::new(EffectFieldValue {
+                command,
+                parameter: u8_to_hex_digit_pair(reader.u8()?),
+            }))
+        }
+        tag => bail!("Invalid effect field tag {tag}"),
+    }
+}
+
+const CHANNEL_MUTED: u8 = 1;
+const CHANNEL_SOLOED: u8 = 2;
+
+fn write_mute_solo(writer: &mut Writer, mute_solo: &MuteSolo, channel_count: usize) {
+    for channel in 0..channel_count {
+        let mut flags = 0;
+        if mute_solo.is_muted(channel) {
+            flags |= CHANNEL_MUTED;
+        }
+        if mute_solo.is_soloed(channel) {
+            flags |= CHANNEL_SOLOED;
+        }
+        writer.u8(flags);
+    }
+}
+
+fn read_mute_solo(reader: &mut Reader, channel_count: usize) -> anyhow::Result<MuteSolo> {
+    let mut mute_solo = MuteSolo::new(channel_count);
+    for channel in 0..channel_count {
+        let flags = reader.u8()?;
+        ensure!(
+            flags & !(CHANNEL_MUTED | CHANNEL_SOLOED) == 0,
+            "Invalid flags {flags:#04x} for channel {channel}"
+        );
+        mute_solo.set_muted(channel, flags & CHANNEL_MUTED != 0);
+        mute_solo.set_soloed(channel, flags & CHANNEL_SOLOED != 0);
+    }
+    Ok(mute_solo)
+}
+
+fn write_instruments(writer: &mut Writer, instruments: &Instruments) {
+    writer.u32_le(instruments.iter().count() as u32);
+    for (index, instrument) in instruments.iter() {
+        writer.u8(index);
+        writer.f32_le(instrument.volume.value());
+        match instrument.source() {
+            Kind::Sine => writer.u8(KIND_SINE),
+            Kind::Square => writer.u8(KIND_SQUARE),
+            Kind::Sawtooth => writer.u8(KIND_SAWTOOTH),
+            Kind::Triangle => writer.u8(KIND_TRIANGLE),
+            Kind::Pulse { width } => {
+                writer.u8(KIND_PULSE);
+                writer.f32_le(*width);
+            }
+            Kind::WhiteNoise => writer.u8(KIND_WHITE_NOISE),
+            Kind::PinkNoise => writer.u8(KIND_PINK_NOISE),
+            Kind::Sample { name, signal, .. } => {
+                writer.u8(KIND_SAMPLE);
+                writer.string(name);
+                writer.f32_le(signal.frame_rate);
+                writer.u32_le(signal.len() as u32);
+                for Vector([left, right]) in signal.iter() {
+                    writer.f32_le(*left);
+                    writer.f32_le(*right);
+                }
+            }
+        }
+    }
+}
+
+fn read_instruments(reader: &mut Reader) -> anyhow::Result<Instruments> {
+    let mut instruments = Instruments::empty();
+    let instrument_count = reader.u32_le()?;
+    for _ in 0..instrument_count {
+        let index = reader.u8()?;
+        let volume = Volume::new_clamped(reader.f32_le()?);
+        let kind = match reader.u8()? {
+            KIND_SINE => Kind::Sine,
+            KIND_SQUARE => Kind::Square,
+            KIND_SAWTOOTH => Kind::Sawtooth,
+            KIND_TRIANGLE => Kind::Triangle,
+            KIND_PULSE => {
+                let width = reader.f32_le()?;
+                ensure!(
+                    width > 0.0 && width < 1.0,
+                    "Invalid pulse width {width} in slot {index}"
+                );
+                Kind::Pulse { width }
+            }
+            KIND_WHITE_NOISE => Kind::WhiteNoise,
+            KIND_PINK_NOISE => Kind::PinkNoise,
+            KIND_SAMPLE => {
+                let name = reader.string()?;
+                let frame_rate = reader.f32_le()?;
+                ensure!(
+                    frame_rate.is_normal() && frame_rate > 0.0,
+                    "Invalid frame rate {frame_rate} for sample {name:?}"
+                );
+                let frame_count = reader.u32_le()? as usize;
+                let samples = reader
+                    .bytes(frame_count.saturating_mul(2 * size_of::<f32>()))
+                    .with_context(|| format!("Sample {name:?} is truncated"))?
+                    .chunks_exact(size_of::<f32>())
+                    .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+                    .collect();
+                Kind::Sample {
+                    name,
+                    signal: signal::stereo::Owned::from_samples(samples, frame_rate)?,
+                    loops: SampleLoops::default(),
+                    interpolation: InterpolationMode::default(),
+                }
+            }
+            tag => bail!("Invalid instrument kind {tag} in slot {index}"),
+        };
+        let mut instrument = Instrument::from(kind);
+        instrument.volume = volume;
+        instruments
+            .set(index, instrument)
+            .with_context(|| format!("Invalid instrument slot {index}"))?;
+    }
+    Ok(instruments)
+}
+
+fn write_envelopes(writer: &mut Writer, instruments: &Instruments) {
+    for (_, instrument) in instruments.iter() {
+        let Envelope {
+            attack,
+            decay,
+            sustain,
+            release,
+        } = instrument.envelope;
+        writer.u32_le(attack.as_millis() as u32);
+        writer.u32_le(decay.as_millis() as u32);
+        writer.f32_le(sustain.value());
+        writer.u32_le(release.as_millis() as u32);
+    }
+}
+
+fn read_envelopes(reader: &mut Reader, instruments: &mut Instruments) -> anyhow::Result<()> {
+    let slots = instruments
+        .iter()
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+    for index in slots {
+        let envelope = read_envelope(reader)
+            .with_context(|| format!("Could not read the envelope of slot {index}"))?;
+        if let Some(instrument) = instruments.get_mut(index) {
+            instrument.envelope = envelope;
+        }
+    }
+    Ok(())
+}
+
+fn read_envelope(reader: &mut Reader) -> anyhow::Result<Envelope> {
+    let attack = Duration::from_millis(reader.u32_le()? as u64);
+    let decay = Duration::from_millis(reader.u32_le()? as u64);
+    let sustain = Volume::new_clamped(reader.f32_le()?);
+    let release = Duration::from_millis(reader.u32_le()? as u64);
+    Ok(Envelope {
+        attack,
+        decay,
+        sustain,
+        release,
+    })
+}
+
+fn write_sample_loops(writer: &mut Writer, instruments: &Instruments) {
+    for (_, instrument) in instruments.iter() {
+        let Kind::Sample { loops, .. } = instrument.source() else {
+            continue;
+        };
+        for sample_loop in [loops.normal, loops.sustain] {
+            match sample_loop {
+                None => {
+                    writer.u8(LOOP_NONE);
+                    writer.u32_le(0);
+                    writer.u32_le(0);
+                }
+                Some(SampleLoop { mode, start, end }) => {
+                    writer.u8(match mode {
+                        LoopMode::Forward => LOOP_FORWARD,
+                        LoopMode::PingPong => LOOP_PING_PONG,
+                    });
+                    writer.u32_le(start as u32);
+                    writer.u32_le(end as u32);
+                }
+            }
+        }
+    }
+}
+
+fn read_sample_loops(reader: &mut Reader, instruments: &mut Instruments) -> anyhow::Result<()> {
+    let slots = instruments
+        .iter()
+        .filter(|(_, instrument)| matches!(instrument.source(), Kind::Sample { .. }))
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+    for index in slots {
+        let loops = SampleLoops {
+            normal: read_sample_loop(reader)?,
+            sustain: read_sample_loop(reader)?,
+        };
+        if let Some(instrument) = instruments.get_mut(index) {
+            instrument
+                .set_sample_loops(loops)
+                .with_context(|| format!("Invalid loops in slot {index}"))?;
+        }
+    }
+    Ok(())
+}
+
+fn read_sample_loop(reader: &mut Reader) -> anyhow::Result<Option<SampleLoop>> {
+    let mode = reader.u8()?;
+    let start = reader.u32_le()? as usize;
+    let end = reader.u32_le()? as usize;
+    let mode = match mode {
+        LOOP_NONE => return Ok(None),
+        LOOP_FORWARD => LoopMode::Forward,
+        LOOP_PING_PONG => LoopMode::PingPong,
+        _ => bail!("Invalid loop mode {mode}"),
+    };
+    SampleLoop::new(mode, start, end).map(Some)
+}
+
+fn write_interpolations(writer: &mut Writer, instruments: &Instruments) {
+    for (_, instrument) in instruments.iter() {
+        let Kind::Sample { interpolation, .. } = instrument.source() else {
+            continue;
+        };
+        writer.u8(match interpolation {
+            InterpolationMode::Nearest => INTERPOLATION_NEAREST,
+            InterpolationMode::Linear => INTERPOLATION_LINEAR,
+            InterpolationMode::Cubic => INTERPOLATION_CUBIC,
+            InterpolationMode::Sinc => INTERPOLATION_SINC,
+        });
+    }
+}
+
+fn read_interpolations(reader: &mut Reader, instruments: &mut Instruments) -> anyhow::Result<()> {
+    let slots = instruments
+        .iter()
+        .filter(|(_, instrument)| matches!(instrument.source(), Kind::Sample { .. }))
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+    for index in slots {
+        let interpolation = match reader.u8()? {
+            INTERPOLATION_NEAREST => InterpolationMode::Nearest,
+            INTERPOLATION_LINEAR => InterpolationMode::Linear,
+            INTERPOLATION_CUBIC => InterpolationMode::Cubic,
+            INTERPOLATION_SINC => InterpolationMode::Sinc,
+            mode => bail!("Invalid interpolation {mode} in slot {index}"),
+        };
+        if let Some(instrument) = instruments.get_mut(index) {
+            instrument.set_interpolation(interpolation)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        audio::Decibels,
+        model::pattern::{HexDigit, NoteName},
+    };
+
+    use super::*;
+
+    fn get_song() -> Song {
+        let mut patterns = Patterns::new(2, 4, 2);
+        patterns.set_order(vec![1, 0, 1]).unwrap();
+        patterns.current_line_mut().note = Field::new(NoteFieldValue::Note(
+            NoteName::CSharp,
+            OctaveValue::OCTAVE_3,
+        ));
+        patterns.current_line_mut().velocity = Field::new((HexDigit::HEX_5, HexDigit::HEX_F));
+        patterns.current_channel = 1;
+        patterns.current_row = 3;
+        patterns.current_line_mut().note = Field::new(NoteFieldValue::Cut);
+        patterns.current_row = 2;
+        patterns.current_line_mut().note = Field::new(NoteFieldValue::Off);
+        patterns.current_row = 3;
+        patterns.current_line_mut().instrument = Field::new((HexDigit::HEX_0, HexDigit::HEX_3));
+        patterns.current_line_mut().effect = Field::new(EffectFieldValue {
+            command: HexDigit::HEX_E,
+            parameter: (HexDigit::HEX_D, HexDigit::HEX_2),
+        });
+
+        let mut instruments = Instruments::empty();
+        instruments.set(0, Instrument::from(Kind::Square)).unwrap();
+        let mut sample = Instrument::from(Kind::Sample {
+            name: "Noise".into(),
+            signal: signal::stereo::Owned::from_samples(vec![0.1, -0.2, 0.3, -0.4], 22050.0)
+                .unwrap(),
+            loops: "none; pingpong 0 2".parse().unwrap(),
+            interpolation: InterpolationMode::Cubic,
+        });
+        sample.volume = Volume::new_unchecked(0.5);
+        sample.envelope = "5 120 40 800".parse().unwrap();
+        instruments.set(12, sample).unwrap();
+
+        let mut mute_solo = MuteSolo::new(2);
+        mute_solo.set_muted(0, true);
+        mute_solo.set_soloed(1, true);
+
+        Song {
+            patterns,
+            instruments,
+            mute_solo,
+            global_octave: OctaveValue::OCTAVE_7,
+            global_volume: Decibels::DEFAULT.volume(),
+            tempo: Tempo::new(135.0, 4, 3).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_song_is_identical_after_save_and_load() {
+        let song = get_song();
+        let loaded = read(&write(&song)).unwrap();
+
+        assert_eq!(song.global_octave, loaded.global_octave);
+        assert_eq!(song.global_volume, loaded.global_volume);
+        assert_eq!(song.tempo, loaded.tempo);
+        assert_eq!(song.mute_solo, loaded.mute_solo);
+        assert_eq!(song.patterns.channel_count, loaded.patterns.channel_count);
+        assert_eq!(song.patterns.channel_len, loaded.patterns.channel_len);
+        assert_eq!(song.patterns.order(), loaded.patterns.order());
+        for (expected, actual) in song
+            .patterns
+            .patterns()
+            .iter()
+            .zip(loaded.patterns.patterns())
+        {
+            assert_eq!(expected.lines, actual.lines);
+        }
+
+        assert_eq!(
+            vec![0, 12],
+            loaded
+                .instruments
+                .iter()
+                .map(|(i, _)| i)
+                .collect::<Vec<_>>()
+        );
+        assert!(matches!(
+            loaded.instruments.get(0).unwrap().source(),
+            Kind::Square
+        ));
+        let sample = loaded.instruments.get(12).unwrap();
+        assert_eq!(Volume::new_unchecked(0.5), sample.volume);
+        assert_eq!("5 120 40 800", sample.envelope.to_string());
+        assert_eq!(
+            Envelope::default(),
+            loaded.instruments.get(0).unwrap().envelope
+        );
+        let Kind::Sample {
+            name,
+            signal,
+            loops,
+            interpolation,
+        } = sample.source()
+        else {
+            panic!("Expected a sample");
+        };
+        assert_eq!("Noise", name);
+        assert_eq!("none; pingpong 0 2", loops.to_string());
+        assert_eq!(InterpolationMode::Cubic, *interpolation);
+        signal::test_utils::assert_signal_eq(
+            signal.clone(),
+            signal::stereo::Owned::from_samples(vec![0.1, -0.2, 0.3, -0.4], 22050.0).unwrap(),
+        );
+    }
+
+    #[test]
+    fn test_oscillators_are_identical_after_save_and_load() {
+        let mut song = get_song();
+        song.instruments = Instruments::empty();
+        for (index, kind) in [
+            Kind::Triangle,
+            Kind::Pulse { width: 0.125 },
+            Kind::WhiteNoise,
+            Kind::PinkNoise,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            song.instruments
+                .set(index as u8, Instrument::from(kind))
+                .unwrap();
+        }
+
+        let loaded = read(&write(&song)).unwrap();
+        assert_eq!(
+            vec!["Triangle", "Pulse 13%", "White noise", "Pink noise"],
+            loaded
+                .instruments
+                .iter()
+                .map(|(_, instrument)| instrument.source().to_string())
+                .collect::<Vec<_>>()
+        );
+        assert!(matches!(
+            loaded.instruments.get(1).unwrap().source(),
+            Kind::Pulse { width: 0.125 }
+        ));
+    }
+
+    /// Song saved with `version`: the tempo is a line rate before version 3, lines have no effect
+    /// before version 4, there is no order before version 2, no mute / solo before version 5, no
+    /// envelope before version 6, no sample loop before version 7 and no interpolation before
+    /// version 9
+    fn write_version(song: &Song, version: u16) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.bytes(MAGIC);
+        writer.u16_le(version);
+        writer.i32_le(song.global_octave.value());
+        writer.f32_le(song.global_volume.value());
+        if version >= 3 {
+            writer.f32_le(song.tempo.bpm);
+            writer.u32_le(song.tempo.rows_per_beat);
+            writer.u32_le(song.tempo.ticks_per_row);
+        } else {
+            writer.f32_le(song.tempo.line_per_second());
+        }
+
+        writer.u32_le(song.patterns.channel_count as u32);
+        writer.u32_le(song.patterns.channel_len as u32);
+        writer.u32_le(song.patterns.patterns().len() as u32);
+        for line in song
+            .patterns
+            .patterns()
+            .iter()
+            .flat_map(|pattern| &pattern.lines)
+        {
+            let mut line_writer = Writer::new();
+            write_line(&mut line_writer, line);
+            let line_bytes = line_writer.into_bytes();
+            let effect_len = match line.effect.value() {
+                _ if version >= 4 => 0,
+                Some(_) => 3,
+                None => 1,
+            };
+            writer.bytes(&line_bytes[..line_bytes.len() - effect_len]);
+        }
+
+        if version >= 2 {
+            write_order(&mut writer, song.patterns.order());
+        }
+        write_instruments(&mut writer, &song.instruments);
+        if version >= 5 {
+            write_mute_solo(
+                &mut writer,
+                &song.mute_solo,
+                song.patterns.channel_count as usize,
+            );
+        }
+        if version >= 6 {
+            write_envelopes(&mut writer, &song.instruments);
+        }
+        if version >= 7 {
+            write_sample_loops(&mut writer, &song.instruments);
+        }
+        writer.into_bytes()
+    }
+
+    #[test]
+    fn test_version_6_songs_have_no_sample_loop() {
+        let song = get_song();
+        let loaded = read(&write_version(&song, 6)).unwrap();
+        let sample = loaded.instruments.get(12).unwrap();
+        assert_eq!("5 120 40 800", sample.envelope.to_string());
+        let Kind::Sample { loops, .. } = sample.source() else {
+            panic!("Expected a sample");
+        };
+        assert_eq!(SampleLoops::default(), *loops);
+    }
+
+    #[test]
+    fn test_version_8_songs_interpolate_linearly() {
+        let loaded = read(&write_version(&get_song(), 8)).unwrap();
+        let Kind::Sample {
+            loops,
+            interpolation,
+            ..
+        } = loaded.instruments.get(12).unwrap().source()
+        else {
+            panic!("Expected a sample");
+        };
+        assert_eq!("none; pingpong 0 2", loops.to_string());
+        assert_eq!(InterpolationMode::Linear, *interpolation);
+    }
+
+    #[test]
+    fn test_loops_past_the_sample_are_rejected() {
+        let mut data = write(&get_song());
+        // The sustain loop end is followed by the interpolation of the only sample
+        let len = data.len();
+        data[len - 5..len - 1].copy_from_slice(&5u32.to_le_bytes());
+        assert!(read(&data).is_err());
+    }
+
+    #[test]
+    fn test_invalid_interpolation_is_rejected() {
+        let mut data = write(&get_song());
+        *data.last_mut().unwrap() = 4;
+        assert!(read(&data).is_err());
+    }
+
+    #[test]
+    fn test_version_5_songs_have_no_envelope() {
+        let song = get_song();
+        let loaded = read(&write_version(&song, 5)).unwrap();
+        assert_eq!(song.mute_solo, loaded.mute_solo);
+        assert!(loaded
+            .instruments
+            .iter()
+            .all(|(_, instrument)| instrument.envelope == Envelope::default()));
+    }
+
+    #[test]
+    fn test_version_4_songs_play_every_channel() {
+        let song = get_song();
+        let loaded = read(&write_version(&song, 4)).unwrap();
+        assert_eq!(MuteSolo::new(2), loaded.mute_solo);
+        assert_eq!(song.tempo, loaded.tempo);
+        assert_eq!(
+            song.patterns.patterns()[0].lines,
+            loaded.patterns.patterns()[0].lines
+        );
+    }
+
+    #[test]
+    fn test_version_1_songs_play_every_pattern() {
+        let loaded = read(&write_version(&get_song(), 1)).unwrap();
+        assert_eq!(&[0, 1], loaded.patterns.order());
+    }
+
+    #[test]
+    fn test_version_2_songs_keep_their_line_rate() {
+        let song = get_song();
+        let loaded = read(&write_version(&song, 2)).unwrap();
+        assert_eq!(song.patterns.order(), loaded.patterns.order());
+        approx::assert_relative_eq!(song.tempo.line_per_second(), loaded.tempo.line_per_second());
+        assert_eq!(Tempo::default().rows_per_beat, loaded.tempo.rows_per_beat);
+    }
+
+    #[test]
+    fn test_version_2_songs_have_no_effect() {
+        let song = get_song();
+        let loaded = read(&write_version(&song, 2)).unwrap();
+        let lines = &loaded.patterns.patterns()[0].lines;
+        assert!(lines.iter().all(|line| line.effect.value().is_none()));
+        assert_eq!(song.patterns.patterns()[0].lines[7].note, lines[7].note);
+    }
+
+    #[test]
+    fn test_invalid_magic_is_rejected() {
+        assert!(read(b"NOT A SONG").is_err());
+    }
+
+    #[test]
+    fn test_truncated_file_is_rejected() {
+        let data = write(&get_song());
+        assert!(read(&data[..data.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_malformed_pattern_dimensions_are_rejected() {
+        // The channel count then the channel length follow the settings
+        let offset = MAGIC.len() + 2 + 5 * 4;
+        for (channel_count, channel_len) in [
+            (0, 4),
+            (2, 0),
+            (0x8000_0000, 4),
+            (2, 0xFFFF_FFFF),
+            (0xFFFF_FFFF, 0xFFFF_FFFF),
+        ] {
+            let mut data = write(&get_song());
+            data[offset..offset + 4].copy_from_slice(&u32::to_le_bytes(channel_count));
+            data[offset + 4..offset + 8].copy_from_slice(&u32::to_le_bytes(channel_len));
+            assert!(read(&data).is_err(), "{channel_count} {channel_len}");
+        }
+    }
+
+    #[test]
+    fn test_newer_version_is_rejected() {
+        let mut data = write(&get_song());
+        data[MAGIC.len()..MAGIC.len() + 2].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(read(&data).is_err());
+    }
+}
@@ -0,0 +1,680 @@
+//! ProTracker `.mod` importer (M.K. and compatible multi-channel tags, plus the older
+//! 15 sample Soundtracker layout).
+//!
+//! Big endian layout:
+//! ```text
+//! title             20 bytes
+//! samples           31 (or 15) * (name: 22 bytes, length: u16, finetune: u8, volume: u8,
+//!                   loop_start: u16, loop_length: u16), lengths are in words
+//! song length       u8, then an unused byte
+//! order table       128 * u8
+//! tag               4 bytes ("M.K.", "6CHN", "16CH"...), absent in 15 sample files
+//! patterns          64 rows * channel_count * 4 bytes
+//! sample data       signed 8 bit, in sample order
+//! ```
+//!
+//! Periods are mapped so that ProTracker's C-2 (which plays a sample at its native rate) lands
+//! on tracky's C-5.
+
+use std::{fs, path::Path};
+
+use anyhow::{ensure, Context};
+use log::info;
+
+use crate::model::{
+    instrument::{Instrument, Instruments},
+    midi::{midi_value_to_note, MidiValue},
+    pattern::{u8_to_hex_digit_pair, Field, NoteFieldValue, Pattern, PatternLine, Patterns},
+    sample_loop::{LoopMode, SampleLoops},
+    Song,
+};
+
+use super::{
+    binary::Reader,
+    import::{self, Import, Location, Report},
+};
+
+pub const EXTENSION: &str = "mod";
+
+const ROW_COUNT: usize = 64;
+const ORDER_TABLE_LEN: usize = 128;
+const TAG_OFFSET: usize = 20 + 31 * 30 + 2 + ORDER_TABLE_LEN;
+
+const MAX_VOLUME: u8 = 64;
+const DEFAULT_SPEED: u8 = 6;
+const DEFAULT_TEMPO: u8 = 125;
+
+/// Frame rate of a sample played at C-2 (period 428) on an NTSC Amiga
+const C2_FRAME_RATE: f32 = 8363.0;
+const C1_PERIOD: f32 = 856.0;
+/// ProTracker's C-1 becomes tracky's C-4
+const C1_MIDI_VALUE: i32 = 60;
+
+const EFFECT_SET_VOLUME: u8 = 0xC;
+const EFFECT_SET_SPEED: u8 = 0xF;
+
+struct Sample {
+    name: String,
+    length: usize,
+    finetune: i8,
+    volume: u8,
+    loop_start: usize,
+    loop_length: usize,
+    data: Vec<i8>,
+}
+
+#[derive(Clone, Copy)]
+struct Cell {
+    sample: u8,
+    period: u16,
+    effect: u8,
+    param: u8,
+}
+
+struct Module {
+    title: String,
+    channel_count: usize,
+    samples: Vec<Sample>,
+    order: Vec<u8>,
+    /// Row major cells of every pattern
+    patterns: Vec<Vec<Cell>>,
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Import> {
+    let data = fs::read(path.as_ref())
+        .with_context(|| format!("Could not open module {:?}", path.as_ref()))?;
+    read(&data).with_context(|| format!("Invalid module {:?}", path.as_ref()))
+}
+
+pub fn read(data: &[u8]) -> anyhow::Result<Import> {
+    let mut report = Report::default();
+    let module = parse(data, &mut report)?;
+    let song = convert(module, &mut report)?;
+    Ok(Import { song, report })
+}
+
+fn channel_count_from_tag(tag: &[u8]) -> Option<usize> {
+    match tag {
+        b"M.K." | b"M!K!" | b"M&K!" | b"FLT4" | b"4CHN" => Some(4),
+        b"FLT8" => Some(8),
+        [digit, b'C', b'H', b'N'] if digit.is_ascii_digit() => Some((digit - b'0') as usize),
+        [tens, units, b'C', b'H' | b'N'] if tens.is_ascii_digit() && units.is_ascii_digit() => {
+            Some(((tens - b'0') * 10 + units - b'0') as usize)
+        }
+        _ => None,
+    }
+    .filter(|channel_count| *channel_count > 0)
+}
+
+fn parse(data: &[u8], report: &mut Report) -> anyhow::Result<Module> {
+    let tagged_channel_count = data
+        .get(TAG_OFFSET..TAG_OFFSET + 4)
+        .and_then(channel_count_from_tag);
+    let (sample_count, channel_count) = match tagged_channel_count {
+        Some(channel_count) => (31, channel_count),
+        None => {
+            report.warn("No format tag found, reading as a 15 sample Soundtracker module");
+            (15, 4)
+        }
+    };
+
+    let mut reader = Reader::new(data);
+    let title = reader.fixed_string(20)?;
+
+    let mut samples = (0..sample_count)
+        .map(|_| {
+            Ok(Sample {
+                name: reader.fixed_string(22)?,
+                length: reader.u16_be()? as usize * 2,
+                // Signed nibble, in eighths of a semitone
+                finetune: (((reader.u8()? & 0x0F) << 4) as i8) >> 4,
+                volume: reader.u8()?.min(MAX_VOLUME),
+                loop_start: reader.u16_be()? as usize * 2,
+                loop_length: reader.u16_be()? as usize * 2,
+                data: Vec::new(),
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()
+        .context("Could not read sample headers")?;
+
+    let song_length = reader.u8()? as usize;
+    let _restart_position = reader.u8()?;
+    let order_table = reader.bytes(ORDER_TABLE_LEN)?;
+    ensure!(
+        (1..=ORDER_TABLE_LEN).contains(&song_length),
+        "Invalid song length {song_length}"
+    );
+    ensure!(
+        order_table
+            .iter()
+            .all(|pattern| (*pattern as usize) < ORDER_TABLE_LEN),
+        "Invalid order table"
+    );
+    if tagged_channel_count.is_some() {
+        reader.bytes(4)?;
+    }
+
+    // Patterns that are only referenced past the song length are still stored in the file
+    let pattern_count = *order_table.iter().max().unwrap() as usize + 1;
+    let patterns = (0..pattern_count)
+        .map(|pattern_index| {
+            (0..ROW_COUNT * channel_count)
+                .map(|_| {
+                    let [b0, b1, b2, b3] = reader.array()?;
+                    Ok(Cell {
+                        sample: (b0 & 0xF0) | (b2 >> 4),
+                        period: u16::from_be_bytes([b0 & 0x0F, b1]),
+                        effect: b2 & 0x0F,
+                        param: b3,
+                    })
+                })
+                .collect::<anyhow::Result<Vec<_>>>()
+                .with_context(|| format!("Could not read pattern {pattern_index}"))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    for (index, sample) in samples.iter_mut().enumerate() {
+        let available = sample.length.min(reader.remaining());
+        if available < sample.length {
+            report.warn(format!(
+                "Sample {} is truncated ({available} of {} bytes)",
+                index + 1,
+                sample.length
+            ));
+        }
+        sample.data = reader
+            .bytes(available)?
+            .iter()
+            .map(|byte| *byte as i8)
+            .collect();
+    }
+
+    Ok(Module {
+        title,
+        channel_count,
+        samples,
+        order: order_table[..song_length].to_vec(),
+        patterns,
+    })
+}
+
+fn period_to_note(period: u16) -> Option<NoteFieldValue> {
+    let semitones = (12.0 * (C1_PERIOD / period as f32).log2()).round() as i32;
+    let midi_value = C1_MIDI_VALUE + semitones;
+    (MidiValue::MIN_VALUE..=MidiValue::MAX_VALUE)
+        .contains(&midi_value)
+        .then(|| {
+            let (note, octave) = midi_value_to_note(MidiValue::new_unchecked(midi_value));
+            NoteFieldValue::Note(note, octave)
+        })
+}
+
+fn convert(module: Module, report: &mut Report) -> anyhow::Result<Song> {
+    let Module {
+        title,
+        channel_count,
+        samples,
+        order,
+        patterns,
+    } = module;
+
+    // Initial speed and tempo come from the first row played
+    let (mut speed, mut tempo) = (DEFAULT_SPEED, DEFAULT_TEMPO);
+    let first_pattern = order[0] as usize;
+    for cell in patterns[first_pattern][..channel_count].iter() {
+        match (cell.effect, cell.param) {
+            (EFFECT_SET_SPEED, 0) => {}
+            (EFFECT_SET_SPEED, param @ 1..=0x1F) => speed = param,
+            (EFFECT_SET_SPEED, param) => tempo = param,
+            _ => {}
+        }
+    }
+
+    let patterns = patterns
+        .iter()
+        .enumerate()
+        .map(|(pattern_index, cells)| {
+            let mut pattern = Pattern::new(channel_count as i32, ROW_COUNT as i32);
+            for (cell_index, cell) in cells.iter().enumerate() {
+                let (row, channel) = (cell_index / channel_count, cell_index % channel_count);
+                let location = Location {
+                    pattern: pattern_index,
+                    row,
+                    channel,
+                };
+                pattern.lines[channel * ROW_COUNT + row] =
+                    convert_cell(cell, location, pattern_index == first_pattern, report);
+            }
+            pattern
+        })
+        .collect();
+    let mut patterns = Patterns::from_patterns(channel_count as i32, ROW_COUNT as i32, patterns)?;
+    patterns.set_order(order.iter().map(|pattern| *pattern as usize).collect())?;
+
+    let mut instruments = Instruments::empty();
+    for (index, sample) in samples.into_iter().enumerate() {
+        // One word samples are the usual placeholder for an empty slot
+        if sample.data.len() <= 2 {
+            continue;
+        }
+        // A one word loop is the usual placeholder for no loop
+        let loops = SampleLoops {
+            normal: (sample.loop_length > 2)
+                .then(|| {
+                    import::sample_loop(
+                        LoopMode::Forward,
+                        sample.loop_start,
+                        sample.loop_start + sample.loop_length,
+                        sample.data.len(),
+                        &format!("Loop of sample {}", index + 1),
+                        report,
+                    )
+                })
+                .flatten(),
+            sustain: None,
+        };
+        let name = if sample.name.is_empty() {
+            format!("Sample {}", index + 1)
+        } else {
+            sample.name
+        };
+        let frame_rate = C2_FRAME_RATE * (sample.finetune as f32 / 96.0).exp2();
+        let kind = import::mono_sample(
+            name,
+            sample.data.iter().map(|value| *value as f32 / 128.0),
+            frame_rate,
+            loops,
+        );
+        let mut instrument = Instrument::from(kind);
+        instrument.volume = import::volume(sample.volume, MAX_VOLUME);
+        instruments.set(index as u8, instrument)?;
+    }
+
+    if !title.is_empty() {
+        info!("Importing module {title:?}");
+    }
+
+    Ok(import::song(
+        patterns,
+        instruments,
+        import::tempo(speed, tempo)?,
+    ))
+}
+
+fn convert_cell(
+    cell: &Cell,
+    location: Location,
+    is_first_pattern: bool,
+    report: &mut Report,
+) -> PatternLine {
+    let mut line = PatternLine::default();
+
+    if cell.period != 0 {
+        match period_to_note(cell.period) {
+            Some(note) => line.note = Field::new(note),
+            None => report.warn_at(
+                location,
+                format!("Period {} is out of the note range", cell.period),
+            ),
+        }
+    }
+
+    if cell.sample != 0 {
+        line.instrument = Field::new(u8_to_hex_digit_pair(cell.sample - 1));
+        // Triggering a sample resets the channel to the sample volume
+        line.velocity = Field::new(u8_to_hex_digit_pair(0xFF));
+    }
+
+    match (cell.effect, cell.param) {
+        (0, 0) => {}
+        (EFFECT_SET_VOLUME, volume) => {
+            line.velocity = Field::new(u8_to_hex_digit_pair(import::velocity(volume, MAX_VOLUME)))
+        }
+        (EFFECT_SET_SPEED, _) if is_first_pattern && location.row == 0 => {}
+        (effect, param) => match import::effect(effect, param) {
+            Some(effect) => line.effect = Field::new(effect),
+            None => report.warn_at(
+                location,
+                format!("Unsupported effect {effect:X}{param:02X}"),
+            ),
+        },
+    }
+
+    line
+}
+
+#[cfg(test)]
+mod test {
+    use joy_vector::Vector;
+
+    use crate::model::{
+        instrument::Kind,
+        pattern::{HexDigit, NoteName, OctaveValue},
+    };
+
+    use super::*;
+
+    struct TestSample {
+        name: &'static str,
+        finetune: u8,
+        volume: u8,
+        loop_start: u16,
+        loop_length: u16,
+        data: Vec<i8>,
+    }
+
+    // (row, channel, [b0, b1, b2, b3])
+    type TestCell = (usize, usize, [u8; 4]);
+
+    fn cell(sample: u8, period: u16, effect: u8, param: u8) -> [u8; 4] {
+        let [period_high, period_low] = period.to_be_bytes();
+        [
+            (sample & 0xF0) | period_high,
+            period_low,
+            (sample << 4) | effect,
+            param,
+        ]
+    }
+
+    fn make_module(tag: &[u8; 4], samples: &[TestSample], patterns: &[&[TestCell]]) -> Vec<u8> {
+        let channel_count = channel_count_from_tag(tag).unwrap();
+        let mut data = Vec::new();
+        data.extend(b"test module\0\0\0\0\0\0\0\0\0");
+        for index in 0..31 {
+            let mut name = [0u8; 22];
+            match samples.get(index) {
+                Some(sample) => {
+                    name[..sample.name.len()].copy_from_slice(sample.name.as_bytes());
+                    data.extend(name);
+                    data.extend((sample.data.len() as u16 / 2).to_be_bytes());
+                    data.extend([sample.finetune, sample.volume]);
+                    data.extend((sample.loop_start / 2).to_be_bytes());
+                    data.extend((sample.loop_length / 2).to_be_bytes());
+                }
+                None => {
+                    data.extend(name);
+                    data.extend([0, 0, 0, 0, 0, 0, 0, 1]);
+                }
+            }
+        }
+        data.push(patterns.len() as u8);
+        data.push(0x7F);
+        let mut order = [0u8; ORDER_TABLE_LEN];
+        for (index, entry) in order.iter_mut().take(patterns.len()).enumerate() {
+            *entry = index as u8;
+        }
+        data.extend(order);
+        data.extend(tag);
+        for cells in patterns {
+            let mut pattern = vec![[0u8; 4]; ROW_COUNT * channel_count];
+            for (row, channel, bytes) in cells.iter() {
+                pattern[row * channel_count + channel] = *bytes;
+            }
+            data.extend(pattern.into_iter().flatten());
+        }
+        for sample in samples {
+            data.extend(sample.data.iter().map(|value| *value as u8));
+        }
+        data
+    }
+
+    fn note(note: NoteName, octave: OctaveValue) -> Field<NoteFieldValue> {
+        Field::new(NoteFieldValue::Note(note, octave))
+    }
+
+    fn line_at(patterns: &Patterns, pattern: usize, channel: usize, row: usize) -> &PatternLine {
+        &patterns.patterns()[pattern].lines[channel * patterns.channel_len as usize + row]
+    }
+
+    fn square_sample() -> TestSample {
+        TestSample {
+            name: "square",
+            finetune: 0,
+            volume: 32,
+            loop_start: 0,
+            loop_length: 0,
+            data: [vec![64; 8], vec![-64; 8]].concat(),
+        }
+    }
+
+    #[test]
+    fn test_channel_count_from_tag() {
+        assert_eq!(Some(4), channel_count_from_tag(b"M.K."));
+        assert_eq!(Some(6), channel_count_from_tag(b"6CHN"));
+        assert_eq!(Some(16), channel_count_from_tag(b"16CH"));
+        assert_eq!(Some(32), channel_count_from_tag(b"32CN"));
+        assert_eq!(None, channel_count_from_tag(b"0CHN"));
+        assert_eq!(None, channel_count_from_tag(b"RIFF"));
+    }
+
+    #[test]
+    fn test_period_to_note() {
+        assert_eq!(
+            Some(NoteFieldValue::Note(NoteName::C, OctaveValue::OCTAVE_4)),
+            period_to_note(856)
+        );
+        assert_eq!(
+            Some(NoteFieldValue::Note(NoteName::C, OctaveValue::OCTAVE_5)),
+            period_to_note(428)
+        );
+        assert_eq!(
+            Some(NoteFieldValue::Note(NoteName::B, OctaveValue::OCTAVE_6)),
+            period_to_note(113)
+        );
+        // Finetuned periods are rounded to the nearest note
+        assert_eq!(
+            Some(NoteFieldValue::Note(NoteName::A, OctaveValue::OCTAVE_4)),
+            period_to_note(504)
+        );
+        assert_eq!(None, period_to_note(1));
+    }
+
+    #[test]
+    fn test_patterns_are_imported() {
+        let data = make_module(
+            b"M.K.",
+            &[square_sample()],
+            &[
+                &[
+                    (0, 0, cell(1, 428, 0, 0)),
+                    (1, 2, cell(0, 254, EFFECT_SET_VOLUME, 0x40)),
+                    (63, 3, cell(1, 0, 0, 0)),
+                    (63, 0, cell(0, 0, 0x4, 0x37)),
+                ],
+                &[(5, 1, cell(0, 214, 0, 0))],
+            ],
+        );
+
+        let import = read(&data).unwrap();
+        let patterns = &import.song.patterns;
+        assert_eq!(4, patterns.channel_count);
+        assert_eq!(64, patterns.channel_len);
+        assert_eq!(2, patterns.pattern_count);
+
+        assert_eq!(
+            &PatternLine {
+                note: note(NoteName::C, OctaveValue::OCTAVE_5),
+                velocity: Field::new((HexDigit::HEX_F, HexDigit::HEX_F)),
+                instrument: Field::new((HexDigit::HEX_0, HexDigit::HEX_0)),
+                effect: Field::empty(),
+            },
+            line_at(patterns, 0, 0, 0)
+        );
+        assert_eq!(
+            &PatternLine {
+                note: note(NoteName::A, OctaveValue::OCTAVE_5),
+                velocity: Field::new((HexDigit::HEX_F, HexDigit::HEX_F)),
+                instrument: Field::empty(),
+                effect: Field::empty(),
+            },
+            line_at(patterns, 0, 2, 1)
+        );
+        assert_eq!(
+            &PatternLine {
+                note: Field::empty(),
+                velocity: Field::new((HexDigit::HEX_F, HexDigit::HEX_F)),
+                instrument: Field::new((HexDigit::HEX_0, HexDigit::HEX_0)),
+                effect: Field::empty(),
+            },
+            line_at(patterns, 0, 3, 63)
+        );
+        assert_eq!(
+            "437",
+            line_at(patterns, 0, 0, 63)
+                .effect
+                .value()
+                .unwrap()
+                .to_string()
+        );
+        assert_eq!(
+            note(NoteName::C, OctaveValue::OCTAVE_6),
+            line_at(patterns, 1, 1, 5).note
+        );
+        assert_eq!(&PatternLine::default(), line_at(patterns, 1, 0, 0));
+        assert!(import.report.warnings.is_empty(), "{:?}", import.report);
+    }
+
+    #[test]
+    fn test_samples_are_imported() {
+        let mut finetuned = square_sample();
+        finetuned.name = "";
+        finetuned.finetune = 0x0F; // -1/8th of a semitone
+        finetuned.volume = 64;
+        let data = make_module(b"M.K.", &[square_sample(), finetuned], &[&[]]);
+
+        let instruments = read(&data).unwrap().song.instruments;
+        let instruments = instruments.iter().collect::<Vec<_>>();
+        assert_eq!(2, instruments.len());
+
+        let (index, instrument) = instruments[0];
+        assert_eq!(0, index);
+        assert_eq!(0.5, instrument.volume.value());
+        let Kind::Sample { name, signal, .. } = instrument.source() else {
+            panic!("Expected a sample");
+        };
+        assert_eq!("square", name);
+        assert_eq!(C2_FRAME_RATE, signal.frame_rate);
+        assert_eq!(16, signal.len());
+        assert_eq!(Vector([0.5, 0.5]), signal[0]);
+        assert_eq!(Vector([-0.5, -0.5]), signal[15]);
+
+        let (index, instrument) = instruments[1];
+        assert_eq!(1, index);
+        assert_eq!(1.0, instrument.volume.value());
+        let Kind::Sample { name, signal, .. } = instrument.source() else {
+            panic!("Expected a sample");
+        };
+        assert_eq!("Sample 2", name);
+        approx::assert_relative_eq!(C2_FRAME_RATE * 2f32.powf(-1.0 / 96.0), signal.frame_rate);
+    }
+
+    #[test]
+    fn test_initial_speed_and_tempo_are_read_from_the_first_row() {
+        let data = make_module(
+            b"M.K.",
+            &[],
+            &[&[
+                (0, 1, cell(0, 0, EFFECT_SET_SPEED, 3)),
+                (0, 2, cell(0, 0, EFFECT_SET_SPEED, 150)),
+                (8, 0, cell(0, 0, EFFECT_SET_SPEED, 4)),
+            ]],
+        );
+
+        let import = read(&data).unwrap();
+        approx::assert_relative_eq!(20.0, import.song.tempo.line_per_second());
+        // The speed change on row 8 can't be represented
+        assert_eq!(1, import.report.warnings.len());
+        assert_eq!(
+            Some(Location {
+                pattern: 0,
+                row: 8,
+                channel: 0
+            }),
+            import.report.warnings[0].location
+        );
+    }
+
+    #[test]
+    fn test_sample_loops_are_imported() {
+        let mut looped = square_sample();
+        looped.loop_start = 4;
+        looped.loop_length = 8;
+        let mut cut = square_sample();
+        cut.loop_start = 8;
+        cut.loop_length = 10;
+        let data = make_module(b"M.K.", &[looped, square_sample(), cut], &[&[]]);
+
+        let import = read(&data).unwrap();
+        let loops = import
+            .song
+            .instruments
+            .iter()
+            .map(|(_, instrument)| match instrument.source() {
+                Kind::Sample { loops, .. } => loops.to_string(),
+                _ => panic!("Expected a sample"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec!["forward 4 12; none", "none; none", "forward 8 16; none"],
+            loops
+        );
+        assert_eq!(
+            vec!["Loop of sample 3 (8..18) goes past the 16 frame(s) of the sample, it is cut"],
+            import
+                .report
+                .warnings
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_unsupported_features_are_reported() {
+        let data = make_module(
+            b"6CHN",
+            &[square_sample()],
+            &[&[
+                (2, 5, cell(1, 428, 0xB, 0x01)),
+                (3, 0, cell(0, 0, 0xE, 0xC2)),
+            ]],
+        );
+
+        let import = read(&data).unwrap();
+        assert_eq!(6, import.song.patterns.channel_count);
+        let warnings = import
+            .report
+            .warnings
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "pattern 0 row 002 channel 5: Unsupported effect B01",
+                "pattern 0 row 003 channel 0: Unsupported effect EC2",
+            ],
+            warnings
+        );
+    }
+
+    #[test]
+    fn test_truncated_sample_data_is_reported() {
+        let mut data = make_module(b"M.K.", &[square_sample()], &[&[]]);
+        data.truncate(data.len() - 6);
+
+        let import = read(&data).unwrap();
+        let (_, instrument) = import.song.instruments.iter().next().unwrap();
+        let Kind::Sample { signal, .. } = instrument.source() else {
+            panic!("Expected a sample");
+        };
+        assert_eq!(10, signal.len());
+        assert_eq!(1, import.report.warnings.len());
+    }
+
+    #[test]
+    fn test_truncated_patterns_are_an_error() {
+        let mut data = make_module(b"M.K.", &[], &[&[]]);
+        data.truncate(TAG_OFFSET + 4 + 100);
+        assert!(read(&data).is_err());
+    }
+}
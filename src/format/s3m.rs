@@ -0,0 +1,800 @@
+//! Scream Tracker 3 `.s3m` importer.
+//!
+//! Little endian layout, "parapointers" are offsets divided by 16:
+//! ```text
+//! title             28 bytes, then 0x1A, type: u8, 2 reserved bytes
+//! song              order_count: u16, instrument_count: u16, pattern_count: u16, flags: u16,
+//!                   tracker version: u16, sample format: u16 (1 signed, 2 unsigned), "SCRM"
+//! settings          global volume: u8, speed: u8, tempo: u8, master volume: u8,
+//!                   ultra click: u8, default pan: u8, 8 reserved bytes, special: u16
+//! channels          32 * u8 (0..=15 PCM channels, 255 unused)
+//! order             order_count * u8 (254 marker, 255 end of song)
+//! parapointers      instrument_count * u16, then pattern_count * u16
+//! instruments       type: u8 (1 sample), file name: 12 bytes, data parapointer: u24,
+//!                   length: u32, loop: 2 * u32, volume: u8, reserved: u8, packing: u8,
+//!                   flags: u8, c2spd: u32, 12 reserved bytes, name: 28 bytes, "SCRS"
+//! patterns          packed size: u16, then 64 rows of packed cells ended by a zero byte
+//! ```
+//!
+//! ST3's C-4 plays a sample at its c2spd, it is mapped to tracky's C-5.
+
+use std::{fs, path::Path};
+
+use anyhow::{ensure, Context};
+
+use crate::model::{
+    instrument::{Instrument, Instruments},
+    midi::{midi_value_to_note, MidiValue},
+    pattern::{u8_to_hex_digit_pair, Field, NoteFieldValue, PatternLine},
+    sample_loop::{LoopMode, SampleLoops},
+    Song,
+};
+
+use super::{
+    binary::Reader,
+    import::{self, Import, Location, Report},
+};
+
+pub const EXTENSION: &str = "s3m";
+
+const ID: &[u8; 4] = b"SCRM";
+const ID_OFFSET: usize = 0x2C;
+const CHANNEL_SETTINGS_OFFSET: usize = 0x40;
+const CHANNEL_COUNT: usize = 32;
+const ROW_COUNT: usize = 64;
+const MAX_PATTERN_COUNT: usize = 100;
+
+const ORDER_MARKER: u8 = 254;
+const ORDER_END: u8 = 255;
+const LAST_PCM_CHANNEL: u8 = 15;
+const STEREO_MASTER_VOLUME: u8 = 0x80;
+
+const SAMPLE_FORMAT_SIGNED: u16 = 1;
+const INSTRUMENT_TYPE_SAMPLE: u8 = 1;
+const SAMPLE_LOOP: u8 = 0b1;
+const SAMPLE_STEREO: u8 = 0b10;
+const SAMPLE_16_BIT: u8 = 0b100;
+
+const MAX_VOLUME: u8 = 64;
+const NOTE_EMPTY: u8 = 255;
+const NOTE_CUT: u8 = 254;
+const VOLUME_EMPTY: u8 = 255;
+/// ST3's C-4 becomes tracky's C-5
+const C4_MIDI_VALUE: i32 = 72;
+
+const EFFECT_SET_SPEED: u8 = 1;
+const EFFECT_SET_TEMPO: u8 = 20;
+
+#[derive(Clone, Copy)]
+struct Cell {
+    note: u8,
+    instrument: u8,
+    volume: u8,
+    effect: u8,
+    param: u8,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Cell {
+            note: NOTE_EMPTY,
+            instrument: 0,
+            volume: VOLUME_EMPTY,
+            effect: 0,
+            param: 0,
+        }
+    }
+}
+
+struct Sample {
+    name: String,
+    volume: u8,
+    c2spd: u32,
+    flags: u8,
+    loop_start: u32,
+    loop_end: u32,
+    data: Vec<f32>,
+}
+
+struct Module {
+    speed: u8,
+    tempo: u8,
+    is_stereo: bool,
+    /// Indexes of the S3M channels that are kept, in order
+    channels: Vec<usize>,
+    order: Vec<u8>,
+    /// Row major cells of every pattern, for every S3M channel
+    patterns: Vec<Vec<Cell>>,
+    samples: Vec<Option<Sample>>,
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Import> {
+    let data = fs::read(path.as_ref())
+        .with_context(|| format!("Could not open module {:?}", path.as_ref()))?;
+    read(&data).with_context(|| format!("Invalid module {:?}", path.as_ref()))
+}
+
+pub fn read(data: &[u8]) -> anyhow::Result<Import> {
+    let mut report = Report::default();
+    let module = parse(data, &mut report)?;
+    let song = convert(module, &mut report)?;
+    Ok(Import { song, report })
+}
+
+fn parse(data: &[u8], report: &mut Report) -> anyhow::Result<Module> {
+    ensure!(
+        data.get(ID_OFFSET..ID_OFFSET + ID.len()) == Some(ID),
+        "Not an S3M module"
+    );
+
+    let mut reader = Reader::new(data);
+    reader.seek(0x20)?;
+    let order_count = reader.u16_le()? as usize;
+    let instrument_count = reader.u16_le()? as usize;
+    let pattern_count = reader.u16_le()? as usize;
+    let _flags = reader.u16_le()?;
+    let _tracker_version = reader.u16_le()?;
+    let sample_format = reader.u16_le()?;
+    reader.seek(ID_OFFSET + ID.len())?;
+    let _global_volume = reader.u8()?;
+    let speed = reader.u8()?;
+    let tempo = reader.u8()?;
+    let master_volume = reader.u8()?;
+
+    reader.seek(CHANNEL_SETTINGS_OFFSET)?;
+    let mut channels = Vec::new();
+    for (index, setting) in reader.array::<CHANNEL_COUNT>()?.into_iter().enumerate() {
+        match setting {
+            0..=LAST_PCM_CHANNEL => channels.push(index),
+            ORDER_END => {}
+            _ if setting & 0x80 != 0 => {}
+            _ => report.warn(format!("AdLib channel {index} is not supported")),
+        }
+    }
+    ensure!(!channels.is_empty(), "Module has no PCM channel");
+
+    let order = reader
+        .bytes(order_count)?
+        .iter()
+        .copied()
+        .take_while(|pattern| *pattern != ORDER_END)
+        .filter(|pattern| *pattern != ORDER_MARKER)
+        .collect::<Vec<_>>();
+    ensure!(!order.is_empty(), "Song order is empty");
+    ensure!(
+        pattern_count <= MAX_PATTERN_COUNT,
+        "Invalid pattern count {pattern_count}"
+    );
+    ensure!(
+        order
+            .iter()
+            .all(|pattern| (*pattern as usize) < pattern_count),
+        "Song order {order:?} references missing patterns (pattern count is {pattern_count})"
+    );
+    let (used_patterns, order) = import::used_patterns(&order, pattern_count, report);
+
+    let instrument_pointers = (0..instrument_count)
+        .map(|_| Ok(reader.u16_le()? as usize * 16))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let pattern_pointers = (0..pattern_count)
+        .map(|_| Ok(reader.u16_le()? as usize * 16))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let is_signed = sample_format == SAMPLE_FORMAT_SIGNED;
+    let samples = instrument_pointers
+        .into_iter()
+        .enumerate()
+        .map(|(index, pointer)| {
+            parse_sample(&mut reader, pointer, is_signed)
+                .with_context(|| format!("Could not read instrument {}", index + 1))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let patterns = used_patterns
+        .into_iter()
+        .map(|index| {
+            parse_pattern(&mut reader, pattern_pointers[index])
+                .with_context(|| format!("Could not read pattern {index}"))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok(Module {
+        speed: speed.max(1),
+        tempo: tempo.max(32),
+        is_stereo: master_volume & STEREO_MASTER_VOLUME != 0,
+        channels,
+        order,
+        patterns,
+        samples,
+    })
+}
+
+fn parse_sample(
+    reader: &mut Reader,
+    pointer: usize,
+    is_signed: bool,
+) -> anyhow::Result<Option<Sample>> {
+    reader.seek(pointer)?;
+    let instrument_type = reader.u8()?;
+    let _file_name = reader.fixed_string(12)?;
+    let data_pointer = {
+        let [high, low_1, low_2] = reader.array()?;
+        (u32::from_le_bytes([low_1, low_2, high, 0]) as usize) * 16
+    };
+    let length = reader.u32_le()? as usize;
+    let loop_start = reader.u32_le()?;
+    let loop_end = reader.u32_le()?;
+    let volume = reader.u8()?.min(MAX_VOLUME);
+    let _reserved = reader.u8()?;
+    let _packing = reader.u8()?;
+    let flags = reader.u8()?;
+    let c2spd = reader.u32_le()?;
+    reader.bytes(12)?;
+    let name = reader.fixed_string(28)?;
+
+    if instrument_type != INSTRUMENT_TYPE_SAMPLE || length == 0 {
+        return Ok(None);
+    }
+
+    let is_16_bit = flags & SAMPLE_16_BIT != 0;
+    // Stereo samples store the whole left channel first
+    let byte_count = length * if is_16_bit { 2 } else { 1 };
+    reader.seek(data_pointer)?;
+    let bytes = reader
+        .bytes(byte_count)
+        .context("Sample data is truncated")?;
+
+    Ok(Some(Sample {
+        name,
+        volume,
+        c2spd,
+        flags,
+        loop_start,
+        loop_end,
+        data: import::pcm_samples(bytes, is_16_bit, is_signed),
+    }))
+}
+
+fn parse_pattern(reader: &mut Reader, pointer: usize) -> anyhow::Result<Vec<Cell>> {
+    let mut cells = vec![Cell::default(); ROW_COUNT * CHANNEL_COUNT];
+    // An empty pattern has no data
+    if pointer == 0 {
+        return Ok(cells);
+    }
+
+    reader.seek(pointer)?;
+    let packed_size = reader.u16_le()? as usize;
+    let mut data = Reader::new(reader.bytes(packed_size.saturating_sub(2))?);
+    for row in 0..ROW_COUNT {
+        loop {
+            let what = data.u8()?;
+            if what == 0 {
+                break;
+            }
+            let cell = &mut cells[row * CHANNEL_COUNT + (what & 0x1F) as usize];
+            if what & 0x20 != 0 {
+                cell.note = data.u8()?;
+                cell.instrument = data.u8()?;
+            }
+            if what & 0x40 != 0 {
+                cell.volume = data.u8()?;
+            }
+            if what & 0x80 != 0 {
+                cell.effect = data.u8()?;
+                cell.param = data.u8()?;
+            }
+        }
+    }
+
+    Ok(cells)
+}
+
+fn note_to_tracky(note: u8) -> Option<NoteFieldValue> {
+    match note {
+        NOTE_CUT => Some(NoteFieldValue::Cut),
+        _ if note & 0x0F < 12 => {
+            let octave = (note >> 4) as i32;
+            let midi_value = C4_MIDI_VALUE + (octave - 4) * 12 + (note & 0x0F) as i32;
+            (midi_value <= MidiValue::MAX_VALUE).then(|| {
+                let (note, octave) = midi_value_to_note(MidiValue::new_unchecked(midi_value));
+                NoteFieldValue::Note(note, octave)
+            })
+        }
+        _ => None,
+    }
+}
+
+fn effect_name(effect: u8, param: u8) -> String {
+    let effect = match effect {
+        1..=26 => (b'A' + effect - 1) as char,
+        _ => '?',
+    };
+    format!("{effect}{param:02X}")
+}
+
+fn convert(module: Module, report: &mut Report) -> anyhow::Result<Song> {
+    let Module {
+        mut speed,
+        mut tempo,
+        is_stereo,
+        channels,
+        order,
+        patterns,
+        samples,
+    } = module;
+
+    if is_stereo {
+        report.warn("Channel panning is not supported, all channels are centered");
+    }
+
+    // Initial speed and tempo can be overridden on the first row played
+    let first_pattern = order[0] as usize;
+    for channel in channels.iter() {
+        match patterns[first_pattern][*channel] {
+            Cell {
+                effect: EFFECT_SET_SPEED,
+                param: param @ 1..,
+                ..
+            } => speed = param,
+            Cell {
+                effect: EFFECT_SET_TEMPO,
+                param: param @ 0x20..,
+                ..
+            } => tempo = param,
+            _ => {}
+        }
+    }
+
+    // Only keep the cells of the kept channels
+    let cells = patterns
+        .iter()
+        .map(|cells| {
+            cells
+                .chunks_exact(CHANNEL_COUNT)
+                .flat_map(|row| channels.iter().map(|channel| row[*channel]))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let mut patterns =
+        import::patterns(&cells, channels.len(), report, |cell, location, report| {
+            convert_cell(cell, location, location.pattern == first_pattern, report)
+        })?;
+    patterns.set_order(order.iter().map(|pattern| *pattern as usize).collect())?;
+
+    let mut instruments = Instruments::empty();
+    for (index, sample) in samples.into_iter().enumerate() {
+        let Some(sample) = sample else {
+            continue;
+        };
+        let number = index + 1;
+        let loops = SampleLoops {
+            normal: (sample.flags & SAMPLE_LOOP != 0)
+                .then(|| {
+                    import::sample_loop(
+                        LoopMode::Forward,
+                        sample.loop_start as usize,
+                        sample.loop_end as usize,
+                        sample.data.len(),
+                        &format!("Loop of instrument {number}"),
+                        report,
+                    )
+                })
+                .flatten(),
+            sustain: None,
+        };
+        if sample.flags & SAMPLE_STEREO != 0 {
+            report.warn(format!(
+                "Instrument {number} is stereo, only its left channel is imported"
+            ));
+        }
+        let name = if sample.name.is_empty() {
+            format!("Instrument {number}")
+        } else {
+            sample.name
+        };
+        let mut instrument = Instrument::from(import::mono_sample(
+            name.clone(),
+            sample.data.into_iter(),
+            sample.c2spd as f32,
+            loops,
+        ));
+        instrument.volume = import::volume(sample.volume, MAX_VOLUME);
+        import::set_instrument(&mut instruments, index, &name, instrument)?;
+    }
+
+    Ok(import::song(
+        patterns,
+        instruments,
+        import::tempo(speed, tempo)?,
+    ))
+}
+
+fn convert_cell(
+    cell: &Cell,
+    location: Location,
+    is_first_pattern: bool,
+    report: &mut Report,
+) -> PatternLine {
+    let mut line = PatternLine::default();
+
+    if cell.note != NOTE_EMPTY {
+        match note_to_tracky(cell.note) {
+            Some(note) => line.note = Field::new(note),
+            None => report.warn_at(location, format!("Invalid note {:02X}", cell.note)),
+        }
+    }
+
+    if cell.instrument != 0 {
+        line.instrument = Field::new(u8_to_hex_digit_pair(cell.instrument - 1));
+        // Triggering an instrument resets the channel to the sample volume
+        line.velocity = Field::new(u8_to_hex_digit_pair(0xFF));
+    }
+
+    if cell.volume != VOLUME_EMPTY {
+        line.velocity = Field::new(u8_to_hex_digit_pair(import::velocity(
+            cell.volume,
+            MAX_VOLUME,
+        )));
+    }
+
+    match (cell.effect, cell.param) {
+        (0, _) => {}
+        (EFFECT_SET_SPEED | EFFECT_SET_TEMPO, _) if is_first_pattern && location.row == 0 => {}
+        (effect, param) => match import::lettered_effect(effect, param) {
+            Some(effect) => line.effect = Field::new(effect),
+            None => report.warn_at(
+                location,
+                format!("Unsupported effect {}", effect_name(effect, param)),
+            ),
+        },
+    }
+
+    line
+}
+
+#[cfg(test)]
+mod test {
+    use joy_vector::Vector;
+
+    use crate::model::{
+        instrument::Kind,
+        pattern::{HexDigit, NoteName, OctaveValue, Patterns},
+    };
+
+    use super::{super::binary::Writer, *};
+
+    struct TestSample {
+        name: &'static str,
+        volume: u8,
+        flags: u8,
+        c2spd: u32,
+        /// Written unsigned
+        data: Vec<u8>,
+    }
+
+    /// (row, channel, (note, instrument), volume, (effect, param)), `None` fields are not stored
+    type TestCell = (u8, u8, Option<(u8, u8)>, Option<u8>, Option<(u8, u8)>);
+
+    fn make_module(
+        channel_settings: &[u8],
+        order: &[u8],
+        samples: &[TestSample],
+        patterns: &[Vec<TestCell>],
+    ) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.bytes(&[0; 28]);
+        writer.bytes(&[0x1A, 16, 0, 0]);
+        writer.u16_le(order.len() as u16);
+        writer.u16_le(samples.len() as u16);
+        writer.u16_le(patterns.len() as u16);
+        writer.u16_le(0);
+        writer.u16_le(0x1320);
+        writer.u16_le(2);
+        writer.bytes(ID);
+        writer.bytes(&[64, 6, 125, 0x30, 0, 0]);
+        writer.bytes(&[0; 10]);
+        let mut channels = [ORDER_END; CHANNEL_COUNT];
+        channels[..channel_settings.len()].copy_from_slice(channel_settings);
+        writer.bytes(&channels);
+        writer.bytes(order);
+
+        // Headers and data are laid out after the parapointers, 16 byte aligned
+        let pointers_offset = CHANNEL_SETTINGS_OFFSET + CHANNEL_COUNT + order.len();
+        let first_offset =
+            (pointers_offset + 2 * (samples.len() + patterns.len())).next_multiple_of(16);
+        let mut offset = first_offset;
+        let mut pointers = Vec::new();
+        let mut blocks = Vec::new();
+        for sample in samples {
+            pointers.push((offset / 16) as u16);
+            let mut header = Writer::new();
+            header.u8(INSTRUMENT_TYPE_SAMPLE);
+            header.bytes(&[0; 12]);
+            let data_pointer = ((offset + 0x50) / 16) as u32;
+            header.u8((data_pointer >> 16) as u8);
+            header.u16_le(data_pointer as u16);
+            let frame_size = if sample.flags & SAMPLE_16_BIT != 0 {
+                2
+            } else {
+                1
+            };
+            header.u32_le((sample.data.len() / frame_size) as u32);
+            header.u32_le(0);
+            header.u32_le((sample.data.len() / frame_size) as u32);
+            header.u8(sample.volume);
+            header.bytes(&[0, 0]);
+            header.u8(sample.flags);
+            header.u32_le(sample.c2spd);
+            header.bytes(&[0; 12]);
+            let mut name = [0; 28];
+            name[..sample.name.len()].copy_from_slice(sample.name.as_bytes());
+            header.bytes(&name);
+            header.bytes(b"SCRS");
+            let mut block = header.into_bytes();
+            block.resize(0x50, 0);
+            block.extend(&sample.data);
+            block.resize(block.len().next_multiple_of(16), 0);
+            offset += block.len();
+            blocks.push(block);
+        }
+        for cells in patterns {
+            pointers.push((offset / 16) as u16);
+            let mut data = Vec::new();
+            for row in 0..ROW_COUNT as u8 {
+                for (_, channel, note, volume, effect) in cells.iter().filter(|cell| cell.0 == row)
+                {
+                    let mut what = *channel;
+                    let mut fields = Vec::new();
+                    if let Some((note, instrument)) = note {
+                        what |= 0x20;
+                        fields.extend([*note, *instrument]);
+                    }
+                    if let Some(volume) = volume {
+                        what |= 0x40;
+                        fields.push(*volume);
+                    }
+                    if let Some((effect, param)) = effect {
+                        what |= 0x80;
+                        fields.extend([*effect, *param]);
+                    }
+                    data.push(what);
+                    data.extend(fields);
+                }
+                data.push(0);
+            }
+            let mut block = ((data.len() + 2) as u16).to_le_bytes().to_vec();
+            block.extend(data);
+            block.resize(block.len().next_multiple_of(16), 0);
+            offset += block.len();
+            blocks.push(block);
+        }
+
+        for pointer in pointers {
+            writer.u16_le(pointer);
+        }
+        let mut data = writer.into_bytes();
+        data.resize(first_offset, 0);
+        data.extend(blocks.into_iter().flatten());
+        data
+    }
+
+    fn line_at(patterns: &Patterns, pattern: usize, channel: usize, row: usize) -> &PatternLine {
+        &patterns.patterns()[pattern].lines[channel * patterns.channel_len as usize + row]
+    }
+
+    #[test]
+    fn test_note_to_tracky() {
+        assert_eq!(
+            Some(NoteFieldValue::Note(NoteName::C, OctaveValue::OCTAVE_5)),
+            note_to_tracky(0x40)
+        );
+        assert_eq!(
+            Some(NoteFieldValue::Note(NoteName::B, OctaveValue::OCTAVE_1)),
+            note_to_tracky(0x0B)
+        );
+        assert_eq!(Some(NoteFieldValue::Cut), note_to_tracky(NOTE_CUT));
+        assert_eq!(None, note_to_tracky(0x4C));
+    }
+
+    #[test]
+    fn test_packed_patterns_are_imported() {
+        let data = make_module(
+            &[0, 8, 1],
+            &[0, ORDER_MARKER, 1, 0, ORDER_END],
+            &[],
+            &[
+                vec![
+                    (0, 0, Some((0x40, 1)), None, None),
+                    (0, 2, None, Some(32), Some((EFFECT_SET_TEMPO, 150))),
+                    (63, 1, Some((NOTE_CUT, 0)), None, Some((4, 0x12))),
+                ],
+                vec![
+                    (5, 0, Some((0x51, 2)), Some(64), None),
+                    (6, 1, None, None, Some((8, 0x48))),
+                ],
+            ],
+        );
+
+        let import = read(&data).unwrap();
+        let patterns = &import.song.patterns;
+        assert_eq!(3, patterns.channel_count);
+        assert_eq!(64, patterns.channel_len);
+        assert_eq!(2, patterns.pattern_count);
+        assert_eq!(&[0, 1, 0], patterns.order());
+        approx::assert_relative_eq!(10.0, import.song.tempo.line_per_second());
+
+        assert_eq!(
+            &PatternLine {
+                note: Field::new(NoteFieldValue::Note(NoteName::C, OctaveValue::OCTAVE_5)),
+                velocity: Field::new((HexDigit::HEX_F, HexDigit::HEX_F)),
+                instrument: Field::new((HexDigit::HEX_0, HexDigit::HEX_0)),
+                effect: Field::empty(),
+            },
+            line_at(patterns, 0, 0, 0)
+        );
+        assert_eq!(
+            Field::new((HexDigit::HEX_8, HexDigit::HEX_0)),
+            line_at(patterns, 0, 2, 0).velocity
+        );
+        assert_eq!(
+            Field::new(NoteFieldValue::Cut),
+            line_at(patterns, 0, 1, 63).note
+        );
+        assert_eq!(
+            &PatternLine {
+                note: Field::new(NoteFieldValue::Note(
+                    NoteName::CSharp,
+                    OctaveValue::OCTAVE_6
+                )),
+                velocity: Field::new((HexDigit::HEX_F, HexDigit::HEX_F)),
+                instrument: Field::new((HexDigit::HEX_0, HexDigit::HEX_1)),
+                effect: Field::empty(),
+            },
+            line_at(patterns, 1, 0, 5)
+        );
+        assert_eq!(
+            "448",
+            line_at(patterns, 1, 1, 6)
+                .effect
+                .value()
+                .unwrap()
+                .to_string()
+        );
+
+        assert_eq!(
+            vec!["pattern 0 row 063 channel 1: Unsupported effect D12"],
+            import
+                .report
+                .warnings
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_unused_and_adlib_channels_are_dropped() {
+        let data = make_module(
+            &[0, ORDER_END, 16, 0x88, 9],
+            &[0],
+            &[],
+            &[vec![
+                (0, 2, Some((0x40, 0)), None, None),
+                (1, 4, Some((0x40, 0)), None, None),
+            ]],
+        );
+
+        let import = read(&data).unwrap();
+        let patterns = &import.song.patterns;
+        assert_eq!(2, patterns.channel_count);
+        assert!(line_at(patterns, 0, 1, 1).note.value().is_some());
+        assert_eq!(
+            "AdLib channel 2 is not supported",
+            import.report.warnings[0].to_string()
+        );
+    }
+
+    #[test]
+    fn test_samples_are_imported() {
+        let data = make_module(
+            &[0],
+            &[0],
+            &[
+                TestSample {
+                    name: "snare",
+                    volume: 32,
+                    flags: 0,
+                    c2spd: 22050,
+                    data: vec![0xC0, 0x40, 0x80],
+                },
+                TestSample {
+                    name: "",
+                    volume: 64,
+                    flags: SAMPLE_16_BIT | SAMPLE_LOOP,
+                    c2spd: 8363,
+                    data: vec![0x00, 0xC0, 0x00, 0x40],
+                },
+            ],
+            &[vec![]],
+        );
+
+        let import = read(&data).unwrap();
+        let instruments = import.song.instruments.iter().collect::<Vec<_>>();
+        assert_eq!(2, instruments.len());
+
+        let (index, instrument) = instruments[0];
+        assert_eq!(0, index);
+        assert_eq!(0.5, instrument.volume.value());
+        let Kind::Sample { name, signal, .. } = instrument.source() else {
+            panic!("Expected a sample");
+        };
+        assert_eq!("snare", name);
+        assert_eq!(22050.0, signal.frame_rate);
+        assert_eq!(
+            vec![Vector([0.5, 0.5]), Vector([-0.5, -0.5]), Vector([0.0, 0.0])],
+            signal.to_vec()
+        );
+
+        let (index, instrument) = instruments[1];
+        assert_eq!(1, index);
+        let Kind::Sample {
+            name,
+            signal,
+            loops,
+            ..
+        } = instrument.source()
+        else {
+            panic!("Expected a sample");
+        };
+        assert_eq!("Instrument 2", name);
+        assert_eq!(
+            vec![Vector([0.5, 0.5]), Vector([-0.5, -0.5])],
+            signal.to_vec()
+        );
+        assert_eq!("forward 0 2; none", loops.to_string());
+        assert!(import.report.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_invalid_data_is_an_error() {
+        assert!(read(b"SCRM").is_err());
+        let mut data = make_module(&[0], &[0], &[], &[vec![]]);
+        data[ID_OFFSET] = b'X';
+        assert!(read(&data).is_err());
+        let data = make_module(&[0], &[3], &[], &[vec![]]);
+        assert!(read(&data).is_err());
+        let data = make_module(&[0], &[0], &[], &vec![vec![]; MAX_PATTERN_COUNT + 1]);
+        assert!(read(&data).is_err());
+    }
+
+    #[test]
+    fn test_patterns_out_of_the_order_are_dropped() {
+        let data = make_module(
+            &[0],
+            &[2, 1, 2],
+            &[],
+            &[
+                vec![],
+                vec![(1, 0, Some((0x40, 1)), None, None)],
+                vec![(2, 0, Some((0x40, 1)), None, None)],
+                vec![],
+            ],
+        );
+
+        let import = read(&data).unwrap();
+        let patterns = &import.song.patterns;
+        assert_eq!(2, patterns.pattern_count);
+        assert_eq!(&[1, 0, 1], patterns.order());
+        assert!(line_at(patterns, 0, 0, 1).note.value().is_some());
+        assert!(line_at(patterns, 1, 0, 2).note.value().is_some());
+        assert_eq!(
+            vec!["2 pattern(s) out of the song order are dropped"],
+            import
+                .report
+                .warnings
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        );
+    }
+}
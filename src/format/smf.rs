@@ -0,0 +1,1062 @@
+//! Standard MIDI File (`.mid`) exporter and importer.
+//!
+//! Big endian layout:
+//! ```text
+//! header            "MThd", length: u32 (6), format: u16 (1), track_count: u16,
+//!                   ticks per quarter note: u16
+//! tracks            ("MTrk", length: u32, events), each event is a variable length delta
+//!                   time in ticks followed by a channel message or a meta event
+//! ```
+//!
+//! The first track only holds the tempo, then every tracky channel becomes a track. Patterns
+//! follow the song order, a row lasts [`TICKS_PER_ROW`] ticks and a beat [`ROWS_PER_BEAT`] rows.
+//!
+//! On import, events are quantized to rows and every track gets as many tracky channels as it
+//! plays notes at once. Notes hold until their note off, which becomes a tracky note-off.
+
+use std::{collections::VecDeque, fs, path::Path};
+
+use anyhow::{bail, ensure, Context};
+use log::warn;
+
+use crate::model::{
+    instrument::{Instruments, MAX_SLOT_COUNT},
+    midi::{midi_value_to_note, note_to_midi_value, MidiValue},
+    pattern::{u8_to_hex_digit_pair, Field, NoteFieldValue, Pattern, PatternLine, Patterns},
+    tempo::{self, Tempo},
+    Song,
+};
+
+use super::{
+    binary::{Reader, Writer},
+    import::{self, Import, Location, Report},
+};
+
+pub const EXTENSION: &str = "mid";
+
+const HEADER_ID: &[u8; 4] = b"MThd";
+const TRACK_ID: &[u8; 4] = b"MTrk";
+const HEADER_LENGTH: u32 = 6;
+const FORMAT_SINGLE_TRACK: u16 = 0;
+const FORMAT_MULTI_TRACK: u16 = 1;
+const DIVISION_SMPTE: u16 = 0x8000;
+
+const ROWS_PER_BEAT: u32 = 4;
+/// Quantization used when opening a MIDI file as a song
+pub const DEFAULT_ROWS_PER_BEAT: u32 = ROWS_PER_BEAT;
+/// Imported songs are cut in patterns of this many rows
+const IMPORTED_PATTERN_LEN: usize = 64;
+/// Notes past this many patterns are dropped, a stray event far in the file would otherwise
+/// allocate millions of rows
+const MAX_IMPORTED_PATTERN_COUNT: usize = 256;
+const DEFAULT_MICROS_PER_BEAT: u32 = 500_000;
+const TICKS_PER_ROW: u32 = 24;
+const TICKS_PER_BEAT: u32 = ROWS_PER_BEAT * TICKS_PER_ROW;
+
+const NOTE_OFF: u8 = 0x80;
+const NOTE_ON: u8 = 0x90;
+const PROGRAM_CHANGE: u8 = 0xC0;
+const SYSEX: u8 = 0xF0;
+const SYSEX_ESCAPE: u8 = 0xF7;
+const META: u8 = 0xFF;
+const META_TRACK_NAME: u8 = 0x03;
+const META_END_OF_TRACK: u8 = 0x2F;
+const META_SET_TEMPO: u8 = 0x51;
+
+const MAX_MIDI_NOTE: i32 = 127;
+const MAX_VELOCITY: u8 = 127;
+const MIDI_CHANNEL_COUNT: usize = 16;
+/// General MIDI plays percussions on channel 10, it is left out
+const PERCUSSION_CHANNEL: usize = 9;
+
+/// A channel message or meta event, at an absolute time in ticks
+struct Event {
+    tick: u32,
+    data: Vec<u8>,
+}
+
+/// The parts of a track the importer understands
+enum TrackEvent {
+    NoteOn { channel: u8, key: u8, velocity: u8 },
+    NoteOff { channel: u8, key: u8 },
+    ProgramChange { channel: u8, program: u8 },
+}
+
+struct Smf {
+    ticks_per_beat: u32,
+    /// Tempo changes as (tick, microseconds per beat)
+    tempos: Vec<(u32, u32)>,
+    /// Events of every track, in time order
+    tracks: Vec<Vec<(u32, TrackEvent)>>,
+}
+
+/// A note once quantized, `end` is the row its note off falls on
+struct Note {
+    start: usize,
+    end: usize,
+    key: u8,
+    velocity: u8,
+    instrument: u8,
+}
+
+pub fn save<P: AsRef<Path>>(song: &Song, path: P) -> anyhow::Result<()> {
+    fs::write(path.as_ref(), write(song))
+        .with_context(|| format!("Could not export song to {:?}", path.as_ref()))
+}
+
+pub fn load<P: AsRef<Path>>(path: P, rows_per_beat: u32) -> anyhow::Result<Import> {
+    let data = fs::read(path.as_ref())
+        .with_context(|| format!("Could not open MIDI file {:?}", path.as_ref()))?;
+    read(&data, rows_per_beat).with_context(|| format!("Invalid MIDI file {:?}", path.as_ref()))
+}
+
+pub fn write(song: &Song) -> Vec<u8> {
+    let patterns = &song.patterns;
+    let channel_count = patterns.channel_count as usize;
+
+    let mut writer = Writer::new();
+    writer.bytes(HEADER_ID);
+    writer.u32_be(HEADER_LENGTH);
+    writer.u16_be(FORMAT_MULTI_TRACK);
+    writer.u16_be(channel_count as u16 + 1);
+    writer.u16_be(TICKS_PER_BEAT as u16);
+
+    let micros_per_beat =
+        (1_000_000.0 * ROWS_PER_BEAT as f32 / song.tempo.line_per_second()).round();
+    let micros_per_beat = (micros_per_beat as u32).clamp(1, 0xFF_FFFF);
+    let tempo_track = vec![Event {
+        tick: 0,
+        data: [
+            &[META, META_SET_TEMPO, 3][..],
+            &micros_per_beat.to_be_bytes()[1..],
+        ]
+        .concat(),
+    }];
+    write_track(&mut writer, tempo_track, 0);
+
+    let end_tick = patterns.order().len() as u32 * patterns.channel_len as u32 * TICKS_PER_ROW;
+    for channel in 0..channel_count {
+        let events = channel_events(patterns, channel);
+        write_track(&mut writer, events, end_tick);
+    }
+
+    writer.into_bytes()
+}
+
+/// tracky channels are spread over the MIDI channels, the percussion one excluded
+fn midi_channel(channel: usize) -> u8 {
+    let channel = channel % (MIDI_CHANNEL_COUNT - 1);
+    if channel >= PERCUSSION_CHANNEL {
+        channel as u8 + 1
+    } else {
+        channel as u8
+    }
+}
+
+/// Plays the channel the way [`crate::model::channel::Channel`] does: a note keeps playing
+/// until the next one or a cut, and nothing plays without an instrument
+fn channel_events(patterns: &Patterns, channel: usize) -> Vec<Event> {
+    let midi_channel = midi_channel(channel);
+    let channel_len = patterns.channel_len as usize;
+
+    let name = format!("Channel {channel}");
+    let mut events = vec![Event {
+        tick: 0,
+        data: [
+            &[META, META_TRACK_NAME][..],
+            &variable_length_quantity(name.len() as u32),
+            name.as_bytes(),
+        ]
+        .concat(),
+    }];
+    let mut playing_note = None;
+    let mut velocity = MAX_VELOCITY;
+    let mut instrument = None;
+    let mut tick = 0;
+
+    for &pattern_index in patterns.order() {
+        let pattern = &patterns.patterns()[pattern_index];
+        let lines = &pattern.lines[channel * channel_len..(channel + 1) * channel_len];
+        for (row, line) in lines.iter().enumerate() {
+            let note_off = |playing_note: &mut Option<u8>, events: &mut Vec<Event>| {
+                if let Some(note) = playing_note.take() {
+                    events.push(Event {
+                        tick,
+                        data: vec![NOTE_OFF | midi_channel, note, 0],
+                    });
+                }
+            };
+
+            let mut note_on = None;
+            match line.note.value() {
+                None => {}
+                Some(NoteFieldValue::Off) => note_off(&mut playing_note, &mut events),
+                Some(NoteFieldValue::Cut) => {
+                    note_off(&mut playing_note, &mut events);
+                    velocity = MAX_VELOCITY;
+                    instrument = None;
+                }
+                Some(NoteFieldValue::Note(note, octave)) => {
+                    note_off(&mut playing_note, &mut events);
+                    let midi_value = note_to_midi_value(*note, *octave).value();
+                    if midi_value <= MAX_MIDI_NOTE {
+                        note_on = Some(midi_value as u8);
+                    } else {
+                        let location = Location {
+                            pattern: pattern_index,
+                            row,
+                            channel,
+                        };
+                        warn!(
+                            "{location}: note {note}{} is out of MIDI's range, it is dropped",
+                            octave.value()
+                        );
+                    }
+                }
+            }
+            if let Some(percentage) = line.velocity.get_percentage() {
+                velocity = (percentage * MAX_VELOCITY as f32).round() as u8;
+            }
+            if let Some(new_instrument) = line.instrument.get_u8() {
+                if instrument != Some(new_instrument) {
+                    instrument = Some(new_instrument);
+                    events.push(Event {
+                        tick,
+                        data: vec![PROGRAM_CHANGE | midi_channel, new_instrument & 0x7F],
+                    });
+                }
+            }
+            // A note on with a zero velocity would be a note off
+            if let (Some(note), Some(_), 1..) = (note_on, instrument, velocity) {
+                events.push(Event {
+                    tick,
+                    data: vec![NOTE_ON | midi_channel, note, velocity],
+                });
+                playing_note = Some(note);
+            }
+
+            tick += TICKS_PER_ROW;
+        }
+    }
+
+    if let Some(note) = playing_note {
+        events.push(Event {
+            tick,
+            data: vec![NOTE_OFF | midi_channel, note, 0],
+        });
+    }
+    events
+}
+
+fn write_track(writer: &mut Writer, events: Vec<Event>, end_tick: u32) {
+    let mut data = Vec::new();
+    let mut previous_tick = 0;
+    let end_of_track = Event {
+        tick: end_tick.max(events.last().map_or(0, |event| event.tick)),
+        data: vec![META, META_END_OF_TRACK, 0],
+    };
+    for event in events.into_iter().chain([end_of_track]) {
+        data.extend(variable_length_quantity(event.tick - previous_tick));
+        data.extend(event.data);
+        previous_tick = event.tick;
+    }
+
+    writer.bytes(TRACK_ID);
+    writer.u32_be(data.len() as u32);
+    writer.bytes(&data);
+}
+
+/// 7 bits per byte, most significant first, the top bit is set on every byte but the last
+fn variable_length_quantity(value: u32) -> Vec<u8> {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    let mut value = value >> 7;
+    while value > 0 {
+        bytes.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.reverse();
+    bytes
+}
+
+/// Reads a MIDI file, quantizing its events to `rows_per_beat` rows per beat
+pub fn read(data: &[u8], rows_per_beat: u32) -> anyhow::Result<Import> {
+    ensure!(
+        tempo::ROWS_PER_BEAT_RANGE.contains(&rows_per_beat),
+        "Rows per beat must be from {} to {}",
+        tempo::ROWS_PER_BEAT_RANGE.start(),
+        tempo::ROWS_PER_BEAT_RANGE.end()
+    );
+    let mut report = Report::default();
+    let smf = parse(data)?;
+    let song = convert(smf, rows_per_beat, &mut report)?;
+    Ok(Import { song, report })
+}
+
+fn parse(data: &[u8]) -> anyhow::Result<Smf> {
+    let mut reader = Reader::new(data);
+    ensure!(
+        reader.bytes(HEADER_ID.len())? == HEADER_ID,
+        "Not a MIDI file"
+    );
+    let header_length = reader.u32_be()? as usize;
+    ensure!(
+        header_length >= HEADER_LENGTH as usize,
+        "Invalid header length {header_length}"
+    );
+    let mut header = Reader::new(reader.bytes(header_length)?);
+    let format = header.u16_be()?;
+    let _track_count = header.u16_be()?;
+    let division = header.u16_be()?;
+    ensure!(
+        matches!(format, FORMAT_SINGLE_TRACK | FORMAT_MULTI_TRACK),
+        "Unsupported MIDI file format {format} (only 0 and 1 are supported)"
+    );
+    ensure!(
+        division & DIVISION_SMPTE == 0,
+        "SMPTE time division is not supported"
+    );
+    ensure!(division > 0, "Invalid time division 0");
+
+    let mut tempos = Vec::new();
+    let mut tracks = Vec::new();
+    while reader.remaining() > 0 {
+        let id = reader.bytes(4)?;
+        let length = reader.u32_be()? as usize;
+        let chunk = reader
+            .bytes(length)
+            .with_context(|| format!("Track {} is truncated", tracks.len()))?;
+        // Unknown chunks are meant to be skipped
+        if id != TRACK_ID {
+            continue;
+        }
+        let events = parse_track(chunk, &mut tempos)
+            .with_context(|| format!("Could not read track {}", tracks.len()))?;
+        tracks.push(events);
+    }
+    tempos.sort_by_key(|(tick, _)| *tick);
+
+    Ok(Smf {
+        ticks_per_beat: division as u32,
+        tempos,
+        tracks,
+    })
+}
+
+fn parse_track(
+    data: &[u8],
+    tempos: &mut Vec<(u32, u32)>,
+) -> anyhow::Result<Vec<(u32, TrackEvent)>> {
+    let mut reader = Reader::new(data);
+    let mut events = Vec::new();
+    let mut tick = 0u32;
+    let mut running_status = None;
+
+    while reader.remaining() > 0 {
+        tick = tick.saturating_add(read_variable_length_quantity(&mut reader)?);
+        let first = reader.u8()?;
+        let (status, first_data) = if first & 0x80 != 0 {
+            (first, None)
+        } else {
+            let Some(status) = running_status else {
+                bail!("Data byte {first:02X} without a status at tick {tick}");
+            };
+            (status, Some(first))
+        };
+
+        match status {
+            META => {
+                let kind = reader.u8()?;
+                let length = read_variable_length_quantity(&mut reader)? as usize;
+                let data = reader.bytes(length)?;
+                match kind {
+                    META_END_OF_TRACK => break,
+                    META_SET_TEMPO if length == 3 => {
+                        tempos.push((tick, u32::from_be_bytes([0, data[0], data[1], data[2]])))
+                    }
+                    _ => {}
+                }
+            }
+            SYSEX | SYSEX_ESCAPE => {
+                let length = read_variable_length_quantity(&mut reader)? as usize;
+                reader.bytes(length)?;
+            }
+            0x80..=0xEF => {
+                running_status = Some(status);
+                let first_data = match first_data {
+                    Some(data) => data,
+                    None => reader.u8()?,
+                };
+                let channel = status & 0x0F;
+                let event = match status & 0xF0 {
+                    NOTE_OFF => {
+                        let _velocity = reader.u8()?;
+                        Some(TrackEvent::NoteOff {
+                            channel,
+                            key: first_data,
+                        })
+                    }
+                    NOTE_ON => match reader.u8()? {
+                        // A note on with a zero velocity is a note off
+                        0 => Some(TrackEvent::NoteOff {
+                            channel,
+                            key: first_data,
+                        }),
+                        velocity => Some(TrackEvent::NoteOn {
+                            channel,
+                            key: first_data,
+                            velocity,
+                        }),
+                    },
+                    PROGRAM_CHANGE => Some(TrackEvent::ProgramChange {
+                        channel,
+                        program: first_data,
+                    }),
+                    // Channel pressure only has one data byte
+                    0xD0 => None,
+                    _ => {
+                        reader.u8()?;
+                        None
+                    }
+                };
+                events.extend(event.map(|event| (tick, event)));
+            }
+            _ => bail!("Unsupported status {status:02X} at tick {tick}"),
+        }
+    }
+
+    Ok(events)
+}
+
+fn read_variable_length_quantity(reader: &mut Reader) -> anyhow::Result<u32> {
+    let mut value = 0;
+    for _ in 0..4 {
+        let byte = reader.u8()?;
+        value = value << 7 | (byte & 0x7F) as u32;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    bail!("Variable length quantity is longer than 4 bytes")
+}
+
+fn convert(smf: Smf, rows_per_beat: u32, report: &mut Report) -> anyhow::Result<Song> {
+    let Smf {
+        ticks_per_beat,
+        tempos,
+        tracks,
+    } = smf;
+    let row_at = |tick: u32| {
+        ((tick as u64 * rows_per_beat as u64 + ticks_per_beat as u64 / 2) / ticks_per_beat as u64)
+            as usize
+    };
+
+    let micros_per_beat = match tempos.as_slice() {
+        [] => DEFAULT_MICROS_PER_BEAT,
+        [(0, tempo), ..] => *tempo,
+        [(tick, _), ..] => {
+            report.warn(format!(
+                "First tempo change at tick {tick} ignored, the song plays at 120 BPM"
+            ));
+            DEFAULT_MICROS_PER_BEAT
+        }
+    };
+    if tempos.len() > 1 {
+        report.warn(format!(
+            "{} tempo change(s) ignored, tracky songs have a single tempo",
+            tempos.len() - 1
+        ));
+    }
+    let bpm = 60_000_000.0 / micros_per_beat.max(1) as f32;
+    if !tempo::BPM_RANGE.contains(&bpm) {
+        report.warn(format!(
+            "Tempo of {bpm} BPM is out of tracky's range, it is clamped"
+        ));
+    }
+    let tempo = Tempo::new(
+        bpm.clamp(*tempo::BPM_RANGE.start(), *tempo::BPM_RANGE.end()),
+        rows_per_beat,
+        Tempo::default().ticks_per_row,
+    )?;
+
+    // Each track gets its own set of tracky channels
+    let mut channels: Vec<Vec<Note>> = Vec::new();
+    for (track_index, events) in tracks.into_iter().enumerate() {
+        let notes = track_notes(track_index, events, &row_at, report);
+        let first_channel = channels.len();
+        for note in notes {
+            let free_channel = channels[first_channel..]
+                .iter()
+                .position(|channel| channel.last().is_none_or(|last| last.end <= note.start));
+            if let Some(collision) = channels[first_channel..].iter().position(|channel| {
+                channel
+                    .last()
+                    .is_some_and(|last| last.start == note.start && last.key == note.key)
+            }) {
+                report.warn_at(
+                    location(note.start, first_channel + collision),
+                    format!(
+                        "Track {track_index}: notes {} collide once quantized, one is dropped",
+                        note.key
+                    ),
+                );
+                continue;
+            }
+            match free_channel {
+                Some(channel) => channels[first_channel + channel].push(note),
+                None => channels.push(vec![note]),
+            }
+        }
+    }
+
+    let max_row_count = MAX_IMPORTED_PATTERN_COUNT * IMPORTED_PATTERN_LEN;
+    let mut dropped_count = 0;
+    for notes in channels.iter_mut() {
+        let note_count = notes.len();
+        notes.retain(|note| note.start < max_row_count);
+        dropped_count += note_count - notes.len();
+    }
+    if dropped_count > 0 {
+        report.warn(format!(
+            "{dropped_count} note(s) past the first {MAX_IMPORTED_PATTERN_COUNT} patterns are dropped"
+        ));
+    }
+
+    let row_count = channels
+        .iter()
+        .flatten()
+        // Room for the last cut
+        .map(|note| (note.end + 1).min(max_row_count))
+        .max()
+        .unwrap_or(1);
+    let pattern_count = row_count.div_ceil(IMPORTED_PATTERN_LEN);
+    let channel_count = channels.len().max(1);
+    let mut patterns =
+        vec![Pattern::new(channel_count as i32, IMPORTED_PATTERN_LEN as i32); pattern_count];
+    for (channel, notes) in channels.iter().enumerate() {
+        for (index, note) in notes.iter().enumerate() {
+            let line = line_at(&mut patterns, channel, note.start);
+            let midi_value = MidiValue::new_unchecked(note.key as i32);
+            let (note_name, octave) = midi_value_to_note(midi_value);
+            line.note = Field::new(NoteFieldValue::Note(note_name, octave));
+            line.velocity = Field::new(u8_to_hex_digit_pair(import::velocity(
+                note.velocity,
+                MAX_VELOCITY,
+            )));
+            line.instrument = Field::new(u8_to_hex_digit_pair(note.instrument));
+
+            // No need to release a note when the next one starts on its note off
+            let next_start = notes.get(index + 1).map(|next| next.start);
+            if next_start != Some(note.end) && note.end < max_row_count {
+                line_at(&mut patterns, channel, note.end).note = Field::new(NoteFieldValue::Off);
+            }
+        }
+    }
+    let patterns =
+        Patterns::from_patterns(channel_count as i32, IMPORTED_PATTERN_LEN as i32, patterns)?;
+
+    Ok(import::song(patterns, Instruments::default(), tempo))
+}
+
+/// Pairs note ons with their note offs and quantizes them, notes too low for tracky are dropped
+fn track_notes(
+    track_index: usize,
+    events: Vec<(u32, TrackEvent)>,
+    row_at: &impl Fn(u32) -> usize,
+    report: &mut Report,
+) -> Vec<Note> {
+    let mut programs = [0u8; MIDI_CHANNEL_COUNT];
+    // Notes waiting for their note off, per channel and key
+    let mut pending: Vec<VecDeque<(u32, u8, u8)>> = vec![VecDeque::new(); MIDI_CHANNEL_COUNT * 128];
+    let mut notes = Vec::new();
+    let mut last_tick = 0;
+
+    let mut push_note = |start_tick: u32,
+                         end_tick: u32,
+                         key: u8,
+                         velocity: u8,
+                         program: u8,
+                         report: &mut Report| {
+        let start = row_at(start_tick);
+        if (key as i32) < MidiValue::MIN_VALUE {
+            report.warn(format!(
+                "Track {track_index}: note {key} at row {start} is below C-0, it is dropped"
+            ));
+            return;
+        }
+        let instrument = if program < MAX_SLOT_COUNT {
+            program
+        } else {
+            report.warn(format!(
+                "Track {track_index}: program {program} at row {start} does not fit in tracky's {MAX_SLOT_COUNT} instrument slots, instrument 00 is used"
+            ));
+            0
+        };
+        notes.push(Note {
+            start,
+            // Notes shorter than a row still last one row
+            end: row_at(end_tick).max(start + 1),
+            key,
+            velocity,
+            instrument,
+        });
+    };
+
+    for (tick, event) in events {
+        last_tick = tick;
+        match event {
+            TrackEvent::ProgramChange { channel, program } => programs[channel as usize] = program,
+            TrackEvent::NoteOn {
+                channel,
+                key,
+                velocity,
+            } => pending[channel as usize * 128 + key as usize].push_back((
+                tick,
+                velocity,
+                programs[channel as usize],
+            )),
+            TrackEvent::NoteOff { channel, key } => {
+                if let Some((start_tick, velocity, program)) =
+                    pending[channel as usize * 128 + key as usize].pop_front()
+                {
+                    push_note(start_tick, tick, key, velocity, program, report);
+                }
+            }
+        }
+    }
+    // Notes never released last until the end of the track
+    for (index, pending) in pending.into_iter().enumerate() {
+        for (start_tick, velocity, program) in pending {
+            push_note(
+                start_tick,
+                last_tick,
+                (index % 128) as u8,
+                velocity,
+                program,
+                report,
+            );
+        }
+    }
+
+    notes.sort_by_key(|note| (note.start, note.key));
+    notes
+}
+
+fn location(row: usize, channel: usize) -> Location {
+    Location {
+        pattern: row / IMPORTED_PATTERN_LEN,
+        row: row % IMPORTED_PATTERN_LEN,
+        channel,
+    }
+}
+
+fn line_at(patterns: &mut [Pattern], channel: usize, row: usize) -> &mut PatternLine {
+    let location = location(row, channel);
+    &mut patterns[location.pattern].lines[channel * IMPORTED_PATTERN_LEN + location.row]
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        format::{binary::Reader, import},
+        model::{
+            instrument::Instruments,
+            pattern::{Pattern, Patterns},
+        },
+    };
+
+    use super::*;
+
+    fn song(channel_count: i32, lines: &[(usize, usize, usize, &str)]) -> Song {
+        let channel_len = 8;
+        let mut patterns = vec![Pattern::new(channel_count, channel_len); 2];
+        for (pattern, channel, row, line) in lines {
+            patterns[*pattern].lines[channel * channel_len as usize + row] = line.parse().unwrap();
+        }
+        let patterns = Patterns::from_patterns(channel_count, channel_len, patterns).unwrap();
+        import::song(
+            patterns,
+            Instruments::empty(),
+            Tempo::from_line_per_second(8.0, ROWS_PER_BEAT, 6).unwrap(),
+        )
+    }
+
+    /// Tracks as (absolute tick, event bytes), running status is never written
+    fn read_tracks(data: &[u8]) -> Vec<Vec<(u32, Vec<u8>)>> {
+        let mut reader = Reader::new(data);
+        assert_eq!(HEADER_ID, reader.bytes(4).unwrap());
+        let mut header = Reader::new(reader.bytes(10).unwrap());
+        let _length = header.bytes(4).unwrap();
+        assert_eq!(FORMAT_MULTI_TRACK, header.u16_be().unwrap());
+        let track_count = header.u16_be().unwrap();
+        assert_eq!(TICKS_PER_BEAT as u16, header.u16_be().unwrap());
+
+        (0..track_count)
+            .map(|_| {
+                assert_eq!(TRACK_ID, reader.bytes(4).unwrap());
+                let length = u32::from_be_bytes(reader.array().unwrap());
+                let mut track = Reader::new(reader.bytes(length as usize).unwrap());
+                let mut events = Vec::new();
+                let mut tick = 0;
+                while track.remaining() > 0 {
+                    let mut delta = 0;
+                    loop {
+                        let byte = track.u8().unwrap();
+                        delta = delta << 7 | (byte & 0x7F) as u32;
+                        if byte & 0x80 == 0 {
+                            break;
+                        }
+                    }
+                    tick += delta;
+                    let status = track.u8().unwrap();
+                    let length = match status {
+                        META => {
+                            let kind = track.u8().unwrap();
+                            let length = track.u8().unwrap();
+                            let mut event = vec![status, kind, length];
+                            event.extend(track.bytes(length as usize).unwrap());
+                            events.push((tick, event));
+                            continue;
+                        }
+                        0xC0..=0xDF => 1,
+                        _ => 2,
+                    };
+                    let mut event = vec![status];
+                    event.extend(track.bytes(length).unwrap());
+                    events.push((tick, event));
+                }
+                events
+            })
+            .collect()
+    }
+
+    fn channel_messages(track: &[(u32, Vec<u8>)]) -> Vec<(u32, Vec<u8>)> {
+        track
+            .iter()
+            .filter(|(_, event)| event[0] != META)
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn test_variable_length_quantity() {
+        assert_eq!(vec![0x00], variable_length_quantity(0));
+        assert_eq!(vec![0x7F], variable_length_quantity(0x7F));
+        assert_eq!(vec![0x81, 0x00], variable_length_quantity(0x80));
+        assert_eq!(vec![0xC0, 0x00], variable_length_quantity(0x2000));
+        assert_eq!(vec![0xFF, 0xFF, 0x7F], variable_length_quantity(0x1F_FFFF));
+    }
+
+    #[test]
+    fn test_tempo_track() {
+        let tracks = read_tracks(&write(&song(2, &[])));
+        assert_eq!(3, tracks.len());
+        // 8 rows per second, 4 rows per beat: 500 000 µs per beat
+        assert_eq!(
+            vec![
+                (0, vec![META, META_SET_TEMPO, 3, 0x07, 0xA1, 0x20]),
+                (0, vec![META, META_END_OF_TRACK, 0]),
+            ],
+            tracks[0]
+        );
+        // Channel tracks last as long as the song
+        assert_eq!(
+            (2 * 8 * TICKS_PER_ROW, vec![META, META_END_OF_TRACK, 0]),
+            tracks[1].last().unwrap().clone()
+        );
+    }
+
+    #[test]
+    fn test_channels_become_tracks() {
+        let song = song(
+            2,
+            &[
+                (0, 0, 0, "C-5 FF 01 ..."),
+                (0, 0, 2, "E-5 80 .. ..."),
+                (0, 0, 4, "CUT .. .. ..."),
+                (0, 1, 1, "A-4 .. 00 ..."),
+                (1, 1, 0, "B-4 00 .. ..."),
+                (1, 1, 3, "C-5 .. 02 ..."),
+            ],
+        );
+        let tracks = read_tracks(&write(&song));
+
+        assert_eq!(
+            vec![
+                (0, vec![PROGRAM_CHANGE, 1]),
+                (0, vec![NOTE_ON, 72, 127]),
+                (2 * TICKS_PER_ROW, vec![NOTE_OFF, 72, 0]),
+                (2 * TICKS_PER_ROW, vec![NOTE_ON, 76, 64]),
+                (4 * TICKS_PER_ROW, vec![NOTE_OFF, 76, 0]),
+            ],
+            channel_messages(&tracks[1])
+        );
+        // A zero velocity is silent until the next velocity
+        assert_eq!(
+            vec![
+                (TICKS_PER_ROW, vec![PROGRAM_CHANGE | 1, 0]),
+                (TICKS_PER_ROW, vec![NOTE_ON | 1, 69, 127]),
+                (8 * TICKS_PER_ROW, vec![NOTE_OFF | 1, 69, 0]),
+                (11 * TICKS_PER_ROW, vec![PROGRAM_CHANGE | 1, 2]),
+            ],
+            channel_messages(&tracks[2])
+        );
+    }
+
+    #[test]
+    fn test_song_order_is_followed() {
+        let mut song = song(
+            1,
+            &[
+                (0, 0, 0, "C-5 FF 00 ..."),
+                (0, 0, 1, "CUT .. .. ..."),
+                (1, 0, 0, "D-5 FF 00 ..."),
+            ],
+        );
+        song.patterns.set_order(vec![1, 0, 0]).unwrap();
+        let tracks = read_tracks(&write(&song));
+
+        assert_eq!(
+            vec![
+                (0, vec![PROGRAM_CHANGE, 0]),
+                (0, vec![NOTE_ON, 74, 127]),
+                (8 * TICKS_PER_ROW, vec![NOTE_OFF, 74, 0]),
+                (8 * TICKS_PER_ROW, vec![NOTE_ON, 72, 127]),
+                (9 * TICKS_PER_ROW, vec![NOTE_OFF, 72, 0]),
+                (16 * TICKS_PER_ROW, vec![PROGRAM_CHANGE, 0]),
+                (16 * TICKS_PER_ROW, vec![NOTE_ON, 72, 127]),
+                (17 * TICKS_PER_ROW, vec![NOTE_OFF, 72, 0]),
+            ],
+            channel_messages(&tracks[1])
+        );
+        assert_eq!(
+            (3 * 8 * TICKS_PER_ROW, vec![META, META_END_OF_TRACK, 0]),
+            tracks[1].last().unwrap().clone()
+        );
+    }
+
+    #[test]
+    fn test_notes_without_instrument_are_silent() {
+        let song = song(
+            1,
+            &[
+                (0, 0, 0, "C-5 FF .. ..."),
+                (0, 0, 1, "CUT .. .. ..."),
+                (0, 0, 2, "D-5 .. .. ..."),
+            ],
+        );
+        let tracks = read_tracks(&write(&song));
+        assert!(channel_messages(&tracks[1]).is_empty());
+    }
+
+    #[test]
+    fn test_percussion_channel_is_skipped() {
+        assert_eq!(8, midi_channel(8));
+        assert_eq!(10, midi_channel(9));
+        assert_eq!(15, midi_channel(14));
+        assert_eq!(0, midi_channel(15));
+    }
+
+    fn smf_bytes(format: u16, division: u16, tracks: &[&[u8]]) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.bytes(HEADER_ID);
+        writer.u32_be(HEADER_LENGTH);
+        writer.u16_be(format);
+        writer.u16_be(tracks.len() as u16);
+        writer.u16_be(division);
+        for track in tracks {
+            writer.bytes(TRACK_ID);
+            writer.u32_be(track.len() as u32);
+            writer.bytes(track);
+        }
+        writer.into_bytes()
+    }
+
+    fn warnings(report: &Report) -> Vec<String> {
+        report.warnings.iter().map(ToString::to_string).collect()
+    }
+
+    fn imported_line(patterns: &Patterns, channel: usize, row: usize) -> String {
+        patterns.patterns()[0].lines[channel * patterns.channel_len as usize + row].to_string()
+    }
+
+    #[test]
+    fn test_exported_song_is_read_back() {
+        let song = song(
+            2,
+            &[
+                (0, 0, 0, "C-5 FF 01 ..."),
+                (0, 0, 2, "CUT .. .. ..."),
+                (0, 1, 1, "A-4 FF 00 ..."),
+                (1, 1, 4, "E-5 FF 00 ..."),
+            ],
+        );
+        let import = read(&write(&song), ROWS_PER_BEAT).unwrap();
+        let patterns = &import.song.patterns;
+
+        assert_eq!(2, patterns.channel_count);
+        assert_eq!(IMPORTED_PATTERN_LEN as i32, patterns.channel_len);
+        assert_eq!(1, patterns.pattern_count);
+        approx::assert_relative_eq!(8.0, import.song.tempo.line_per_second());
+        let lines = [
+            (0, 0, "C-5 FF 01 ..."),
+            (0, 2, "OFF .. .. ..."),
+            (1, 1, "A-4 FF 00 ..."),
+            (1, 12, "E-5 FF 00 ..."),
+            (1, 16, "OFF .. .. ..."),
+        ];
+        for channel in 0..2 {
+            for row in 0..IMPORTED_PATTERN_LEN {
+                let expected = lines
+                    .iter()
+                    .find(|line| (line.0, line.1) == (channel, row))
+                    .map_or("... .. .. ...", |line| line.2);
+                assert_eq!(
+                    expected,
+                    imported_line(patterns, channel, row),
+                    "channel {channel} row {row}"
+                );
+            }
+        }
+        assert!(import.report.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_polyphony_is_spread_over_channels() {
+        // Running status after the first note on, note offs as zero velocity note ons
+        #[rustfmt::skip]
+        let track: &[u8] = &[
+            0, PROGRAM_CHANGE, 3,
+            0, NOTE_ON, 60, 127,
+            0, 64, 64,
+            0, 67, 32,
+            48, 60, 0,
+            0, 64, 0,
+            0, 67, 0,
+            0, 62, 127,
+            24, NOTE_OFF, 62, 0,
+            0, META, META_END_OF_TRACK, 0,
+        ];
+        let import = read(&smf_bytes(FORMAT_SINGLE_TRACK, 96, &[track]), 4).unwrap();
+        let patterns = &import.song.patterns;
+
+        assert_eq!(3, patterns.channel_count);
+        // The next note reuses the first channel, no cut is needed in between
+        assert_eq!("C-4 FF 03 ...", imported_line(patterns, 0, 0));
+        assert_eq!("D-4 FF 03 ...", imported_line(patterns, 0, 2));
+        assert_eq!("OFF .. .. ...", imported_line(patterns, 0, 3));
+        assert_eq!("E-4 81 03 ...", imported_line(patterns, 1, 0));
+        assert_eq!("OFF .. .. ...", imported_line(patterns, 1, 2));
+        assert_eq!("G-4 40 03 ...", imported_line(patterns, 2, 0));
+        assert_eq!("OFF .. .. ...", imported_line(patterns, 2, 2));
+        // 120 BPM by default
+        approx::assert_relative_eq!(8.0, import.song.tempo.line_per_second());
+        assert!(import.report.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_quantization_issues_are_reported() {
+        #[rustfmt::skip]
+        let tempo_track: &[u8] = &[
+            0, META, META_SET_TEMPO, 3, 0x0F, 0x42, 0x40,
+            96, META, META_SET_TEMPO, 3, 0x07, 0xA1, 0x20,
+            0, META, META_END_OF_TRACK, 0,
+        ];
+        #[rustfmt::skip]
+        let track: &[u8] = &[
+            0, NOTE_ON, 60, 100,
+            5, NOTE_ON, 60, 100,
+            0, NOTE_ON, 5, 100,
+            0, PROGRAM_CHANGE | 1, 80,
+            0, NOTE_ON | 1, 72, 100,
+            // Shorter than a row
+            5, NOTE_OFF, 5, 0,
+            14, NOTE_OFF, 60, 0,
+            24, NOTE_OFF, 60, 0,
+            0, NOTE_OFF | 1, 72, 0,
+        ];
+        let import = read(&smf_bytes(FORMAT_MULTI_TRACK, 96, &[tempo_track, track]), 4).unwrap();
+        let patterns = &import.song.patterns;
+
+        // 60 BPM
+        approx::assert_relative_eq!(4.0, import.song.tempo.line_per_second());
+        assert_eq!(2, patterns.channel_count);
+        assert_eq!("C-4 C9 00 ...", imported_line(patterns, 0, 0));
+        assert_eq!("OFF .. .. ...", imported_line(patterns, 0, 1));
+        assert_eq!("C-5 C9 00 ...", imported_line(patterns, 1, 0));
+        assert_eq!("OFF .. .. ...", imported_line(patterns, 1, 2));
+        assert_eq!(
+            vec![
+                "1 tempo change(s) ignored, tracky songs have a single tempo",
+                "Track 1: note 5 at row 0 is below C-0, it is dropped",
+                "Track 1: program 80 at row 0 does not fit in tracky's 64 instrument slots, instrument 00 is used",
+                "pattern 0 row 000 channel 0: Track 1: notes 60 collide once quantized, one is dropped",
+            ],
+            warnings(&import.report)
+        );
+    }
+
+    #[test]
+    fn test_tempo_out_of_range_is_clamped() {
+        #[rustfmt::skip]
+        let tempo_track: &[u8] = &[
+            // 1 µs per beat
+            0, META, META_SET_TEMPO, 3, 0x00, 0x00, 0x01,
+            0, META, META_END_OF_TRACK, 0,
+        ];
+        let import = read(&smf_bytes(FORMAT_MULTI_TRACK, 96, &[tempo_track]), 4).unwrap();
+
+        approx::assert_relative_eq!(*tempo::BPM_RANGE.end(), import.song.tempo.bpm);
+        assert_eq!(
+            vec!["Tempo of 60000000 BPM is out of tracky's range, it is clamped"],
+            warnings(&import.report)
+        );
+        assert!(read(&smf_bytes(FORMAT_MULTI_TRACK, 96, &[tempo_track]), 65).is_err());
+    }
+
+    #[test]
+    fn test_long_songs_are_cut_in_patterns() {
+        let track: &[u8] = &[60, NOTE_ON, 60, 127, 10, NOTE_OFF, 60, 0];
+        // One tick per row
+        let patterns = read(&smf_bytes(FORMAT_SINGLE_TRACK, 4, &[track]), 4)
+            .unwrap()
+            .song
+            .patterns;
+        assert_eq!(2, patterns.pattern_count);
+        assert_eq!("C-4 FF 00 ...", imported_line(&patterns, 0, 60));
+        assert_eq!(
+            "OFF .. .. ...",
+            patterns.patterns()[1].lines[70 - IMPORTED_PATTERN_LEN].to_string()
+        );
+    }
+
+    #[test]
+    fn test_notes_past_the_pattern_limit_are_dropped() {
+        // One tick per row, the second note is at row 0x0FFF_FFFF
+        #[rustfmt::skip]
+        let track: &[u8] = &[
+            0, NOTE_ON, 60, 127,
+            0x81, 0x80, 0x80, 0x00, NOTE_OFF, 60, 0,
+            0xFF, 0xFF, 0xFF, 0x7F, NOTE_ON, 62, 127,
+            1, NOTE_OFF, 62, 0,
+        ];
+        let import = read(&smf_bytes(FORMAT_SINGLE_TRACK, 4, &[track]), 4).unwrap();
+        let patterns = &import.song.patterns;
+
+        // The first note rings past the last pattern
+        assert_eq!(MAX_IMPORTED_PATTERN_COUNT as i32, patterns.pattern_count);
+        assert_eq!("C-4 FF 00 ...", imported_line(patterns, 0, 0));
+        assert_eq!(
+            vec!["1 note(s) past the first 256 patterns are dropped"],
+            warnings(&import.report)
+        );
+    }
+
+    #[test]
+    fn test_invalid_data_is_an_error() {
+        assert!(read(b"Not a MIDI file", 4).is_err());
+        assert!(read(&smf_bytes(2, 96, &[]), 4).is_err());
+        assert!(read(&smf_bytes(FORMAT_SINGLE_TRACK, 0xE728, &[]), 4).is_err());
+        assert!(read(&smf_bytes(FORMAT_SINGLE_TRACK, 96, &[&[0, 60, 100]]), 4).is_err());
+        assert!(read(&smf_bytes(FORMAT_SINGLE_TRACK, 96, &[]), 0).is_err());
+    }
+}
@@ -0,0 +1,355 @@
+//! Plain-text pattern format, meant to be diffed, pasted around and written by hand.
+//!
+//! ```text
+//! # Lines starting with '#' and blank lines are ignored
+//! channels 2
+//! rows 4
+//!
+//! pattern 0
+//! ROW | CH 0          | CH 1
+//! 000 | C-5 40 00 ... | ... .. .. ...
+//! 001 | ... .. .. 482 | D#4 7F 01 ...
+//! 002 | CUT .. .. ... | ... .. .. A04
+//! 003 | ... .. .. ... | ... .. .. ...
+//! ```
+//!
+//! - `channels` and `rows` must come before the first pattern, there are at most
+//!   256 channels of 1000 rows
+//! - patterns are numbered from 0 and must appear in order
+//! - the `ROW | CH ...` column header is optional
+//! - every row starts with its decimal row number, then one `|` separated column per channel
+//!   using the pattern line syntax (`note velocity instrument effect`, empty fields are dots),
+//!   the effect can be left out
+//! - notes are written `C-5` or `C#5`, `OFF` releases the playing note and `CUT` stops it
+
+use std::{fmt::Write, fs, path::Path};
+
+use anyhow::{anyhow, bail, ensure, Context};
+
+use crate::model::pattern::{Pattern, PatternLine, Patterns};
+
+pub const EXTENSION: &str = "txt";
+
+/// Largest patterns read, row numbers are written with 3 digits
+const MAX_CHANNEL_COUNT: usize = 256;
+const MAX_CHANNEL_LEN: usize = 1000;
+
+const COMMENT_PREFIX: char = '#';
+const COLUMN_SEPARATOR: char = '|';
+const COLUMN_HEADER_PREFIX: &str = "ROW";
+
+pub fn save<P: AsRef<Path>>(patterns: &Patterns, path: P) -> anyhow::Result<()> {
+    fs::write(path.as_ref(), write(patterns))
+        .with_context(|| format!("Could not export patterns to {:?}", path.as_ref()))
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Patterns> {
+    let text = fs::read_to_string(path.as_ref())
+        .with_context(|| format!("Could not open {:?}", path.as_ref()))?;
+    read(&text).with_context(|| format!("Invalid pattern text file {:?}", path.as_ref()))
+}
+
+pub fn write(patterns: &Patterns) -> String {
+    let channel_count = patterns.channel_count as usize;
+    let channel_len = patterns.channel_len as usize;
+    let line_width = PatternLine::default().to_string().len();
+
+    let mut text = String::new();
+    writeln!(text, "{COMMENT_PREFIX} tracky pattern text").unwrap();
+    writeln!(text, "channels {channel_count}").unwrap();
+    writeln!(text, "rows {channel_len}").unwrap();
+
+    for (pattern_index, pattern) in patterns.patterns().iter().enumerate() {
+        writeln!(text).unwrap();
+        writeln!(text, "pattern {pattern_index}").unwrap();
+
+        write!(text, "{COLUMN_HEADER_PREFIX}").unwrap();
+        for channel_index in 0..channel_count {
+            let title = format!("CH {channel_index}");
+            write!(text, " {COLUMN_SEPARATOR} {title:<line_width$}").unwrap();
+        }
+        writeln!(text).unwrap();
+
+        for row in 0..channel_len {
+            write!(text, "{row:03}").unwrap();
+            for channel_index in 0..channel_count {
+                let line = &pattern.lines[channel_index * channel_len + row];
+                write!(text, " {COLUMN_SEPARATOR} {line}").unwrap();
+            }
+            writeln!(text).unwrap();
+        }
+    }
+
+    // Column headers are padded to the line width, don't leave trailing spaces behind
+    text.lines()
+        .map(str::trim_end)
+        .fold(String::new(), |mut acc, line| {
+            acc.push_str(line);
+            acc.push('\n');
+            acc
+        })
+}
+
+struct PatternBuilder {
+    channel_count: usize,
+    channel_len: usize,
+    pattern: Pattern,
+    next_row: usize,
+}
+
+impl PatternBuilder {
+    fn new(channel_count: usize, channel_len: usize) -> PatternBuilder {
+        PatternBuilder {
+            channel_count,
+            channel_len,
+            pattern: Pattern::new(channel_count as i32, channel_len as i32),
+            next_row: 0,
+        }
+    }
+
+    fn push_row(&mut self, row: &str) -> anyhow::Result<()> {
+        let mut columns = row.split(COLUMN_SEPARATOR).map(str::trim);
+        let row_number = columns.next().unwrap_or_default();
+        let row_number = row_number
+            .parse::<usize>()
+            .map_err(|_| anyhow!("Invalid row number {row_number:?}"))?;
+        ensure!(
+            row_number == self.next_row,
+            "Expected row {} but found row {row_number}",
+            self.next_row
+        );
+        ensure!(
+            row_number < self.channel_len,
+            "Row {row_number} is out of bounds, patterns have {} row(s)",
+            self.channel_len
+        );
+
+        let columns = columns.collect::<Vec<_>>();
+        ensure!(
+            columns.len() == self.channel_count,
+            "Expected {} channel(s) but found {}",
+            self.channel_count,
+            columns.len()
+        );
+
+        for (channel_index, column) in columns.into_iter().enumerate() {
+            let line = column
+                .parse::<PatternLine>()
+                .with_context(|| format!("Invalid line in channel {channel_index}"))?;
+            self.pattern.lines[channel_index * self.channel_len + row_number] = line;
+        }
+
+        self.next_row += 1;
+        Ok(())
+    }
+
+    fn build(self, pattern_index: usize) -> anyhow::Result<Pattern> {
+        ensure!(
+            self.next_row == self.channel_len,
+            "Pattern {pattern_index} has {} row(s) but {} were expected",
+            self.next_row,
+            self.channel_len
+        );
+        Ok(self.pattern)
+    }
+}
+
+pub fn read(text: &str) -> anyhow::Result<Patterns> {
+    let mut channel_count = None;
+    let mut channel_len = None;
+    let mut patterns = Vec::new();
+    let mut current: Option<PatternBuilder> = None;
+
+    for (line_index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(COMMENT_PREFIX) {
+            continue;
+        }
+
+        (|| -> anyhow::Result<()> {
+            let (keyword, value) = line.split_once(' ').unwrap_or((line, ""));
+            match keyword {
+                "channels" | "rows" => {
+                    ensure!(
+                        current.is_none() && patterns.is_empty(),
+                        "{keyword:?} must be set before the first pattern"
+                    );
+                    let max = if keyword == "channels" {
+                        MAX_CHANNEL_COUNT
+                    } else {
+                        MAX_CHANNEL_LEN
+                    };
+                    let value = value
+                        .trim()
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|value| (1..=max).contains(value))
+                        .ok_or_else(|| {
+                            anyhow!("Invalid {keyword} count {value:?}, expected 1 to {max}")
+                        })?;
+                    if keyword == "channels" {
+                        channel_count = Some(value);
+                    } else {
+                        channel_len = Some(value);
+                    }
+                }
+                "pattern" => {
+                    let (Some(channel_count), Some(channel_len)) = (channel_count, channel_len)
+                    else {
+                        bail!("\"channels\" and \"rows\" must be set before the first pattern");
+                    };
+                    ensure!(
+                        channel_count.checked_mul(channel_len).is_some(),
+                        "Patterns of {channel_count} channel(s) of {channel_len} row(s) are too large"
+                    );
+                    if let Some(builder) = current.take() {
+                        patterns.push(builder.build(patterns.len())?);
+                    }
+                    let pattern_index = value.trim();
+                    ensure!(
+                        pattern_index.parse::<usize>().ok() == Some(patterns.len()),
+                        "Expected pattern {} but found pattern {pattern_index:?}",
+                        patterns.len()
+                    );
+                    current = Some(PatternBuilder::new(channel_count, channel_len));
+                }
+                _ if keyword == COLUMN_HEADER_PREFIX => {
+                    ensure!(current.is_some(), "Column header outside of a pattern");
+                }
+                _ => match &mut current {
+                    Some(builder) => builder.push_row(line)?,
+                    None => bail!("Unexpected {line:?} outside of a pattern"),
+                },
+            }
+            Ok(())
+        })()
+        .with_context(|| format!("Line {}", line_index + 1))?;
+    }
+
+    if let Some(builder) = current.take() {
+        patterns.push(builder.build(patterns.len())?);
+    }
+
+    let (Some(channel_count), Some(channel_len)) = (channel_count, channel_len) else {
+        bail!("Missing \"channels\" or \"rows\" header");
+    };
+
+    Patterns::from_patterns(channel_count as i32, channel_len as i32, patterns)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::model::pattern::{Field, HexDigit, NoteFieldValue, NoteName, OctaveValue};
+
+    use super::*;
+
+    const EXAMPLE: &str = "\
+# tracky pattern text
+channels 2
+rows 4
+
+pattern 0
+ROW | CH 0          | CH 1
+000 | C-5 40 00 ... | ... .. .. ...
+001 | ... .. .. 482 | D#4 7F 01 ...
+002 | CUT .. .. ... | ... .. .. A04
+003 | ... .. .. ... | ... .. .. ...
+";
+
+    #[test]
+    fn test_example_is_read() {
+        let patterns = read(EXAMPLE).unwrap();
+        assert_eq!(2, patterns.channel_count);
+        assert_eq!(4, patterns.channel_len);
+        assert_eq!(1, patterns.pattern_count);
+
+        let lines = &patterns.patterns()[0].lines;
+        assert_eq!(
+            PatternLine {
+                note: Field::new(NoteFieldValue::Note(NoteName::C, OctaveValue::OCTAVE_5)),
+                velocity: Field::new((HexDigit::HEX_4, HexDigit::HEX_0)),
+                instrument: Field::new((HexDigit::HEX_0, HexDigit::HEX_0)),
+                effect: Field::empty(),
+            },
+            lines[0]
+        );
+        assert_eq!("... .. .. 482", lines[1].to_string());
+        assert_eq!(Field::new(NoteFieldValue::Cut), lines[2].note);
+        assert_eq!(
+            PatternLine {
+                note: Field::new(NoteFieldValue::Note(
+                    NoteName::DSharp,
+                    OctaveValue::OCTAVE_4
+                )),
+                velocity: Field::new((HexDigit::HEX_7, HexDigit::HEX_F)),
+                instrument: Field::new((HexDigit::HEX_0, HexDigit::HEX_1)),
+                effect: Field::empty(),
+            },
+            lines[4 + 1]
+        );
+    }
+
+    #[test]
+    fn test_written_text_is_stable() {
+        assert_eq!(EXAMPLE, write(&read(EXAMPLE).unwrap()));
+    }
+
+    #[test]
+    fn test_lines_without_effect_are_read() {
+        let text = "channels 1\nrows 2\npattern 0\n000 | C-5 40 00\n001 | ... .. ..\n";
+        let patterns = read(text).unwrap();
+        assert_eq!("C-5 40 00 ...", patterns.patterns()[0].lines[0].to_string());
+    }
+
+    #[test]
+    fn test_multiple_patterns_round_trip() {
+        let text = EXAMPLE.to_string() + "\npattern 1\n000 | ... .. .. | C-1 .. ..\n001 | ... .. .. | ... .. ..\n002 | ... .. .. | ... .. ..\n003 | B-9 FF FF | ... .. ..\n";
+        let patterns = read(&text).unwrap();
+        assert_eq!(2, patterns.pattern_count);
+        assert_eq!(write(&patterns), write(&read(&write(&patterns)).unwrap()));
+    }
+
+    #[test]
+    fn test_errors_report_the_line_number() {
+        let text = EXAMPLE.replace("001 | ... .. .. 482 | D#4", "001 | ... .. .. 482 | H#4");
+        let err = format!("{:?}", read(&text).unwrap_err());
+        assert!(err.contains("Line 8"), "{err}");
+        assert!(err.contains("channel 1"), "{err}");
+    }
+
+    #[test]
+    fn test_wrong_channel_count_is_an_error() {
+        let text = EXAMPLE.replace("003 | ... .. .. ... | ... .. .. ...", "003 | ... .. .. ...");
+        assert!(read(&text).is_err());
+    }
+
+    #[test]
+    fn test_missing_row_is_an_error() {
+        let text = EXAMPLE.replace("003 | ... .. .. ... | ... .. .. ...\n", "");
+        assert!(read(&text).is_err());
+    }
+
+    #[test]
+    fn test_rows_out_of_order_are_an_error() {
+        let text = EXAMPLE.replace("002 |", "005 |");
+        assert!(read(&text).is_err());
+    }
+
+    #[test]
+    fn test_missing_header_is_an_error() {
+        assert!(read(&EXAMPLE.replace("channels 2\n", "")).is_err());
+    }
+
+    #[test]
+    fn test_oversized_header_is_an_error() {
+        for header in [
+            "channels 257\n",
+            "channels 18446744073709551615\n",
+            "channels 4294967296\n",
+        ] {
+            let text = EXAMPLE.replace("channels 2\n", header);
+            assert!(read(&text).is_err(), "{header:?}");
+        }
+        assert!(read(&EXAMPLE.replace("rows 4\n", "rows 1001\n")).is_err());
+    }
+}
@@ -0,0 +1,433 @@
+//! WAV (`.wav`) writer for rendered signals, and reader for sampled instruments.
+//!
+//! Little endian layout:
+//! ```text
+//! header            "RIFF", length: u32, "WAVE"
+//! format chunk      "fmt ", length: u32 (16), format tag: u16 (1 = PCM, 3 = float),
+//!                   channel count: u16, frame rate: u32, byte rate: u32, block align: u16,
+//!                   bits per sample: u16
+//! data chunk        "data", length: u32, interleaved samples
+//! sampler chunk     "smpl", length: u32, 7 * u32 of sampler settings, loop count: u32,
+//!                   sampler data length: u32, then per loop: cue point: u32,
+//!                   type: u32 (0 = forward, 1 = ping-pong), start: u32, end: u32 (played),
+//!                   fraction: u32, play count: u32
+//! ```
+//!
+//! Integer samples are clipped to [-1, 1] before being quantized, float samples are written
+//! untouched. Samples are decoded by audrey, only their loops are read here. A sampler chunk with
+//! two loops holds the sustain loop first.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{bail, ensure, Context};
+use log::warn;
+
+use crate::{
+    audio::{dsp::interpolation::InterpolationMode, render::Stems, signal},
+    model::{
+        instrument::Kind,
+        sample_loop::{LoopMode, SampleLoop, SampleLoops},
+    },
+};
+
+use super::binary::{Reader, Writer};
+
+pub const EXTENSION: &str = "wav";
+
+const FORMAT_TAG_PCM: u16 = 1;
+const FORMAT_TAG_FLOAT: u16 = 3;
+
+/// The loop count follows the sampler settings
+const SMPL_LOOP_COUNT_OFFSET: usize = 7 * 4;
+const SMPL_LOOP_FORWARD: u32 = 0;
+const SMPL_LOOP_PING_PONG: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleFormat {
+    Int16,
+    Int24,
+    Float32,
+}
+
+impl SampleFormat {
+    fn bits_per_sample(self) -> u16 {
+        match self {
+            SampleFormat::Int16 => 16,
+            SampleFormat::Int24 => 24,
+            SampleFormat::Float32 => 32,
+        }
+    }
+
+    fn format_tag(self) -> u16 {
+        match self {
+            SampleFormat::Int16 | SampleFormat::Int24 => FORMAT_TAG_PCM,
+            SampleFormat::Float32 => FORMAT_TAG_FLOAT,
+        }
+    }
+
+    fn write_sample(self, writer: &mut Writer, sample: f32) {
+        match self {
+            SampleFormat::Int16 => {
+                let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+                writer.bytes(&value.to_le_bytes());
+            }
+            SampleFormat::Int24 => {
+                const I24_MAX: f32 = ((1 << 23) - 1) as f32;
+                let value = (sample.clamp(-1.0, 1.0) * I24_MAX).round() as i32;
+                writer.bytes(&value.to_le_bytes()[..3]);
+            }
+            SampleFormat::Float32 => writer.f32_le(sample),
+        }
+    }
+}
+
+impl FromStr for SampleFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "16" => Ok(SampleFormat::Int16),
+            "24" => Ok(SampleFormat::Int24),
+            "float" | "32" => Ok(SampleFormat::Float32),
+            _ => bail!("Unknown WAV sample format {s:?}, expected 16, 24 or float"),
+        }
+    }
+}
+
+pub fn save<P: AsRef<Path>>(
+    signal: signal::stereo::Ref,
+    sample_format: SampleFormat,
+    path: P,
+) -> anyhow::Result<()> {
+    let data = write(signal, sample_format)?;
+    fs::write(path.as_ref(), data).with_context(|| format!("{:?}", path.as_ref()))
+}
+
+/// Saves every stem next to `path`, suffixing its file name with the stem name:
+/// `song.wav` gives `song_master.wav`, `song_channel_00.wav`, `song_channel_01.wav`...
+pub fn save_stems<P: AsRef<Path>>(
+    stems: &Stems,
+    sample_format: SampleFormat,
+    path: P,
+) -> anyhow::Result<()> {
+    save(
+        stems.master.as_ref(),
+        sample_format,
+        stem_path(path.as_ref(), "master"),
+    )?;
+    for (index, channel) in stems.channels.iter().enumerate() {
+        save(
+            channel.as_ref(),
+            sample_format,
+            stem_path(path.as_ref(), &format!("channel_{index:02}")),
+        )?;
+    }
+    Ok(())
+}
+
+fn stem_path(path: &Path, name: &str) -> PathBuf {
+    let file_stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!("{file_stem}_{name}.{EXTENSION}"))
+}
+
+/// Sampled instrument source, looped as told by the sampler chunk
+pub fn load_sample<P: AsRef<Path>>(name: String, path: P) -> anyhow::Result<Kind> {
+    let path = path.as_ref();
+    let signal = signal::stereo::Owned::from_path(path)?;
+    let data = fs::read(path).with_context(|| format!("{path:?}"))?;
+    let loops = read_loops(&data, signal.len())
+        .with_context(|| format!("Invalid sample loops in {path:?}"))?;
+    Ok(Kind::Sample {
+        name,
+        signal,
+        loops,
+        interpolation: InterpolationMode::default(),
+    })
+}
+
+fn read_loops(data: &[u8], frame_count: usize) -> anyhow::Result<SampleLoops> {
+    let mut reader = Reader::new(data);
+    ensure!(&reader.array::<4>()? == b"RIFF", "Not a RIFF file");
+    reader.u32_le()?;
+    ensure!(&reader.array::<4>()? == b"WAVE", "Not a WAV file");
+    while reader.remaining() >= 8 {
+        let id = reader.array::<4>()?;
+        let len = reader.u32_le()? as usize;
+        let chunk = reader
+            .bytes(len)
+            .with_context(|| format!("Chunk {:?} is truncated", String::from_utf8_lossy(&id)))?;
+        if &id == b"smpl" {
+            return read_sampler_chunk(chunk, frame_count);
+        }
+        // Chunks are padded to an even length
+        if len % 2 == 1 && reader.remaining() > 0 {
+            reader.u8()?;
+        }
+    }
+    Ok(SampleLoops::default())
+}
+
+fn read_sampler_chunk(chunk: &[u8], frame_count: usize) -> anyhow::Result<SampleLoops> {
+    let mut reader = Reader::new(chunk);
+    reader.seek(SMPL_LOOP_COUNT_OFFSET)?;
+    let loop_count = reader.u32_le()?;
+    let _sampler_data_len = reader.u32_le()?;
+    let mut sample_loops = Vec::new();
+    for index in 0..loop_count {
+        let _cue_point = reader.u32_le()?;
+        let loop_type = reader.u32_le()?;
+        let start = reader.u32_le()? as usize;
+        let end = reader.u32_le()? as usize;
+        let _fraction = reader.u32_le()?;
+        let _play_count = reader.u32_le()?;
+        let mode = match loop_type {
+            SMPL_LOOP_FORWARD => LoopMode::Forward,
+            SMPL_LOOP_PING_PONG => LoopMode::PingPong,
+            _ => {
+                warn!("Loop {index} of type {loop_type} ignored, only forward and ping-pong loops are supported");
+                continue;
+            }
+        };
+        sample_loops.push(SampleLoop::new(mode, start, end + 1)?);
+    }
+
+    let loops = match sample_loops[..] {
+        [] => SampleLoops::default(),
+        [normal] => SampleLoops {
+            normal: Some(normal),
+            sustain: None,
+        },
+        [sustain, normal, ..] => SampleLoops {
+            normal: Some(normal),
+            sustain: Some(sustain),
+        },
+    };
+    loops.ensure_fits(frame_count)?;
+    Ok(loops)
+}
+
+pub fn write(signal: signal::stereo::Ref, sample_format: SampleFormat) -> anyhow::Result<Vec<u8>> {
+    const CHANNEL_COUNT: u16 = 2;
+
+    ensure!(
+        signal.frame_rate.is_normal() && signal.frame_rate > 0.0,
+        "Invalid frame rate {}",
+        signal.frame_rate
+    );
+
+    let bytes_per_sample = sample_format.bits_per_sample() / 8;
+    let block_align = CHANNEL_COUNT * bytes_per_sample;
+    let frame_rate = signal.frame_rate.round() as u32;
+    let data_len = signal.frame_count() as u64 * block_align as u64;
+    // The RIFF length covers "WAVE", the format chunk and the data chunk
+    let riff_len = 4 + (8 + 16) + 8 + data_len;
+    ensure!(
+        riff_len <= u32::MAX as u64,
+        "Signal too long to fit in a WAV file"
+    );
+
+    let mut writer = Writer::new();
+    writer.bytes(b"RIFF");
+    writer.u32_le(riff_len as u32);
+    writer.bytes(b"WAVE");
+
+    writer.bytes(b"fmt ");
+    writer.u32_le(16);
+    writer.u16_le(sample_format.format_tag());
+    writer.u16_le(CHANNEL_COUNT);
+    writer.u32_le(frame_rate);
+    writer.u32_le(frame_rate * block_align as u32);
+    writer.u16_le(block_align);
+    writer.u16_le(sample_format.bits_per_sample());
+
+    writer.bytes(b"data");
+    writer.u32_le(data_len as u32);
+    for sample in signal.samples() {
+        sample_format.write_sample(&mut writer, sample);
+    }
+
+    Ok(writer.into_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    use joy_vector::vector;
+
+    use crate::{
+        audio::{load_samples_from_file, signal::stereo},
+        format::binary::Reader,
+    };
+
+    use super::*;
+
+    fn signal() -> stereo::Owned {
+        stereo::Owned::from_frames(
+            vec![
+                vector!(0.0, 0.0),
+                vector!(0.5, -0.5),
+                vector!(1.0, -1.0),
+                vector!(2.0, -0.25),
+            ],
+            48000.0,
+        )
+    }
+
+    fn assert_read_back_clipped(sample_format: SampleFormat) {
+        let path = std::env::temp_dir().join(format!(
+            "tracky_wav_{sample_format:?}_{}.wav",
+            std::process::id()
+        ));
+        save(signal().as_ref(), sample_format, &path).unwrap();
+        let audio_data = load_samples_from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(2, audio_data.channel_count);
+        approx::assert_relative_eq!(48000.0, audio_data.frame_rate);
+        let expected = [0.0, 0.0, 0.5, -0.5, 1.0, -1.0, 1.0, -0.25];
+        assert_eq!(expected.len(), audio_data.samples.len());
+        for (expected, actual) in expected.iter().zip(&audio_data.samples) {
+            approx::assert_relative_eq!(*expected, *actual, epsilon = 0.001);
+        }
+    }
+
+    /// WAV file of `signal` with a sampler chunk holding `(type, start, end)` loops
+    fn with_sampler_chunk(loops: &[(u32, u32, u32)]) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.bytes(&write(signal().as_ref(), SampleFormat::Int16).unwrap());
+        writer.bytes(b"smpl");
+        writer.u32_le(9 * 4 + loops.len() as u32 * 6 * 4);
+        writer.bytes(&[0; SMPL_LOOP_COUNT_OFFSET]);
+        writer.u32_le(loops.len() as u32);
+        writer.u32_le(0);
+        for (index, (loop_type, start, end)) in loops.iter().enumerate() {
+            writer.u32_le(index as u32);
+            writer.u32_le(*loop_type);
+            writer.u32_le(*start);
+            writer.u32_le(*end);
+            writer.u32_le(0);
+            writer.u32_le(0);
+        }
+        writer.into_bytes()
+    }
+
+    #[test]
+    fn test_header() {
+        let data = write(signal().as_ref(), SampleFormat::Int24).unwrap();
+        let mut reader = Reader::new(&data);
+
+        assert_eq!(b"RIFF", &reader.array::<4>().unwrap());
+        assert_eq!(data.len() as u32 - 8, reader.u32_le().unwrap());
+        assert_eq!(b"WAVE", &reader.array::<4>().unwrap());
+        assert_eq!(b"fmt ", &reader.array::<4>().unwrap());
+        assert_eq!(16, reader.u32_le().unwrap());
+        assert_eq!(FORMAT_TAG_PCM, reader.u16_le().unwrap());
+        assert_eq!(2, reader.u16_le().unwrap());
+        assert_eq!(48000, reader.u32_le().unwrap());
+        assert_eq!(48000 * 6, reader.u32_le().unwrap());
+        assert_eq!(6, reader.u16_le().unwrap());
+        assert_eq!(24, reader.u16_le().unwrap());
+        assert_eq!(b"data", &reader.array::<4>().unwrap());
+        assert_eq!(4 * 6, reader.u32_le().unwrap());
+        assert_eq!(4 * 6, reader.remaining());
+    }
+
+    #[test]
+    fn test_int16_is_read_back_clipped() {
+        assert_read_back_clipped(SampleFormat::Int16);
+    }
+
+    #[test]
+    fn test_int24_is_read_back_clipped() {
+        assert_read_back_clipped(SampleFormat::Int24);
+    }
+
+    #[test]
+    fn test_sample_format_from_str() {
+        assert_eq!(SampleFormat::Int16, "16".parse().unwrap());
+        assert_eq!(SampleFormat::Int24, " 24".parse().unwrap());
+        assert_eq!(SampleFormat::Float32, "Float".parse().unwrap());
+        assert!("8".parse::<SampleFormat>().is_err());
+    }
+
+    #[test]
+    fn test_stem_path() {
+        assert_eq!(
+            PathBuf::from("songs/demo_channel_03.wav"),
+            stem_path(Path::new("songs/demo.wav"), "channel_03")
+        );
+        assert_eq!(
+            PathBuf::from("demo_master.wav"),
+            stem_path(Path::new("demo"), "master")
+        );
+    }
+
+    #[test]
+    fn test_float32_is_written_untouched() {
+        let data = write(signal().as_ref(), SampleFormat::Float32).unwrap();
+        let mut reader = Reader::new(&data);
+        reader.seek(20).unwrap();
+        assert_eq!(FORMAT_TAG_FLOAT, reader.u16_le().unwrap());
+        reader.seek(44).unwrap();
+        let samples = (0..8).map(|_| reader.f32_le().unwrap()).collect::<Vec<_>>();
+        assert_eq!(vec![0.0, 0.0, 0.5, -0.5, 1.0, -1.0, 2.0, -0.25], samples);
+    }
+
+    #[test]
+    fn test_sampler_chunk_loops() {
+        let data = with_sampler_chunk(&[(SMPL_LOOP_PING_PONG, 1, 2), (SMPL_LOOP_FORWARD, 0, 3)]);
+        assert_eq!(
+            SampleLoops {
+                normal: Some(SampleLoop::new(LoopMode::Forward, 0, 4).unwrap()),
+                sustain: Some(SampleLoop::new(LoopMode::PingPong, 1, 3).unwrap()),
+            },
+            read_loops(&data, 4).unwrap()
+        );
+
+        let data = with_sampler_chunk(&[(SMPL_LOOP_FORWARD, 2, 3)]);
+        assert_eq!(
+            "forward 2 4; none",
+            read_loops(&data, 4).unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_unsupported_loops_are_ignored() {
+        let data = with_sampler_chunk(&[(2, 0, 3)]);
+        assert_eq!(SampleLoops::default(), read_loops(&data, 4).unwrap());
+    }
+
+    #[test]
+    fn test_loops_past_the_end_are_errors() {
+        let data = with_sampler_chunk(&[(SMPL_LOOP_FORWARD, 0, 4)]);
+        assert!(read_loops(&data, 4).is_err());
+    }
+
+    #[test]
+    fn test_sample_without_sampler_chunk_does_not_loop() {
+        let path =
+            std::env::temp_dir().join(format!("tracky_wav_loops_{}.wav", std::process::id()));
+        save(signal().as_ref(), SampleFormat::Int16, &path).unwrap();
+        let kind = load_sample("Signal".into(), &path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let Kind::Sample {
+            name,
+            signal,
+            loops,
+            ..
+        } = kind
+        else {
+            panic!("Expected a sample");
+        };
+        assert_eq!("Signal", name);
+        assert_eq!(4, signal.len());
+        assert_eq!(SampleLoops::default(), loops);
+    }
+}
@@ -0,0 +1,1448 @@
+//! FastTracker 2 `.xm` importer and exporter.
+//!
+//! Little endian layout:
+//! ```text
+//! id                "Extended Module: "
+//! name              20 bytes, then 0x1A
+//! tracker name      20 bytes
+//! version           u16
+//! header size       u32, counted from its own offset
+//! song              length: u16, restart: u16, channel_count: u16, pattern_count: u16,
+//!                   instrument_count: u16, flags: u16, speed: u16, tempo: u16, order: 256 * u8
+//! patterns          (header size: u32, packing: u8, row_count: u16, data size: u16, packed data)
+//! instruments       (header size: u32, name: 22 bytes, type: u8, sample_count: u16,
+//!                   when sample_count > 0: sample header size: u32, keymap: 96 * u8,
+//!                   envelopes, vibrato, fadeout..., then the sample headers and the
+//!                   delta encoded data of every sample)
+//! ```
+//!
+//! XM's C-4 plays a sample at its native rate, it is mapped to tracky's C-5 like ProTracker's
+//! C-2 is.
+//!
+//! Exported songs keep their order. Oscillators are rendered as looped single cycle samples, the
+//! noises too so they repeat at the note's pitch, velocities go to the volume column and the
+//! effects tracky runs to the effect column. Sample sustain loops, which XM doesn't have, and
+//! instrument envelopes are dropped.
+
+use std::{fs, iter, path::Path};
+
+use anyhow::{ensure, Context};
+use joy_vector::Vector;
+use log::warn;
+
+use crate::{
+    audio::{Pan, Volume},
+    model::{
+        envelope::Envelope,
+        instrument::{Instrument, Instruments, Kind},
+        midi::{midi_value_to_note, note_to_midi_value, MidiValue, C5_FREQ},
+        pattern::{u8_to_hex_digit_pair, Field, NoteFieldValue, PatternLine},
+        sample_loop::{LoopMode, SampleLoop, SampleLoops},
+        tempo::Tempo,
+        Song,
+    },
+};
+
+use super::{
+    binary::{Reader, Writer},
+    import::{self, Import, Location, Report},
+};
+
+pub const EXTENSION: &str = "xm";
+
+const ID: &[u8; 17] = b"Extended Module: ";
+const TRACKER_NAME: &str = "tracky";
+const VERSION: u16 = 0x0104;
+const HEADER_SIZE_OFFSET: usize = 60;
+/// Header size, order table included
+const HEADER_SIZE: u32 = 276;
+const PATTERN_HEADER_SIZE: u32 = 9;
+const INSTRUMENT_HEADER_SIZE: u32 = 263;
+const EMPTY_INSTRUMENT_HEADER_SIZE: u32 = 29;
+const SAMPLE_HEADER_SIZE: u32 = 40;
+const FLAG_LINEAR_FREQUENCIES: u16 = 0b1;
+const MAX_CHANNEL_COUNT: usize = 32;
+const MAX_ROW_COUNT: usize = 256;
+const MAX_PATTERN_COUNT: usize = 256;
+const KEYMAP_LEN: usize = 96;
+
+const MAX_VOLUME: u8 = 64;
+
+/// Frame rate of a sample played at C-4 without relative note nor finetune
+const C4_FRAME_RATE: f32 = 8363.0;
+/// XM notes start at C-0 (1), tracky's C-1
+const C0_MIDI_VALUE: i32 = 24;
+const NOTE_KEY_OFF: u8 = 97;
+
+const VOLUME_COLUMN_SET_VOLUME: std::ops::RangeInclusive<u8> = 0x10..=0x50;
+const EFFECT_SET_VOLUME: u8 = 0xC;
+const EFFECT_SET_SPEED: u8 = 0xF;
+
+const ENVELOPE_ENABLED: u8 = 0b1;
+const SAMPLE_LOOP_MASK: u8 = 0b11;
+const SAMPLE_FORWARD_LOOP: u8 = 0b1;
+const SAMPLE_PING_PONG_LOOP: u8 = 0b10;
+const SAMPLE_16_BIT: u8 = 0b1_0000;
+const PANNING_CENTER: u8 = 0x80;
+
+/// Frames in the single cycle an oscillator is rendered to
+const CYCLE_FRAME_COUNT: usize = 64;
+
+#[derive(Clone, Copy, Default)]
+struct Cell {
+    note: u8,
+    instrument: u8,
+    volume: u8,
+    effect: u8,
+    param: u8,
+}
+
+struct Sample {
+    name: String,
+    volume: u8,
+    finetune: i8,
+    loop_type: u8,
+    loop_start: usize,
+    loop_length: usize,
+    panning: u8,
+    relative_note: i8,
+    data: Vec<f32>,
+}
+
+struct XmInstrument {
+    name: String,
+    keymap: Vec<u8>,
+    volume_envelope: bool,
+    panning_envelope: bool,
+    samples: Vec<Sample>,
+}
+
+struct Module {
+    channel_count: usize,
+    speed: u8,
+    tempo: u8,
+    order: Vec<u8>,
+    /// Row major cells of every pattern, patterns can have different row counts
+    patterns: Vec<Vec<Cell>>,
+    instruments: Vec<XmInstrument>,
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Import> {
+    let data = fs::read(path.as_ref())
+        .with_context(|| format!("Could not open module {:?}", path.as_ref()))?;
+    read(&data).with_context(|| format!("Invalid module {:?}", path.as_ref()))
+}
+
+pub fn read(data: &[u8]) -> anyhow::Result<Import> {
+    let mut report = Report::default();
+    let module = parse(data)?;
+    let song = convert(module, &mut report)?;
+    Ok(Import { song, report })
+}
+
+fn parse(data: &[u8]) -> anyhow::Result<Module> {
+    let mut reader = Reader::new(data);
+    ensure!(reader.bytes(ID.len())? == ID, "Not an XM module");
+    let _name = reader.fixed_string(20)?;
+    reader.u8()?;
+    let _tracker_name = reader.fixed_string(20)?;
+    let version = reader.u16_le()?;
+    ensure!(
+        version >= 0x0104,
+        "Unsupported XM version {version:04X} (only 0104 and later are supported)"
+    );
+
+    let header_size = reader.u32_le()? as usize;
+    let song_length = reader.u16_le()? as usize;
+    let _restart_position = reader.u16_le()?;
+    let channel_count = reader.u16_le()? as usize;
+    let pattern_count = reader.u16_le()? as usize;
+    let instrument_count = reader.u16_le()? as usize;
+    let _flags = reader.u16_le()?;
+    let speed = reader.u16_le()?;
+    let tempo = reader.u16_le()?;
+    let order_table = reader.array::<256>()?;
+
+    ensure!(
+        (1..=MAX_CHANNEL_COUNT).contains(&channel_count),
+        "Invalid channel count {channel_count}"
+    );
+    ensure!(
+        (1..=order_table.len()).contains(&song_length),
+        "Invalid song length {song_length}"
+    );
+    ensure!(
+        pattern_count <= MAX_PATTERN_COUNT,
+        "Invalid pattern count {pattern_count}"
+    );
+    let order = order_table[..song_length].to_vec();
+    ensure!(
+        order
+            .iter()
+            .all(|pattern| (*pattern as usize) < pattern_count),
+        "Song order {order:?} references missing patterns (pattern count is {pattern_count})"
+    );
+
+    reader.seek(HEADER_SIZE_OFFSET + header_size)?;
+
+    let patterns = (0..pattern_count)
+        .map(|pattern_index| {
+            parse_pattern(&mut reader, channel_count)
+                .with_context(|| format!("Could not read pattern {pattern_index}"))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let instruments = (0..instrument_count)
+        .map(|instrument_index| {
+            parse_instrument(&mut reader)
+                .with_context(|| format!("Could not read instrument {}", instrument_index + 1))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok(Module {
+        channel_count,
+        speed: speed.clamp(1, 31) as u8,
+        tempo: tempo.clamp(32, 255) as u8,
+        order,
+        patterns,
+        instruments,
+    })
+}
+
+fn parse_pattern(reader: &mut Reader, channel_count: usize) -> anyhow::Result<Vec<Cell>> {
+    let start = reader.position();
+    let header_size = reader.u32_le()? as usize;
+    let _packing = reader.u8()?;
+    let row_count = reader.u16_le()? as usize;
+    let data_size = reader.u16_le()? as usize;
+    ensure!(
+        (1..=MAX_ROW_COUNT).contains(&row_count),
+        "Invalid row count {row_count}"
+    );
+    // A shorter header would read itself again
+    ensure!(
+        header_size >= PATTERN_HEADER_SIZE as usize,
+        "Invalid pattern header size {header_size}"
+    );
+    reader.seek(start + header_size)?;
+
+    let mut cells = vec![Cell::default(); row_count * channel_count];
+    // An empty pattern is stored without data
+    if data_size == 0 {
+        return Ok(cells);
+    }
+
+    let mut data = Reader::new(reader.bytes(data_size)?);
+    for cell in cells.iter_mut() {
+        let first = data.u8()?;
+        *cell = if first & 0x80 == 0 {
+            Cell {
+                note: first,
+                instrument: data.u8()?,
+                volume: data.u8()?,
+                effect: data.u8()?,
+                param: data.u8()?,
+            }
+        } else {
+            // The low bits of a packed cell tell which fields follow
+            let mut field = |bit: u8| if first & bit != 0 { data.u8() } else { Ok(0) };
+            Cell {
+                note: field(0b1)?,
+                instrument: field(0b10)?,
+                volume: field(0b100)?,
+                effect: field(0b1000)?,
+                param: field(0b1_0000)?,
+            }
+        };
+    }
+    Ok(cells)
+}
+
+fn parse_instrument(reader: &mut Reader) -> anyhow::Result<XmInstrument> {
+    let start = reader.position();
+    let header_size = reader.u32_le()? as usize;
+    let name = reader.fixed_string(22)?;
+    let _type = reader.u8()?;
+    let sample_count = reader.u16_le()? as usize;
+
+    if sample_count == 0 {
+        reader.seek(start + header_size)?;
+        return Ok(XmInstrument {
+            name,
+            keymap: Vec::new(),
+            volume_envelope: false,
+            panning_envelope: false,
+            samples: Vec::new(),
+        });
+    }
+
+    let sample_header_size = reader.u32_le()? as usize;
+    let keymap = reader.bytes(KEYMAP_LEN)?.to_vec();
+    // Envelope points (2 * 12 * (x: u16, y: u16)), point counts, sustain and loop points
+    reader.bytes(2 * 12 * 4 + 2 + 6)?;
+    let volume_type = reader.u8()?;
+    let panning_type = reader.u8()?;
+    reader.seek(start + header_size)?;
+
+    let headers = (0..sample_count)
+        .map(|_| {
+            let start = reader.position();
+            let length = reader.u32_le()? as usize;
+            let loop_start = reader.u32_le()? as usize;
+            let loop_length = reader.u32_le()? as usize;
+            let volume = reader.u8()?.min(MAX_VOLUME);
+            let finetune = reader.i8()?;
+            let sample_type = reader.u8()?;
+            let panning = reader.u8()?;
+            let relative_note = reader.i8()?;
+            let _reserved = reader.u8()?;
+            let name = reader.fixed_string(22)?;
+            reader.seek(start + sample_header_size)?;
+            let sample = Sample {
+                name,
+                volume,
+                finetune,
+                loop_type: sample_type & SAMPLE_LOOP_MASK,
+                loop_start,
+                loop_length,
+                panning,
+                relative_note,
+                data: Vec::new(),
+            };
+            Ok((length, sample_type, sample))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let samples = headers
+        .into_iter()
+        .enumerate()
+        .map(|(index, (length, sample_type, mut sample))| {
+            let bytes = reader
+                .bytes(length)
+                .with_context(|| format!("Sample {index} is truncated"))?;
+            sample.data = decode_delta_samples(bytes, sample_type & SAMPLE_16_BIT != 0);
+            // Loop points are in bytes
+            if sample_type & SAMPLE_16_BIT != 0 {
+                sample.loop_start /= 2;
+                sample.loop_length /= 2;
+            }
+            Ok(sample)
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok(XmInstrument {
+        name,
+        keymap,
+        volume_envelope: volume_type & ENVELOPE_ENABLED != 0,
+        panning_envelope: panning_type & ENVELOPE_ENABLED != 0,
+        samples,
+    })
+}
+
+/// Sample data is stored as the difference between consecutive values
+fn decode_delta_samples(bytes: &[u8], is_16_bit: bool) -> Vec<f32> {
+    if is_16_bit {
+        bytes
+            .chunks_exact(2)
+            .scan(0i16, |value, bytes| {
+                *value = value.wrapping_add(i16::from_le_bytes([bytes[0], bytes[1]]));
+                Some(*value as f32 / 32768.0)
+            })
+            .collect()
+    } else {
+        bytes
+            .iter()
+            .scan(0i8, |value, byte| {
+                *value = value.wrapping_add(*byte as i8);
+                Some(*value as f32 / 128.0)
+            })
+            .collect()
+    }
+}
+
+fn note_to_tracky(note: u8) -> Option<NoteFieldValue> {
+    match note {
+        NOTE_KEY_OFF => Some(NoteFieldValue::Off),
+        1..NOTE_KEY_OFF => {
+            let midi_value = C0_MIDI_VALUE + note as i32 - 1;
+            (midi_value <= MidiValue::MAX_VALUE).then(|| {
+                let (note, octave) = midi_value_to_note(MidiValue::new_unchecked(midi_value));
+                NoteFieldValue::Note(note, octave)
+            })
+        }
+        _ => None,
+    }
+}
+
+fn effect_name(effect: u8, param: u8) -> String {
+    let effect = match effect {
+        0..=9 => (b'0' + effect) as char,
+        _ => (b'A' + effect - 10) as char,
+    };
+    format!("{effect}{param:02X}")
+}
+
+fn convert(module: Module, report: &mut Report) -> anyhow::Result<Song> {
+    let Module {
+        channel_count,
+        mut speed,
+        mut tempo,
+        order,
+        patterns,
+        instruments,
+    } = module;
+
+    // Initial speed and tempo can be overridden on the first row played
+    let first_pattern = order[0] as usize;
+    for cell in patterns
+        .get(first_pattern)
+        .map(|cells| &cells[..channel_count])
+        .unwrap_or_default()
+    {
+        match (cell.effect, cell.param) {
+            (EFFECT_SET_SPEED, 0) => {}
+            (EFFECT_SET_SPEED, param @ 1..=0x1F) => speed = param,
+            (EFFECT_SET_SPEED, param) => tempo = param,
+            _ => {}
+        }
+    }
+
+    let mut patterns = import::patterns(
+        &patterns,
+        channel_count,
+        report,
+        |cell, location, report| {
+            convert_cell(cell, location, location.pattern == first_pattern, report)
+        },
+    )?;
+    patterns.set_order(order.iter().map(|pattern| *pattern as usize).collect())?;
+
+    let mut slots = Instruments::empty();
+    for (index, instrument) in instruments.into_iter().enumerate() {
+        if instrument
+            .samples
+            .iter()
+            .all(|sample| sample.data.is_empty())
+        {
+            continue;
+        }
+        let name = instrument.name.clone();
+        import::set_instrument(
+            &mut slots,
+            index,
+            &name,
+            convert_instrument(index, instrument, report),
+        )?;
+    }
+
+    Ok(import::song(patterns, slots, import::tempo(speed, tempo)?))
+}
+
+fn convert_instrument(index: usize, instrument: XmInstrument, report: &mut Report) -> Instrument {
+    let XmInstrument {
+        name,
+        keymap,
+        volume_envelope,
+        panning_envelope,
+        mut samples,
+    } = instrument;
+    let number = index + 1;
+
+    // A tracky instrument plays a single sample, keep the one C-4 is mapped to
+    let sample_index = keymap
+        .get(48)
+        .map(|sample_index| *sample_index as usize)
+        .filter(|sample_index| *sample_index < samples.len())
+        .unwrap_or_default();
+    if samples.len() > 1 {
+        report.warn(format!(
+            "Instrument {number} has {} samples, only sample {sample_index} (mapped to C-4) is imported",
+            samples.len()
+        ));
+    }
+    if volume_envelope {
+        report.warn(format!(
+            "Volume envelope of instrument {number} ignored, point envelopes can't be mapped to tracky's ADSR envelopes"
+        ));
+    }
+    if panning_envelope {
+        report.warn(format!(
+            "Panning envelope of instrument {number} ignored, tracky has no panning envelope"
+        ));
+    }
+
+    let sample = samples.swap_remove(sample_index);
+    let loop_mode = match sample.loop_type {
+        SAMPLE_FORWARD_LOOP => Some(LoopMode::Forward),
+        SAMPLE_PING_PONG_LOOP => Some(LoopMode::PingPong),
+        _ => None,
+    };
+    let loops = SampleLoops {
+        normal: loop_mode.and_then(|mode| {
+            import::sample_loop(
+                mode,
+                sample.loop_start,
+                sample.loop_start + sample.loop_length,
+                sample.data.len(),
+                &format!("Loop of instrument {number}"),
+                report,
+            )
+        }),
+        sustain: None,
+    };
+    if sample.panning != PANNING_CENTER {
+        report.warn(format!(
+            "Panning {:02X} of instrument {number} ignored, instrument panning is not supported yet",
+            sample.panning
+        ));
+    }
+
+    let name = [name, sample.name]
+        .into_iter()
+        .find(|name| !name.is_empty())
+        .unwrap_or_else(|| format!("Instrument {number}"));
+    // Finetune is in 128th of a semitone
+    let semitones = sample.relative_note as f32 + sample.finetune as f32 / 128.0;
+    let frame_rate = C4_FRAME_RATE * (semitones / 12.0).exp2();
+
+    let mut converted = Instrument::from(import::mono_sample(
+        name,
+        sample.data.into_iter(),
+        frame_rate,
+        loops,
+    ));
+    converted.volume = import::volume(sample.volume, MAX_VOLUME);
+    converted
+}
+
+fn convert_cell(
+    cell: &Cell,
+    location: Location,
+    is_first_pattern: bool,
+    report: &mut Report,
+) -> PatternLine {
+    let mut line = PatternLine::default();
+
+    if cell.note != 0 {
+        match note_to_tracky(cell.note) {
+            Some(note) => line.note = Field::new(note),
+            None => report.warn_at(location, format!("Invalid note {}", cell.note)),
+        }
+    }
+
+    if cell.instrument != 0 {
+        line.instrument = Field::new(u8_to_hex_digit_pair(cell.instrument - 1));
+        // Triggering an instrument resets the channel to the sample volume
+        line.velocity = Field::new(u8_to_hex_digit_pair(0xFF));
+    }
+
+    match cell.volume {
+        0 => {}
+        volume if VOLUME_COLUMN_SET_VOLUME.contains(&volume) => {
+            line.velocity = Field::new(u8_to_hex_digit_pair(import::velocity(
+                volume - VOLUME_COLUMN_SET_VOLUME.start(),
+                MAX_VOLUME,
+            )))
+        }
+        volume => report.warn_at(
+            location,
+            format!("Unsupported volume column command {volume:02X}"),
+        ),
+    }
+
+    match (cell.effect, cell.param) {
+        (0, 0) => {}
+        (EFFECT_SET_VOLUME, volume) => {
+            line.velocity = Field::new(u8_to_hex_digit_pair(import::velocity(volume, MAX_VOLUME)))
+        }
+        (EFFECT_SET_SPEED, _) if is_first_pattern && location.row == 0 => {}
+        (effect, param) => match import::effect(effect, param) {
+            Some(effect) => line.effect = Field::new(effect),
+            None => report.warn_at(
+                location,
+                format!("Unsupported effect {}", effect_name(effect, param)),
+            ),
+        },
+    }
+
+    line
+}
+
+pub fn save<P: AsRef<Path>>(song: &Song, path: P) -> anyhow::Result<()> {
+    let data = write(song)?;
+    fs::write(path.as_ref(), data)
+        .with_context(|| format!("Could not export song to {:?}", path.as_ref()))
+}
+
+pub fn write(song: &Song) -> anyhow::Result<Vec<u8>> {
+    let patterns = &song.patterns;
+    let channel_len = patterns.channel_len as usize;
+    ensure!(
+        (1..=MAX_CHANNEL_COUNT).contains(&(patterns.channel_count as usize)),
+        "XM modules have at most {MAX_CHANNEL_COUNT} channels, the song has {}",
+        patterns.channel_count
+    );
+    ensure!(
+        (1..=MAX_ROW_COUNT).contains(&channel_len),
+        "XM patterns have at most {MAX_ROW_COUNT} rows, the song has {channel_len}"
+    );
+    ensure!(
+        patterns.patterns().len() <= MAX_PATTERN_COUNT,
+        "XM modules have at most {MAX_PATTERN_COUNT} patterns, the song has {}",
+        patterns.patterns().len()
+    );
+    ensure!(
+        patterns.order().len() <= 256,
+        "XM modules have at most 256 order entries, the song has {}",
+        patterns.order().len()
+    );
+    // FastTracker 2 only plays an even number of channels
+    let channel_count = (patterns.channel_count as usize).next_multiple_of(2);
+    let instrument_count = song
+        .instruments
+        .iter()
+        .last()
+        .map_or(0, |(index, _)| index as usize + 1);
+    let (speed, tempo) = speed_and_tempo(&song.tempo);
+
+    let mut writer = Writer::new();
+    writer.bytes(ID);
+    writer.fixed_string("", 20);
+    writer.u8(0x1A);
+    writer.fixed_string(TRACKER_NAME, 20);
+    writer.u16_le(VERSION);
+    writer.u32_le(HEADER_SIZE);
+    writer.u16_le(patterns.order().len() as u16);
+    // Restart position
+    writer.u16_le(0);
+    writer.u16_le(channel_count as u16);
+    writer.u16_le(patterns.patterns().len() as u16);
+    writer.u16_le(instrument_count as u16);
+    writer.u16_le(FLAG_LINEAR_FREQUENCIES);
+    writer.u16_le(speed as u16);
+    writer.u16_le(tempo as u16);
+    let mut order_table = [0; 256];
+    for (position, pattern) in order_table.iter_mut().zip(patterns.order()) {
+        *position = *pattern as u8;
+    }
+    writer.bytes(&order_table);
+
+    for (pattern_index, pattern) in patterns.patterns().iter().enumerate() {
+        let mut data = Vec::new();
+        for row in 0..channel_len {
+            for channel in 0..channel_count {
+                let cell = pattern
+                    .lines
+                    .get(channel * channel_len + row)
+                    .filter(|_| channel < patterns.channel_count as usize)
+                    .map(|line| {
+                        let location = Location {
+                            pattern: pattern_index,
+                            row,
+                            channel,
+                        };
+                        export_cell(line, location)
+                    })
+                    .unwrap_or_default();
+                write_cell(&mut data, &cell);
+            }
+        }
+        writer.u32_le(PATTERN_HEADER_SIZE);
+        writer.u8(0);
+        writer.u16_le(channel_len as u16);
+        writer.u16_le(data.len() as u16);
+        writer.bytes(&data);
+    }
+
+    for index in 0..instrument_count {
+        match song.instruments.get(index as u8) {
+            Some(instrument) => write_instrument(&mut writer, instrument),
+            None => {
+                writer.u32_le(EMPTY_INSTRUMENT_HEADER_SIZE);
+                writer.fixed_string("", 22);
+                writer.u8(0);
+                writer.u16_le(0);
+            }
+        }
+    }
+
+    Ok(writer.into_bytes())
+}
+
+/// Speed and tempo the closest to the line rate. The song ticks per row are kept as speed if
+/// possible, then FastTracker's default speed.
+fn speed_and_tempo(song_tempo: &Tempo) -> (u8, u8) {
+    let line_per_second = song_tempo.line_per_second();
+    let tempo_for = |speed: u8| (line_per_second * 2.5 * speed as f32).round();
+    u8::try_from(song_tempo.ticks_per_row)
+        .ok()
+        .filter(|speed| (1..=31).contains(speed))
+        .into_iter()
+        .chain(iter::once(6))
+        .chain(1..=31)
+        .find(|speed| (32.0..=255.0).contains(&tempo_for(*speed)))
+        .map(|speed| (speed, tempo_for(speed) as u8))
+        .unwrap_or_else(|| {
+            // Too slow or too fast to be represented, use the closest limit
+            if line_per_second < 1.0 {
+                (31, 32)
+            } else {
+                (1, 255)
+            }
+        })
+}
+
+fn export_cell(line: &PatternLine, location: Location) -> Cell {
+    let mut cell = Cell::default();
+
+    match line.note.value() {
+        None => {}
+        // Exported instruments have no volume envelope, FastTracker 2 cuts their notes on a key
+        // off so the release of a note-off is lost
+        Some(NoteFieldValue::Off | NoteFieldValue::Cut) => cell.note = NOTE_KEY_OFF,
+        Some(NoteFieldValue::Note(note, octave)) => {
+            let midi_value = note_to_midi_value(*note, *octave).value();
+            let xm_note = midi_value - C0_MIDI_VALUE + 1;
+            if (1..NOTE_KEY_OFF as i32).contains(&xm_note) {
+                cell.note = xm_note as u8;
+            } else {
+                warn!(
+                    "{location}: note {note}{} is out of XM's range, it is dropped",
+                    octave.value()
+                );
+            }
+        }
+    }
+    if let Some(instrument) = line.instrument.get_u8() {
+        cell.instrument = instrument + 1;
+    }
+    if let Some(velocity) = line.velocity.get_u8() {
+        let volume = (velocity as u32 * MAX_VOLUME as u32 + 0xFF / 2) / 0xFF;
+        cell.volume = VOLUME_COLUMN_SET_VOLUME.start() + volume as u8;
+    }
+    if let Some(effect) = line.effect.value() {
+        let (command, param) = (effect.command.value(), effect.parameter_u8());
+        // XM runs the other commands, or remembers zero parameters, where tracky doesn't
+        if import::effect(command, param) == Some(*effect) {
+            (cell.effect, cell.param) = (command, param);
+        } else {
+            warn!("{location}: effect {effect} is not supported, it is dropped");
+        }
+    }
+
+    cell
+}
+
+/// Every cell is packed, the leading byte tells which fields follow
+fn write_cell(data: &mut Vec<u8>, cell: &Cell) {
+    let fields = [
+        cell.note,
+        cell.instrument,
+        cell.volume,
+        cell.effect,
+        cell.param,
+    ];
+    let flags = fields
+        .iter()
+        .enumerate()
+        .filter(|(_, field)| **field != 0)
+        .fold(0x80, |flags, (index, _)| flags | 1 << index);
+    data.push(flags);
+    data.extend(fields.iter().filter(|field| **field != 0));
+}
+
+fn write_instrument(writer: &mut Writer, instrument: &Instrument) {
+    let name = instrument.source().to_string();
+    if instrument.envelope != Envelope::default() {
+        warn!(
+            "Envelope {} of {name:?} is dropped, it is not converted to an XM point envelope",
+            instrument.envelope
+        );
+    }
+    let (frames, frame_rate, sample_loop) = match instrument.source() {
+        Kind::Sample {
+            signal,
+            loops,
+            name,
+            ..
+        } => {
+            if let Some(sustain) = loops.sustain {
+                warn!("Sustain loop {sustain} of {name:?} is dropped, XM has no sustain loop");
+            }
+            (
+                signal
+                    .iter()
+                    .map(|Vector([left, right])| (left + right) / 2.0)
+                    .collect::<Vec<_>>(),
+                signal.frame_rate,
+                loops.normal,
+            )
+        }
+        oscillator => {
+            // One cycle at C-5 so the sample plays the oscillator's pitch
+            let frame_rate = CYCLE_FRAME_COUNT as f32 * *C5_FREQ;
+            let mut phase = 0.0;
+            let frames = (0..CYCLE_FRAME_COUNT)
+                .map(|_| {
+                    let Vector([left, right]) = oscillator.next_frame(
+                        *C5_FREQ,
+                        Volume::DEFAULT,
+                        Pan::DEFAULT,
+                        &mut phase,
+                        frame_rate,
+                        false,
+                    );
+                    (left + right) / 2.0
+                })
+                .collect::<Vec<_>>();
+            let sample_loop = SampleLoop::new(LoopMode::Forward, 0, frames.len()).ok();
+            (frames, frame_rate, sample_loop)
+        }
+    };
+    let (relative_note, finetune) = relative_note_and_finetune(frame_rate);
+    let byte_len = frames.len() as u32 * 2;
+
+    writer.u32_le(INSTRUMENT_HEADER_SIZE);
+    writer.fixed_string(&name, 22);
+    writer.u8(0);
+    writer.u16_le(1);
+    writer.u32_le(SAMPLE_HEADER_SIZE);
+    // Every note plays the only sample, envelopes and vibrato are off
+    writer.bytes(&[0; INSTRUMENT_HEADER_SIZE as usize - 33]);
+
+    // Loop points are in bytes
+    let (loop_type, loop_start, loop_end) = match sample_loop {
+        Some(SampleLoop { mode, start, end }) => {
+            let loop_type = match mode {
+                LoopMode::Forward => SAMPLE_FORWARD_LOOP,
+                LoopMode::PingPong => SAMPLE_PING_PONG_LOOP,
+            };
+            (loop_type, start as u32 * 2, end as u32 * 2)
+        }
+        None => (0, 0, 0),
+    };
+    writer.u32_le(byte_len);
+    writer.u32_le(loop_start);
+    writer.u32_le(loop_end - loop_start);
+    writer.u8((instrument.volume.value() * MAX_VOLUME as f32).round() as u8);
+    writer.u8(finetune as u8);
+    writer.u8(SAMPLE_16_BIT | loop_type);
+    writer.u8(PANNING_CENTER);
+    writer.u8(relative_note as u8);
+    writer.u8(0);
+    writer.fixed_string(&name, 22);
+
+    // Sample data is stored as the difference between consecutive values
+    let mut previous = 0i16;
+    for frame in frames {
+        let value = (frame * 32768.0)
+            .round()
+            .clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+        writer.bytes(&value.wrapping_sub(previous).to_le_bytes());
+        previous = value;
+    }
+}
+
+/// Inverse of the frame rate computed on import, finetune is in 128th of a semitone
+fn relative_note_and_finetune(frame_rate: f32) -> (i8, i8) {
+    let semitones = 12.0 * (frame_rate / C4_FRAME_RATE).log2();
+    let relative_note = semitones.round().clamp(-96.0, 95.0);
+    let finetune = ((semitones - relative_note) * 128.0)
+        .round()
+        .clamp(-128.0, 127.0);
+    (relative_note as i8, finetune as i8)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        audio::{dsp::interpolation::InterpolationMode, signal},
+        model::{
+            instrument::MAX_SLOT_COUNT,
+            pattern::{HexDigit, NoteName, OctaveValue, Pattern, Patterns},
+        },
+    };
+
+    use super::*;
+
+    struct TestSample {
+        name: &'static str,
+        volume: u8,
+        finetune: i8,
+        loop_type: u8,
+        panning: u8,
+        relative_note: i8,
+        /// Written as 16 bit samples
+        data: Vec<i16>,
+    }
+
+    struct TestInstrument {
+        name: &'static str,
+        volume_envelope: bool,
+        samples: Vec<TestSample>,
+    }
+
+    /// (note, instrument, volume, effect, param) per channel, row major
+    type TestPattern = Vec<Vec<[u8; 5]>>;
+
+    fn make_module(
+        channel_count: u16,
+        order: &[u8],
+        patterns: &[TestPattern],
+        instruments: &[TestInstrument],
+    ) -> Vec<u8> {
+        let mut writer = Writer::new();
+        writer.bytes(b"Extended Module: ");
+        writer.bytes(&[0; 20]);
+        writer.u8(0x1A);
+        writer.bytes(&[0; 20]);
+        writer.u16_le(0x0104);
+        writer.u32_le(276);
+        writer.u16_le(order.len() as u16);
+        writer.u16_le(0);
+        writer.u16_le(channel_count);
+        writer.u16_le(patterns.len() as u16);
+        writer.u16_le(instruments.len() as u16);
+        writer.u16_le(1);
+        writer.u16_le(6);
+        writer.u16_le(125);
+        let mut order_table = [0; 256];
+        order_table[..order.len()].copy_from_slice(order);
+        writer.bytes(&order_table);
+
+        for rows in patterns {
+            // Mix of packed and unpacked cells
+            let mut data = Vec::new();
+            for cell in rows.iter().flatten() {
+                if cell.iter().all(|field| *field != 0) {
+                    data.extend(cell);
+                } else {
+                    let flags = cell
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, field)| **field != 0)
+                        .fold(0x80, |flags, (index, _)| flags | 1 << index);
+                    data.push(flags);
+                    data.extend(cell.iter().filter(|field| **field != 0));
+                }
+            }
+            writer.u32_le(9);
+            writer.u8(0);
+            writer.u16_le(rows.len() as u16);
+            writer.u16_le(data.len() as u16);
+            writer.bytes(&data);
+        }
+
+        for instrument in instruments {
+            let mut name = [0; 22];
+            name[..instrument.name.len()].copy_from_slice(instrument.name.as_bytes());
+            if instrument.samples.is_empty() {
+                writer.u32_le(29);
+                writer.bytes(&name);
+                writer.u8(0);
+                writer.u16_le(0);
+                continue;
+            }
+            writer.u32_le(263);
+            writer.bytes(&name);
+            writer.u8(0);
+            writer.u16_le(instrument.samples.len() as u16);
+            writer.u32_le(40);
+            writer.bytes(&[0; 96]);
+            writer.bytes(&[0; 2 * 12 * 4 + 2 + 6]);
+            writer.u8(instrument.volume_envelope as u8);
+            writer.u8(0);
+            writer.bytes(&[0; 263 - 235]);
+            for sample in instrument.samples.iter() {
+                let mut name = [0; 22];
+                name[..sample.name.len()].copy_from_slice(sample.name.as_bytes());
+                writer.u32_le(sample.data.len() as u32 * 2);
+                writer.u32_le(0);
+                writer.u32_le(if sample.loop_type == 0 {
+                    0
+                } else {
+                    sample.data.len() as u32 * 2
+                });
+                writer.u8(sample.volume);
+                writer.u8(sample.finetune as u8);
+                writer.u8(sample.loop_type | 0b1_0000);
+                writer.u8(sample.panning);
+                writer.u8(sample.relative_note as u8);
+                writer.u8(0);
+                writer.bytes(&name);
+            }
+            for sample in instrument.samples.iter() {
+                let mut previous = 0i16;
+                for value in sample.data.iter() {
+                    writer.bytes(&value.wrapping_sub(previous).to_le_bytes());
+                    previous = *value;
+                }
+            }
+        }
+
+        writer.into_bytes()
+    }
+
+    fn sample(data: Vec<i16>) -> TestSample {
+        TestSample {
+            name: "",
+            volume: 64,
+            finetune: 0,
+            loop_type: 0,
+            panning: 0x80,
+            relative_note: 0,
+            data,
+        }
+    }
+
+    fn empty_rows(channel_count: usize, row_count: usize) -> TestPattern {
+        vec![vec![[0; 5]; channel_count]; row_count]
+    }
+
+    fn line_at(patterns: &Patterns, pattern: usize, channel: usize, row: usize) -> &PatternLine {
+        &patterns.patterns()[pattern].lines[channel * patterns.channel_len as usize + row]
+    }
+
+    #[test]
+    fn test_note_to_tracky() {
+        assert_eq!(
+            Some(NoteFieldValue::Note(NoteName::C, OctaveValue::OCTAVE_1)),
+            note_to_tracky(1)
+        );
+        assert_eq!(
+            Some(NoteFieldValue::Note(NoteName::C, OctaveValue::OCTAVE_5)),
+            note_to_tracky(49)
+        );
+        assert_eq!(
+            Some(NoteFieldValue::Note(NoteName::B, OctaveValue::OCTAVE_8)),
+            note_to_tracky(96)
+        );
+        assert_eq!(Some(NoteFieldValue::Off), note_to_tracky(NOTE_KEY_OFF));
+        assert_eq!(None, note_to_tracky(98));
+    }
+
+    #[test]
+    fn test_delta_samples_are_decoded() {
+        assert_eq!(
+            vec![0.5, 0.25, -0.5],
+            decode_delta_samples(&[64, (-32i8) as u8, (-96i8) as u8], false)
+        );
+        let bytes = [0x4000i16, -0x2000, -0x6000]
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect::<Vec<_>>();
+        assert_eq!(vec![0.5, 0.25, -0.5], decode_delta_samples(&bytes, true));
+    }
+
+    #[test]
+    fn test_patterns_are_imported() {
+        let mut first = empty_rows(3, 32);
+        first[0][0] = [49, 1, 0x30, 0, 0];
+        first[1][2] = [NOTE_KEY_OFF, 0, 0, 0, 0];
+        first[31][1] = [62, 2, 0x10, EFFECT_SET_VOLUME, 0x40];
+        let mut second = empty_rows(3, 64);
+        second[63][2] = [1, 1, 1, 0xB, 1];
+
+        let data = make_module(3, &[0, 1], &[first, second], &[]);
+        let import = read(&data).unwrap();
+        let patterns = &import.song.patterns;
+        assert_eq!(3, patterns.channel_count);
+        assert_eq!(64, patterns.channel_len);
+        assert_eq!(2, patterns.pattern_count);
+        assert_eq!(&[0, 1], patterns.order());
+
+        assert_eq!(
+            &PatternLine {
+                note: Field::new(NoteFieldValue::Note(NoteName::C, OctaveValue::OCTAVE_5)),
+                velocity: Field::new((HexDigit::HEX_8, HexDigit::HEX_0)),
+                instrument: Field::new((HexDigit::HEX_0, HexDigit::HEX_0)),
+                effect: Field::empty(),
+            },
+            line_at(patterns, 0, 0, 0)
+        );
+        assert_eq!(
+            Field::new(NoteFieldValue::Off),
+            line_at(patterns, 0, 2, 1).note
+        );
+        assert_eq!(
+            &PatternLine {
+                note: Field::new(NoteFieldValue::Note(
+                    NoteName::CSharp,
+                    OctaveValue::OCTAVE_6
+                )),
+                velocity: Field::new((HexDigit::HEX_F, HexDigit::HEX_F)),
+                instrument: Field::new((HexDigit::HEX_0, HexDigit::HEX_1)),
+                effect: Field::empty(),
+            },
+            line_at(patterns, 0, 1, 31)
+        );
+        // Padding
+        assert_eq!(&PatternLine::default(), line_at(patterns, 0, 1, 32));
+
+        let warnings = import
+            .report
+            .warnings
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "Pattern 0 has 32 row(s), it is padded to 64",
+                "pattern 1 row 063 channel 2: Unsupported volume column command 01",
+                "pattern 1 row 063 channel 2: Unsupported effect B01",
+            ],
+            warnings
+        );
+    }
+
+    #[test]
+    fn test_instruments_are_imported() {
+        let instruments = [
+            TestInstrument {
+                name: "lead",
+                volume_envelope: false,
+                samples: vec![TestSample {
+                    volume: 32,
+                    relative_note: 12,
+                    finetune: -64,
+                    ..sample(vec![0x4000, -0x4000])
+                }],
+            },
+            TestInstrument {
+                name: "",
+                volume_envelope: false,
+                samples: vec![],
+            },
+            TestInstrument {
+                name: "",
+                volume_envelope: true,
+                samples: vec![
+                    TestSample {
+                        name: "kick",
+                        loop_type: SAMPLE_PING_PONG_LOOP,
+                        panning: 0,
+                        ..sample(vec![0; 4])
+                    },
+                    sample(vec![0; 2]),
+                ],
+            },
+        ];
+        let data = make_module(1, &[0], &[empty_rows(1, 64)], &instruments);
+        let import = read(&data).unwrap();
+
+        let instruments = import.song.instruments.iter().collect::<Vec<_>>();
+        assert_eq!(2, instruments.len());
+
+        let (index, instrument) = instruments[0];
+        assert_eq!(0, index);
+        assert_eq!(0.5, instrument.volume.value());
+        let Kind::Sample { name, signal, .. } = instrument.source() else {
+            panic!("Expected a sample");
+        };
+        assert_eq!("lead", name);
+        approx::assert_relative_eq!(
+            C4_FRAME_RATE * 2.0 * 2f32.powf(-0.5 / 12.0),
+            signal.frame_rate
+        );
+        assert_eq!(
+            vec![Vector([0.5, 0.5]), Vector([-0.5, -0.5])],
+            signal.to_vec()
+        );
+
+        let (index, instrument) = instruments[1];
+        assert_eq!(2, index);
+        let Kind::Sample {
+            name,
+            signal,
+            loops,
+            ..
+        } = instrument.source()
+        else {
+            panic!("Expected a sample");
+        };
+        assert_eq!("kick", name);
+        assert_eq!(4, signal.len());
+        assert_eq!("pingpong 0 4; none", loops.to_string());
+
+        assert_eq!(
+            vec![
+                "Instrument 3 has 2 samples, only sample 0 (mapped to C-4) is imported",
+                "Volume envelope of instrument 3 ignored, point envelopes can't be mapped to tracky's ADSR envelopes",
+                "Panning 00 of instrument 3 ignored, instrument panning is not supported yet",
+            ],
+            import
+                .report
+                .warnings
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_instruments_past_the_slot_limit_are_an_error() {
+        let mut instruments = (0..MAX_SLOT_COUNT)
+            .map(|_| TestInstrument {
+                name: "",
+                volume_envelope: false,
+                samples: vec![],
+            })
+            .collect::<Vec<_>>();
+        instruments.push(TestInstrument {
+            name: "too far",
+            volume_envelope: false,
+            samples: vec![sample(vec![1, 2])],
+        });
+        let data = make_module(1, &[0], &[empty_rows(1, 64)], &instruments);
+
+        let err = read(&data).unwrap_err().to_string();
+        assert!(err.contains("Instrument 65"), "{err}");
+        assert!(err.contains("too far"), "{err}");
+    }
+
+    #[test]
+    fn test_32_channels_are_supported() {
+        let mut rows = empty_rows(32, 64);
+        rows[10][31] = [49, 0, 0, 0, 0];
+        let data = make_module(32, &[0], &[rows], &[]);
+
+        let patterns = read(&data).unwrap().song.patterns;
+        assert_eq!(32, patterns.channel_count);
+        assert!(line_at(&patterns, 0, 31, 10).note.value().is_some());
+    }
+
+    #[test]
+    fn test_invalid_data_is_an_error() {
+        assert!(read(b"Extended Module: but not really").is_err());
+        assert!(read(b"Definitely not an xm").is_err());
+        let mut data = make_module(2, &[0], &[empty_rows(2, 64)], &[]);
+        data[HEADER_SIZE_OFFSET + 4 + 2 + 2] = 33;
+        assert!(read(&data).is_err());
+
+        let data = make_module(2, &[0], &[empty_rows(2, 64)], &[]);
+        let pattern_count_offset = HEADER_SIZE_OFFSET + 4 + 2 + 2 + 2;
+        let mut too_many_patterns = data.clone();
+        too_many_patterns[pattern_count_offset..pattern_count_offset + 2]
+            .copy_from_slice(&(MAX_PATTERN_COUNT as u16 + 1).to_le_bytes());
+        assert!(read(&too_many_patterns).is_err());
+        let pattern_offset = HEADER_SIZE_OFFSET + HEADER_SIZE as usize;
+        let mut null_pattern_header = data;
+        null_pattern_header[pattern_offset..pattern_offset + 4].fill(0);
+        assert!(read(&null_pattern_header).is_err());
+    }
+
+    fn exported_song(channel_count: i32, lines: &[(usize, usize, usize, &str)]) -> Song {
+        let channel_len = 16;
+        let mut patterns = vec![Pattern::new(channel_count, channel_len); 2];
+        for (pattern, channel, row, line) in lines {
+            patterns[*pattern].lines[channel * channel_len as usize + row] = line.parse().unwrap();
+        }
+        let patterns = Patterns::from_patterns(channel_count, channel_len, patterns).unwrap();
+        import::song(
+            patterns,
+            Instruments::empty(),
+            import::tempo(6, 125).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_exported_patterns_are_read_back() {
+        let lines = [
+            (0, 0, 0, "C-5 FF 00 047"),
+            (0, 1, 3, "D#4 80 02"),
+            (0, 2, 15, "OFF .. .."),
+            (1, 0, 7, "A-6 .. .. 482"),
+            (1, 2, 8, "... 00 01 ED2"),
+        ];
+        let mut song = exported_song(3, &lines);
+        song.patterns.set_order(vec![1, 0, 1]).unwrap();
+
+        let import = read(&write(&song).unwrap()).unwrap();
+        let patterns = &import.song.patterns;
+        // Channels are padded to an even count
+        assert_eq!(4, patterns.channel_count);
+        assert_eq!(16, patterns.channel_len);
+        assert_eq!(2, patterns.pattern_count);
+        assert_eq!(&[1, 0, 1], patterns.order());
+        assert_eq!(song.tempo, import.song.tempo);
+        for pattern in 0..2 {
+            for channel in 0..4 {
+                for row in 0..16 {
+                    let expected = lines
+                        .iter()
+                        .find(|line| (line.0, line.1, line.2) == (pattern, channel, row))
+                        .map(|line| line.3.parse::<PatternLine>().unwrap())
+                        .unwrap_or_default();
+                    assert_eq!(
+                        &expected,
+                        line_at(patterns, pattern, channel, row),
+                        "pattern {pattern} channel {channel} row {row}"
+                    );
+                }
+            }
+        }
+        assert!(import.report.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_exported_instruments_are_read_back() {
+        let mut song = exported_song(2, &[]);
+        song.instruments
+            .set(0, Instrument::from(Kind::Sine))
+            .unwrap();
+        let mut sample = Instrument::from(Kind::Sample {
+            name: "Piano".into(),
+            signal: signal::stereo::Owned::from_frames(
+                vec![Vector([0.5, 0.5]), Vector([-0.5, 0.0]), Vector([1.0, 1.0])],
+                44100.0,
+            ),
+            loops: "pingpong 1 3; forward 0 2".parse().unwrap(),
+            interpolation: InterpolationMode::default(),
+        });
+        sample.volume = Volume::new_unchecked(0.5);
+        song.instruments.set(2, sample).unwrap();
+
+        let import = read(&write(&song).unwrap()).unwrap();
+        let instruments = import.song.instruments.iter().collect::<Vec<_>>();
+        assert_eq!(2, instruments.len());
+
+        let (index, instrument) = instruments[0];
+        assert_eq!(0, index);
+        assert_eq!(1.0, instrument.volume.value());
+        let Kind::Sample {
+            name,
+            signal,
+            loops,
+            ..
+        } = instrument.source()
+        else {
+            panic!("Expected a sample");
+        };
+        assert_eq!("Sine", name);
+        assert_eq!(
+            format!("forward 0 {CYCLE_FRAME_COUNT}; none"),
+            loops.to_string()
+        );
+        // The single cycle plays at the oscillator's pitch
+        assert_eq!(CYCLE_FRAME_COUNT, signal.len());
+        approx::assert_relative_eq!(
+            CYCLE_FRAME_COUNT as f32 * *C5_FREQ,
+            signal.frame_rate,
+            max_relative = 1e-3
+        );
+        approx::assert_abs_diff_eq!(0.0, signal[0].0[0], epsilon = 1e-4);
+        approx::assert_abs_diff_eq!(1.0, signal[CYCLE_FRAME_COUNT / 4].0[0], epsilon = 1e-4);
+        approx::assert_abs_diff_eq!(-1.0, signal[CYCLE_FRAME_COUNT * 3 / 4].0[1], epsilon = 1e-4);
+
+        let (index, instrument) = instruments[1];
+        assert_eq!(2, index);
+        assert_eq!(0.5, instrument.volume.value());
+        let Kind::Sample {
+            name,
+            signal,
+            loops,
+            ..
+        } = instrument.source()
+        else {
+            panic!("Expected a sample");
+        };
+        assert_eq!("Piano", name);
+        // XM has no sustain loop
+        assert_eq!("pingpong 1 3; none", loops.to_string());
+        approx::assert_relative_eq!(44100.0, signal.frame_rate, max_relative = 1e-3);
+        // Stereo samples are mixed down to mono, 16 bit
+        assert_eq!(
+            vec![
+                Vector([0.5, 0.5]),
+                Vector([-0.25, -0.25]),
+                Vector([32767.0 / 32768.0, 32767.0 / 32768.0])
+            ],
+            signal.to_vec()
+        );
+
+        assert!(import.report.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_imported_effects_are_exported() {
+        let mut rows = empty_rows(2, 16);
+        rows[0][0] = [49, 0, 0, 0x0, 0x47];
+        rows[1][1] = [0, 0, 0, 0x1, 0x20];
+        rows[2][0] = [0, 0, 0, 0x3, 0xFF];
+        rows[3][1] = [0, 0, 0, 0x9, 0x10];
+        rows[4][0] = [0, 0, 0, 0xA, 0x04];
+        rows[5][1] = [0, 0, 0, 0xE, 0x93];
+        let import = read(&make_module(2, &[0], &[rows], &[])).unwrap();
+        assert!(import.report.warnings.is_empty(), "{:?}", import.report);
+        let patterns = &import.song.patterns;
+        for (channel, row, effect) in [
+            (0, 0, "047"),
+            (1, 1, "120"),
+            (0, 2, "3FF"),
+            (1, 3, "910"),
+            (0, 4, "A04"),
+            (1, 5, "E93"),
+        ] {
+            let expected = format!("... .. .. {effect}")
+                .parse::<PatternLine>()
+                .unwrap();
+            assert_eq!(
+                expected.effect,
+                line_at(patterns, 0, channel, row).effect,
+                "{effect}"
+            );
+        }
+
+        let exported = read(&write(&import.song).unwrap()).unwrap().song.patterns;
+        assert_eq!(patterns.patterns()[0].lines, exported.patterns()[0].lines);
+    }
+
+    #[test]
+    fn test_unsupported_effects_are_not_exported() {
+        let song = exported_song(2, &[(0, 0, 0, "... .. .. F06"), (0, 1, 0, "... .. .. 300")]);
+        let patterns = read(&write(&song).unwrap()).unwrap().song.patterns;
+        assert_eq!(&PatternLine::default(), line_at(&patterns, 0, 0, 0));
+        assert_eq!(&PatternLine::default(), line_at(&patterns, 0, 1, 0));
+    }
+
+    #[test]
+    fn test_notes_out_of_range_are_not_exported() {
+        let song = exported_song(2, &[(0, 0, 0, "B-0 .. .."), (0, 1, 0, "C-1 .. ..")]);
+        let patterns = read(&write(&song).unwrap()).unwrap().song.patterns;
+        assert_eq!(&PatternLine::default(), line_at(&patterns, 0, 0, 0));
+        assert_eq!(
+            Field::new(NoteFieldValue::Note(
+                NoteName::C,
+                OctaveValue::new_unchecked(1)
+            )),
+            line_at(&patterns, 0, 1, 0).note
+        );
+    }
+
+    #[test]
+    fn test_speed_and_tempo() {
+        let speed_and_tempo = |line_per_second: f32, ticks_per_row: u32| {
+            speed_and_tempo(
+                &Tempo::from_line_per_second(line_per_second, 4, ticks_per_row).unwrap(),
+            )
+        };
+        assert_eq!((6, 125), speed_and_tempo(125.0 / 15.0, 6));
+        assert_eq!((6, 150), speed_and_tempo(10.0, 6));
+        // The ticks per row are kept
+        assert_eq!((3, 75), speed_and_tempo(10.0, 3));
+        assert_eq!((6, 150), speed_and_tempo(10.0, 31));
+        // Too slow for speed 6
+        assert_eq!((7, 35), speed_and_tempo(2.0, 6));
+        // Too fast for speed 6
+        assert_eq!((1, 200), speed_and_tempo(80.0, 6));
+        assert_eq!(
+            (1, 255),
+            super::speed_and_tempo(&Tempo::new(2000.0, 64, 6).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_songs_too_large_are_not_exported() {
+        assert!(write(&exported_song(MAX_CHANNEL_COUNT as i32 + 1, &[])).is_err());
+    }
+}
@@ -5,7 +5,12 @@ use winit::keyboard::{KeyCode, ModifiersState};
 
 use crate::{
     event::{self, Action},
-    model::pattern::{HexDigit, NoteName, OctaveValue},
+    model::{
+        pattern::{HexDigit, NoteName, OctaveValue},
+        playback::song::PlaybackStart,
+        selection::PasteMode,
+        transform::Interpolation,
+    },
     utils::Direction,
 };
 
@@ -82,6 +87,7 @@ impl Default for Keybindings {
                 KeyCode::Digit7 => song_note_event(NoteName::ASharp, 0),
                 KeyCode::KeyU => song_note_event(NoteName::B, 0),
                 KeyCode::Digit1 => Action::SetNoteCut,
+                KeyCode::Backquote => Action::SetNoteOff,
             ),
             InputContext::Octave => hash_map_of!(
                 KeyCode::Digit0 => Action::SetOctaveField(OctaveValue::OCTAVE_0),
@@ -112,11 +118,53 @@ impl Default for Keybindings {
                 KeyCode::ArrowUp => Action::Move(Direction::Up),
                 KeyCode::ArrowLeft => Action::Move(Direction::Left),
                 KeyCode::ArrowRight => Action::Move(Direction::Right),
+                (ModifiersState::SHIFT, KeyCode::ArrowDown) => Action::ExtendSelection(Direction::Down),
+                (ModifiersState::SHIFT, KeyCode::ArrowUp) => Action::ExtendSelection(Direction::Up),
+                (ModifiersState::SHIFT, KeyCode::ArrowLeft) => Action::ExtendSelection(Direction::Left),
+                (ModifiersState::SHIFT, KeyCode::ArrowRight) => Action::ExtendSelection(Direction::Right),
+                (ModifiersState::CONTROL, KeyCode::KeyC) => Action::Copy,
+                (ModifiersState::CONTROL, KeyCode::KeyX) => Action::Cut,
+                (ModifiersState::CONTROL, KeyCode::KeyV) => Action::Paste(PasteMode::Insert),
+                (ModifiersState::CONTROL | ModifiersState::SHIFT, KeyCode::KeyV) => Action::Paste(PasteMode::Overwrite),
+                (ModifiersState::CONTROL | ModifiersState::ALT, KeyCode::KeyV) => Action::Paste(PasteMode::Mix),
+                (ModifiersState::ALT, KeyCode::ArrowUp) => Action::Transpose { semitones: 1 },
+                (ModifiersState::ALT, KeyCode::ArrowDown) => Action::Transpose { semitones: -1 },
+                (ModifiersState::ALT | ModifiersState::SHIFT, KeyCode::ArrowUp) => Action::Transpose { semitones: 12 },
+                (ModifiersState::ALT | ModifiersState::SHIFT, KeyCode::ArrowDown) => Action::Transpose { semitones: -12 },
+                (ModifiersState::ALT, KeyCode::KeyI) => Action::InterpolateVelocity(Interpolation::Linear),
+                (ModifiersState::ALT | ModifiersState::SHIFT, KeyCode::KeyI) => Action::InterpolateVelocity(Interpolation::Exponential),
+                (ModifiersState::ALT, KeyCode::KeyH) => Action::HumanizeVelocity,
+                (ModifiersState::ALT, KeyCode::KeyT) => Action::ChangeTempo,
+                (ModifiersState::ALT, KeyCode::KeyE) => Action::ChangeEnvelope,
+                (ModifiersState::ALT, KeyCode::KeyL) => Action::ChangeSampleLoops,
+                (ModifiersState::ALT, KeyCode::KeyN) => Action::ChangeInterpolation,
+                (ModifiersState::ALT, KeyCode::KeyK) => Action::ChangeResampleQuality,
+                (ModifiersState::ALT, KeyCode::KeyD) => Action::ToggleResampleOnDeviceChange,
+                (ModifiersState::ALT, KeyCode::KeyM) => Action::ToggleMute,
+                (ModifiersState::ALT, KeyCode::KeyS) => Action::ToggleSolo,
                 KeyCode::Insert => Action::CreateNewPattern,
+                (ModifiersState::CONTROL, KeyCode::KeyD) => Action::DuplicatePattern,
+                (ModifiersState::CONTROL, KeyCode::Delete) => Action::DeletePattern,
                 KeyCode::NumpadAdd => Action::GoToNextPattern,
                 KeyCode::NumpadSubtract => Action::GoToPreviousPattern,
+                (ModifiersState::CONTROL, KeyCode::NumpadAdd) => Action::ChangeCurrentOrder { increment: 1 },
+                (ModifiersState::CONTROL, KeyCode::NumpadSubtract) => Action::ChangeCurrentOrder { increment: -1 },
+                (ModifiersState::CONTROL, KeyCode::Insert) => Action::InsertOrderEntry,
+                (ModifiersState::CONTROL | ModifiersState::SHIFT, KeyCode::Delete) => Action::RemoveOrderEntry,
+                (ModifiersState::CONTROL, KeyCode::KeyZ) => Action::Undo,
+                (ModifiersState::CONTROL, KeyCode::KeyY) => Action::Redo,
+                (ModifiersState::CONTROL | ModifiersState::SHIFT, KeyCode::KeyZ) => Action::Redo,
                 KeyCode::Delete => Action::ClearField,
                 KeyCode::Space => Action::TogglePlay,
+                (ModifiersState::SHIFT, KeyCode::Space) => Action::StartPlayback(PlaybackStart::FromCursor),
+                (ModifiersState::CONTROL, KeyCode::Space) => Action::StartPlayback(PlaybackStart::LoopPattern),
+                (ModifiersState::CONTROL | ModifiersState::SHIFT, KeyCode::Space) => Action::StartPlayback(PlaybackStart::LoopSelection),
+                (ModifiersState::SHIFT, KeyCode::Enter) => Action::StartPlayback(PlaybackStart::CurrentRow),
+                (ModifiersState::CONTROL, KeyCode::KeyF) => Action::ToggleFollowPlaying,
+                (ModifiersState::ALT, KeyCode::KeyR) => Action::ToggleRecord,
+                (ModifiersState::ALT, KeyCode::KeyQ) => Action::ToggleRecordQuantize,
+                (ModifiersState::ALT, KeyCode::KeyP) => Action::ToggleRecordPolyphony,
+                (ModifiersState::ALT, KeyCode::KeyJ) => Action::ToggleJam,
                 KeyCode::NumpadMultiply => Action::ChangeGlobalOctave { increment: 1 },
                 KeyCode::Tab => Action::Forward,
                 (ModifiersState::SHIFT, KeyCode::Tab) => Action::Backward,
@@ -130,8 +178,18 @@ impl Default for Keybindings {
                 (ModifiersState::ALT, KeyCode::KeyV) => Action::ShowGlobalVolumePopup,
                 KeyCode::PageDown => Action::ChangeSelectedInstrument { increment: 1 },
                 KeyCode::PageUp => Action::ChangeSelectedInstrument { increment: -1 },
+                (ModifiersState::CONTROL, KeyCode::KeyS) => Action::SaveSong,
+                (ModifiersState::CONTROL | ModifiersState::SHIFT, KeyCode::KeyS) => Action::SaveSongAs,
+                (ModifiersState::CONTROL, KeyCode::KeyO) => Action::OpenSong,
+                (ModifiersState::CONTROL, KeyCode::KeyE) => Action::ExportPatternText,
+                (ModifiersState::CONTROL, KeyCode::KeyI) => Action::ImportPatternText,
+                (ModifiersState::CONTROL | ModifiersState::SHIFT, KeyCode::KeyE) => Action::ExportXm,
+                (ModifiersState::CONTROL | ModifiersState::SHIFT, KeyCode::KeyM) => Action::ExportMidi,
+                (ModifiersState::CONTROL, KeyCode::KeyR) => Action::RenderWav,
+                (ModifiersState::CONTROL | ModifiersState::SHIFT, KeyCode::KeyR) => Action::RenderStems,
             ),
             InputContext::Text => hash_map_of!(
+                KeyCode::Space => Action::Text(event::Text::WriteDataAtCursor(' ')),
                 KeyCode::Backspace => Action::Text(event::Text::RemoveCharAtCursor),
                 KeyCode::ArrowLeft => Action::Text(event::Text::MoveCursorLeft),
                 KeyCode::ArrowRight => Action::Text(event::Text::MoveCursorRight),
@@ -4,12 +4,12 @@ use std::{env, panic, thread};
 
 use ::log::{error, info, warn};
 use audio::device::{self, Devices};
-use event::{Action, AsyncAction, Event, HandleAction, Text};
+use event::{Action, AsyncAction, Event, RenderTarget, Text};
 use model::pattern::{HexDigit, NoteName};
 use ratatui::Terminal;
 use ratatui_wgpu::WgpuBackend;
 use tracky::Tracky;
-use view::popup::{change_volume, Popup};
+use view::popup::{change_volume, progress, text_input, Popup};
 use view::render_root;
 use view::screen::{device_selection, Screen};
 use view::theme::THEME;
@@ -24,6 +24,7 @@ use crate::utils::BackgroundColorEdgesPostProcessor;
 
 mod audio;
 mod event;
+mod format;
 mod keybindings;
 mod model;
 mod service;
@@ -104,11 +105,17 @@ impl ApplicationHandler<Event> for App<'_> {
                 device_id: _,
                 event,
                 is_synthetic: _,
-            } if event.state == ElementState::Pressed => {
-                self.event_sender
+            } => match event.state {
+                ElementState::Pressed => self
+                    .event_sender
                     .send_event(Event::KeyPressed(self.modifiers_state, event))
-                    .unwrap();
-            }
+                    .unwrap(),
+                ElementState::Released if self.tracky.is_jamming() => self
+                    .event_sender
+                    .send_event(Event::KeyReleased(event))
+                    .unwrap(),
+                ElementState::Released => {}
+            },
             WindowEvent::Resized(new_size) => {
                 terminal
                     .backend_mut()
@@ -171,47 +178,315 @@ impl ApplicationHandler<Event> for App<'_> {
                     }
                     keybindings::InputContext::Text => {
                         if let Some(text) = key_event.text {
-                            send!(Event::Text(Text::WriteDataAtCursor(
+                            send!(Event::Action(Action::Text(Text::WriteDataAtCursor(
                                 text.chars().next().unwrap()
-                            )));
+                            ))));
                         }
                     }
                     _ => {}
                 }
             }
-            Event::Action(action) => {
-                match action {
-                    Action::RequestChangeScreenToDeviceSelection => {
-                        send!(Event::StartLoading);
-                        let event_tx_clone = self.event_sender.clone();
-                        thread::spawn(move || {
-                            event_tx_clone
-                                .send_event(Event::LoadingDone(AsyncAction::GetDevices(
-                                    Devices::load(),
-                                )))
-                                .unwrap();
-                        });
+            Event::KeyReleased(key_event) => {
+                // Modifiers are left out, they may be released before the note key
+                if let PhysicalKey::Code(key_code) = key_event.physical_key {
+                    if let Some(Action::SetNoteField {
+                        note,
+                        octave_modifier,
+                    }) = self.tracky.keybindings.action(
+                        ModifiersState::empty(),
+                        key_code,
+                        keybindings::InputContext::Note,
+                    ) {
+                        send!(Event::State(model::Command::JamNoteOff {
+                            note,
+                            octave_modifier
+                        }));
                     }
-                    Action::RequestChangeScreenToSongEditor => {
-                        send!(Event::ChangeScreen(Screen::SongEditor));
+                }
+            }
+            Event::Action(action) => match action {
+                Action::RequestChangeScreenToDeviceSelection => {
+                    send!(Event::StartLoading);
+                    let event_tx_clone = self.event_sender.clone();
+                    thread::spawn(move || {
+                        event_tx_clone
+                            .send_event(Event::LoadingDone(
+                                AsyncAction::GetDevices(Devices::load()),
+                            ))
+                            .unwrap();
+                    });
+                }
+                Action::RequestChangeScreenToSongEditor => {
+                    send!(Event::ChangeScreen(Screen::SongEditor));
+                }
+                Action::SaveSong => match self.tracky.song_path.clone() {
+                    Some(path) => send!(Event::SaveSong(path)),
+                    None => send!(Event::Action(Action::SaveSongAs)),
+                },
+                Action::SaveSongAs => {
+                    self.tracky
+                        .open_popup(Popup::TextInput(text_input::Popup::new(
+                            "Save song as",
+                            self.tracky.song_path_or_default(),
+                            |path, event_sender| {
+                                event_sender
+                                    .send_event(Event::Composite(vec![
+                                        Event::ClosePopup,
+                                        Event::SaveSong(path.into()),
+                                    ]))
+                                    .unwrap();
+                            },
+                        )));
+                }
+                Action::OpenSong => {
+                    self.tracky
+                        .open_popup(Popup::TextInput(text_input::Popup::new(
+                            "Open song",
+                            self.tracky.song_path_or_default(),
+                            |path, event_sender| {
+                                event_sender
+                                    .send_event(Event::Composite(vec![
+                                        Event::ClosePopup,
+                                        Event::OpenSong(path.into()),
+                                    ]))
+                                    .unwrap();
+                            },
+                        )));
+                }
+                Action::ExportPatternText => {
+                    self.tracky
+                        .open_popup(Popup::TextInput(text_input::Popup::new(
+                            "Export patterns as text",
+                            self.tracky.pattern_text_path_or_default(),
+                            |path, event_sender| {
+                                event_sender
+                                    .send_event(Event::Composite(vec![
+                                        Event::ClosePopup,
+                                        Event::ExportPatternText(path.into()),
+                                    ]))
+                                    .unwrap();
+                            },
+                        )));
+                }
+                Action::ExportXm => {
+                    self.tracky
+                        .open_popup(Popup::TextInput(text_input::Popup::new(
+                            "Export song as XM",
+                            self.tracky.xm_path_or_default(),
+                            |path, event_sender| {
+                                event_sender
+                                    .send_event(Event::Composite(vec![
+                                        Event::ClosePopup,
+                                        Event::ExportXm(path.into()),
+                                    ]))
+                                    .unwrap();
+                            },
+                        )));
+                }
+                Action::ExportMidi => {
+                    self.tracky
+                        .open_popup(Popup::TextInput(text_input::Popup::new(
+                            "Export song as MIDI",
+                            self.tracky.midi_path_or_default(),
+                            |path, event_sender| {
+                                event_sender
+                                    .send_event(Event::Composite(vec![
+                                        Event::ClosePopup,
+                                        Event::ExportMidi(path.into()),
+                                    ]))
+                                    .unwrap();
+                            },
+                        )));
+                }
+                Action::RenderWav => {
+                    self.tracky
+                        .open_popup(Popup::TextInput(text_input::Popup::new(
+                            "Render song as WAV",
+                            self.tracky.wav_path_or_default(),
+                            |path, event_sender| {
+                                event_sender
+                                    .send_event(Event::Composite(vec![
+                                        Event::ClosePopup,
+                                        Event::ChooseWavSampleFormat(
+                                            path.into(),
+                                            RenderTarget::Song,
+                                        ),
+                                    ]))
+                                    .unwrap();
+                            },
+                        )));
+                }
+                Action::RenderStems => {
+                    self.tracky
+                        .open_popup(Popup::TextInput(text_input::Popup::new(
+                            "Render channel stems as WAV next to",
+                            self.tracky.wav_path_or_default(),
+                            |path, event_sender| {
+                                event_sender
+                                    .send_event(Event::Composite(vec![
+                                        Event::ClosePopup,
+                                        Event::ChooseStemsGlobalVolume(path.into()),
+                                    ]))
+                                    .unwrap();
+                            },
+                        )));
+                }
+                Action::HumanizeVelocity => {
+                    self.tracky
+                        .open_popup(Popup::TextInput(text_input::Popup::new(
+                            "Humanize velocities by up to (hex)",
+                            format!("{:02X}", self.tracky.state.humanize_spread),
+                            |spread, event_sender| match u8::from_str_radix(spread.trim(), 16) {
+                                Ok(spread) => event_sender
+                                    .send_event(Event::Composite(vec![
+                                        Event::ClosePopup,
+                                        Event::State(model::Command::HumanizeVelocity {
+                                            spread,
+                                            seed: fastrand::u64(..),
+                                        }),
+                                    ]))
+                                    .unwrap(),
+                                Err(err) => error!("Invalid spread {spread:?}: {err}"),
+                            },
+                        )));
+                }
+                Action::ChangeTempo => {
+                    self.tracky
+                        .open_popup(Popup::TextInput(text_input::Popup::new(
+                            "Tempo (BPM, rows per beat, ticks per row)",
+                            self.tracky.state.tempo.to_string(),
+                            |tempo, event_sender| match tempo.parse() {
+                                Ok(tempo) => event_sender
+                                    .send_event(Event::Composite(vec![
+                                        Event::ClosePopup,
+                                        Event::State(model::Command::SetTempo(tempo)),
+                                    ]))
+                                    .unwrap(),
+                                Err(err) => error!("Invalid tempo {tempo:?}: {err:#}"),
+                            },
+                        )));
+                }
+                Action::ChangeEnvelope => {
+                    let instruments = &self.tracky.state.instruments;
+                    let Some(instrument) = instruments.get_selected() else {
+                        warn!("No instrument in slot {:02X}", instruments.selected_index());
+                        return;
+                    };
+                    self.tracky
+                        .open_popup(Popup::TextInput(text_input::Popup::new(
+                            "Envelope (attack ms, decay ms, sustain %, release ms)",
+                            instrument.envelope.to_string(),
+                            |envelope, event_sender| match envelope.parse() {
+                                Ok(envelope) => event_sender
+                                    .send_event(Event::Composite(vec![
+                                        Event::ClosePopup,
+                                        Event::State(model::Command::SetEnvelope(envelope)),
+                                    ]))
+                                    .unwrap(),
+                                Err(err) => error!("Invalid envelope {envelope:?}: {err:#}"),
+                            },
+                        )));
+                }
+                Action::ChangeSampleLoops => {
+                    let instruments = &self.tracky.state.instruments;
+                    let Some(instrument) = instruments.get_selected() else {
+                        warn!("No instrument in slot {:02X}", instruments.selected_index());
+                        return;
+                    };
+                    let model::instrument::Kind::Sample { loops, .. } = instrument.source() else {
+                        warn!("Only samples have loops");
+                        return;
+                    };
+                    self.tracky
+                        .open_popup(Popup::TextInput(text_input::Popup::new(
+                            "Loops in frames (forward|pingpong start end or none; sustain loop)",
+                            loops.to_string(),
+                            |loops, event_sender| match loops.parse() {
+                                Ok(loops) => event_sender
+                                    .send_event(Event::Composite(vec![
+                                        Event::ClosePopup,
+                                        Event::State(model::Command::SetSampleLoops(loops)),
+                                    ]))
+                                    .unwrap(),
+                                Err(err) => error!("Invalid loops {loops:?}: {err:#}"),
+                            },
+                        )));
+                }
+                Action::ChangeInterpolation => {
+                    let instruments = &self.tracky.state.instruments;
+                    let Some(instrument) = instruments.get_selected() else {
+                        warn!("No instrument in slot {:02X}", instruments.selected_index());
+                        return;
+                    };
+                    let model::instrument::Kind::Sample { interpolation, .. } = instrument.source()
+                    else {
+                        warn!("Only samples are interpolated");
+                        return;
+                    };
+                    self.tracky
+                        .open_popup(Popup::TextInput(text_input::Popup::new(
+                            "Interpolation (nearest, linear, cubic or sinc)",
+                            interpolation.to_string(),
+                            |interpolation, event_sender| match interpolation.parse() {
+                                Ok(interpolation) => event_sender
+                                    .send_event(Event::Composite(vec![
+                                        Event::ClosePopup,
+                                        Event::State(model::Command::SetInterpolation(
+                                            interpolation,
+                                        )),
+                                    ]))
+                                    .unwrap(),
+                                Err(err) => {
+                                    error!("Invalid interpolation {interpolation:?}: {err:#}")
+                                }
+                            },
+                        )));
+                }
+                Action::ChangeResampleQuality => {
+                    self.tracky
+                        .open_popup(Popup::TextInput(text_input::Popup::new(
+                            "Resampling quality (fast, good or best)",
+                            self.tracky.state.resample_quality.to_string(),
+                            |quality, event_sender| match quality.parse() {
+                                Ok(quality) => event_sender
+                                    .send_event(Event::Composite(vec![
+                                        Event::ClosePopup,
+                                        Event::State(model::Command::SetResampleQuality(quality)),
+                                    ]))
+                                    .unwrap(),
+                                Err(err) => error!("Invalid quality {quality:?}: {err:#}"),
+                            },
+                        )));
+                }
+                Action::ImportPatternText => {
+                    self.tracky
+                        .open_popup(Popup::TextInput(text_input::Popup::new(
+                            "Import patterns from text",
+                            self.tracky.pattern_text_path_or_default(),
+                            |path, event_sender| {
+                                event_sender
+                                    .send_event(Event::Composite(vec![
+                                        Event::ClosePopup,
+                                        Event::ImportPatternText(path.into()),
+                                    ]))
+                                    .unwrap();
+                            },
+                        )));
+                }
+                action => {
+                    if let Some(popup) = &mut self.tracky.current_popup {
+                        popup.handle_event(action, self.event_sender.clone());
+                        return;
                     }
-                    action => {
-                        if let Some(popup) = &mut self.tracky.current_popup {
-                            let _ = popup.handle_event(action, self.event_sender.clone());
-                            return;
-                        }
+
+                    if !matches!(self.tracky.current_screen, Screen::SongEditor) {
                         self.tracky
                             .current_screen
                             .handle_event(action, self.event_sender.clone());
-                    }
-                }
-
-                match &mut self.tracky.current_screen {
-                    Screen::DeviceSelection(state) => {
-                        let _ = state.handle_action(action, self.event_sender.clone());
                         return;
                     }
-                    Screen::SongEditor => match action {
+
+                    match action {
                         Action::TogglePlay => {
                             if self.tracky.state.is_song_playing() {
                                 send!(Event::State(model::Command::StopSongPlayback));
@@ -221,7 +496,28 @@ impl ApplicationHandler<Event> for App<'_> {
                                 warn!("Select a device with F1 to play the song")
                             }
                         }
-                        Action::Cancel => {}
+                        Action::StartPlayback(start) => {
+                            if self.tracky.audio_state.is_some() {
+                                send!(Event::State(model::Command::StartPlayback(start)));
+                            } else {
+                                warn!("Select a device with F1 to play the song")
+                            }
+                        }
+                        Action::ToggleFollowPlaying => {
+                            send!(Event::State(model::Command::ToggleFollowPlaying))
+                        }
+                        Action::ToggleRecord => send!(Event::State(model::Command::ToggleRecord)),
+                        Action::ToggleJam => send!(Event::State(model::Command::ToggleJam)),
+                        Action::ToggleRecordQuantize => {
+                            send!(Event::State(model::Command::ToggleRecordQuantize))
+                        }
+                        Action::ToggleRecordPolyphony => {
+                            send!(Event::State(model::Command::ToggleRecordPolyphony))
+                        }
+                        Action::ToggleResampleOnDeviceChange => {
+                            send!(Event::State(model::Command::ToggleResampleOnDeviceChange))
+                        }
+                        Action::Cancel => send!(Event::State(model::Command::ClearSelection)),
                         Action::Confirm => {}
                         Action::Move(direction) => {
                             send!(Event::State(model::Command::MoveCursor(direction)))
@@ -269,11 +565,28 @@ impl ApplicationHandler<Event> for App<'_> {
                         Action::SetNoteField {
                             note,
                             octave_modifier,
-                        } => send!(Event::State(model::Command::SetNoteField {
+                        } if self.tracky.is_jamming() => {
+                            send!(Event::State(model::Command::JamNoteOn {
+                                note,
+                                octave_modifier
+                            }))
+                        }
+                        Action::SetNoteField {
                             note,
-                            octave_modifier
-                        })),
+                            octave_modifier,
+                        } => {
+                            let command = self
+                                .tracky
+                                .state
+                                .record_note(note, octave_modifier)
+                                .unwrap_or(model::Command::SetNoteField {
+                                    note,
+                                    octave_modifier,
+                                });
+                            send!(Event::State(command))
+                        }
                         Action::SetNoteCut => send!(Event::State(model::Command::SetNoteCut)),
+                        Action::SetNoteOff => send!(Event::State(model::Command::SetNoteOff)),
                         Action::ClearField => send!(Event::State(model::Command::ClearField)),
                         Action::SetOctaveField(octave_value) => {
                             send!(Event::State(model::Command::SetOctaveField(octave_value)))
@@ -284,16 +597,66 @@ impl ApplicationHandler<Event> for App<'_> {
                         Action::CreateNewPattern => {
                             send!(Event::State(model::Command::CreateNewPattern))
                         }
+                        Action::DuplicatePattern => {
+                            send!(Event::State(model::Command::DuplicatePattern))
+                        }
+                        Action::DeletePattern => send!(Event::State(model::Command::DeletePattern)),
                         Action::GoToNextPattern => {
                             send!(Event::State(model::Command::GoToNextPattern))
                         }
                         Action::GoToPreviousPattern => {
                             send!(Event::State(model::Command::GoToPreviousPattern))
                         }
+                        Action::ChangeCurrentOrder { increment } => {
+                            send!(Event::State(model::Command::ChangeCurrentOrder {
+                                increment
+                            }))
+                        }
+                        Action::InsertOrderEntry => {
+                            send!(Event::State(model::Command::InsertOrderEntry))
+                        }
+                        Action::RemoveOrderEntry => {
+                            send!(Event::State(model::Command::RemoveOrderEntry))
+                        }
+                        Action::Undo => send!(Event::State(model::Command::Undo)),
+                        Action::Redo => send!(Event::State(model::Command::Redo)),
+                        Action::ExtendSelection(direction) => {
+                            send!(Event::State(model::Command::ExtendSelection(direction)))
+                        }
+                        Action::Copy => send!(Event::State(model::Command::Copy)),
+                        Action::Cut => send!(Event::State(model::Command::Cut)),
+                        Action::Paste(mode) => send!(Event::State(model::Command::Paste(mode))),
+                        Action::Transpose { semitones } => {
+                            send!(Event::State(model::Command::Transpose { semitones }))
+                        }
+                        Action::InterpolateVelocity(interpolation) => {
+                            send!(Event::State(model::Command::InterpolateVelocity(
+                                interpolation
+                            )))
+                        }
+                        Action::ToggleMute => send!(Event::State(model::Command::ToggleMute)),
+                        Action::ToggleSolo => send!(Event::State(model::Command::ToggleSolo)),
                         Action::Text(text) => send!(Event::Text(text)),
-                    },
+                        Action::RequestChangeScreenToDeviceSelection
+                        | Action::RequestChangeScreenToSongEditor
+                        | Action::SaveSong
+                        | Action::SaveSongAs
+                        | Action::OpenSong
+                        | Action::ExportPatternText
+                        | Action::ImportPatternText
+                        | Action::ExportXm
+                        | Action::ExportMidi
+                        | Action::RenderWav
+                        | Action::RenderStems
+                        | Action::HumanizeVelocity
+                        | Action::ChangeTempo
+                        | Action::ChangeEnvelope
+                        | Action::ChangeSampleLoops
+                        | Action::ChangeInterpolation
+                        | Action::ChangeResampleQuality => unreachable!(),
+                    }
                 }
-            }
+            },
             Event::Panic(error) => {
                 panic!("{error:?}");
             }
@@ -309,6 +672,26 @@ impl ApplicationHandler<Event> for App<'_> {
                         device_selection::State::from(devices)
                     )))
                 }
+                event::AsyncAction::LoadSong(path, song) => match song {
+                    Ok(song) => {
+                        info!("Loaded song {path:?}");
+                        // Imported songs are saved next to the original in the native format
+                        self.tracky.song_path =
+                            Some(path.with_extension(format::native::EXTENSION));
+                        send!(Event::State(model::Command::LoadSong(song)));
+                    }
+                    Err(err) => error!("{err:?}"),
+                },
+                event::AsyncAction::RenderWav(path, target, result) => {
+                    self.tracky.close_popup();
+                    match (result, target) {
+                        (Ok(()), RenderTarget::Song) => info!("Rendered song to {path:?}"),
+                        (Ok(()), RenderTarget::Stems { .. }) => {
+                            info!("Rendered stems next to {path:?}")
+                        }
+                        (Err(err), _) => error!("{err:?}"),
+                    }
+                }
             },
             Event::StartLoading => self.tracky.loader_count += 1,
             Event::LoadingDone(async_action) => {
@@ -345,6 +728,142 @@ impl ApplicationHandler<Event> for App<'_> {
             Event::ChangeScreen(screen) => {
                 self.tracky.change_screen(screen);
             }
+            Event::SaveSong(path) => match format::native::save(&self.tracky.state.song(), &path) {
+                Ok(()) => {
+                    info!("Saved song to {path:?}");
+                    self.tracky.song_path = Some(path);
+                }
+                Err(err) => error!("{err:?}"),
+            },
+            Event::OpenSong(path) => {
+                send!(Event::StartLoading);
+                let event_tx_clone = self.event_sender.clone();
+                // Samples are brought to the device frame rate as they are loaded
+                let frame_rate = self.tracky.frame_rate();
+                let quality = self.tracky.state.resample_quality;
+                thread::spawn(move || {
+                    let song = format::open_song(&path).map(|mut song| {
+                        if let Some(frame_rate) = frame_rate {
+                            let count = song.instruments.resample(frame_rate, quality);
+                            if count > 0 {
+                                info!("Resampled {count} sample(s) to {frame_rate} Hz");
+                            }
+                        }
+                        Box::new(song)
+                    });
+                    event_tx_clone
+                        .send_event(Event::LoadingDone(AsyncAction::LoadSong(path, song)))
+                        .unwrap();
+                });
+            }
+            Event::ExportPatternText(path) => {
+                match format::text::save(&self.tracky.state.patterns, &path) {
+                    Ok(()) => info!("Exported patterns to {path:?}"),
+                    Err(err) => error!("{err:?}"),
+                }
+            }
+            Event::ExportXm(path) => match format::xm::save(&self.tracky.state.song(), &path) {
+                Ok(()) => info!("Exported song to {path:?}"),
+                Err(err) => error!("{err:?}"),
+            },
+            Event::ExportMidi(path) => match format::smf::save(&self.tracky.state.song(), &path) {
+                Ok(()) => info!("Exported song to {path:?}"),
+                Err(err) => error!("{err:?}"),
+            },
+            Event::ChooseStemsGlobalVolume(path) => {
+                self.tracky
+                    .open_popup(Popup::TextInput(text_input::Popup::new(
+                        "Apply global volume to stems (yes or no)",
+                        String::from("yes"),
+                        move |answer, event_sender| {
+                            let with_global_volume = match answer.trim().to_lowercase().as_str() {
+                                "yes" | "y" => true,
+                                "no" | "n" => false,
+                                _ => {
+                                    error!("Expected yes or no, got {answer:?}");
+                                    return;
+                                }
+                            };
+                            event_sender
+                                .send_event(Event::Composite(vec![
+                                    Event::ClosePopup,
+                                    Event::ChooseWavSampleFormat(
+                                        path.clone(),
+                                        RenderTarget::Stems { with_global_volume },
+                                    ),
+                                ]))
+                                .unwrap();
+                        },
+                    )));
+            }
+            Event::ChooseWavSampleFormat(path, target) => {
+                self.tracky
+                    .open_popup(Popup::TextInput(text_input::Popup::new(
+                        "WAV sample format (16, 24 or float)",
+                        String::from("16"),
+                        move |sample_format, event_sender| match sample_format.parse() {
+                            Ok(sample_format) => event_sender
+                                .send_event(Event::Composite(vec![
+                                    Event::ClosePopup,
+                                    Event::RenderWav(path.clone(), sample_format, target),
+                                ]))
+                                .unwrap(),
+                            Err(err) => error!("{err}"),
+                        },
+                    )));
+            }
+            Event::RenderWav(path, sample_format, target) => {
+                self.tracky
+                    .open_popup(Popup::Progress(progress::Popup::new("Rendering song")));
+                let song = self.tracky.state.song();
+                let event_tx_clone = self.event_sender.clone();
+                thread::spawn(move || {
+                    let mut last_percent = 0;
+                    let on_progress = |progress: f32| {
+                        // Only a hundred progress events at most are sent
+                        let percent = (progress * 100.0) as u32;
+                        if percent != last_percent {
+                            last_percent = percent;
+                            event_tx_clone
+                                .send_event(Event::RenderProgress(progress))
+                                .unwrap();
+                        }
+                    };
+                    let result = match target {
+                        RenderTarget::Song => {
+                            audio::render::render(song, Default::default(), on_progress).and_then(
+                                |signal| format::wav::save(signal.as_ref(), sample_format, &path),
+                            )
+                        }
+                        RenderTarget::Stems { with_global_volume } => audio::render::render_stems(
+                            song,
+                            Default::default(),
+                            with_global_volume,
+                            on_progress,
+                        )
+                        .and_then(|stems| format::wav::save_stems(&stems, sample_format, &path)),
+                    };
+                    event_tx_clone
+                        .send_event(Event::AsyncAction(AsyncAction::RenderWav(
+                            path, target, result,
+                        )))
+                        .unwrap();
+                });
+            }
+            Event::RenderProgress(progress) => {
+                if let Some(Popup::Progress(popup)) = &mut self.tracky.current_popup {
+                    popup.set_progress(progress);
+                }
+            }
+            Event::ImportPatternText(path) => match format::text::load(&path) {
+                Ok(patterns) => {
+                    info!("Imported patterns from {path:?}");
+                    let mut song = self.tracky.state.song();
+                    song.patterns = patterns;
+                    send!(Event::State(model::Command::LoadSong(Box::new(song))));
+                }
+                Err(err) => error!("{err:?}"),
+            },
         }
     }
 }
@@ -1,13 +1,18 @@
-use std::iter;
+use std::{f32::consts::TAU, iter};
 
 use crate::audio::{signal, Pan, Volume};
 
 use super::{
+    effect::Effect,
+    envelope::EnvelopeState,
     instrument::Instruments,
-    midi::note_to_freq,
+    midi::{note_to_freq, MidiValue},
     pattern::{NoteFieldValue, NoteName, OctaveValue, PatternLine},
 };
 
+/// Sample frames skipped per unit of the sample offset parameter
+const SAMPLE_OFFSET_STEP: usize = 256;
+
 #[derive(Clone, Debug)]
 pub struct PlayingInstrument {
     pub phase: f32,
@@ -19,6 +24,25 @@ pub struct Channel {
     pub current_note: Option<(NoteName, OctaveValue)>,
     pub current_volume: Option<Volume>,
     pub current_instrument: Option<PlayingInstrument>,
+    /// Effect of the current line
+    effect: Option<Effect>,
+    /// Semitones added to the note by the portamentos, until the next note
+    pitch_slide: f32,
+    /// Pitch slide the tone portamento goes to
+    tone_portamento_target: f32,
+    /// Semitones added to the note during the current tick, by the arpeggio or the vibrato
+    pitch_modulation: f32,
+    /// In cycles
+    vibrato_phase: f32,
+    /// Line waiting for its note delay
+    delayed_line: Option<PatternLine>,
+    /// Sample frame the note starts from, applied by the next mix as it needs the instrument
+    pending_sample_offset: Option<usize>,
+    /// Envelope of the current note, with the one of the instrument
+    envelope: EnvelopeState,
+    /// The note-off leaves the sustain loop of the sample, applied by the next mix as it needs the
+    /// instrument
+    pending_release: bool,
 }
 
 impl Channel {
@@ -27,6 +51,15 @@ impl Channel {
             current_note: None,
             current_volume: None,
             current_instrument: None,
+            effect: None,
+            pitch_slide: 0.0,
+            tone_portamento_target: 0.0,
+            pitch_modulation: 0.0,
+            vibrato_phase: 0.0,
+            delayed_line: None,
+            pending_sample_offset: None,
+            envelope: EnvelopeState::start(),
+            pending_release: false,
         }
     }
 
@@ -34,23 +67,110 @@ impl Channel {
         self.current_note.is_some() && self.current_instrument.is_some()
     }
 
+    /// Starts a line, this is its tick 0
     pub fn setup_line(&mut self, line: &PatternLine) {
+        self.effect = line.effect.value().and_then(Effect::from_field_value);
+        self.pitch_modulation = 0.0;
+        self.delayed_line = None;
+        match self.effect {
+            Some(Effect::NoteDelay(delay)) if delay > 0 => self.delayed_line = Some(line.clone()),
+            _ => self.trigger_line(line),
+        }
+    }
+
+    /// Runs the effect of the current line for one of its ticks after the first one
+    pub fn perform_tick(&mut self, tick: u32) {
+        self.pitch_modulation = 0.0;
+        let Some(effect) = self.effect else {
+            return;
+        };
+        match effect {
+            Effect::Arpeggio { first, second } => {
+                self.pitch_modulation = match tick % 3 {
+                    0 => 0.0,
+                    1 => first as f32,
+                    _ => second as f32,
+                };
+            }
+            Effect::PortamentoUp(speed) => self.pitch_slide += speed as f32 / 16.0,
+            Effect::PortamentoDown(speed) => self.pitch_slide -= speed as f32 / 16.0,
+            Effect::TonePortamento(speed) => {
+                let step = speed as f32 / 16.0;
+                self.pitch_slide +=
+                    (self.tone_portamento_target - self.pitch_slide).clamp(-step, step);
+            }
+            Effect::Vibrato { speed, depth } => {
+                self.vibrato_phase = (self.vibrato_phase + speed as f32 / 64.0).fract();
+                self.pitch_modulation = (self.vibrato_phase * TAU).sin() * depth as f32 / 16.0;
+            }
+            Effect::VolumeSlide { up, down } => {
+                // Sliding up wins when both are set
+                let slide = if up > 0 { up as f32 } else { -(down as f32) } / 64.0;
+                let volume = self.current_volume.unwrap_or_default().value() + slide;
+                self.current_volume = Some(Volume::new_clamped(volume));
+            }
+            Effect::Retrigger(interval) => {
+                if interval > 0 && tick.is_multiple_of(interval as u32) {
+                    if let Some(playing_instrument) = self.current_instrument.as_mut() {
+                        playing_instrument.phase = 0.0;
+                    }
+                    self.envelope = EnvelopeState::start();
+                    self.pending_release = false;
+                }
+            }
+            Effect::NoteDelay(delay) => {
+                if tick == delay as u32 {
+                    if let Some(line) = self.delayed_line.take() {
+                        self.trigger_line(&line);
+                    }
+                }
+            }
+            Effect::SampleOffset(_) => {}
+        }
+    }
+
+    fn trigger_line(&mut self, line: &PatternLine) {
         if let Some(note) = line.note.value().cloned() {
-            self.current_note = match note {
-                NoteFieldValue::Note(note, octave) => {
+            match (note, self.current_note, self.effect) {
+                // The playing note slides to the new one instead of being replaced
+                (
+                    NoteFieldValue::Note(note, octave),
+                    Some(current_note),
+                    Some(Effect::TonePortamento(_)),
+                ) => {
+                    self.tone_portamento_target = (MidiValue::from((note, octave)).value()
+                        - MidiValue::from(current_note).value())
+                        as f32;
+                }
+                (NoteFieldValue::Note(note, octave), _, _) => {
                     if let Some((_, playing_instrument)) =
                         self.current_note.zip(self.current_instrument.as_mut())
                     {
                         playing_instrument.phase = 0.0;
                     }
-                    Some((note, octave))
+                    self.current_note = Some((note, octave));
+                    self.envelope = EnvelopeState::start();
+                    self.pending_release = false;
+                    self.pitch_slide = 0.0;
+                    self.tone_portamento_target = 0.0;
+                    self.vibrato_phase = 0.0;
+                    self.pending_sample_offset = match self.effect {
+                        Some(Effect::SampleOffset(offset)) => {
+                            Some(offset as usize * SAMPLE_OFFSET_STEP)
+                        }
+                        _ => None,
+                    };
+                }
+                (NoteFieldValue::Off, _, _) => {
+                    self.pending_release |= !self.envelope.is_released();
+                    self.envelope.release();
                 }
-                NoteFieldValue::Cut => {
+                (NoteFieldValue::Cut, _, _) => {
+                    self.current_note = None;
                     self.current_volume = None;
                     self.current_instrument = None;
-                    None
                 }
-            };
+            }
         }
         if let Some(volume) = line.velocity.get_percentage().map(Volume::new_unchecked) {
             self.current_volume = Some(volume);
@@ -75,36 +195,105 @@ impl Channel {
         instruments: &Instruments,
         global_volume: Volume,
     ) {
+        let sample_offset = self.pending_sample_offset.take();
+        let release = std::mem::take(&mut self.pending_release);
         if let (Some((note, octave)), volume, Some(PlayingInstrument { index, phase })) = (
             self.current_note,
             self.current_volume,
             &mut self.current_instrument,
         ) {
-            let freq = note_to_freq(note, octave);
+            let freq = note_to_freq(note, octave)
+                * ((self.pitch_slide + self.pitch_modulation) / 12.0).exp2();
             let frame_rate = output_signal.frame_rate;
 
             if let Some(instrument) = instruments.get(*index) {
+                if let Some(frame) = sample_offset {
+                    *phase = instrument.source().frame_phase(frame);
+                }
+                if release {
+                    *phase = instrument.source().release_phase(*phase);
+                }
+                let released = self.envelope.is_released();
+                let envelope = &mut self.envelope;
                 for (output, generated) in output_signal.iter_mut().zip(iter::repeat_with(|| {
+                    let level = envelope.next_level(&instrument.envelope, frame_rate);
                     instrument.next_frame(
                         freq,
-                        volume.unwrap_or_default() * global_volume,
+                        volume.unwrap_or_default() * global_volume * Volume::new_unchecked(level),
                         Pan::DEFAULT,
                         phase,
                         frame_rate,
+                        released,
                     )
                 })) {
                     *output += generated;
                 }
+                // The note is over once released
+                if self.envelope.is_done() {
+                    self.current_note = None;
+                }
             }
         }
     }
 }
 
+/// Mute and solo switches of the channels. A channel is heard unless it is muted, or some
+/// channels are soloed but not this one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MuteSolo {
+    muted: Vec<bool>,
+    soloed: Vec<bool>,
+}
+
+impl MuteSolo {
+    pub fn new(channel_count: usize) -> Self {
+        Self {
+            muted: vec![false; channel_count],
+            soloed: vec![false; channel_count],
+        }
+    }
+
+    /// Keeps the switches of the remaining channels, added channels are heard
+    pub fn resize(&mut self, channel_count: usize) {
+        self.muted.resize(channel_count, false);
+        self.soloed.resize(channel_count, false);
+    }
+
+    pub fn is_muted(&self, channel: usize) -> bool {
+        self.muted.get(channel).copied().unwrap_or_default()
+    }
+
+    pub fn is_soloed(&self, channel: usize) -> bool {
+        self.soloed.get(channel).copied().unwrap_or_default()
+    }
+
+    pub fn set_muted(&mut self, channel: usize, muted: bool) {
+        if let Some(switch) = self.muted.get_mut(channel) {
+            *switch = muted;
+        }
+    }
+
+    pub fn set_soloed(&mut self, channel: usize, soloed: bool) {
+        if let Some(switch) = self.soloed.get_mut(channel) {
+            *switch = soloed;
+        }
+    }
+
+    pub fn is_audible(&self, channel: usize) -> bool {
+        !self.is_muted(channel)
+            && (self.is_soloed(channel) || !self.soloed.iter().any(|soloed| *soloed))
+    }
+}
+
 #[cfg(test)]
 mod test {
 
-    use crate::model::pattern::{
-        Field, HexDigit, NoteFieldValue, PatternLine, PatternLineDescriptor,
+    use crate::{
+        audio::dsp::interpolation::InterpolationMode,
+        model::{
+            instrument::{Instrument, Kind},
+            pattern::{Field, HexDigit, NoteFieldValue, PatternLine},
+        },
     };
 
     use super::*;
@@ -115,113 +304,7 @@ mod test {
 
     // example line: "C#5 5F 03"
     fn make_line(line: &'static str) -> PatternLine {
-        assert_eq!(
-            line.len(),
-            PatternLineDescriptor::LINE_LEN as usize + PatternLineDescriptor::COUNT - 1
-        );
-        fn parse_note(note: &'static str) -> Option<NoteFieldValue> {
-            assert_eq!(PatternLineDescriptor::Note.field_len(), note.len());
-            if note == "CUT" {
-                Some(NoteFieldValue::Cut)
-            } else if note == "..." {
-                None
-            } else {
-                let (note_1, note_2, octave) = {
-                    let mut chars = note.chars();
-                    let n1 = chars.next().unwrap();
-                    let n2 = chars.next().unwrap();
-                    let o = chars.next().unwrap();
-                    (n1, n2, o)
-                };
-                assert!(note_1 as i32 >= 65 && note_1 as i32 <= 71);
-                assert!(note_2 == '#' || note_2 == '-');
-                assert!(octave as i32 >= 48 && octave as i32 <= 57);
-
-                let is_sharp = note_2 == '#';
-
-                let note_name = if is_sharp {
-                    match note_1 {
-                        'C' => NoteName::CSharp,
-                        'D' => NoteName::DSharp,
-                        'F' => NoteName::FSharp,
-                        'G' => NoteName::GSharp,
-                        'A' => NoteName::ASharp,
-                        _ => panic!("illegal value"),
-                    }
-                } else {
-                    match note_1 {
-                        'C' => NoteName::C,
-                        'D' => NoteName::D,
-                        'E' => NoteName::E,
-                        'F' => NoteName::F,
-                        'G' => NoteName::G,
-                        'A' => NoteName::A,
-                        'B' => NoteName::B,
-                        _ => panic!("illegal value"),
-                    }
-                };
-
-                let octave = match octave {
-                    '0' => OctaveValue::OCTAVE_0,
-                    '1' => OctaveValue::OCTAVE_1,
-                    '2' => OctaveValue::OCTAVE_2,
-                    '3' => OctaveValue::OCTAVE_3,
-                    '4' => OctaveValue::OCTAVE_4,
-                    '5' => OctaveValue::OCTAVE_5,
-                    '6' => OctaveValue::OCTAVE_6,
-                    '7' => OctaveValue::OCTAVE_7,
-                    '8' => OctaveValue::OCTAVE_8,
-                    '9' => OctaveValue::OCTAVE_9,
-                    _ => unreachable!(),
-                };
-
-                Some(NoteFieldValue::Note(note_name, octave))
-            }
-        }
-
-        fn parse_hex(hex: &'static str) -> Option<(HexDigit, HexDigit)> {
-            assert_eq!(2, hex.len());
-
-            if hex == ".." {
-                None
-            } else {
-                let mut chars = hex.chars();
-                let hex_1 = chars.next().unwrap();
-                let hex_2 = chars.next().unwrap();
-
-                fn parse_digit(c: char) -> HexDigit {
-                    match c {
-                        'A' => HexDigit::HEX_A,
-                        'B' => HexDigit::HEX_B,
-                        'C' => HexDigit::HEX_C,
-                        'D' => HexDigit::HEX_D,
-                        'E' => HexDigit::HEX_E,
-                        'F' => HexDigit::HEX_F,
-                        '0' => HexDigit::HEX_0,
-                        '1' => HexDigit::HEX_1,
-                        '2' => HexDigit::HEX_2,
-                        '3' => HexDigit::HEX_3,
-                        '4' => HexDigit::HEX_4,
-                        '5' => HexDigit::HEX_5,
-                        '6' => HexDigit::HEX_6,
-                        '7' => HexDigit::HEX_7,
-                        '8' => HexDigit::HEX_8,
-                        '9' => HexDigit::HEX_9,
-                        _ => panic!("Illegal character"),
-                    }
-                }
-                Some((parse_digit(hex_1), parse_digit(hex_2)))
-            }
-        }
-
-        let note = parse_note(&line[0..3]).map_or_else(Field::empty, Field::new);
-        let velocity = parse_hex(&line[4..6]).map_or_else(Field::empty, Field::new);
-        let instrument = parse_hex(&line[7..9]).map_or_else(Field::empty, Field::new);
-        PatternLine {
-            note,
-            velocity,
-            instrument,
-        }
+        line.parse().unwrap()
     }
 
     #[test]
@@ -311,6 +394,7 @@ mod test {
                 )),
                 velocity: Field::new((HexDigit::HEX_2, HexDigit::HEX_4)),
                 instrument: Field::new((HexDigit::HEX_A, HexDigit::HEX_B)),
+                effect: Field::empty(),
             },
             make_line("D#3 24 AB")
         );
@@ -323,6 +407,7 @@ mod test {
                 note: Field::empty(),
                 velocity: Field::new((HexDigit::HEX_2, HexDigit::HEX_4)),
                 instrument: Field::new((HexDigit::HEX_A, HexDigit::HEX_B)),
+                effect: Field::empty(),
             },
             make_line("... 24 AB")
         );
@@ -338,6 +423,7 @@ mod test {
                 )),
                 velocity: Field::empty(),
                 instrument: Field::new((HexDigit::HEX_A, HexDigit::HEX_B)),
+                effect: Field::empty(),
             },
             make_line("D#3 .. AB")
         );
@@ -353,6 +439,7 @@ mod test {
                 )),
                 velocity: Field::new((HexDigit::HEX_A, HexDigit::HEX_B)),
                 instrument: Field::empty(),
+                effect: Field::empty(),
             },
             make_line("D#3 AB ..")
         );
@@ -503,4 +590,196 @@ mod test {
             &channel,
         );
     }
+
+    /// Plays the lines one after the other, each for `ticks_per_row` ticks
+    fn play_lines(channel: &mut Channel, lines: &[&'static str], ticks_per_row: u32) {
+        for line in lines {
+            channel.setup_line(&make_line(line));
+            for tick in 1..ticks_per_row {
+                channel.perform_tick(tick);
+            }
+        }
+    }
+
+    #[test]
+    fn test_arpeggio_cycles_every_tick() {
+        let mut channel = get_channel();
+        channel.setup_line(&make_line("C-5 .. 00 047"));
+        let mut offsets = vec![channel.pitch_modulation];
+        for tick in 1..6 {
+            channel.perform_tick(tick);
+            offsets.push(channel.pitch_modulation);
+        }
+        assert_eq!(vec![0.0, 4.0, 7.0, 0.0, 4.0, 7.0], offsets);
+    }
+
+    #[test]
+    fn test_portamento_lasts_until_the_next_note() {
+        let mut channel = get_channel();
+        play_lines(&mut channel, &["C-5 .. 00 110", "... .. .. 210"], 3);
+        approx::assert_relative_eq!(0.0, channel.pitch_slide);
+        play_lines(&mut channel, &["... .. .. 110", "... .. .. ..."], 3);
+        approx::assert_relative_eq!(2.0, channel.pitch_slide);
+
+        channel.setup_line(&make_line("D-5 .. .. ..."));
+        approx::assert_relative_eq!(0.0, channel.pitch_slide);
+    }
+
+    #[test]
+    fn test_tone_portamento_slides_without_retriggering() {
+        let mut channel = get_channel();
+        play_lines(&mut channel, &["C-5 .. 00 ...", "D-5 .. .. 310"], 4);
+        assert_eq!(
+            Some((NoteName::C, OctaveValue::OCTAVE_5)),
+            channel.current_note
+        );
+        approx::assert_relative_eq!(2.0, channel.pitch_slide);
+
+        // The slide stops on the target note
+        play_lines(&mut channel, &["... .. .. 3FF"], 4);
+        approx::assert_relative_eq!(2.0, channel.pitch_slide);
+    }
+
+    #[test]
+    fn test_volume_slide_is_clamped() {
+        let mut channel = get_channel();
+        play_lines(&mut channel, &["C-5 08 00 A0F"], 4);
+        assert_channel_state(
+            Some((NoteName::C, OctaveValue::OCTAVE_5)),
+            Some(0.0),
+            Some(0),
+            &channel,
+        );
+        play_lines(&mut channel, &["... .. .. A80"], 6);
+        approx::assert_relative_eq!(0.625, channel.current_volume.unwrap().value());
+    }
+
+    #[test]
+    fn test_note_delay_waits_for_its_tick() {
+        let mut channel = get_channel();
+        channel.setup_line(&make_line("C-5 40 02 ED2"));
+        assert_channel_state(None, None, None, &channel);
+        channel.perform_tick(1);
+        assert_channel_state(None, None, None, &channel);
+        channel.perform_tick(2);
+        assert_channel_state(
+            Some((NoteName::C, OctaveValue::OCTAVE_5)),
+            Some(0.25),
+            Some(2),
+            &channel,
+        );
+    }
+
+    #[test]
+    fn test_retrigger_restarts_the_instrument() {
+        let mut channel = get_channel();
+        channel.setup_line(&make_line("C-5 .. 00 E92"));
+        let set_phase = |channel: &mut Channel| {
+            channel.current_instrument.as_mut().unwrap().phase = 0.5;
+        };
+        let phase = |channel: &Channel| channel.current_instrument.as_ref().unwrap().phase;
+
+        set_phase(&mut channel);
+        channel.perform_tick(1);
+        approx::assert_relative_eq!(0.5, phase(&channel));
+        channel.perform_tick(2);
+        approx::assert_relative_eq!(0.0, phase(&channel));
+    }
+
+    /// Peak level of the channel mixed for `duration_ms`, with a slow release on instrument 0
+    fn mix_peak(channel: &mut Channel, duration_ms: u64) -> f32 {
+        let mut instrument = Instrument::from(Kind::Square);
+        instrument.volume = Volume::MAX;
+        instrument.envelope.release = std::time::Duration::from_millis(100);
+        let mut instruments = Instruments::empty();
+        instruments.set(0, instrument).unwrap();
+        let mut output = signal::stereo::Owned::from_duration(
+            std::time::Duration::from_millis(duration_ms),
+            48000.0,
+        );
+        channel.collect_mix_in(output.as_mut(), &instruments, Volume::MAX);
+        output
+            .iter()
+            .map(|frame| frame.0[0].abs())
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn test_note_off_releases_the_note() {
+        let mut channel = get_channel();
+        channel.setup_line(&make_line("C-5 .. 00 ..."));
+        approx::assert_relative_eq!(1.0, mix_peak(&mut channel, 10));
+
+        channel.setup_line(&make_line("OFF .. .. ..."));
+        let released_peak = mix_peak(&mut channel, 50);
+        assert!(
+            released_peak > 0.9 && released_peak < 1.0,
+            "{released_peak}"
+        );
+        assert!(channel.is_playing());
+        mix_peak(&mut channel, 100);
+        assert!(!channel.is_playing());
+    }
+
+    #[test]
+    fn test_note_cut_skips_the_release() {
+        let mut channel = get_channel();
+        channel.setup_line(&make_line("C-5 .. 00 ..."));
+        mix_peak(&mut channel, 10);
+        channel.setup_line(&make_line("CUT .. .. ..."));
+        assert!(!channel.is_playing());
+        approx::assert_relative_eq!(0.0, mix_peak(&mut channel, 10));
+    }
+
+    #[test]
+    fn test_note_off_leaves_the_sustain_loop() {
+        let mut instrument = Instrument::from(Kind::Sample {
+            name: "Held".into(),
+            signal: signal::stereo::Owned::from_samples(vec![1.0; 8], 1000.0).unwrap(),
+            loops: "none; forward 0 4".parse().unwrap(),
+            interpolation: InterpolationMode::default(),
+        });
+        instrument.envelope.release = std::time::Duration::from_secs(1);
+        let mut instruments = Instruments::empty();
+        instruments.set(0, instrument).unwrap();
+        let mix = |channel: &mut Channel, frame_count| {
+            let mut output = signal::stereo::Owned::from_duration(
+                std::time::Duration::from_millis(frame_count),
+                1000.0,
+            );
+            channel.collect_mix_in(output.as_mut(), &instruments, Volume::MAX);
+            output.iter().map(|frame| frame.0[0]).collect::<Vec<_>>()
+        };
+
+        let mut channel = get_channel();
+        channel.setup_line(&make_line("C-5 .. 00 ..."));
+        // The 4 frames loop plays way past the end of the sample
+        assert!(mix(&mut channel, 50).iter().all(|sample| *sample > 0.99));
+
+        channel.setup_line(&make_line("OFF .. .. ..."));
+        let released = mix(&mut channel, 10);
+        assert!(released[0] > 0.99, "{released:?}");
+        assert_eq!(vec![0.0; 3], released[7..]);
+        assert!(channel.is_playing());
+    }
+
+    #[test]
+    fn test_soloed_channels_silence_the_others() {
+        let mut mute_solo = MuteSolo::new(3);
+        assert!((0..3).all(|channel| mute_solo.is_audible(channel)));
+
+        mute_solo.set_muted(0, true);
+        assert_eq!(
+            vec![false, true, true],
+            (0..3).map(|c| mute_solo.is_audible(c)).collect::<Vec<_>>()
+        );
+
+        mute_solo.set_soloed(0, true);
+        mute_solo.set_soloed(2, true);
+        // Muting wins over soloing
+        assert_eq!(
+            vec![false, false, true],
+            (0..3).map(|c| mute_solo.is_audible(c)).collect::<Vec<_>>()
+        );
+    }
 }
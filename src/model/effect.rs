@@ -0,0 +1,94 @@
+//! Effects of the effect column, run by the channels on every tick of their row.
+//!
+//! Commands follow the ProTracker numbering, the ones missing here are kept in the patterns but
+//! do nothing:
+//!
+//! | Field | Effect                                                                   |
+//! |-------|--------------------------------------------------------------------------|
+//! | `0xy` | Arpeggio, cycles every tick between the note, +x and +y semitones        |
+//! | `1xx` | Portamento up by xx sixteenths of a semitone per tick                    |
+//! | `2xx` | Portamento down by xx sixteenths of a semitone per tick                  |
+//! | `3xx` | Slides to the note of the line by xx sixteenths of a semitone per tick   |
+//! | `4xy` | Vibrato, x sixty-fourths of a cycle per tick, y sixteenths of a semitone |
+//! | `9xx` | Starts the sample at frame xx * 256                                      |
+//! | `Axy` | Volume slide, up by x or down by y sixty-fourths per tick                |
+//! | `E9x` | Retriggers the note every x ticks                                        |
+//! | `EDx` | Delays the line by x ticks                                               |
+//!
+//! Parameters are not remembered, `300` does not continue a previous slide.
+
+use super::pattern::EffectFieldValue;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Effect {
+    Arpeggio { first: u8, second: u8 },
+    PortamentoUp(u8),
+    PortamentoDown(u8),
+    TonePortamento(u8),
+    Vibrato { speed: u8, depth: u8 },
+    SampleOffset(u8),
+    VolumeSlide { up: u8, down: u8 },
+    Retrigger(u8),
+    NoteDelay(u8),
+}
+
+impl Effect {
+    /// `None` for the unsupported commands, and `000` which is no arpeggio at all
+    pub fn from_field_value(value: &EffectFieldValue) -> Option<Effect> {
+        let parameter = value.parameter_u8();
+        let (x, y) = (value.parameter.0.value(), value.parameter.1.value());
+        let effect = match value.command.value() {
+            0x0 if parameter == 0 => return None,
+            0x0 => Effect::Arpeggio {
+                first: x,
+                second: y,
+            },
+            0x1 => Effect::PortamentoUp(parameter),
+            0x2 => Effect::PortamentoDown(parameter),
+            0x3 => Effect::TonePortamento(parameter),
+            0x4 => Effect::Vibrato { speed: x, depth: y },
+            0x9 => Effect::SampleOffset(parameter),
+            0xA => Effect::VolumeSlide { up: x, down: y },
+            0xE if x == 0x9 => Effect::Retrigger(y),
+            0xE if x == 0xD => Effect::NoteDelay(y),
+            _ => return None,
+        };
+        Some(effect)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::model::pattern::PatternLine;
+
+    use super::*;
+
+    fn effect(text: &str) -> Option<Effect> {
+        let line = format!("... .. .. {text}").parse::<PatternLine>().unwrap();
+        Effect::from_field_value(line.effect.value().unwrap())
+    }
+
+    #[test]
+    fn test_effects_are_decoded() {
+        assert_eq!(
+            Some(Effect::Arpeggio {
+                first: 4,
+                second: 7
+            }),
+            effect("047")
+        );
+        assert_eq!(Some(Effect::PortamentoUp(0x20)), effect("120"));
+        assert_eq!(Some(Effect::TonePortamento(0xFF)), effect("3FF"));
+        assert_eq!(Some(Effect::Vibrato { speed: 8, depth: 2 }), effect("482"));
+        assert_eq!(Some(Effect::VolumeSlide { up: 0, down: 4 }), effect("A04"));
+        assert_eq!(Some(Effect::Retrigger(3)), effect("E93"));
+        assert_eq!(Some(Effect::NoteDelay(2)), effect("ED2"));
+    }
+
+    #[test]
+    fn test_unsupported_effects_do_nothing() {
+        for text in ["000", "B01", "E12", "F06"] {
+            assert_eq!(None, effect(text), "{text}");
+        }
+    }
+}
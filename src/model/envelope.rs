@@ -0,0 +1,240 @@
+//! Amplitude envelopes of the instruments.
+//!
+//! A note rises from silence to the full level in `attack`, falls to the `sustain` level in
+//! `decay` and stays there until its note-off, then fades out in `release`. A note cut skips the
+//! release. Null durations are instantaneous, the default envelope plays notes at full level.
+
+use std::{fmt, str::FromStr, time::Duration};
+
+use anyhow::{bail, Context};
+
+use crate::audio::Volume;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Envelope {
+    pub attack: Duration,
+    pub decay: Duration,
+    pub sustain: Volume,
+    pub release: Duration,
+}
+
+impl Default for Envelope {
+    fn default() -> Self {
+        Self {
+            attack: Duration::ZERO,
+            decay: Duration::ZERO,
+            sustain: Volume::MAX,
+            release: Duration::ZERO,
+        }
+    }
+}
+
+/// Printed as "<attack ms> <decay ms> <sustain %> <release ms>", e.g. "10 200 60 500"
+impl fmt::Display for Envelope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            self.attack.as_millis(),
+            self.decay.as_millis(),
+            (self.sustain.value() * 100.0).round(),
+            self.release.as_millis()
+        )
+    }
+}
+
+impl FromStr for Envelope {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let values = s.split_whitespace().collect::<Vec<_>>();
+        let [attack, decay, sustain, release] = values[..] else {
+            bail!("Expected \"<attack ms> <decay ms> <sustain %> <release ms>\", found {s:?}");
+        };
+        let duration = |text: &str, name: &str| {
+            text.parse()
+                .map(Duration::from_millis)
+                .with_context(|| format!("Invalid {name} {text:?}"))
+        };
+        let sustain_percentage = sustain
+            .parse::<f32>()
+            .ok()
+            .filter(|percentage| (0.0..=100.0).contains(percentage))
+            .with_context(|| format!("Invalid sustain {sustain:?}, expected 0 to 100"))?;
+        Ok(Self {
+            attack: duration(attack, "attack")?,
+            decay: duration(decay, "decay")?,
+            sustain: Volume::new_clamped(sustain_percentage / 100.0),
+            release: duration(release, "release")?,
+        })
+    }
+}
+
+/// Level under which a released note is over, the fade out may not land exactly on 0
+const SILENCE: f32 = 1e-6;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Stage {
+    Attack,
+    Decay,
+    Sustain,
+    /// Fading out from `from`
+    Release {
+        from: f32,
+    },
+    Done,
+}
+
+/// Progress of the envelope of a playing note
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EnvelopeState {
+    stage: Stage,
+    level: f32,
+}
+
+impl EnvelopeState {
+    pub fn start() -> Self {
+        Self {
+            stage: Stage::Attack,
+            level: 0.0,
+        }
+    }
+
+    /// Note-off, the note fades out from its current level
+    pub fn release(&mut self) {
+        if self.stage != Stage::Done {
+            self.stage = Stage::Release { from: self.level };
+        }
+    }
+
+    /// The note-off happened
+    pub fn is_released(&self) -> bool {
+        matches!(self.stage, Stage::Release { .. } | Stage::Done)
+    }
+
+    /// The release is over
+    pub fn is_done(&self) -> bool {
+        self.stage == Stage::Done
+    }
+
+    /// Level of the next frame, from 0 to 1
+    pub fn next_level(&mut self, envelope: &Envelope, frame_rate: f32) -> f32 {
+        // Level change per frame going through `range` in `duration`
+        let step = |range: f32, duration: Duration| {
+            let frame_count = duration.as_secs_f32() * frame_rate;
+            if frame_count < 1.0 {
+                f32::INFINITY
+            } else {
+                range / frame_count
+            }
+        };
+        let sustain = envelope.sustain.value();
+        match self.stage {
+            Stage::Attack => {
+                self.level += step(1.0, envelope.attack);
+                if self.level >= 1.0 {
+                    self.level = 1.0;
+                    self.stage = Stage::Decay;
+                }
+            }
+            Stage::Decay => {
+                self.level -= step(1.0 - sustain, envelope.decay);
+                if self.level <= sustain {
+                    self.level = sustain;
+                    self.stage = Stage::Sustain;
+                }
+            }
+            Stage::Sustain => self.level = sustain,
+            Stage::Release { from } => {
+                self.level -= step(from, envelope.release);
+                if self.level <= SILENCE {
+                    self.level = 0.0;
+                    self.stage = Stage::Done;
+                }
+            }
+            Stage::Done => self.level = 0.0,
+        }
+        self.level
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const FRAME_RATE: f32 = 1000.0;
+
+    fn levels(state: &mut EnvelopeState, envelope: &Envelope, count: usize) -> Vec<f32> {
+        (0..count)
+            .map(|_| state.next_level(envelope, FRAME_RATE))
+            .collect()
+    }
+
+    fn envelope(attack: u64, decay: u64, sustain: f32, release: u64) -> Envelope {
+        Envelope {
+            attack: Duration::from_millis(attack),
+            decay: Duration::from_millis(decay),
+            sustain: Volume::new_unchecked(sustain),
+            release: Duration::from_millis(release),
+        }
+    }
+
+    #[test]
+    fn test_default_envelope_plays_at_full_level_until_released() {
+        let envelope = Envelope::default();
+        let mut state = EnvelopeState::start();
+        assert_eq!(vec![1.0; 3], levels(&mut state, &envelope, 3));
+        state.release();
+        assert_eq!(vec![0.0], levels(&mut state, &envelope, 1));
+        assert!(state.is_done());
+    }
+
+    #[test]
+    fn test_stages() {
+        let envelope = envelope(4, 2, 0.5, 5);
+        let mut state = EnvelopeState::start();
+        let expected = [0.25, 0.5, 0.75, 1.0, 0.75, 0.5, 0.5, 0.5];
+        for (expected, actual) in expected.iter().zip(levels(&mut state, &envelope, 8)) {
+            approx::assert_relative_eq!(*expected, actual);
+        }
+
+        state.release();
+        let expected = [0.4, 0.3, 0.2, 0.1, 0.0];
+        for (expected, actual) in expected.iter().zip(levels(&mut state, &envelope, 5)) {
+            approx::assert_relative_eq!(*expected, actual, epsilon = 1e-6);
+        }
+        assert!(state.is_done());
+    }
+
+    #[test]
+    fn test_release_during_the_attack_fades_from_the_current_level() {
+        let envelope = envelope(10, 0, 1.0, 2);
+        let mut state = EnvelopeState::start();
+        levels(&mut state, &envelope, 4);
+        state.release();
+        let levels = levels(&mut state, &envelope, 2);
+        approx::assert_relative_eq!(0.2, levels[0]);
+        approx::assert_relative_eq!(0.0, levels[1]);
+    }
+
+    #[test]
+    fn test_envelope_round_trips_through_text() {
+        let envelope = envelope(10, 200, 0.6, 500);
+        assert_eq!("10 200 60 500", envelope.to_string());
+        assert_eq!(envelope, envelope.to_string().parse().unwrap());
+    }
+
+    #[test]
+    fn test_invalid_envelopes_are_errors() {
+        for text in [
+            "",
+            "1 2 3",
+            "1 2 101 4",
+            "-1 2 50 4",
+            "a 2 50 4",
+            "1 2 50 4 5",
+        ] {
+            assert!(text.parse::<Envelope>().is_err(), "{text:?}");
+        }
+    }
+}
@@ -0,0 +1,217 @@
+//! Undo / redo of the pattern edits.
+//!
+//! Every editing command gives the [`Edit`]s reverting it, they are kept as one step along with
+//! the cursor before and after the command. Undoing a step applies its edits, which gives back the
+//! edits redoing it.
+
+use super::pattern::{Pattern, PatternLine, Patterns};
+
+/// Older steps are forgotten past this count
+const MAX_STEP_COUNT: usize = 1024;
+
+/// Elementary reversible change of the patterns, [`Patterns::apply_edit`] returns its inverse
+#[derive(Debug, Clone)]
+pub enum Edit {
+    SetLine {
+        pattern_index: usize,
+        line_index: usize,
+        line: PatternLine,
+    },
+    InsertPattern {
+        pattern_index: usize,
+        pattern: Pattern,
+    },
+    RemovePattern {
+        pattern_index: usize,
+    },
+    SetOrder(Vec<usize>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cursor {
+    pub channel: i32,
+    pub field: i32,
+    pub row: i32,
+    pub pattern: usize,
+    pub order: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Step {
+    /// Edits to apply in order to revert the step
+    edits: Vec<Edit>,
+    cursor_before: Cursor,
+    cursor_after: Cursor,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    undo_steps: Vec<Step>,
+    redo_steps: Vec<Step>,
+}
+
+impl History {
+    /// Records the edits reverting a command as a single step, nothing is recorded when the
+    /// command changed nothing
+    pub fn record(&mut self, edits: Vec<Edit>, cursor_before: Cursor, cursor_after: Cursor) {
+        if edits.is_empty() {
+            return;
+        }
+        self.redo_steps.clear();
+        if self.undo_steps.len() == MAX_STEP_COUNT {
+            self.undo_steps.remove(0);
+        }
+        self.undo_steps.push(Step {
+            edits,
+            cursor_before,
+            cursor_after,
+        });
+    }
+
+    /// Reverts the last recorded step, returns whether there was one
+    pub fn undo(&mut self, patterns: &mut Patterns) -> bool {
+        let Some(step) = self.undo_steps.pop() else {
+            return false;
+        };
+        let edits = patterns.apply_edits(step.edits);
+        patterns.set_cursor(step.cursor_before);
+        self.redo_steps.push(Step { edits, ..step });
+        true
+    }
+
+    /// Applies again the last undone step, returns whether there was one
+    pub fn redo(&mut self, patterns: &mut Patterns) -> bool {
+        let Some(step) = self.redo_steps.pop() else {
+            return false;
+        };
+        let edits = patterns.apply_edits(step.edits);
+        patterns.set_cursor(step.cursor_after);
+        self.undo_steps.push(Step { edits, ..step });
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.undo_steps.clear();
+        self.redo_steps.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lines(patterns: &Patterns) -> Vec<String> {
+        patterns
+            .patterns()
+            .iter()
+            .flat_map(|pattern| pattern.lines.iter().map(ToString::to_string))
+            .collect()
+    }
+
+    fn set_current_line(patterns: &mut Patterns, text: &str) -> Vec<Edit> {
+        let line = text.parse::<PatternLine>().unwrap();
+        patterns
+            .edit_current_line(|current| *current = line)
+            .into_iter()
+            .collect()
+    }
+
+    #[test]
+    fn test_line_edits_are_undone_and_redone() {
+        let mut patterns = Patterns::new(1, 2, 1);
+        let mut history = History::default();
+
+        let before = patterns.cursor();
+        let edits = set_current_line(&mut patterns, "C-5 40 01 ...");
+        history.record(edits, before, patterns.cursor());
+        patterns.current_row = 1;
+        let before = patterns.cursor();
+        let edits = set_current_line(&mut patterns, "D-5 .. .. ...");
+        history.record(edits, before, patterns.cursor());
+        let edited = lines(&patterns);
+
+        assert!(history.undo(&mut patterns));
+        assert_eq!(vec!["C-5 40 01 ...", "... .. .. ..."], lines(&patterns));
+        assert!(history.undo(&mut patterns));
+        assert_eq!(vec!["... .. .. ...", "... .. .. ..."], lines(&patterns));
+        assert_eq!(0, patterns.current_row);
+        assert!(!history.undo(&mut patterns));
+
+        assert!(history.redo(&mut patterns));
+        assert!(history.redo(&mut patterns));
+        assert!(!history.redo(&mut patterns));
+        assert_eq!(edited, lines(&patterns));
+        assert_eq!(1, patterns.current_row);
+    }
+
+    #[test]
+    fn test_recording_forgets_the_redo_steps() {
+        let mut patterns = Patterns::new(1, 2, 1);
+        let mut history = History::default();
+
+        let cursor = patterns.cursor();
+        let edits = set_current_line(&mut patterns, "C-5 .. .. ...");
+        history.record(edits, cursor, cursor);
+        history.undo(&mut patterns);
+        let edits = set_current_line(&mut patterns, "E-5 .. .. ...");
+        history.record(edits, cursor, cursor);
+
+        assert!(!history.redo(&mut patterns));
+        history.undo(&mut patterns);
+        assert_eq!(vec!["... .. .. ...", "... .. .. ..."], lines(&patterns));
+    }
+
+    #[test]
+    fn test_empty_steps_are_not_recorded() {
+        let mut patterns = Patterns::new(1, 2, 1);
+        let mut history = History::default();
+        history.record(vec![], patterns.cursor(), patterns.cursor());
+        assert!(!history.undo(&mut patterns));
+    }
+
+    #[test]
+    fn test_pattern_operations_are_undone_as_one_step() {
+        let mut patterns = Patterns::new(1, 2, 2);
+        patterns.set_order(vec![1, 0, 1]).unwrap();
+        patterns.current_pattern = 1;
+        patterns.edit_current_line(|line| *line = "G-4 .. .. ...".parse().unwrap());
+        let mut history = History::default();
+
+        let before = patterns.cursor();
+        let edits = patterns.delete_current_pattern().unwrap();
+        history.record(edits, before, patterns.cursor());
+        assert_eq!(&[0], patterns.order());
+        assert_eq!(1, patterns.pattern_count);
+
+        history.undo(&mut patterns);
+        assert_eq!(&[1, 0, 1], patterns.order());
+        assert_eq!(2, patterns.pattern_count);
+        assert_eq!(before, patterns.cursor());
+        assert_eq!("G-4 .. .. ...", lines(&patterns)[2]);
+
+        history.redo(&mut patterns);
+        assert_eq!(&[0], patterns.order());
+        assert_eq!(1, patterns.pattern_count);
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let mut patterns = Patterns::new(1, 1, 1);
+        let mut history = History::default();
+        let cursor = patterns.cursor();
+        for index in 0..MAX_STEP_COUNT + 10 {
+            let text = if index % 2 == 0 {
+                "C-5 .. .. ..."
+            } else {
+                "D-5 .. .. ..."
+            };
+            let edits = set_current_line(&mut patterns, text);
+            history.record(edits, cursor, cursor);
+        }
+        let mut undo_count = 0;
+        while history.undo(&mut patterns) {
+            undo_count += 1;
+        }
+        assert_eq!(MAX_STEP_COUNT, undo_count);
+    }
+}
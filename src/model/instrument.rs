@@ -1,22 +1,44 @@
-use std::{
-    fmt::{Debug, Display},
-    time::Duration,
-};
+use std::fmt::{Debug, Display};
 
+use anyhow::{bail, ensure};
 use joy_vector::{vector, Vector};
+use log::warn;
 
-use crate::audio::{frame::StereoFrame, signal, synthesis, Pan, Volume};
+use crate::{
+    audio::{
+        dsp::{
+            interpolation::{InterpolationMode, Interpolator},
+            resampling::{self, Quality},
+        },
+        frame::StereoFrame,
+        signal, synthesis, Pan, Volume,
+    },
+    format::wav,
+};
 
-use super::midi::C5_FREQ;
+use super::{
+    envelope::Envelope,
+    midi::C5_FREQ,
+    sample_loop::{SampleLoop, SampleLoops},
+};
 
 #[derive(Clone, Debug)]
 pub enum Kind {
     Sine,
     Square,
     Sawtooth,
+    Triangle,
+    /// Square wave spending `width` (0 to 1) of its cycle up
+    Pulse {
+        width: f32,
+    },
+    WhiteNoise,
+    PinkNoise,
     Sample {
         name: String,
         signal: signal::stereo::Owned,
+        loops: SampleLoops,
+        interpolation: InterpolationMode,
     },
 }
 
@@ -26,12 +48,77 @@ impl Display for Kind {
             Self::Sine => write!(f, "Sine"),
             Self::Square => write!(f, "Square"),
             Self::Sawtooth => write!(f, "Sawtooth"),
+            Self::Triangle => write!(f, "Triangle"),
+            Self::Pulse { width } => write!(f, "Pulse {}%", (width * 100.0).round()),
+            Self::WhiteNoise => write!(f, "White noise"),
+            Self::PinkNoise => write!(f, "Pink noise"),
             Self::Sample { name, .. } => write!(f, "{name}"),
         }
     }
 }
 
 impl Kind {
+    /// Phase starting the source at `frame`, only samples have frames to skip
+    pub fn frame_phase(&self, frame: usize) -> f32 {
+        match self {
+            Kind::Sample { signal, .. } => frame as f32 / signal.frame_rate,
+            Kind::Sine
+            | Kind::Square
+            | Kind::Sawtooth
+            | Kind::Triangle
+            | Kind::Pulse { .. }
+            | Kind::WhiteNoise
+            | Kind::PinkNoise => 0.0,
+        }
+    }
+
+    /// Phase the note goes on from once released, out of the sustain loop of a sample
+    pub fn release_phase(&self, phase: f32) -> f32 {
+        match self {
+            Kind::Sample { signal, loops, .. } => {
+                loops.release(phase * signal.frame_rate) / signal.frame_rate
+            }
+            Kind::Sine
+            | Kind::Square
+            | Kind::Sawtooth
+            | Kind::Triangle
+            | Kind::Pulse { .. }
+            | Kind::WhiteNoise
+            | Kind::PinkNoise => phase,
+        }
+    }
+
+    /// Brings a sample to `frame_rate` along with its loops, returns whether it was resampled.
+    /// Oscillators have no frame rate
+    fn resample(&mut self, frame_rate: f32, quality: Quality) -> bool {
+        let Kind::Sample {
+            name,
+            signal,
+            loops,
+            ..
+        } = self
+        else {
+            return false;
+        };
+        if signal.frame_rate == frame_rate {
+            return false;
+        }
+
+        let ratio = frame_rate as f64 / signal.frame_rate as f64;
+        *signal = resampling::resample(signal.as_ref(), frame_rate, quality);
+        let frame_count = signal.len();
+        let move_loop = |sample_loop: Option<SampleLoop>| {
+            let SampleLoop { mode, start, end } = sample_loop?;
+            let scale = |frame: usize| ((frame as f64 * ratio).round() as usize).min(frame_count);
+            SampleLoop::new(mode, scale(start), scale(end))
+                .inspect_err(|err| warn!("Loop of {name:?} is dropped: {err:#}"))
+                .ok()
+        };
+        loops.normal = move_loop(loops.normal);
+        loops.sustain = move_loop(loops.sustain);
+        true
+    }
+
     pub fn next_frame(
         &self,
         freq: f32,
@@ -39,18 +126,43 @@ impl Kind {
         pan: Pan,
         phase: &mut f32,
         frame_rate: f32,
+        released: bool,
     ) -> StereoFrame {
         match self {
             Kind::Sine => synthesis::sine_wave(freq, volume, pan, phase, frame_rate),
             Kind::Square => synthesis::square_wave(freq, volume, pan, phase, frame_rate),
             Kind::Sawtooth => synthesis::sawtooth_wave(freq, volume, pan, phase, frame_rate),
-            Kind::Sample { signal, .. } => {
-                let Vector([l, r]) = signal
-                    .as_ref()
-                    .lerp_frame_at_duration(Duration::from_secs_f32(*phase))
-                    .unwrap_or_default();
+            Kind::Triangle => synthesis::triangle_wave(freq, volume, pan, phase, frame_rate),
+            Kind::Pulse { width } => {
+                synthesis::pulse_wave(*width, freq, volume, pan, phase, frame_rate)
+            }
+            Kind::WhiteNoise => synthesis::white_noise(volume, pan, phase),
+            Kind::PinkNoise => synthesis::pink_noise(volume, pan, phase),
+            Kind::Sample {
+                signal,
+                loops,
+                interpolation,
+                ..
+            } => {
+                // Loops are in frames of the sample, the phase in seconds of the sample. The
+                // frames are read along the loop so they are interpolated across its ends.
+                let sample_loop = loops.active(released);
+                let frame = |index: isize| {
+                    let index = sample_loop.map_or(index as f32, |sample_loop| {
+                        sample_loop.fold(sample_loop.wrap(index as f32))
+                    });
+                    if index < 0.0 {
+                        return StereoFrame::default();
+                    }
+                    signal.get(index as usize).copied().unwrap_or_default()
+                };
+                let position = *phase * signal.frame_rate;
+                let step = freq / *C5_FREQ / frame_rate * signal.frame_rate;
+                let Vector([l, r]) = interpolation.interpolate(frame, position, step);
 
-                *phase += freq / *C5_FREQ / frame_rate;
+                let position = position + step;
+                *phase = sample_loop.map_or(position, |sample_loop| sample_loop.wrap(position))
+                    / signal.frame_rate;
 
                 let left_amp = volume.value() * pan.left_volume().value();
                 let right_amp = volume.value() * pan.right_volume().value();
@@ -64,6 +176,7 @@ impl Kind {
 pub struct Instrument {
     source: Kind, // TODO: Wrap in interpolator (for volume, and freq at least)
     pub volume: Volume,
+    pub envelope: Envelope,
 }
 
 impl From<Kind> for Instrument {
@@ -71,11 +184,36 @@ impl From<Kind> for Instrument {
         Instrument {
             source: value,
             volume: Volume::DEFAULT,
+            envelope: Envelope::default(),
         }
     }
 }
 
 impl Instrument {
+    pub fn source(&self) -> &Kind {
+        &self.source
+    }
+
+    pub fn set_sample_loops(&mut self, new_loops: SampleLoops) -> anyhow::Result<()> {
+        let Kind::Sample { signal, loops, .. } = &mut self.source else {
+            bail!("Only samples have loops");
+        };
+        new_loops.ensure_fits(signal.len())?;
+        *loops = new_loops;
+        Ok(())
+    }
+
+    pub fn set_interpolation(
+        &mut self,
+        new_interpolation: InterpolationMode,
+    ) -> anyhow::Result<()> {
+        let Kind::Sample { interpolation, .. } = &mut self.source else {
+            bail!("Only samples are interpolated");
+        };
+        *interpolation = new_interpolation;
+        Ok(())
+    }
+
     pub fn next_frame(
         &self,
         freq: f32,
@@ -83,8 +221,11 @@ impl Instrument {
         pan: Pan,
         phase: &mut f32,
         frame_rate: f32,
+        released: bool,
     ) -> StereoFrame {
-        self.source.next_frame(freq, volume, pan, phase, frame_rate) * self.volume
+        self.source
+            .next_frame(freq, volume, pan, phase, frame_rate, released)
+            * self.volume
     }
 }
 
@@ -103,10 +244,13 @@ impl Default for Instruments {
         slots[0] = Some(Instrument::from(Kind::Sine));
         slots[1] = Some(Instrument::from(Kind::Square));
         slots[2] = Some(Instrument::from(Kind::Sawtooth));
-        slots[3] = Some(Instrument::from(Kind::Sample {
-            name: "Piano".into(),
-            signal: signal::stereo::Owned::from_path("assets/stereo.wav").unwrap(),
-        }));
+        slots[3] = Some(Instrument::from(
+            wav::load_sample("Piano".into(), "assets/stereo.wav").unwrap(),
+        ));
+        slots[4] = Some(Instrument::from(Kind::Triangle));
+        slots[5] = Some(Instrument::from(Kind::Pulse { width: 0.25 }));
+        slots[6] = Some(Instrument::from(Kind::WhiteNoise));
+        slots[7] = Some(Instrument::from(Kind::PinkNoise));
         Self {
             slots,
             selected_index: 0,
@@ -115,10 +259,54 @@ impl Default for Instruments {
 }
 
 impl Instruments {
+    pub fn empty() -> Self {
+        Self {
+            slots: [const { None }; MAX_SLOT_COUNT as usize],
+            selected_index: 0,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u8, &Instrument)> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(index, slot)| slot.as_ref().map(|instrument| (index as u8, instrument)))
+    }
+
+    pub fn set(&mut self, index: u8, instrument: Instrument) -> anyhow::Result<()> {
+        ensure!(
+            index < MAX_SLOT_COUNT,
+            "Instrument slot {index} is out of bounds (max is {})",
+            MAX_SLOT_COUNT - 1
+        );
+        self.slots[index as usize] = Some(instrument);
+        Ok(())
+    }
+
     pub fn get(&self, index: u8) -> Option<&Instrument> {
         self.slots.get(index as usize).and_then(Option::as_ref)
     }
 
+    pub fn get_mut(&mut self, index: u8) -> Option<&mut Instrument> {
+        self.slots.get_mut(index as usize).and_then(Option::as_mut)
+    }
+
+    /// Resamples the samples which are not at `frame_rate`, returns how many were
+    pub fn resample(&mut self, frame_rate: f32, quality: Quality) -> usize {
+        self.slots
+            .iter_mut()
+            .flatten()
+            .map(|instrument| instrument.source.resample(frame_rate, quality))
+            .filter(|resampled| *resampled)
+            .count()
+    }
+
+    pub fn get_selected_mut(&mut self) -> Option<&mut Instrument> {
+        self.slots
+            .get_mut(self.selected_index as usize)
+            .and_then(Option::as_mut)
+    }
+
     pub fn get_selected(&self) -> Option<&Instrument> {
         self.slots
             .get(self.selected_index as usize)
@@ -136,3 +324,39 @@ impl Instruments {
         self.selected_index = selected_index as u8;
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_resampled_samples_keep_their_loops_in_place() {
+        let mut instruments = Instruments::empty();
+        instruments.set(0, Instrument::from(Kind::Square)).unwrap();
+        instruments
+            .set(
+                1,
+                Instrument::from(Kind::Sample {
+                    name: "Loop".into(),
+                    signal: signal::stereo::Owned::from_frames(
+                        vec![vector!(0.5, 0.5); 300],
+                        22050.0,
+                    ),
+                    loops: "forward 100 300; pingpong 10 20".parse().unwrap(),
+                    interpolation: InterpolationMode::default(),
+                }),
+            )
+            .unwrap();
+
+        assert_eq!(1, instruments.resample(44100.0, Quality::Fast));
+        let Kind::Sample { signal, loops, .. } = instruments.get(1).unwrap().source() else {
+            panic!("Expected a sample");
+        };
+        approx::assert_relative_eq!(44100.0, signal.frame_rate);
+        assert_eq!(600, signal.len());
+        assert_eq!("forward 200 600; pingpong 20 40", loops.to_string());
+
+        // Already at the frame rate
+        assert_eq!(0, instruments.resample(44100.0, Quality::Fast));
+    }
+}
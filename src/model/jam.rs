@@ -0,0 +1,134 @@
+//! Jam mode, the note keys play the selected instrument without writing to the patterns.
+//!
+//! Every held key plays on its own voice, separate from the song channels so jamming goes on
+//! over the playback. A released voice is dropped once its note is over.
+
+use crate::audio::{signal, Volume};
+
+use super::{
+    channel::Channel,
+    instrument::Instruments,
+    pattern::{NoteName, PatternLine},
+};
+
+/// Note key as bound, the global octave may change while it is held
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JamKey {
+    pub note: NoteName,
+    pub octave_modifier: i32,
+}
+
+#[derive(Debug, Clone)]
+struct Voice {
+    /// `None` once released
+    key: Option<JamKey>,
+    channel: Channel,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Jam {
+    pub enabled: bool,
+    voices: Vec<Voice>,
+}
+
+impl Jam {
+    /// Plays `line` until the key is released, nothing happens while the key is already held
+    pub fn note_on(&mut self, key: JamKey, line: &PatternLine) {
+        if self.held_voice(key).is_some() {
+            return;
+        }
+        let mut channel = Channel::new();
+        channel.setup_line(line);
+        self.voices.push(Voice {
+            key: Some(key),
+            channel,
+        });
+    }
+
+    /// Plays `line` on the voice of the key, usually a note-off
+    pub fn note_off(&mut self, key: JamKey, line: &PatternLine) {
+        if let Some(voice) = self.held_voice(key) {
+            voice.channel.setup_line(line);
+            voice.key = None;
+        }
+    }
+
+    pub fn voice_count(&self) -> usize {
+        self.voices.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.voices.clear();
+    }
+
+    pub fn collect_mix_in(
+        &mut self,
+        mut output_signal: signal::stereo::Mut,
+        instruments: &Instruments,
+        volume: Volume,
+    ) {
+        self.voices
+            .retain(|voice| voice.key.is_some() || voice.channel.is_playing());
+        let frame_count = output_signal.len();
+        for voice in self.voices.iter_mut() {
+            voice.channel.collect_mix_in(
+                output_signal.sub_signal_mut(..frame_count),
+                instruments,
+                volume,
+            );
+        }
+    }
+
+    fn held_voice(&mut self, key: JamKey) -> Option<&mut Voice> {
+        self.voices.iter_mut().find(|voice| voice.key == Some(key))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::model::instrument::{Instrument, Kind};
+
+    use super::*;
+
+    const A: JamKey = JamKey {
+        note: NoteName::A,
+        octave_modifier: 0,
+    };
+    const C: JamKey = JamKey {
+        note: NoteName::C,
+        octave_modifier: 1,
+    };
+
+    fn mix(jam: &mut Jam) -> bool {
+        let mut instruments = Instruments::empty();
+        instruments.set(0, Instrument::from(Kind::Sine)).unwrap();
+        let mut output = signal::Owned::from_sample_count(256, 8000.0);
+        jam.collect_mix_in(output.as_mut(), &instruments, Volume::MAX);
+        output.iter().any(|frame| frame.0 != [0.0, 0.0])
+    }
+
+    #[test]
+    fn test_held_keys_play_together() {
+        let mut jam = Jam::default();
+        jam.note_on(A, &"A-5 .. 00 ...".parse().unwrap());
+        jam.note_on(C, &"C-6 .. 00 ...".parse().unwrap());
+        jam.note_on(A, &"A-5 .. 00 ...".parse().unwrap());
+        assert_eq!(2, jam.voice_count());
+        assert!(mix(&mut jam));
+    }
+
+    #[test]
+    fn test_released_voices_are_dropped() {
+        let mut jam = Jam::default();
+        let cut = "CUT .. .. ...".parse().unwrap();
+        jam.note_on(A, &"A-5 .. 00 ...".parse().unwrap());
+        jam.note_on(C, &"C-6 .. 00 ...".parse().unwrap());
+        jam.note_off(A, &cut);
+        assert!(mix(&mut jam));
+        assert_eq!(1, jam.voice_count());
+
+        jam.note_off(C, &cut);
+        assert!(!mix(&mut jam));
+        assert_eq!(0, jam.voice_count());
+    }
+}
@@ -22,6 +22,14 @@ pub fn note_to_midi_value(note: NoteName, octave: OctaveValue) -> MidiValue {
     (octave * 12 + 12 + note_index).into()
 }
 
+pub fn midi_value_to_note(midi_value: MidiValue) -> (NoteName, OctaveValue) {
+    let value = midi_value.value() - 12;
+    (
+        NoteName::VARIANTS[(value % 12) as usize],
+        OctaveValue::new_unchecked(value / 12),
+    )
+}
+
 impl From<(NoteName, OctaveValue)> for MidiValue {
     fn from((note, octave): (NoteName, OctaveValue)) -> Self {
         note_to_midi_value(note, octave)
@@ -119,6 +127,15 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_that_midi_value_to_note_is_the_inverse_of_note_to_midi_value() {
+        for midi_value in MidiValue::MIN_VALUE..=MidiValue::MAX_VALUE {
+            let midi_value = MidiValue::new_unchecked(midi_value);
+            let (note, octave) = midi_value_to_note(midi_value);
+            assert_eq!(midi_value, note_to_midi_value(note, octave));
+        }
+    }
+
     #[test]
     fn a4_should_be_freq_440_0() {
         let freq = note_to_freq(NoteName::A, OctaveValue::OCTAVE_4);
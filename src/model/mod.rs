@@ -1,20 +1,51 @@
 use anyhow::anyhow;
-use channel::Channel;
+use channel::{Channel, MuteSolo};
+use envelope::Envelope;
+use history::History;
 use instrument::Instruments;
-use pattern::{HexDigit, NoteName, OctaveValue, Patterns};
-use playback::song;
+use jam::Jam;
+use pattern::{HexDigit, NoteName, OctaveValue, PatternLine, Patterns};
+use playback::song::{self, PlaybackStart};
+use record::Record;
+use sample_loop::SampleLoops;
+use selection::{Block, PasteMode, Selection};
+use tempo::Tempo;
+use transform::Interpolation;
 
 use crate::{
-    audio::{signal, Decibels, Volume},
+    audio::{
+        dsp::{interpolation::InterpolationMode, resampling::Quality},
+        signal, Decibels, Volume,
+    },
     model::pattern::NoteFieldValue,
     utils::Direction,
 };
 
 pub mod channel;
+pub mod effect;
+pub mod envelope;
+pub mod history;
 pub mod instrument;
+pub mod jam;
 pub mod midi;
 pub mod pattern;
 pub mod playback;
+pub mod record;
+pub mod sample_loop;
+pub mod selection;
+pub mod tempo;
+pub mod transform;
+
+/// Everything that is saved to / loaded from a song file
+#[derive(Clone, Debug)]
+pub struct Song {
+    pub patterns: Patterns,
+    pub instruments: Instruments,
+    pub global_octave: OctaveValue,
+    pub global_volume: Volume,
+    pub tempo: Tempo,
+    pub mute_solo: MuteSolo,
+}
 
 #[derive(Clone, Debug)]
 pub struct State {
@@ -23,16 +54,36 @@ pub struct State {
 
     pub global_octave: OctaveValue,
     pub global_volume: Volume,
-    pub line_per_second: f32,
+    pub tempo: Tempo,
+    pub mute_solo: MuteSolo,
 
     pub follow_playing: bool,
+    pub record: Record,
+    pub jam: Jam,
 
     pub step_output: Option<signal::stereo::Owned>,
     pub computed_frame_count: usize,
+    /// Contribution of every channel to the step output, before the global volume. Only filled
+    /// when [`Command::CaptureChannelOutputs`] is enabled
+    pub channel_step_outputs: Option<Vec<signal::stereo::Owned>>,
 
     pub song_playback: Option<song::Playback>,
 
     pub instruments: Instruments,
+
+    /// Undo / redo steps of the pattern edits, not saved with the song
+    pub history: History,
+
+    pub selection: Option<Selection>,
+    /// Last copied block, it is kept when loading another song
+    pub clipboard: Option<Block>,
+    /// Last spread used to humanize velocities
+    pub humanize_spread: u8,
+    /// Quality of the resampling of the samples to the device frame rate, done when a song is
+    /// loaded
+    pub resample_quality: Quality,
+    /// Whether the samples are resampled again when the audio device changes
+    pub resample_on_device_change: bool,
 }
 
 impl Default for State {
@@ -42,13 +93,23 @@ impl Default for State {
             channels: vec![Channel::new(); patterns.channel_count as usize],
             step_output: None,
             global_octave: Default::default(),
-            line_per_second: 16.0,
+            tempo: Tempo::default(),
+            mute_solo: MuteSolo::new(patterns.channel_count as usize),
             global_volume: Decibels::DEFAULT.volume(),
             song_playback: None,
             instruments: Default::default(),
             follow_playing: false,
+            record: Record::default(),
+            jam: Jam::default(),
             patterns,
             computed_frame_count: 0,
+            channel_step_outputs: None,
+            history: Default::default(),
+            selection: None,
+            clipboard: None,
+            humanize_spread: 0x10,
+            resample_quality: Quality::default(),
+            resample_on_device_change: false,
         }
     }
 }
@@ -66,6 +127,19 @@ impl State {
             .map(|playback| playback.current_line)
     }
 
+    /// Index of the order entry being played
+    pub fn currently_played_order(&self) -> Option<usize> {
+        self.song_playback
+            .as_ref()
+            .map(|playback| playback.current_order)
+    }
+
+    pub fn currently_played_pattern(&self) -> Option<usize> {
+        self.song_playback
+            .as_ref()
+            .and_then(|playback| playback.current_pattern(self.patterns.order()))
+    }
+
     pub fn output_samples(&self) -> anyhow::Result<signal::stereo::Ref> {
         self.step_output
             .as_ref()
@@ -73,11 +147,34 @@ impl State {
             .and_then(|output| output.sub_signal(0, self.computed_frame_count))
     }
 
+    pub fn channel_output_samples(
+        &self,
+        channel_index: usize,
+    ) -> anyhow::Result<signal::stereo::Ref> {
+        self.channel_step_outputs
+            .as_ref()
+            .and_then(|outputs| outputs.get(channel_index))
+            .ok_or_else(|| anyhow!("Channel {channel_index} output is not captured"))
+            .and_then(|output| output.sub_signal(0, self.computed_frame_count))
+    }
+
+    pub fn song(&self) -> Song {
+        Song {
+            patterns: self.patterns.clone(),
+            instruments: self.instruments.clone(),
+            global_octave: self.global_octave,
+            global_volume: self.global_volume,
+            tempo: self.tempo,
+            mute_solo: self.mute_solo.clone(),
+        }
+    }
+
     pub fn should_perform_step(&self) -> bool {
         self.song_playback
             .as_ref()
             .is_some_and(|playback| playback.is_playing) // Channel playing should be sufficent but this is needed to play empty patterns
             || self.channels.iter().any(Channel::is_playing)
+            || self.jam.voice_count() > 0
     }
 }
 
@@ -89,6 +186,19 @@ pub enum Command {
     ChangeGlobalVolume {
         volume: Volume,
     },
+    /// Takes effect right away, even while playing
+    SetTempo(Tempo),
+    /// Envelope of the selected instrument
+    SetEnvelope(Envelope),
+    /// Loops of the selected instrument, which must be a sample
+    SetSampleLoops(SampleLoops),
+    /// Interpolation of the selected instrument, which must be a sample
+    SetInterpolation(InterpolationMode),
+    SetResampleQuality(Quality),
+    ToggleResampleOnDeviceChange,
+    /// Mute and solo apply to the channel under the cursor, the playback goes on
+    ToggleMute,
+    ToggleSolo,
     ChangeSelectedInstrument {
         increment: i32,
     },
@@ -96,20 +206,73 @@ pub enum Command {
         note: NoteName,
         octave_modifier: i32,
     },
+    /// Writes a line of any pattern. Notes recorded while playing are sent this way, at the line
+    /// picked once so every copy of the state writes the same one.
+    SetLine {
+        pattern: usize,
+        row: usize,
+        channel: usize,
+        line: PatternLine,
+    },
     SetNoteCut,
+    SetNoteOff,
     MoveCursor(Direction),
+    /// Moves the cursor, selecting the fields from the previous position
+    ExtendSelection(Direction),
+    ClearSelection,
+    /// Copies the selection, or the field under the cursor when nothing is selected
+    Copy,
+    Cut,
+    Paste(PasteMode),
+    Transpose {
+        semitones: i32,
+    },
+    InterpolateVelocity(Interpolation),
+    /// The seed is picked once so every copy of the state humanizes the same way
+    HumanizeVelocity {
+        spread: u8,
+        seed: u64,
+    },
     ClearField,
     SetOctaveField(OctaveValue),
     SetHexField(HexDigit),
     CreateNewPattern,
+    DuplicatePattern,
+    DeletePattern,
     GoToNextPattern,
     GoToPreviousPattern,
+    ChangeCurrentOrder {
+        increment: i32,
+    },
+    InsertOrderEntry,
+    RemoveOrderEntry,
+    Undo,
+    Redo,
     StartSongPlaybackFromBeginning,
+    /// Restarts the playback when already playing
+    StartPlayback(PlaybackStart),
     StopSongPlayback,
+    ToggleFollowPlaying,
+    /// Notes entered while playing are written at the played line once armed
+    ToggleRecord,
+    ToggleRecordQuantize,
+    ToggleRecordPolyphony,
+    ToggleJam,
+    /// Plays the note with the selected instrument until the matching [`Command::JamNoteOff`]
+    JamNoteOn {
+        note: NoteName,
+        octave_modifier: i32,
+    },
+    JamNoteOff {
+        note: NoteName,
+        octave_modifier: i32,
+    },
     InitializeAudio {
         frame_rate: f32,
     },
     UpdatePlaybackSampleCount(usize),
     PerformPlaybacksStep,
+    CaptureChannelOutputs(bool),
     ClearChannels,
+    LoadSong(Box<Song>),
 }
@@ -1,12 +1,17 @@
-use std::fmt::{self, Debug};
+use std::{
+    fmt::{self, Debug},
+    str::FromStr,
+};
 
-use anyhow::anyhow;
+use anyhow::{anyhow, bail, ensure, Context};
 
 use joy_macro::EnumIter;
 use joy_value_object::{mk_vo, mk_vo_consts};
 
 use crate::keybindings;
 
+use super::history::{Cursor, Edit};
+
 mk_vo! {
     pub HexDigit: u8,
     default: 0,
@@ -130,9 +135,25 @@ impl fmt::Display for NoteName {
 #[derive(Debug, Clone, PartialEq)]
 pub enum NoteFieldValue {
     Note(NoteName, OctaveValue),
+    /// Note-off, the playing note goes through the release of its envelope
+    Off,
+    /// Stops the playing note right away
     Cut,
 }
 
+/// Effect command digit followed by its 2 digits parameter, see [`super::effect::Effect`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EffectFieldValue {
+    pub command: HexDigit,
+    pub parameter: (HexDigit, HexDigit),
+}
+
+impl EffectFieldValue {
+    pub fn parameter_u8(&self) -> u8 {
+        self.parameter.0.value() * 0x10 + self.parameter.1.value()
+    }
+}
+
 macro_rules! declare_field {
     ($($snake_case:ident $pascal_case:ident $size:literal $ty:ty),* $(,)?) => {
         #[derive(Default, Debug, Clone, PartialEq)]
@@ -160,6 +181,35 @@ macro_rules! declare_field {
                 }
             }
         }
+
+        impl PatternLine {
+            pub fn is_field_empty(&self, descriptor: PatternLineDescriptor) -> bool {
+                match descriptor {
+                    $(
+                        PatternLineDescriptor::$pascal_case => self.$snake_case.value().is_none(),
+                    )*
+                }
+            }
+
+            /// Copies the field of `other` in this line, empty or not
+            pub fn copy_field(&mut self, descriptor: PatternLineDescriptor, other: &PatternLine) {
+                match descriptor {
+                    $(
+                        PatternLineDescriptor::$pascal_case => {
+                            self.$snake_case = other.$snake_case.clone()
+                        }
+                    )*
+                }
+            }
+
+            pub fn clear_field(&mut self, descriptor: PatternLineDescriptor) {
+                match descriptor {
+                    $(
+                        PatternLineDescriptor::$pascal_case => self.$snake_case.clear(),
+                    )*
+                }
+            }
+        }
     };
 }
 
@@ -204,6 +254,211 @@ declare_field! {
     note Note 3 NoteFieldValue,
     velocity Velocity 2 (HexDigit, HexDigit),
     instrument Instrument 2 (HexDigit, HexDigit),
+    effect Effect 3 EffectFieldValue,
+}
+
+const EMPTY_FIELD_CHAR: char = '.';
+
+fn empty_field_string(descriptor: PatternLineDescriptor) -> String {
+    EMPTY_FIELD_CHAR.to_string().repeat(descriptor.field_len())
+}
+
+impl fmt::Display for HexDigit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:X}", self.value())
+    }
+}
+
+impl fmt::Display for NoteFieldValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NoteFieldValue::Note(note, octave) => {
+                write!(f, "{:-<2}{}", note.to_string(), octave.value())
+            }
+            NoteFieldValue::Off => write!(f, "OFF"),
+            NoteFieldValue::Cut => write!(f, "CUT"),
+        }
+    }
+}
+
+impl FromStr for NoteFieldValue {
+    type Err = anyhow::Error;
+
+    // "C-5", "C#5", "OFF" or "CUT"
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "OFF" => return Ok(NoteFieldValue::Off),
+            "CUT" => return Ok(NoteFieldValue::Cut),
+            _ => {}
+        }
+
+        let chars = s.chars().collect::<Vec<_>>();
+        let [name, accidental, octave] = chars[..] else {
+            bail!("expected 3 characters, found {}", chars.len());
+        };
+
+        let note_name = match (name, accidental) {
+            ('C', '-') => NoteName::C,
+            ('C', '#') => NoteName::CSharp,
+            ('D', '-') => NoteName::D,
+            ('D', '#') => NoteName::DSharp,
+            ('E', '-') => NoteName::E,
+            ('F', '-') => NoteName::F,
+            ('F', '#') => NoteName::FSharp,
+            ('G', '-') => NoteName::G,
+            ('G', '#') => NoteName::GSharp,
+            ('A', '-') => NoteName::A,
+            ('A', '#') => NoteName::ASharp,
+            ('B', '-') => NoteName::B,
+            _ => bail!("invalid note name \"{name}{accidental}\""),
+        };
+
+        let Some(octave) = octave.to_digit(10) else {
+            bail!("invalid octave {octave:?}");
+        };
+
+        Ok(NoteFieldValue::Note(
+            note_name,
+            OctaveValue::new_unchecked(octave as i32),
+        ))
+    }
+}
+
+impl fmt::Display for EffectFieldValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}{}",
+            self.command, self.parameter.0, self.parameter.1
+        )
+    }
+}
+
+fn note_field_to_string(field: &Field<NoteFieldValue>) -> String {
+    field.value().map_or_else(
+        || empty_field_string(PatternLineDescriptor::Note),
+        ToString::to_string,
+    )
+}
+
+fn hex_field_to_string(
+    field: &Field<(HexDigit, HexDigit)>,
+    descriptor: PatternLineDescriptor,
+) -> String {
+    field.value().map_or_else(
+        || empty_field_string(descriptor),
+        |(first, second)| format!("{first}{second}"),
+    )
+}
+
+fn effect_field_to_string(field: &Field<EffectFieldValue>) -> String {
+    field.value().map_or_else(
+        || empty_field_string(PatternLineDescriptor::Effect),
+        ToString::to_string,
+    )
+}
+
+fn parse_note_field(s: &str) -> anyhow::Result<Field<NoteFieldValue>> {
+    if s == empty_field_string(PatternLineDescriptor::Note) {
+        Ok(Field::empty())
+    } else {
+        s.parse()
+            .map(Field::new)
+            .with_context(|| format!("Invalid note field {s:?}"))
+    }
+}
+
+fn parse_hex_field(
+    s: &str,
+    descriptor: PatternLineDescriptor,
+) -> anyhow::Result<Field<(HexDigit, HexDigit)>> {
+    if s == empty_field_string(descriptor) {
+        return Ok(Field::empty());
+    }
+
+    let parse_digit = |c: char| {
+        c.to_digit(16)
+            .map(|digit| HexDigit::new_unchecked(digit as u8))
+            .ok_or_else(|| anyhow!("Invalid {descriptor:?} field {s:?}: {c:?} is not a hex digit"))
+    };
+
+    let chars = s.chars().collect::<Vec<_>>();
+    let [first, second] = chars[..] else {
+        bail!(
+            "Invalid {descriptor:?} field {s:?}: expected {} characters",
+            descriptor.field_len()
+        );
+    };
+
+    let value = (parse_digit(first)?, parse_digit(second)?);
+    Ok(Field::new(value))
+}
+
+fn parse_effect_field(s: &str) -> anyhow::Result<Field<EffectFieldValue>> {
+    if s == empty_field_string(PatternLineDescriptor::Effect) {
+        return Ok(Field::empty());
+    }
+
+    let digits = s
+        .chars()
+        .map(|c| {
+            c.to_digit(16)
+                .map(|digit| HexDigit::new_unchecked(digit as u8))
+                .ok_or_else(|| anyhow!("Invalid Effect field {s:?}: {c:?} is not a hex digit"))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let [command, first, second] = digits[..] else {
+        bail!(
+            "Invalid Effect field {s:?}: expected {} characters",
+            PatternLineDescriptor::Effect.field_len()
+        );
+    };
+
+    Ok(Field::new(EffectFieldValue {
+        command,
+        parameter: (first, second),
+    }))
+}
+
+/// Printed as "C#5 5F 03 A04", empty fields are filled with dots: "... .. .. ..."
+impl fmt::Display for PatternLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            note_field_to_string(&self.note),
+            hex_field_to_string(&self.velocity, PatternLineDescriptor::Velocity),
+            hex_field_to_string(&self.instrument, PatternLineDescriptor::Instrument),
+            effect_field_to_string(&self.effect),
+        )
+    }
+}
+
+/// The effect field is optional, lines written before effects existed have no effect
+impl FromStr for PatternLine {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let fields = s.split(' ').collect::<Vec<_>>();
+        let (note, velocity, instrument, effect) = match fields[..] {
+            [note, velocity, instrument] => (note, velocity, instrument, Field::empty()),
+            [note, velocity, instrument, effect] => {
+                (note, velocity, instrument, parse_effect_field(effect)?)
+            }
+            _ => bail!(
+                "Expected {} space separated fields, found {} in {s:?}",
+                PatternLineDescriptor::COUNT,
+                fields.len()
+            ),
+        };
+
+        Ok(PatternLine {
+            note: parse_note_field(note)?,
+            velocity: parse_hex_field(velocity, PatternLineDescriptor::Velocity)?,
+            instrument: parse_hex_field(instrument, PatternLineDescriptor::Instrument)?,
+            effect,
+        })
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -223,6 +478,8 @@ impl Pattern {
 #[derive(Clone)]
 pub struct Patterns {
     patterns: Vec<Pattern>,
+    /// Pattern indexes in the order the song plays them, repeats are allowed
+    order: Vec<usize>,
     pub channel_len: i32,
     pub channel_count: i32,
     pub pattern_count: i32,
@@ -230,12 +487,15 @@ pub struct Patterns {
     pub current_field: i32,
     pub current_row: i32,
     pub current_pattern: usize,
+    /// Index of the selected entry of the order
+    pub current_order: usize,
 }
 
 impl Debug for Patterns {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Patterns")
             .field("patterns", &"...")
+            .field("order", &self.order)
             .field("channel_len", &self.channel_len)
             .field("channel_count", &self.channel_count)
             .field("pattern_count", &self.pattern_count)
@@ -243,6 +503,7 @@ impl Debug for Patterns {
             .field("current_field", &self.current_field)
             .field("current_row", &self.current_row)
             .field("current_pattern", &self.current_pattern)
+            .field("current_order", &self.current_order)
             .finish()
     }
 }
@@ -265,11 +526,12 @@ impl Patterns {
 
         debug_assert_eq!(
             channel_count * channel_len * pattern_count,
-            patterns.len() as i32 * patterns.iter().map(|p| p.lines.len()).sum::<usize>() as i32
+            patterns.iter().map(|p| p.lines.len()).sum::<usize>() as i32
         );
 
         Patterns {
             patterns,
+            order: (0..pattern_count as usize).collect(),
             channel_len,
             channel_count,
             pattern_count,
@@ -277,7 +539,224 @@ impl Patterns {
             current_field: 0,
             current_row: 0,
             current_pattern: 0,
+            current_order: 0,
+        }
+    }
+
+    pub fn from_patterns(
+        channel_count: i32,
+        channel_len: i32,
+        patterns: Vec<Pattern>,
+    ) -> anyhow::Result<Patterns> {
+        ensure!(!patterns.is_empty(), "At least one pattern is needed");
+        ensure!(
+            channel_count > 0 && channel_len > 0,
+            "Invalid pattern dimensions: {channel_count} channel(s) of {channel_len} line(s)"
+        );
+        let line_count = channel_count
+            .checked_mul(channel_len)
+            .context("Patterns are too large")? as usize;
+        for (index, pattern) in patterns.iter().enumerate() {
+            ensure!(
+                pattern.lines.len() == line_count,
+                "Pattern {index} has {} line(s) but {line_count} were expected",
+                pattern.lines.len()
+            );
+        }
+
+        Ok(Patterns {
+            pattern_count: patterns.len() as i32,
+            order: (0..patterns.len()).collect(),
+            patterns,
+            channel_len,
+            channel_count,
+            current_channel: 0,
+            current_field: 0,
+            current_row: 0,
+            current_pattern: 0,
+            current_order: 0,
+        })
+    }
+
+    pub fn patterns(&self) -> &[Pattern] {
+        &self.patterns
+    }
+
+    pub fn order(&self) -> &[usize] {
+        &self.order
+    }
+
+    pub fn set_order(&mut self, order: Vec<usize>) -> anyhow::Result<()> {
+        ensure!(!order.is_empty(), "Song order is empty");
+        if let Some(pattern) = order
+            .iter()
+            .find(|pattern| **pattern >= self.patterns.len())
+        {
+            bail!(
+                "Song order references missing pattern {pattern} (pattern count is {})",
+                self.patterns.len()
+            );
         }
+        self.order = order;
+        self.current_order = self.current_order.min(self.order.len() - 1);
+        Ok(())
+    }
+
+    pub fn cursor(&self) -> Cursor {
+        Cursor {
+            channel: self.current_channel,
+            field: self.current_field,
+            row: self.current_row,
+            pattern: self.current_pattern,
+            order: self.current_order,
+        }
+    }
+
+    pub fn set_cursor(&mut self, cursor: Cursor) {
+        self.current_channel = cursor.channel;
+        self.current_field = cursor.field;
+        self.current_row = cursor.row;
+        self.current_pattern = cursor.pattern.min(self.patterns.len() - 1);
+        self.current_order = cursor.order.min(self.order.len() - 1);
+    }
+
+    /// Applies the edit and returns the one reverting it. Edits are assumed valid, they come from
+    /// the editing methods or the history.
+    pub fn apply_edit(&mut self, edit: Edit) -> Edit {
+        let inverse = match edit {
+            Edit::SetLine {
+                pattern_index,
+                line_index,
+                line,
+            } => Edit::SetLine {
+                pattern_index,
+                line_index,
+                line: std::mem::replace(&mut self.patterns[pattern_index].lines[line_index], line),
+            },
+            Edit::InsertPattern {
+                pattern_index,
+                pattern,
+            } => {
+                self.patterns.insert(pattern_index, pattern);
+                Edit::RemovePattern { pattern_index }
+            }
+            Edit::RemovePattern { pattern_index } => Edit::InsertPattern {
+                pattern_index,
+                pattern: self.patterns.remove(pattern_index),
+            },
+            Edit::SetOrder(order) => Edit::SetOrder(std::mem::replace(&mut self.order, order)),
+        };
+        self.pattern_count = self.patterns.len() as i32;
+        self.current_pattern = self.current_pattern.min(self.patterns.len() - 1);
+        self.current_order = self.current_order.min(self.order.len() - 1);
+        inverse
+    }
+
+    /// Applies the edits in order and returns the ones reverting them all, in order
+    pub fn apply_edits(&mut self, edits: Vec<Edit>) -> Vec<Edit> {
+        let mut inverses = edits
+            .into_iter()
+            .map(|edit| self.apply_edit(edit))
+            .collect::<Vec<_>>();
+        inverses.reverse();
+        inverses
+    }
+
+    /// Adds an empty pattern, played right after the selected order entry, and selects it.
+    /// Returns the edits reverting it.
+    pub fn create_pattern(&mut self) -> Vec<Edit> {
+        self.add_pattern(Pattern::new(self.channel_count, self.channel_len))
+    }
+
+    /// Same as [`Patterns::create_pattern`] with a copy of the current pattern
+    pub fn duplicate_pattern(&mut self) -> Vec<Edit> {
+        self.add_pattern(self.current_pattern().clone())
+    }
+
+    fn add_pattern(&mut self, pattern: Pattern) -> Vec<Edit> {
+        let pattern_index = self.patterns.len();
+        let order_index = (self.current_order + 1).min(self.order.len());
+        let mut order = self.order.clone();
+        order.insert(order_index, pattern_index);
+
+        let edits = self.apply_edits(vec![
+            Edit::InsertPattern {
+                pattern_index,
+                pattern,
+            },
+            Edit::SetOrder(order),
+        ]);
+        self.current_pattern = pattern_index;
+        self.current_order = order_index;
+        edits
+    }
+
+    /// Removes the current pattern along with its order entries, the last pattern can't be
+    /// deleted. Returns the edits reverting it.
+    pub fn delete_current_pattern(&mut self) -> anyhow::Result<Vec<Edit>> {
+        ensure!(self.patterns.len() > 1, "The last pattern can't be deleted");
+        let deleted = self.current_pattern;
+
+        let mut order = self
+            .order
+            .iter()
+            .filter(|pattern| **pattern != deleted)
+            .map(|pattern| {
+                if *pattern > deleted {
+                    pattern - 1
+                } else {
+                    *pattern
+                }
+            })
+            .collect::<Vec<_>>();
+        if order.is_empty() {
+            order.push(0);
+        }
+
+        let edits = self.apply_edits(vec![
+            Edit::SetOrder(order),
+            Edit::RemovePattern {
+                pattern_index: deleted,
+            },
+        ]);
+        self.current_pattern = deleted.min(self.patterns.len() - 1);
+        Ok(edits)
+    }
+
+    pub fn change_current_pattern(&mut self, increment: i32) {
+        self.current_pattern =
+            (self.current_pattern as i32 + increment).rem_euclid(self.pattern_count) as usize;
+    }
+
+    /// Selects another order entry and shows its pattern
+    pub fn change_current_order(&mut self, increment: i32) {
+        self.current_order =
+            (self.current_order as i32 + increment).rem_euclid(self.order.len() as i32) as usize;
+        self.current_pattern = self.order[self.current_order];
+    }
+
+    /// Plays the current pattern right after the selected order entry, and selects the new entry.
+    /// Returns the edits reverting it.
+    pub fn insert_order_entry(&mut self) -> Vec<Edit> {
+        let order_index = (self.current_order + 1).min(self.order.len());
+        let mut order = self.order.clone();
+        order.insert(order_index, self.current_pattern);
+
+        let edits = self.apply_edits(vec![Edit::SetOrder(order)]);
+        self.current_order = order_index;
+        edits
+    }
+
+    /// Removes the selected order entry, the last entry can't be removed. Returns the edits
+    /// reverting it.
+    pub fn remove_order_entry(&mut self) -> anyhow::Result<Vec<Edit>> {
+        ensure!(
+            self.order.len() > 1,
+            "The last order entry can't be removed"
+        );
+        let mut order = self.order.clone();
+        order.remove(self.current_order);
+        Ok(self.apply_edits(vec![Edit::SetOrder(order)]))
     }
 
     fn current_pattern(&self) -> &Pattern {
@@ -315,21 +794,79 @@ impl Patterns {
             },
             (PatternLineDescriptor::Velocity, _) => keybindings::InputContext::Hex,
             (PatternLineDescriptor::Instrument, _) => keybindings::InputContext::Hex,
+            (PatternLineDescriptor::Effect, _) => keybindings::InputContext::Hex,
         }
     }
 
     pub fn current_line_mut(&mut self) -> &mut PatternLine {
-        let line_index = self.current_channel * self.channel_len + self.current_row;
+        let line_index = self.current_line_index();
         self.current_pattern_mut()
             .lines
-            .get_mut(line_index as usize)
+            .get_mut(line_index)
             .ok_or_else(|| anyhow!("Invalid state: {line_index}"))
             .unwrap()
     }
 
-    pub fn current_pattern_row(&self, index: usize) -> impl Iterator<Item = &PatternLine> {
+    fn current_line_index(&self) -> usize {
+        (self.current_channel * self.channel_len + self.current_row) as usize
+    }
+
+    /// Line of the current pattern
+    pub fn line(&self, channel: usize, row: usize) -> &PatternLine {
+        &self.current_pattern().lines[channel * self.channel_len as usize + row]
+    }
+
+    /// Edits the line under the cursor, returns the edit reverting it unless the line is left
+    /// untouched
+    pub fn edit_current_line<EditFn>(&mut self, edit: EditFn) -> Option<Edit>
+    where
+        EditFn: FnOnce(&mut PatternLine),
+    {
+        self.edit_line(
+            self.current_channel as usize,
+            self.current_row as usize,
+            edit,
+        )
+    }
+
+    /// Same as [`Patterns::edit_current_line`] for any line of the current pattern
+    pub fn edit_line<EditFn>(&mut self, channel: usize, row: usize, edit: EditFn) -> Option<Edit>
+    where
+        EditFn: FnOnce(&mut PatternLine),
+    {
+        self.edit_pattern_line(self.current_pattern, channel, row, edit)
+    }
+
+    /// Same as [`Patterns::edit_line`] for any pattern
+    pub fn edit_pattern_line<EditFn>(
+        &mut self,
+        pattern_index: usize,
+        channel: usize,
+        row: usize,
+        edit: EditFn,
+    ) -> Option<Edit>
+    where
+        EditFn: FnOnce(&mut PatternLine),
+    {
+        let line_index = channel * self.channel_len as usize + row;
+        let line = &mut self.patterns[pattern_index].lines[line_index];
+        let previous = line.clone();
+        edit(line);
+        (*line != previous).then_some(Edit::SetLine {
+            pattern_index,
+            line_index,
+            line: previous,
+        })
+    }
+
+    pub fn pattern_row(
+        &self,
+        pattern_index: usize,
+        index: usize,
+    ) -> impl Iterator<Item = &PatternLine> {
+        let pattern = &self.patterns[pattern_index];
         (0..self.channel_count as usize).map(move |channel_index| {
-            &self.current_pattern().lines[channel_index * self.channel_len as usize + index]
+            &pattern.lines[channel_index * self.channel_len as usize + index]
         })
     }
 }
@@ -362,4 +899,158 @@ mod test {
     fn test_that_u8_to_hex_digit_pair_works_when_value_is_16() {
         assert_u8_to_hex_digit_pair(16, (HexDigit::HEX_1, HexDigit::HEX_0));
     }
+
+    #[test]
+    fn test_pattern_line_is_printed() {
+        let line = PatternLine {
+            note: Field::new(NoteFieldValue::Note(
+                NoteName::CSharp,
+                OctaveValue::OCTAVE_5,
+            )),
+            velocity: Field::new((HexDigit::HEX_5, HexDigit::HEX_F)),
+            instrument: Field::new((HexDigit::HEX_0, HexDigit::HEX_3)),
+            effect: Field::new(EffectFieldValue {
+                command: HexDigit::HEX_A,
+                parameter: (HexDigit::HEX_0, HexDigit::HEX_4),
+            }),
+        };
+        assert_eq!("C#5 5F 03 A04", line.to_string());
+        assert_eq!("... .. .. ...", PatternLine::default().to_string());
+    }
+
+    #[test]
+    fn test_pattern_line_round_trips() {
+        for text in [
+            "C-0 .. .. ...",
+            "B-9 FF FF FFF",
+            "CUT 00 .. ...",
+            "... 24 AB 000",
+            "G#4 .. 7F 3C0",
+        ] {
+            assert_eq!(text, text.parse::<PatternLine>().unwrap().to_string());
+        }
+    }
+
+    #[test]
+    fn test_pattern_lines_without_effect_are_read() {
+        assert_eq!(
+            "C#5 5F 03 ...",
+            "C#5 5F 03".parse::<PatternLine>().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_invalid_pattern_lines_are_errors() {
+        for text in [
+            "",
+            "C-5",
+            "C-5 .. .. ... ..",
+            "E#5 .. ..",
+            "C-5 .. .. 1G0",
+            "C-5 .. .. 10",
+            "C-X .. ..",
+            "... G0 ..",
+            "... 0 ..",
+        ] {
+            assert!(text.parse::<PatternLine>().is_err(), "{text:?}");
+        }
+    }
+
+    fn patterns_with_order(pattern_count: i32, order: Vec<usize>) -> Patterns {
+        let mut patterns = Patterns::new(1, 2, pattern_count);
+        for (index, pattern) in patterns.patterns.iter_mut().enumerate() {
+            pattern.lines[0]
+                .velocity
+                .set(u8_to_hex_digit_pair(index as u8));
+        }
+        patterns.set_order(order).unwrap();
+        patterns
+    }
+
+    fn velocities(patterns: &Patterns) -> Vec<u8> {
+        patterns
+            .patterns()
+            .iter()
+            .map(|pattern| pattern.lines[0].velocity.get_u8().unwrap_or(0xFF))
+            .collect()
+    }
+
+    #[test]
+    fn test_default_order_plays_every_pattern() {
+        assert_eq!(&[0, 1, 2], Patterns::new(1, 2, 3).order());
+    }
+
+    #[test]
+    fn test_invalid_orders_are_errors() {
+        let mut patterns = Patterns::new(1, 2, 2);
+        assert!(patterns.set_order(vec![]).is_err());
+        assert!(patterns.set_order(vec![0, 2]).is_err());
+        assert!(patterns.set_order(vec![1, 1, 0]).is_ok());
+    }
+
+    #[test]
+    fn test_created_pattern_plays_after_the_current_order_entry() {
+        let mut patterns = patterns_with_order(2, vec![0, 1, 0]);
+        patterns.current_order = 1;
+        patterns.create_pattern();
+
+        assert_eq!(3, patterns.pattern_count);
+        assert_eq!(2, patterns.current_pattern);
+        assert_eq!(2, patterns.current_order);
+        assert_eq!(&[0, 1, 2, 0], patterns.order());
+        assert_eq!(vec![0, 1, 0xFF], velocities(&patterns));
+    }
+
+    #[test]
+    fn test_duplicated_pattern_is_a_copy() {
+        let mut patterns = patterns_with_order(2, vec![0, 1]);
+        patterns.current_pattern = 1;
+        patterns.current_order = 1;
+        patterns.duplicate_pattern();
+
+        assert_eq!(&[0, 1, 2], patterns.order());
+        assert_eq!(vec![0, 1, 1], velocities(&patterns));
+    }
+
+    #[test]
+    fn test_deleted_pattern_leaves_the_order() {
+        let mut patterns = patterns_with_order(3, vec![1, 0, 2, 1, 2]);
+        patterns.current_pattern = 1;
+        patterns.current_order = 4;
+        patterns.delete_current_pattern().unwrap();
+
+        assert_eq!(2, patterns.pattern_count);
+        assert_eq!(vec![0, 2], velocities(&patterns));
+        assert_eq!(&[0, 1, 1], patterns.order());
+        assert_eq!(2, patterns.current_order);
+        assert_eq!(1, patterns.current_pattern);
+    }
+
+    #[test]
+    fn test_order_is_never_empty() {
+        let mut patterns = patterns_with_order(2, vec![1]);
+        patterns.current_pattern = 1;
+        patterns.delete_current_pattern().unwrap();
+        assert_eq!(&[0], patterns.order());
+
+        assert!(patterns.delete_current_pattern().is_err());
+        assert!(patterns.remove_order_entry().is_err());
+    }
+
+    #[test]
+    fn test_order_entries_are_edited() {
+        let mut patterns = patterns_with_order(2, vec![0, 1]);
+        patterns.change_current_order(-1);
+        assert_eq!((1, 1), (patterns.current_order, patterns.current_pattern));
+
+        patterns.change_current_pattern(1);
+        assert_eq!(0, patterns.current_pattern);
+        patterns.insert_order_entry();
+        assert_eq!(&[0, 1, 0], patterns.order());
+        assert_eq!(2, patterns.current_order);
+
+        patterns.current_order = 0;
+        patterns.remove_order_entry().unwrap();
+        assert_eq!(&[1, 0], patterns.order());
+    }
 }
@@ -1,24 +1,207 @@
-use std::{fmt, time::Duration};
+use std::{fmt, ops::Range, time::Duration};
 
-use crate::audio::signal;
+use crate::{
+    audio::signal,
+    model::{record::RecordedLine, tempo::Tempo},
+};
+
+/// What the playback goes through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaybackStart {
+    /// The song from the row under the cursor
+    FromCursor,
+    LoopPattern,
+    /// The selected rows of the current pattern, or the cursor row
+    LoopSelection,
+    /// Only the row under the cursor, once
+    CurrentRow,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mode {
+    /// Goes through the order list until its end
+    Song,
+    /// Stays on some rows of a pattern, back to the first one after the last when looped
+    Rows {
+        pattern: usize,
+        rows: Range<usize>,
+        looped: bool,
+    },
+}
 
 #[derive(Clone)]
 pub struct Playback {
     pub line_signal: signal::stereo::Owned,
+    pub mode: Mode,
+    pub current_order: usize,
     pub current_line: usize,
-    pub current_line_duration: Duration,
-    pub line_duration: Duration,
+    pub current_tick: u32,
+    /// Time spent in the current tick
+    pub current_tick_duration: Duration,
     pub is_playing: bool,
 }
 
+impl Playback {
+    /// `None` once the playback went past its end
+    pub fn current_pattern(&self, order: &[usize]) -> Option<usize> {
+        self.pattern_at(order, self.current_order, self.current_line)
+    }
+
+    /// Goes to the next line to play, past the end of the playback when it is over
+    pub fn advance_line(&mut self, channel_len: usize) {
+        (self.current_order, self.current_line) = self.next_line(channel_len);
+    }
+
+    /// Line a note entered now is recorded at, the played one or the nearest one when
+    /// `quantized`. The played line is kept when there is no next line.
+    pub fn recorded_line(
+        &self,
+        order: &[usize],
+        channel_len: usize,
+        tempo: &Tempo,
+        quantized: bool,
+    ) -> Option<RecordedLine> {
+        let played = RecordedLine {
+            pattern: self.current_pattern(order)?,
+            row: self.current_line,
+        };
+        let elapsed = tempo.tick_duration() * self.current_tick + self.current_tick_duration;
+        if !quantized || elapsed * 2 < tempo.line_duration() {
+            return Some(played);
+        }
+        let (next_order, next_line) = self.next_line(channel_len);
+        Some(
+            self.pattern_at(order, next_order, next_line)
+                .map_or(played, |pattern| RecordedLine {
+                    pattern,
+                    row: next_line,
+                }),
+        )
+    }
+
+    fn pattern_at(&self, order: &[usize], order_index: usize, line: usize) -> Option<usize> {
+        match &self.mode {
+            Mode::Song => order.get(order_index).copied(),
+            Mode::Rows { pattern, rows, .. } => rows.contains(&line).then_some(*pattern),
+        }
+    }
+
+    /// Order entry and line following the played ones
+    fn next_line(&self, channel_len: usize) -> (usize, usize) {
+        let line = self.current_line + 1;
+        match &self.mode {
+            Mode::Song if line >= channel_len => (self.current_order + 1, 0),
+            Mode::Rows {
+                rows, looped: true, ..
+            } if line >= rows.end => (self.current_order, rows.start),
+            _ => (self.current_order, line),
+        }
+    }
+}
+
 impl fmt::Debug for Playback {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("SongPlayback")
             .field("step_signal", &"...")
             .field("line_signal", &"...")
+            .field("mode", &self.mode)
+            .field("current_order", &self.current_order)
             .field("current_line", &self.current_line)
-            .field("current_line_duration", &self.current_line_duration)
-            .field("line_duration", &self.line_duration)
+            .field("current_tick", &self.current_tick)
+            .field("current_tick_duration", &self.current_tick_duration)
             .finish()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn playback(mode: Mode, current_line: usize) -> Playback {
+        Playback {
+            line_signal: signal::Owned::new(44100.0),
+            mode,
+            current_order: 0,
+            current_line,
+            current_tick: 0,
+            current_tick_duration: Duration::ZERO,
+            is_playing: true,
+        }
+    }
+
+    fn played_lines(mut playback: Playback, order: &[usize], count: usize) -> Vec<(usize, usize)> {
+        let mut lines = Vec::new();
+        while let Some(pattern) = playback.current_pattern(order) {
+            if lines.len() == count {
+                break;
+            }
+            lines.push((pattern, playback.current_line));
+            playback.advance_line(2);
+        }
+        lines
+    }
+
+    #[test]
+    fn test_song_goes_through_the_order() {
+        assert_eq!(
+            vec![(1, 1), (0, 0), (0, 1)],
+            played_lines(playback(Mode::Song, 1), &[1, 0], 10)
+        );
+    }
+
+    #[test]
+    fn test_looped_rows_start_over() {
+        let mode = Mode::Rows {
+            pattern: 1,
+            rows: 2..4,
+            looped: true,
+        };
+        assert_eq!(
+            vec![(1, 2), (1, 3), (1, 2), (1, 3), (1, 2)],
+            played_lines(playback(mode, 2), &[0], 5)
+        );
+    }
+
+    #[test]
+    fn test_recorded_line_is_quantized_to_the_nearest_row() {
+        let tempo = Tempo::new(150.0, 4, 5).unwrap();
+        let mode = Mode::Rows {
+            pattern: 1,
+            rows: 0..2,
+            looped: true,
+        };
+        let mut playback = playback(mode, 1);
+        playback.current_tick = 2;
+        playback.current_tick_duration = Duration::from_millis(5);
+        let recorded_row = |playback: &Playback, quantized| {
+            playback
+                .recorded_line(&[0], 2, &tempo, quantized)
+                .map(|line| (line.pattern, line.row))
+        };
+
+        assert_eq!(Some((1, 1)), recorded_row(&playback, false));
+        assert_eq!(Some((1, 1)), recorded_row(&playback, true));
+        playback.current_tick_duration = Duration::from_millis(10);
+        assert_eq!(Some((1, 1)), recorded_row(&playback, false));
+        assert_eq!(Some((1, 0)), recorded_row(&playback, true));
+    }
+
+    #[test]
+    fn test_last_line_is_recorded_at_the_end_of_the_song() {
+        let tempo = Tempo::default();
+        let mut playback = playback(Mode::Song, 1);
+        playback.current_tick = tempo.ticks_per_row - 1;
+        let line = playback.recorded_line(&[3], 2, &tempo, true).unwrap();
+        assert_eq!((3, 1), (line.pattern, line.row));
+    }
+
+    #[test]
+    fn test_rows_stop_after_the_last_one() {
+        let mode = Mode::Rows {
+            pattern: 0,
+            rows: 3..4,
+            looped: false,
+        };
+        assert_eq!(vec![(0, 3)], played_lines(playback(mode, 3), &[0], 10));
+    }
+}
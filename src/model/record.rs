@@ -0,0 +1,68 @@
+//! Live recording of the notes entered while the song plays.
+//!
+//! Once armed, notes are written at the played line instead of the cursor, in the current
+//! channel. Polyphonic recording spreads the notes landing on the same line across the following
+//! channels, so a chord is not reduced to its last note.
+
+#[derive(Debug, Clone, Default)]
+pub struct Record {
+    pub armed: bool,
+    /// Notes are written at the nearest row, the next one once past the middle of the played row
+    pub quantized: bool,
+    pub polyphonic: bool,
+    /// Last line a note was recorded at, with the count of notes recorded there
+    last_line: Option<(RecordedLine, usize)>,
+}
+
+/// Where a note is recorded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordedLine {
+    pub pattern: usize,
+    pub row: usize,
+}
+
+impl Record {
+    /// Channel of the next note recorded at `line`, from `first_channel` on when polyphonic
+    pub fn next_channel(
+        &mut self,
+        line: RecordedLine,
+        first_channel: usize,
+        channel_count: usize,
+    ) -> usize {
+        let recorded_count = match self.last_line {
+            Some((last_line, count)) if self.polyphonic && last_line == line => count,
+            _ => 0,
+        };
+        self.last_line = Some((line, recorded_count + 1));
+        (first_channel + recorded_count) % channel_count
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const LINE: RecordedLine = RecordedLine { pattern: 0, row: 4 };
+
+    #[test]
+    fn test_monophonic_record_stays_in_the_channel() {
+        let mut record = Record::default();
+        assert_eq!(1, record.next_channel(LINE, 1, 4));
+        assert_eq!(1, record.next_channel(LINE, 1, 4));
+    }
+
+    #[test]
+    fn test_polyphonic_record_spreads_notes_of_the_same_line() {
+        let mut record = Record {
+            polyphonic: true,
+            ..Default::default()
+        };
+        let channels = (0..4)
+            .map(|_| record.next_channel(LINE, 1, 3))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![1, 2, 0, 1], channels);
+
+        let next_line = RecordedLine { row: 5, ..LINE };
+        assert_eq!(1, record.next_channel(next_line, 1, 3));
+    }
+}
@@ -0,0 +1,262 @@
+//! Loops of the sampled instruments.
+//!
+//! A sample plays once to its end unless it loops: past the loop end it goes back to the loop
+//! start (forward), or plays backward down to the loop start then forward again (ping-pong).
+//! While a note is held, its sample plays the sustain loop instead. The note-off leaves the sustain
+//! loop, the sample goes on with the loop or to its end.
+//!
+//! Loop points are frames of the sample, the end is excluded.
+
+use std::{fmt, str::FromStr};
+
+use anyhow::{bail, ensure, Context};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopMode {
+    Forward,
+    PingPong,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SampleLoop {
+    pub mode: LoopMode,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl SampleLoop {
+    pub fn new(mode: LoopMode, start: usize, end: usize) -> anyhow::Result<Self> {
+        ensure!(start < end, "Loop {start}..{end} is empty");
+        Ok(Self { mode, start, end })
+    }
+
+    /// Frames played before the loop is back at its start, a ping-pong loop plays its frames
+    /// twice but its ends once
+    fn period(&self) -> f32 {
+        let len = (self.end - self.start) as f32;
+        match self.mode {
+            LoopMode::Forward => len,
+            LoopMode::PingPong => (2.0 * (len - 1.0)).max(1.0),
+        }
+    }
+
+    /// Brings a position which went past the loop back in its period
+    pub fn wrap(&self, position: f32) -> f32 {
+        let start = self.start as f32;
+        if position < start + self.period() {
+            position
+        } else {
+            start + (position - start) % self.period()
+        }
+    }
+
+    /// Frame a wrapped position plays, the second half of a ping-pong period goes backward
+    pub fn fold(&self, position: f32) -> f32 {
+        let last = (self.end - 1) as f32;
+        match self.mode {
+            LoopMode::PingPong if position > last => (2.0 * last - position).max(self.start as f32),
+            _ => position,
+        }
+    }
+}
+
+/// Printed as "<mode> <start> <end>", e.g. "pingpong 100 2000"
+impl fmt::Display for SampleLoop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mode = match self.mode {
+            LoopMode::Forward => "forward",
+            LoopMode::PingPong => "pingpong",
+        };
+        write!(f, "{mode} {} {}", self.start, self.end)
+    }
+}
+
+impl FromStr for SampleLoop {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let values = s.split_whitespace().collect::<Vec<_>>();
+        let [mode, start, end] = values[..] else {
+            bail!("Expected \"<forward|pingpong> <start> <end>\", found {s:?}");
+        };
+        let mode = match mode.to_lowercase().as_str() {
+            "forward" => LoopMode::Forward,
+            "pingpong" => LoopMode::PingPong,
+            _ => bail!("Unknown loop mode {mode:?}, expected forward or pingpong"),
+        };
+        Self::new(
+            mode,
+            start
+                .parse()
+                .with_context(|| format!("Invalid loop start {start:?}"))?,
+            end.parse()
+                .with_context(|| format!("Invalid loop end {end:?}"))?,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SampleLoops {
+    pub normal: Option<SampleLoop>,
+    pub sustain: Option<SampleLoop>,
+}
+
+impl SampleLoops {
+    pub fn ensure_fits(&self, frame_count: usize) -> anyhow::Result<()> {
+        for sample_loop in self.normal.iter().chain(&self.sustain) {
+            ensure!(
+                sample_loop.end <= frame_count,
+                "Loop {}..{} goes past the {frame_count} frame(s) of the sample",
+                sample_loop.start,
+                sample_loop.end
+            );
+        }
+        Ok(())
+    }
+
+    /// Loop played depending on whether the note is released
+    pub fn active(&self, released: bool) -> Option<&SampleLoop> {
+        match self.sustain {
+            Some(ref sustain) if !released => Some(sustain),
+            _ => self.normal.as_ref(),
+        }
+    }
+
+    /// Position the note-off goes on from, it leaves the sustain loop forward
+    pub fn release(&self, position: f32) -> f32 {
+        self.sustain
+            .map_or(position, |sustain| sustain.fold(position))
+    }
+}
+
+/// Printed as "<loop>; <sustain loop>" where a missing loop is "none",
+/// e.g. "forward 0 4410; pingpong 100 200"
+impl fmt::Display for SampleLoops {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = |sample_loop: Option<SampleLoop>| {
+            sample_loop.map_or_else(|| "none".to_string(), |sample_loop| sample_loop.to_string())
+        };
+        write!(f, "{}; {}", text(self.normal), text(self.sustain))
+    }
+}
+
+/// The sustain loop can be left out
+impl FromStr for SampleLoops {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let parse = |text: &str| match text.trim() {
+            "" | "none" => Ok(None),
+            text => text.parse().map(Some),
+        };
+        let (normal, sustain) = s.split_once(';').unwrap_or((s, ""));
+        Ok(Self {
+            normal: parse(normal).context("Invalid loop")?,
+            sustain: parse(sustain).context("Invalid sustain loop")?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Frames played from `position`, one per step
+    fn played(sample_loop: &SampleLoop, mut position: f32, count: usize) -> Vec<f32> {
+        (0..count)
+            .map(|_| {
+                let frame = sample_loop.fold(position);
+                position = sample_loop.wrap(position + 1.0);
+                frame
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_forward_loop_goes_back_to_its_start() {
+        let sample_loop = SampleLoop::new(LoopMode::Forward, 2, 5).unwrap();
+        assert_eq!(
+            vec![0.0, 1.0, 2.0, 3.0, 4.0, 2.0, 3.0, 4.0, 2.0],
+            played(&sample_loop, 0.0, 9)
+        );
+    }
+
+    #[test]
+    fn test_ping_pong_loop_plays_backward() {
+        let sample_loop = SampleLoop::new(LoopMode::PingPong, 1, 4).unwrap();
+        assert_eq!(
+            vec![0.0, 1.0, 2.0, 3.0, 2.0, 1.0, 2.0, 3.0, 2.0],
+            played(&sample_loop, 0.0, 9)
+        );
+    }
+
+    #[test]
+    fn test_single_frame_ping_pong_loop_holds_its_frame() {
+        let sample_loop = SampleLoop::new(LoopMode::PingPong, 3, 4).unwrap();
+        assert_eq!(vec![3.0; 4], played(&sample_loop, 3.0, 4));
+    }
+
+    #[test]
+    fn test_sustain_loop_is_left_on_release() {
+        let loops = SampleLoops {
+            normal: Some(SampleLoop::new(LoopMode::Forward, 0, 10).unwrap()),
+            sustain: Some(SampleLoop::new(LoopMode::PingPong, 2, 6).unwrap()),
+        };
+        assert_eq!(loops.sustain.as_ref(), loops.active(false));
+        assert_eq!(loops.normal.as_ref(), loops.active(true));
+        // Backward in the sustain loop at frame 4
+        approx::assert_relative_eq!(4.0, loops.release(6.0));
+        approx::assert_relative_eq!(3.0, loops.release(3.0));
+
+        let loops = SampleLoops {
+            sustain: None,
+            ..loops
+        };
+        assert_eq!(loops.normal.as_ref(), loops.active(false));
+        approx::assert_relative_eq!(6.0, loops.release(6.0));
+    }
+
+    #[test]
+    fn test_loops_must_fit_in_the_sample() {
+        let loops = SampleLoops {
+            normal: None,
+            sustain: Some(SampleLoop::new(LoopMode::Forward, 2, 6).unwrap()),
+        };
+        assert!(loops.ensure_fits(6).is_ok());
+        assert!(loops.ensure_fits(5).is_err());
+    }
+
+    #[test]
+    fn test_loops_round_trip_through_text() {
+        let loops = SampleLoops {
+            normal: Some(SampleLoop::new(LoopMode::Forward, 0, 4410).unwrap()),
+            sustain: Some(SampleLoop::new(LoopMode::PingPong, 100, 200).unwrap()),
+        };
+        assert_eq!("forward 0 4410; pingpong 100 200", loops.to_string());
+        assert_eq!(loops, loops.to_string().parse().unwrap());
+        assert_eq!("none; none", SampleLoops::default().to_string());
+        assert_eq!(
+            SampleLoops {
+                normal: loops.normal,
+                sustain: None
+            },
+            " Forward 0 4410 ".parse().unwrap()
+        );
+        assert_eq!(SampleLoops::default(), "".parse().unwrap());
+    }
+
+    #[test]
+    fn test_invalid_loops_are_errors() {
+        for text in [
+            "forward 10",
+            "forward 10 10",
+            "forward 10 5",
+            "backward 0 10",
+            "forward a 10",
+            "none; pingpong -1 10",
+            "forward 0 10 20",
+        ] {
+            assert!(text.parse::<SampleLoops>().is_err(), "{text:?}");
+        }
+    }
+}
@@ -0,0 +1,403 @@
+//! Rectangular selections of pattern fields, and the blocks copied from them.
+//!
+//! The fields of every channel are laid out side by side in columns, a selection spans whole
+//! fields: selecting a single digit of the velocity selects the velocity. Selections only hold
+//! positions, so they survive switching patterns.
+
+use std::ops::Range;
+
+use super::{
+    history::Edit,
+    pattern::{PatternLine, PatternLineDescriptor, Patterns},
+};
+
+const FIELD_COUNT: i32 = PatternLineDescriptor::COUNT as i32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldPosition {
+    /// `channel * field count + field index`
+    pub column: i32,
+    pub row: i32,
+}
+
+impl FieldPosition {
+    pub fn from_cursor(patterns: &Patterns) -> Self {
+        Self {
+            column: patterns.current_channel * FIELD_COUNT
+                + PatternLineDescriptor::field_index_by_cursor(patterns.current_field) as i32,
+            row: patterns.current_row,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Selection {
+    anchor: FieldPosition,
+    end: FieldPosition,
+}
+
+impl Selection {
+    pub fn new(position: FieldPosition) -> Self {
+        Self {
+            anchor: position,
+            end: position,
+        }
+    }
+
+    /// Moves the end of the selection, the anchor stays in place
+    pub fn extend_to(&mut self, position: FieldPosition) {
+        self.end = position;
+    }
+
+    fn columns(&self) -> Range<i32> {
+        self.anchor.column.min(self.end.column)..self.anchor.column.max(self.end.column) + 1
+    }
+
+    pub fn rows(&self) -> Range<i32> {
+        self.anchor.row.min(self.end.row)..self.anchor.row.max(self.end.row) + 1
+    }
+
+    /// Indexes of the fields selected in the line, empty when the line is not selected
+    pub fn selected_fields(&self, channel: usize, row: usize) -> Range<usize> {
+        if !self.rows().contains(&(row as i32)) {
+            return 0..0;
+        }
+        channel_fields(self.columns(), channel as i32)
+    }
+}
+
+/// Field indexes of the columns in the channel
+fn channel_fields(columns: Range<i32>, channel: i32) -> Range<usize> {
+    let channel_start = channel * FIELD_COUNT;
+    let start = columns
+        .start
+        .clamp(channel_start, channel_start + FIELD_COUNT);
+    let end = columns.end.clamp(start, channel_start + FIELD_COUNT);
+    (start - channel_start) as usize..(end - channel_start) as usize
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasteMode {
+    /// Pushes the following rows of the pasted fields down, the ones past the end are lost
+    Insert,
+    Overwrite,
+    /// Only fills the empty fields
+    Mix,
+}
+
+/// Fields copied from a selection. Its columns keep their field, pasting a velocity column always
+/// writes velocities.
+#[derive(Debug, Clone)]
+pub struct Block {
+    /// Columns relative to the first copied channel
+    columns: Range<i32>,
+    row_count: usize,
+    /// `channel offset * row count + row`, the fields out of the columns are empty
+    lines: Vec<PatternLine>,
+}
+
+impl Block {
+    fn channel_count(&self) -> usize {
+        (self.columns.end - 1) as usize / FIELD_COUNT as usize + 1
+    }
+
+    fn line(&self, channel_offset: usize, row: usize) -> &PatternLine {
+        &self.lines[channel_offset * self.row_count + row]
+    }
+}
+
+impl Patterns {
+    /// Copies the selected fields of the current pattern
+    pub fn copy_block(&self, selection: &Selection) -> Block {
+        let columns = selection.columns();
+        let rows = selection.rows();
+        let first_channel = columns.start / FIELD_COUNT;
+        let channel_offset = first_channel * FIELD_COUNT;
+        let columns = columns.start - channel_offset..columns.end - channel_offset;
+        let row_count = rows.len();
+
+        let mut block = Block {
+            columns,
+            row_count,
+            lines: Vec::new(),
+        };
+        for channel_offset in 0..block.channel_count() {
+            let fields = channel_fields(block.columns.clone(), channel_offset as i32);
+            let channel = first_channel as usize + channel_offset;
+            for row in rows.clone() {
+                let mut line = PatternLine::default();
+                if channel < self.channel_count as usize && row < self.channel_len {
+                    let source = self.line(channel, row as usize);
+                    for field in fields.clone() {
+                        line.copy_field(PatternLineDescriptor::VARIANTS[field], source);
+                    }
+                }
+                block.lines.push(line);
+            }
+        }
+        block
+    }
+
+    /// Edits every line of the current pattern having selected fields, `edit` receives the line
+    /// and the indexes of its selected fields. Returns the edits reverting it.
+    pub fn edit_block<EditFn>(&mut self, selection: &Selection, mut edit: EditFn) -> Vec<Edit>
+    where
+        EditFn: FnMut(&mut PatternLine, Range<usize>),
+    {
+        let mut edits = Vec::new();
+        for channel in 0..self.channel_count as usize {
+            for row in 0..self.channel_len as usize {
+                let fields = selection.selected_fields(channel, row);
+                if fields.is_empty() {
+                    continue;
+                }
+                edits.extend(self.edit_line(channel, row, |line| edit(line, fields)));
+            }
+        }
+        edits.reverse();
+        edits
+    }
+
+    /// Empties the selected fields of the current pattern, returns the edits reverting it
+    pub fn clear_block(&mut self, selection: &Selection) -> Vec<Edit> {
+        self.edit_block(selection, |line, fields| {
+            for field in fields {
+                line.clear_field(PatternLineDescriptor::VARIANTS[field]);
+            }
+        })
+    }
+
+    /// Pastes the block in the current pattern, from the cursor channel and row. Returns the edits
+    /// reverting it.
+    pub fn paste_block(&mut self, block: &Block, mode: PasteMode) -> Vec<Edit> {
+        let first_channel = self.current_channel as usize;
+        let first_row = self.current_row as usize;
+        let channel_len = self.channel_len as usize;
+        let channel_count =
+            (self.channel_count as usize - first_channel).min(block.channel_count());
+
+        let mut edits = Vec::new();
+        for channel_offset in 0..channel_count {
+            let channel = first_channel + channel_offset;
+            let fields = channel_fields(block.columns.clone(), channel_offset as i32);
+            let previous_lines = (0..channel_len)
+                .map(|row| self.line(channel, row).clone())
+                .collect::<Vec<_>>();
+            let last_row = match mode {
+                PasteMode::Insert => channel_len,
+                PasteMode::Overwrite | PasteMode::Mix => {
+                    (first_row + block.row_count).min(channel_len)
+                }
+            };
+
+            for row in first_row..last_row {
+                let source = match row - first_row {
+                    block_row if block_row < block.row_count => {
+                        block.line(channel_offset, block_row)
+                    }
+                    _ => &previous_lines[row - block.row_count],
+                };
+                edits.extend(self.edit_line(channel, row, |line| {
+                    for field in fields.clone() {
+                        let descriptor = PatternLineDescriptor::VARIANTS[field];
+                        if mode != PasteMode::Mix || line.is_field_empty(descriptor) {
+                            line.copy_field(descriptor, source);
+                        }
+                    }
+                }));
+            }
+        }
+        edits.reverse();
+        edits
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const LINES: [&str; 4] = [
+        "C-5 10 01 ...",
+        "D-5 .. 02 ...",
+        "E-5 30 .. ...",
+        "F-5 40 04 ...",
+    ];
+
+    /// Two channels of four lines, both with the same lines
+    fn patterns() -> Patterns {
+        let mut patterns = Patterns::new(2, 4, 2);
+        for channel in 0..2 {
+            for (row, text) in LINES.iter().enumerate() {
+                patterns.edit_line(channel, row, |line| *line = text.parse().unwrap());
+            }
+        }
+        patterns
+    }
+
+    fn channel_lines(patterns: &Patterns, channel: usize) -> Vec<String> {
+        (0..patterns.channel_len as usize)
+            .map(|row| patterns.line(channel, row).to_string())
+            .collect()
+    }
+
+    fn selection(from: (i32, i32), to: (i32, i32)) -> Selection {
+        let mut selection = Selection::new(FieldPosition {
+            column: from.0,
+            row: from.1,
+        });
+        selection.extend_to(FieldPosition {
+            column: to.0,
+            row: to.1,
+        });
+        selection
+    }
+
+    #[test]
+    fn test_selected_fields() {
+        // From the instrument of channel 0 to the velocity of channel 1, rows 1 to 2
+        let selection = selection((5, 2), (2, 1));
+        assert_eq!(0..0, selection.selected_fields(0, 0));
+        assert_eq!(2..4, selection.selected_fields(0, 1));
+        assert_eq!(0..2, selection.selected_fields(1, 2));
+        assert_eq!(0..0, selection.selected_fields(2, 2));
+    }
+
+    #[test]
+    fn test_cursor_position_covers_the_whole_field() {
+        let mut patterns = patterns();
+        patterns.current_channel = 1;
+        patterns.current_field = 4;
+        assert_eq!(
+            FieldPosition { column: 5, row: 0 },
+            FieldPosition::from_cursor(&patterns)
+        );
+        patterns.current_field = 6;
+        patterns.current_row = 3;
+        assert_eq!(
+            FieldPosition { column: 6, row: 3 },
+            FieldPosition::from_cursor(&patterns)
+        );
+    }
+
+    #[test]
+    fn test_cut_and_paste_in_another_pattern() {
+        let mut patterns = patterns();
+        // Velocity and instrument of channel 0, rows 0 to 1
+        let selection = selection((1, 0), (2, 1));
+        let block = patterns.copy_block(&selection);
+        patterns.clear_block(&selection);
+        assert_eq!(
+            vec![
+                "C-5 .. .. ...",
+                "D-5 .. .. ...",
+                "E-5 30 .. ...",
+                "F-5 40 04 ..."
+            ],
+            channel_lines(&patterns, 0)
+        );
+
+        patterns.change_current_pattern(1);
+        patterns.current_channel = 1;
+        patterns.current_row = 2;
+        patterns.paste_block(&block, PasteMode::Overwrite);
+        assert_eq!(
+            vec![
+                "... .. .. ...",
+                "... .. .. ...",
+                "... 10 01 ...",
+                "... .. 02 ..."
+            ],
+            channel_lines(&patterns, 1)
+        );
+    }
+
+    #[test]
+    fn test_overwrite_paste_writes_empty_fields() {
+        let mut patterns = patterns();
+        let block = patterns.copy_block(&selection((0, 1), (2, 2)));
+        patterns.paste_block(&block, PasteMode::Overwrite);
+        assert_eq!(
+            vec![
+                "D-5 .. 02 ...",
+                "E-5 30 .. ...",
+                "E-5 30 .. ...",
+                "F-5 40 04 ..."
+            ],
+            channel_lines(&patterns, 0)
+        );
+    }
+
+    #[test]
+    fn test_mix_paste_only_fills_empty_fields() {
+        let mut patterns = patterns();
+        let block = patterns.copy_block(&selection((0, 2), (2, 3)));
+        patterns.current_row = 1;
+        patterns.paste_block(&block, PasteMode::Mix);
+        assert_eq!(
+            vec![
+                "C-5 10 01 ...",
+                "D-5 30 02 ...",
+                "E-5 30 04 ...",
+                "F-5 40 04 ..."
+            ],
+            channel_lines(&patterns, 0)
+        );
+    }
+
+    #[test]
+    fn test_insert_paste_pushes_the_following_rows() {
+        let mut patterns = patterns();
+        // Notes of both channels, row 3
+        let block = patterns.copy_block(&selection((0, 3), (4, 3)));
+        patterns.current_row = 1;
+        patterns.paste_block(&block, PasteMode::Insert);
+        assert_eq!(
+            vec![
+                "C-5 10 01 ...",
+                "F-5 40 04 ...",
+                "D-5 .. 02 ...",
+                "E-5 30 .. ..."
+            ],
+            channel_lines(&patterns, 0)
+        );
+        assert_eq!(
+            vec![
+                "C-5 10 01 ...",
+                "F-5 .. 02 ...",
+                "D-5 30 .. ...",
+                "E-5 40 04 ..."
+            ],
+            channel_lines(&patterns, 1)
+        );
+    }
+
+    #[test]
+    fn test_paste_is_clipped_to_the_pattern() {
+        let mut patterns = patterns();
+        let block = patterns.copy_block(&selection((0, 0), (7, 3)));
+        patterns.current_channel = 1;
+        patterns.current_row = 3;
+        patterns.clear_block(&selection((4, 0), (7, 3)));
+        patterns.paste_block(&block, PasteMode::Overwrite);
+        assert_eq!(
+            vec![
+                "... .. .. ...",
+                "... .. .. ...",
+                "... .. .. ...",
+                "C-5 10 01 ..."
+            ],
+            channel_lines(&patterns, 1)
+        );
+    }
+
+    #[test]
+    fn test_paste_is_undone_at_once() {
+        let mut patterns = patterns();
+        let before = channel_lines(&patterns, 0);
+        let block = patterns.copy_block(&selection((0, 2), (2, 3)));
+        let edits = patterns.paste_block(&block, PasteMode::Insert);
+        assert_eq!(4, edits.len());
+        patterns.apply_edits(edits);
+        assert_eq!(before, channel_lines(&patterns, 0));
+    }
+}
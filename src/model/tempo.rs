@@ -0,0 +1,167 @@
+//! Musical tempo of a song.
+//!
+//! A beat lasts `60 / bpm` seconds and is split in `rows_per_beat` rows, each row is split in
+//! `ticks_per_row` ticks. Ticks are the time unit effects run on.
+
+use std::{fmt, ops::RangeInclusive, str::FromStr, time::Duration};
+
+use anyhow::{bail, ensure, Context};
+
+/// Tracker modules reach 1530 BPM, at speed 1 and tempo 255
+pub const BPM_RANGE: RangeInclusive<f32> = 1.0..=2000.0;
+pub const ROWS_PER_BEAT_RANGE: RangeInclusive<u32> = 1..=64;
+pub const TICKS_PER_ROW_RANGE: RangeInclusive<u32> = 1..=31;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tempo {
+    pub bpm: f32,
+    pub rows_per_beat: u32,
+    pub ticks_per_row: u32,
+}
+
+/// 16 rows per second
+impl Default for Tempo {
+    fn default() -> Self {
+        Self {
+            bpm: 120.0,
+            rows_per_beat: 8,
+            ticks_per_row: 6,
+        }
+    }
+}
+
+impl Tempo {
+    pub fn new(bpm: f32, rows_per_beat: u32, ticks_per_row: u32) -> anyhow::Result<Tempo> {
+        ensure!(
+            BPM_RANGE.contains(&bpm),
+            "Invalid BPM {bpm}, expected {} to {}",
+            BPM_RANGE.start(),
+            BPM_RANGE.end()
+        );
+        ensure!(
+            ROWS_PER_BEAT_RANGE.contains(&rows_per_beat),
+            "Invalid rows per beat {rows_per_beat}, expected {} to {}",
+            ROWS_PER_BEAT_RANGE.start(),
+            ROWS_PER_BEAT_RANGE.end()
+        );
+        ensure!(
+            TICKS_PER_ROW_RANGE.contains(&ticks_per_row),
+            "Invalid ticks per row {ticks_per_row}, expected {} to {}",
+            TICKS_PER_ROW_RANGE.start(),
+            TICKS_PER_ROW_RANGE.end()
+        );
+        Ok(Self {
+            bpm,
+            rows_per_beat,
+            ticks_per_row,
+        })
+    }
+
+    pub fn from_line_per_second(
+        line_per_second: f32,
+        rows_per_beat: u32,
+        ticks_per_row: u32,
+    ) -> anyhow::Result<Tempo> {
+        Self::new(
+            line_per_second * 60.0 / rows_per_beat as f32,
+            rows_per_beat,
+            ticks_per_row,
+        )
+    }
+
+    pub fn line_per_second(&self) -> f32 {
+        self.bpm * self.rows_per_beat as f32 / 60.0
+    }
+
+    pub fn line_duration(&self) -> Duration {
+        Duration::from_secs_f64(60.0 / (self.bpm as f64 * self.rows_per_beat as f64))
+    }
+
+    pub fn tick_duration(&self) -> Duration {
+        self.line_duration() / self.ticks_per_row
+    }
+}
+
+/// Printed as "<bpm> <rows per beat> <ticks per row>", e.g. "120 8 6"
+impl fmt::Display for Tempo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            self.bpm, self.rows_per_beat, self.ticks_per_row
+        )
+    }
+}
+
+impl FromStr for Tempo {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let values = s.split_whitespace().collect::<Vec<_>>();
+        let [bpm, rows_per_beat, ticks_per_row] = values[..] else {
+            bail!("Expected \"<bpm> <rows per beat> <ticks per row>\", found {s:?}");
+        };
+        Self::new(
+            bpm.parse()
+                .with_context(|| format!("Invalid BPM {bpm:?}"))?,
+            rows_per_beat
+                .parse()
+                .with_context(|| format!("Invalid rows per beat {rows_per_beat:?}"))?,
+            ticks_per_row
+                .parse()
+                .with_context(|| format!("Invalid ticks per row {ticks_per_row:?}"))?,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_default_tempo_plays_16_lines_per_second() {
+        approx::assert_relative_eq!(16.0, Tempo::default().line_per_second());
+    }
+
+    #[test]
+    fn test_durations() {
+        let tempo = Tempo::new(150.0, 4, 5).unwrap();
+        approx::assert_relative_eq!(10.0, tempo.line_per_second());
+        assert_eq!(Duration::from_millis(100), tempo.line_duration());
+        assert_eq!(Duration::from_millis(20), tempo.tick_duration());
+    }
+
+    #[test]
+    fn test_from_line_per_second() {
+        let tempo = Tempo::from_line_per_second(12.0, 4, 6).unwrap();
+        approx::assert_relative_eq!(180.0, tempo.bpm);
+        approx::assert_relative_eq!(12.0, tempo.line_per_second());
+    }
+
+    #[test]
+    fn test_tempo_round_trips_through_text() {
+        let tempo = Tempo::new(125.5, 4, 3).unwrap();
+        assert_eq!(tempo, tempo.to_string().parse().unwrap());
+        assert_eq!(Tempo::default(), " 120  8 6 ".parse().unwrap());
+    }
+
+    #[test]
+    fn test_invalid_tempos_are_errors() {
+        for text in [
+            "",
+            "120 8",
+            "0 8 6",
+            "-5 8 6",
+            "120 0 6",
+            "120 8 0",
+            "fast 8 6",
+            "NaN 8 6",
+            "1e10 8 6",
+            "1e-30 8 6",
+            "120 4294967295 6",
+            "120 8 32",
+        ] {
+            assert!(text.parse::<Tempo>().is_err(), "{text:?}");
+        }
+    }
+}
@@ -0,0 +1,275 @@
+//! Batch operations on the selected fields of the current pattern.
+//!
+//! Every operation returns the edits reverting it so it is undone in one step.
+
+use super::{
+    history::Edit,
+    midi::{midi_value_to_note, MidiValue},
+    pattern::{u8_to_hex_digit_pair, NoteFieldValue, PatternLineDescriptor, Patterns},
+    selection::Selection,
+};
+
+const NOTE_FIELD: usize = PatternLineDescriptor::Note as usize;
+const VELOCITY_FIELD: usize = PatternLineDescriptor::Velocity as usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    Linear,
+    /// Constant ratio between consecutive rows, a null velocity is treated as 1
+    Exponential,
+}
+
+impl Interpolation {
+    /// Value at `ratio` from `start` (0) to `end` (1)
+    fn value(self, start: u8, end: u8, ratio: f32) -> u8 {
+        let value = match self {
+            Interpolation::Linear => start as f32 + (end as f32 - start as f32) * ratio,
+            Interpolation::Exponential => {
+                let start = start.max(1) as f32;
+                let end = end.max(1) as f32;
+                start * (end / start).powf(ratio)
+            }
+        };
+        value.round().clamp(0.0, u8::MAX as f32) as u8
+    }
+}
+
+impl Patterns {
+    /// Transposes the selected notes, they are clamped to the playable notes
+    pub fn transpose_block(&mut self, selection: &Selection, semitones: i32) -> Vec<Edit> {
+        self.edit_block(selection, |line, fields| {
+            if !fields.contains(&NOTE_FIELD) {
+                return;
+            }
+            if let Some(&NoteFieldValue::Note(note, octave)) = line.note.value() {
+                let (note, octave) =
+                    midi_value_to_note(MidiValue::from((note, octave)) + semitones);
+                line.note.set(NoteFieldValue::Note(note, octave));
+            }
+        })
+    }
+
+    /// Fills the selected velocities between the first and the last selected row, of every
+    /// channel where both are set
+    pub fn interpolate_velocity(
+        &mut self,
+        selection: &Selection,
+        interpolation: Interpolation,
+    ) -> Vec<Edit> {
+        let rows = selection.rows();
+        let first_row = rows.start as usize;
+        let last_row = (rows.end as usize).min(self.channel_len as usize) - 1;
+        if last_row <= first_row {
+            return Vec::new();
+        }
+
+        let mut edits = Vec::new();
+        for channel in 0..self.channel_count as usize {
+            if !selection
+                .selected_fields(channel, first_row)
+                .contains(&VELOCITY_FIELD)
+            {
+                continue;
+            }
+            let (Some(start), Some(end)) = (
+                self.line(channel, first_row).velocity.get_u8(),
+                self.line(channel, last_row).velocity.get_u8(),
+            ) else {
+                continue;
+            };
+
+            for row in first_row + 1..last_row {
+                let ratio = (row - first_row) as f32 / (last_row - first_row) as f32;
+                let velocity = u8_to_hex_digit_pair(interpolation.value(start, end, ratio));
+                edits.extend(self.edit_line(channel, row, |line| line.velocity.set(velocity)));
+            }
+        }
+        edits.reverse();
+        edits
+    }
+
+    /// Moves every selected velocity by a random amount, up to `spread` in both directions
+    pub fn humanize_velocity(
+        &mut self,
+        selection: &Selection,
+        spread: u8,
+        rng: &mut fastrand::Rng,
+    ) -> Vec<Edit> {
+        let spread = spread as i32;
+        self.edit_block(selection, |line, fields| {
+            if !fields.contains(&VELOCITY_FIELD) {
+                return;
+            }
+            if let Some(velocity) = line.velocity.get_u8() {
+                let velocity = (velocity as i32 + rng.i32(-spread..=spread)).clamp(0, 0xFF);
+                line.velocity.set(u8_to_hex_digit_pair(velocity as u8));
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::model::{pattern::PatternLine, selection::FieldPosition};
+
+    use super::*;
+
+    fn patterns(lines: &[&str]) -> Patterns {
+        let mut patterns = Patterns::new(1, lines.len() as i32, 1);
+        for (row, text) in lines.iter().enumerate() {
+            patterns.edit_line(0, row, |line| *line = text.parse().unwrap());
+        }
+        patterns
+    }
+
+    fn lines(patterns: &Patterns) -> Vec<String> {
+        (0..patterns.channel_len as usize)
+            .map(|row| patterns.line(0, row).to_string())
+            .collect()
+    }
+
+    /// Selects `fields` of every row
+    fn selection(patterns: &Patterns, fields: (i32, i32)) -> Selection {
+        let mut selection = Selection::new(FieldPosition {
+            column: fields.0,
+            row: 0,
+        });
+        selection.extend_to(FieldPosition {
+            column: fields.1,
+            row: patterns.channel_len - 1,
+        });
+        selection
+    }
+
+    #[test]
+    fn test_transpose_is_clamped() {
+        let mut patterns = patterns(&[
+            "C-5 10 .. ...",
+            "CUT .. .. ...",
+            "B-9 .. .. ...",
+            "C#0 .. .. ...",
+        ]);
+        let all = selection(&patterns, (0, 2));
+
+        patterns.transpose_block(&all, 1);
+        assert_eq!(
+            vec![
+                "C#5 10 .. ...",
+                "CUT .. .. ...",
+                "B-9 .. .. ...",
+                "D-0 .. .. ..."
+            ],
+            lines(&patterns)
+        );
+        patterns.transpose_block(&all, -12);
+        assert_eq!(
+            vec![
+                "C#4 10 .. ...",
+                "CUT .. .. ...",
+                "B-8 .. .. ...",
+                "C-0 .. .. ..."
+            ],
+            lines(&patterns)
+        );
+    }
+
+    #[test]
+    fn test_transpose_needs_the_note_column() {
+        let mut patterns = patterns(&["C-5 10 .. ..."]);
+        let velocity = selection(&patterns, (1, 2));
+        assert!(patterns.transpose_block(&velocity, 1).is_empty());
+    }
+
+    #[test]
+    fn test_linear_interpolation() {
+        let mut patterns = patterns(&[
+            "C-5 00 .. ...",
+            "... .. .. ...",
+            "... 55 .. ...",
+            "... .. .. ...",
+            "... 80 .. ...",
+        ]);
+        let velocity = selection(&patterns, (1, 1));
+        let edits = patterns.interpolate_velocity(&velocity, Interpolation::Linear);
+
+        assert_eq!(
+            vec![
+                "C-5 00 .. ...",
+                "... 20 .. ...",
+                "... 40 .. ...",
+                "... 60 .. ...",
+                "... 80 .. ..."
+            ],
+            lines(&patterns)
+        );
+        patterns.apply_edits(edits);
+        assert_eq!("... 55 .. ...", lines(&patterns)[2]);
+    }
+
+    #[test]
+    fn test_exponential_interpolation() {
+        let mut patterns = patterns(&[
+            "... 01 .. ...",
+            "... .. .. ...",
+            "... .. .. ...",
+            "... 08 .. ...",
+        ]);
+        let velocity = selection(&patterns, (1, 1));
+        patterns.interpolate_velocity(&velocity, Interpolation::Exponential);
+
+        assert_eq!(
+            vec![
+                "... 01 .. ...",
+                "... 02 .. ...",
+                "... 04 .. ...",
+                "... 08 .. ..."
+            ],
+            lines(&patterns)
+        );
+    }
+
+    #[test]
+    fn test_interpolation_needs_both_ends() {
+        let mut patterns = patterns(&["... 01 .. ...", "... .. .. ...", "... .. .. ..."]);
+        let velocity = selection(&patterns, (1, 1));
+        assert!(patterns
+            .interpolate_velocity(&velocity, Interpolation::Linear)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_humanize_stays_within_the_spread() {
+        let original = [
+            "... 80 .. ...",
+            "... 02 .. ...",
+            "... .. .. ...",
+            "... FE .. ...",
+        ];
+        let mut patterns = patterns(&original);
+        let velocity = selection(&patterns, (1, 1));
+        let mut rng = fastrand::Rng::with_seed(7);
+        patterns.humanize_velocity(&velocity, 4, &mut rng);
+
+        for (row, original) in original.iter().enumerate() {
+            let line = patterns.line(0, row);
+            let original = original.parse::<PatternLine>().unwrap();
+            match (original.velocity.get_u8(), line.velocity.get_u8()) {
+                (Some(original), Some(humanized)) => {
+                    assert!((original as i32 - humanized as i32).abs() <= 4)
+                }
+                (None, None) => {}
+                velocities => panic!("{velocities:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_humanize_without_spread_changes_nothing() {
+        let mut patterns = patterns(&["... 80 .. ...", "... 02 .. ..."]);
+        let velocity = selection(&patterns, (1, 1));
+        let mut rng = fastrand::Rng::with_seed(7);
+        assert!(patterns
+            .humanize_velocity(&velocity, 0, &mut rng)
+            .is_empty());
+    }
+}
@@ -1,5 +1,5 @@
 use crate::model::pattern::{
-    Field, HexDigit, NoteFieldValue, NoteName, OctaveValue, PatternLineDescriptor,
+    EffectFieldValue, Field, HexDigit, NoteFieldValue, NoteName, OctaveValue, PatternLineDescriptor,
 };
 
 impl Field<NoteFieldValue> {
@@ -7,7 +7,9 @@ impl Field<NoteFieldValue> {
         match self.value() {
             Some(note_value) => match note_value {
                 NoteFieldValue::Note(_, _) => self.set(NoteFieldValue::Note(note, octave)),
-                NoteFieldValue::Cut => self.set(NoteFieldValue::Note(note, octave)),
+                NoteFieldValue::Off | NoteFieldValue::Cut => {
+                    self.set(NoteFieldValue::Note(note, octave))
+                }
             },
             None => self.set(NoteFieldValue::Note(note, octave)),
         }
@@ -17,7 +19,7 @@ impl Field<NoteFieldValue> {
         if let Some(note_value) = self.value() {
             match note_value {
                 NoteFieldValue::Note(note, _) => self.set(NoteFieldValue::Note(*note, octave)),
-                NoteFieldValue::Cut => {}
+                NoteFieldValue::Off | NoteFieldValue::Cut => {}
             }
         }
     }
@@ -55,3 +57,20 @@ impl Field<(HexDigit, HexDigit)> {
         }
     }
 }
+
+impl Field<EffectFieldValue> {
+    /// Sets the command or one of the parameter digits, the others default to 0
+    pub fn set_by_index(&mut self, field_index: i32, value: HexDigit) {
+        let mut effect = self.value().copied().unwrap_or(EffectFieldValue {
+            command: HexDigit::DEFAULT,
+            parameter: (HexDigit::DEFAULT, HexDigit::DEFAULT),
+        });
+        match PatternLineDescriptor::local_field_cursor(field_index) {
+            0 => effect.command = value,
+            1 => effect.parameter.0 = value,
+            2 => effect.parameter.1 = value,
+            _ => unreachable!(),
+        }
+        self.set(effect);
+    }
+}
@@ -1,22 +1,30 @@
 pub mod field;
 
-use std::time::Duration;
+use std::{ops::Range, time::Duration};
 
 use joy_vector::Vector;
+use log::warn;
 
 use crate::{
     assert_log,
     audio::{
         frame::Frame,
         signal::{self},
+        Volume,
     },
     model::{
         self,
-        channel::Channel,
+        channel::{Channel, MuteSolo},
+        history::Edit,
+        instrument::Instruments,
+        jam::JamKey,
         pattern::{
-            u8_to_hex_digit_pair, HexDigit, NoteFieldValue, NoteName, OctaveValue,
+            u8_to_hex_digit_pair, HexDigit, NoteFieldValue, NoteName, OctaveValue, PatternLine,
             PatternLineDescriptor,
         },
+        playback::song::{Mode, PlaybackStart},
+        record::RecordedLine,
+        selection::{FieldPosition, PasteMode, Selection},
     },
     utils::Direction,
 };
@@ -26,6 +34,7 @@ use crate::{
 ///
 impl model::State {
     pub fn handle_command(&mut self, event: model::Command) {
+        let pattern_count = self.patterns.pattern_count;
         match event {
             model::Command::ChangeGlobalOctave { increment } => {
                 self.change_global_octave(increment)
@@ -33,31 +42,184 @@ impl model::State {
             model::Command::SetNoteField {
                 note,
                 octave_modifier,
-            } => self.set_note_field(note, octave_modifier),
+            } => self.edit(|state| state.set_note_field(note, octave_modifier)),
+            model::Command::SetLine {
+                pattern,
+                row,
+                channel,
+                line,
+            } => self.edit(|state| state.set_line(pattern, row, channel, line)),
             model::Command::MoveCursor(direction) => self.move_cursor(direction),
-            model::Command::SetNoteCut => self.set_note_cut(),
-            model::Command::ClearField => self.clear_field(),
-            model::Command::SetOctaveField(octave) => self.set_octave_field(octave),
-            model::Command::SetHexField(digit) => self.set_hex_field(digit),
-            model::Command::CreateNewPattern => todo!(),
-            model::Command::GoToNextPattern => todo!(),
-            model::Command::GoToPreviousPattern => todo!(),
+            model::Command::ExtendSelection(direction) => self.extend_selection(direction),
+            model::Command::ClearSelection => self.selection = None,
+            model::Command::Copy => {
+                self.clipboard = Some(self.patterns.copy_block(&self.selection_or_cursor()))
+            }
+            model::Command::Cut => self.edit(Self::cut),
+            model::Command::Paste(mode) => self.edit(|state| state.paste(mode)),
+            model::Command::Transpose { semitones } => self.edit(|state| {
+                let selection = state.selection_or_cursor();
+                state.patterns.transpose_block(&selection, semitones)
+            }),
+            model::Command::InterpolateVelocity(interpolation) => self.edit(|state| {
+                let selection = state.selection_or_cursor();
+                state
+                    .patterns
+                    .interpolate_velocity(&selection, interpolation)
+            }),
+            model::Command::HumanizeVelocity { spread, seed } => self.edit(|state| {
+                state.humanize_spread = spread;
+                let selection = state.selection_or_cursor();
+                state.patterns.humanize_velocity(
+                    &selection,
+                    spread,
+                    &mut fastrand::Rng::with_seed(seed),
+                )
+            }),
+            model::Command::SetNoteCut => self.edit(Self::set_note_cut),
+            model::Command::SetNoteOff => self.edit(Self::set_note_off),
+            model::Command::ClearField => self.edit(Self::clear_field),
+            model::Command::SetOctaveField(octave) => {
+                self.edit(|state| state.set_octave_field(octave))
+            }
+            model::Command::SetHexField(digit) => self.edit(|state| state.set_hex_field(digit)),
+            model::Command::CreateNewPattern => self.edit(|state| state.patterns.create_pattern()),
+            model::Command::DuplicatePattern => {
+                self.edit(|state| state.patterns.duplicate_pattern())
+            }
+            model::Command::DeletePattern => self.edit(|state| {
+                state
+                    .patterns
+                    .delete_current_pattern()
+                    .unwrap_or_else(|err| {
+                        warn!("{err}");
+                        Vec::new()
+                    })
+            }),
+            model::Command::GoToNextPattern => self.patterns.change_current_pattern(1),
+            model::Command::GoToPreviousPattern => self.patterns.change_current_pattern(-1),
+            model::Command::ChangeCurrentOrder { increment } => {
+                self.patterns.change_current_order(increment)
+            }
+            model::Command::InsertOrderEntry => {
+                self.edit(|state| state.patterns.insert_order_entry())
+            }
+            model::Command::RemoveOrderEntry => self.edit(|state| {
+                state.patterns.remove_order_entry().unwrap_or_else(|err| {
+                    warn!("{err}");
+                    Vec::new()
+                })
+            }),
+            model::Command::Undo => {
+                self.history.undo(&mut self.patterns);
+            }
+            model::Command::Redo => {
+                self.history.redo(&mut self.patterns);
+            }
             model::Command::StartSongPlaybackFromBeginning => {
                 self.start_song_playback_from_beginning()
             }
+            model::Command::StartPlayback(start) => self.start_playback_from(start),
             model::Command::StopSongPlayback => self.stop_song_playback(),
+            model::Command::ToggleFollowPlaying => self.follow_playing = !self.follow_playing,
+            model::Command::ToggleRecord => self.record.armed = !self.record.armed,
+            model::Command::ToggleJam => {
+                self.jam.enabled = !self.jam.enabled;
+                self.jam.clear();
+            }
+            model::Command::JamNoteOn {
+                note,
+                octave_modifier,
+            } => {
+                let mut line = PatternLine::default();
+                line.note
+                    .set_note_name(note, self.global_octave + octave_modifier);
+                line.instrument
+                    .set(u8_to_hex_digit_pair(self.instruments.selected_index()));
+                self.jam.note_on(
+                    JamKey {
+                        note,
+                        octave_modifier,
+                    },
+                    &line,
+                );
+            }
+            model::Command::JamNoteOff {
+                note,
+                octave_modifier,
+            } => {
+                let mut line = PatternLine::default();
+                line.note.set(NoteFieldValue::Off);
+                self.jam.note_off(
+                    JamKey {
+                        note,
+                        octave_modifier,
+                    },
+                    &line,
+                );
+            }
+            model::Command::ToggleRecordQuantize => self.record.quantized = !self.record.quantized,
+            model::Command::ToggleRecordPolyphony => {
+                self.record.polyphonic = !self.record.polyphonic
+            }
             model::Command::UpdatePlaybackSampleCount(sample_count) => {
                 self.audio_stream_sample_count_changed(sample_count)
             }
             model::Command::PerformPlaybacksStep => self.perform_playbacks_step(),
+            model::Command::CaptureChannelOutputs(enabled) => self.capture_channel_outputs(enabled),
             model::Command::InitializeAudio { frame_rate } => self.initialize_audio(frame_rate),
-            model::Command::ClearChannels => self.clear_channels(),
+            model::Command::ClearChannels => {
+                self.clear_channels();
+                self.jam.clear();
+            }
             model::Command::ChangeSelectedInstrument { increment } => {
                 self.change_selected_instrument(increment)
             }
             model::Command::ChangeGlobalVolume { volume } => {
                 self.global_volume = volume;
             }
+            model::Command::SetTempo(tempo) => self.tempo = tempo,
+            model::Command::SetEnvelope(envelope) => match self.instruments.get_selected_mut() {
+                Some(instrument) => instrument.envelope = envelope,
+                None => warn!("No instrument in the selected slot"),
+            },
+            model::Command::SetSampleLoops(loops) => match self.instruments.get_selected_mut() {
+                Some(instrument) => {
+                    if let Err(err) = instrument.set_sample_loops(loops) {
+                        warn!("Could not set the loops: {err:#}");
+                    }
+                }
+                None => warn!("No instrument in the selected slot"),
+            },
+            model::Command::SetInterpolation(interpolation) => {
+                match self.instruments.get_selected_mut() {
+                    Some(instrument) => {
+                        if let Err(err) = instrument.set_interpolation(interpolation) {
+                            warn!("Could not set the interpolation: {err:#}");
+                        }
+                    }
+                    None => warn!("No instrument in the selected slot"),
+                }
+            }
+            model::Command::SetResampleQuality(quality) => self.resample_quality = quality,
+            model::Command::ToggleResampleOnDeviceChange => {
+                self.resample_on_device_change = !self.resample_on_device_change
+            }
+            model::Command::ToggleMute => {
+                let channel = self.patterns.current_channel as usize;
+                self.mute_solo
+                    .set_muted(channel, !self.mute_solo.is_muted(channel));
+            }
+            model::Command::ToggleSolo => {
+                let channel = self.patterns.current_channel as usize;
+                self.mute_solo
+                    .set_soloed(channel, !self.mute_solo.is_soloed(channel));
+            }
+            model::Command::LoadSong(song) => self.load_song(*song),
+        }
+        // Inserted or removed patterns shift the indices, the looped one may be gone
+        if self.patterns.pattern_count != pattern_count {
+            self.stop_looped_rows();
         }
     }
 
@@ -66,14 +228,99 @@ impl model::State {
         self.global_octave = self.global_octave + increment;
     }
 
-    fn set_note_field(&mut self, note: NoteName, octave_modifier: i32) {
+    /// Performs an editing command, the edits reverting it are recorded as a single undo step
+    fn edit<EditFn>(&mut self, edit: EditFn)
+    where
+        EditFn: FnOnce(&mut Self) -> Vec<Edit>,
+    {
+        let cursor_before = self.patterns.cursor();
+        let edits = edit(self);
+        self.history
+            .record(edits, cursor_before, self.patterns.cursor());
+    }
+
+    /// Sets the note along with the selected instrument
+    fn note_setter(&self, note: NoteName, octave_modifier: i32) -> impl Fn(&mut PatternLine) {
+        let octave = self.global_octave + octave_modifier;
+        let instrument = u8_to_hex_digit_pair(self.instruments.selected_index());
+        move |line: &mut PatternLine| {
+            line.note.set_note_name(note, octave);
+            line.instrument.set(instrument);
+        }
+    }
+
+    fn set_note_field(&mut self, note: NoteName, octave_modifier: i32) -> Vec<Edit> {
         let current_channel = self.patterns.current_channel as usize;
-        let line = self.patterns.current_line_mut();
-        line.note
-            .set_note_name(note, self.global_octave + octave_modifier);
-        line.instrument
-            .set(u8_to_hex_digit_pair(self.instruments.selected_index()));
-        self.channels[current_channel].setup_line(line);
+        let edit = self
+            .patterns
+            .edit_current_line(self.note_setter(note, octave_modifier));
+        self.channels[current_channel].setup_line(self.patterns.current_line_mut());
+        edit.into_iter().collect()
+    }
+
+    fn set_line(
+        &mut self,
+        pattern: usize,
+        row: usize,
+        channel: usize,
+        line: PatternLine,
+    ) -> Vec<Edit> {
+        // The patterns may have changed since the line was picked
+        if pattern >= self.patterns.patterns().len()
+            || row >= self.patterns.channel_len as usize
+            || channel >= self.patterns.channel_count as usize
+        {
+            warn!("Line {row} of channel {channel} in pattern {pattern} does not exist anymore");
+            return Vec::new();
+        }
+        let edit = self
+            .patterns
+            .edit_pattern_line(pattern, channel, row, |edited| *edited = line);
+        // A note quantized to the next row is heard when the playback reaches it
+        if self.currently_played_line() == Some(row) {
+            let line = self.patterns.pattern_row(pattern, row).nth(channel);
+            if let (Some(line), Some(channel)) = (line, self.channels.get_mut(channel)) {
+                channel.setup_line(line);
+            }
+        }
+        edit.into_iter().collect()
+    }
+
+    /// Command writing a note entered while recording at the played line, `None` when the note
+    /// goes under the cursor. Only the UI copy of the state picks the line, the audio player's
+    /// copy may be a step ahead.
+    pub fn record_note(&mut self, note: NoteName, octave_modifier: i32) -> Option<model::Command> {
+        let recorded_line = self.recorded_line()?;
+        let channel = self.record.next_channel(
+            recorded_line,
+            self.patterns.current_channel as usize,
+            self.patterns.channel_count as usize,
+        );
+        let mut line = self
+            .patterns
+            .pattern_row(recorded_line.pattern, recorded_line.row)
+            .nth(channel)?
+            .clone();
+        self.note_setter(note, octave_modifier)(&mut line);
+        Some(model::Command::SetLine {
+            pattern: recorded_line.pattern,
+            row: recorded_line.row,
+            channel,
+            line,
+        })
+    }
+
+    /// Where an entered note is recorded, `None` when it goes under the cursor
+    fn recorded_line(&self) -> Option<RecordedLine> {
+        if !self.record.armed || !self.is_song_playing() {
+            return None;
+        }
+        self.song_playback.as_ref()?.recorded_line(
+            self.patterns.order(),
+            self.patterns.channel_len as usize,
+            &self.tempo,
+            self.record.quantized,
+        )
     }
 
     fn move_cursor(&mut self, direction: Direction) {
@@ -108,61 +355,198 @@ impl model::State {
         }
     }
 
-    fn set_note_cut(&mut self) {
+    fn extend_selection(&mut self, direction: Direction) {
+        let anchor = FieldPosition::from_cursor(&self.patterns);
+        self.move_cursor(direction);
+        self.selection
+            .get_or_insert(Selection::new(anchor))
+            .extend_to(FieldPosition::from_cursor(&self.patterns));
+    }
+
+    fn selection_or_cursor(&self) -> Selection {
+        self.selection
+            .unwrap_or_else(|| Selection::new(FieldPosition::from_cursor(&self.patterns)))
+    }
+
+    fn cut(&mut self) -> Vec<Edit> {
+        let selection = self.selection_or_cursor();
+        self.clipboard = Some(self.patterns.copy_block(&selection));
+        self.patterns.clear_block(&selection)
+    }
+
+    fn paste(&mut self, mode: PasteMode) -> Vec<Edit> {
+        let Some(block) = self.clipboard.as_ref() else {
+            warn!("Nothing to paste, copy a selection first");
+            return Vec::new();
+        };
+        self.patterns.paste_block(block, mode)
+    }
+
+    fn set_note_cut(&mut self) -> Vec<Edit> {
         self.patterns
-            .current_line_mut()
-            .note
-            .set(NoteFieldValue::Cut);
+            .edit_current_line(|line| line.note.set(NoteFieldValue::Cut))
+            .into_iter()
+            .collect()
     }
 
-    fn clear_field(&mut self) {
+    fn set_note_off(&mut self) -> Vec<Edit> {
+        self.patterns
+            .edit_current_line(|line| line.note.set(NoteFieldValue::Off))
+            .into_iter()
+            .collect()
+    }
+
+    fn clear_field(&mut self) -> Vec<Edit> {
         let field_cursor = self.patterns.current_field;
-        let line = self.patterns.current_line_mut();
-        match PatternLineDescriptor::field_by_cursor(field_cursor) {
-            PatternLineDescriptor::Note => {
-                line.note.clear();
-                line.velocity.clear();
-                line.instrument.clear();
-            }
-            PatternLineDescriptor::Velocity => line.velocity.clear(),
-            PatternLineDescriptor::Instrument => line.instrument.clear(),
-        }
+        self.patterns
+            .edit_current_line(
+                |line| match PatternLineDescriptor::field_by_cursor(field_cursor) {
+                    PatternLineDescriptor::Note => {
+                        line.note.clear();
+                        line.velocity.clear();
+                        line.instrument.clear();
+                    }
+                    PatternLineDescriptor::Velocity => line.velocity.clear(),
+                    PatternLineDescriptor::Instrument => line.instrument.clear(),
+                    PatternLineDescriptor::Effect => line.effect.clear(),
+                },
+            )
+            .into_iter()
+            .collect()
     }
 
-    fn set_octave_field(&mut self, octave: OctaveValue) {
-        self.patterns.current_line_mut().note.set_octave(octave);
+    fn set_octave_field(&mut self, octave: OctaveValue) -> Vec<Edit> {
+        self.patterns
+            .edit_current_line(|line| line.note.set_octave(octave))
+            .into_iter()
+            .collect()
     }
 
-    fn set_hex_field(&mut self, digit: HexDigit) {
+    fn set_hex_field(&mut self, digit: HexDigit) -> Vec<Edit> {
         let current_field = self.patterns.current_field;
-        let line = self.patterns.current_line_mut();
-        let field = match PatternLineDescriptor::field_by_cursor(current_field) {
-            PatternLineDescriptor::Velocity => &mut line.velocity,
-            PatternLineDescriptor::Instrument => &mut line.instrument,
-            _ => unreachable!(),
-        };
-        field.set_by_index(current_field, digit);
+        self.patterns
+            .edit_current_line(
+                |line| match PatternLineDescriptor::field_by_cursor(current_field) {
+                    PatternLineDescriptor::Velocity => {
+                        line.velocity.set_by_index(current_field, digit)
+                    }
+                    PatternLineDescriptor::Instrument => {
+                        line.instrument.set_by_index(current_field, digit)
+                    }
+                    PatternLineDescriptor::Effect => line.effect.set_by_index(current_field, digit),
+                    PatternLineDescriptor::Note => unreachable!(),
+                },
+            )
+            .into_iter()
+            .collect()
     }
 
     fn start_song_playback_from_beginning(&mut self) {
+        self.start_playback(0, 0, Mode::Song);
+    }
+
+    fn start_playback_from(&mut self, start: PlaybackStart) {
+        let mut order = self.patterns.current_order;
+        let pattern = self.patterns.current_pattern;
+        let row = self.patterns.current_row as usize;
+        let mode = match start {
+            PlaybackStart::FromCursor => {
+                // The pattern may have been changed without moving in the order list
+                if self.patterns.order().get(order) != Some(&pattern) {
+                    let Some(position) = self.patterns.order().iter().position(|&p| p == pattern)
+                    else {
+                        warn!("The current pattern is not in the order list");
+                        return;
+                    };
+                    order = position;
+                }
+                Mode::Song
+            }
+            PlaybackStart::LoopPattern => Mode::Rows {
+                pattern,
+                rows: 0..self.patterns.channel_len as usize,
+                looped: true,
+            },
+            PlaybackStart::LoopSelection => {
+                let rows = self.selection_or_cursor().rows();
+                let end = rows.end.min(self.patterns.channel_len);
+                Mode::Rows {
+                    pattern,
+                    rows: rows.start as usize..end as usize,
+                    looped: true,
+                }
+            }
+            PlaybackStart::CurrentRow => Mode::Rows {
+                pattern,
+                rows: row..row + 1,
+                looped: false,
+            },
+        };
+        let row = match &mode {
+            Mode::Song => row,
+            Mode::Rows { rows, .. } => rows.start,
+        };
+        self.start_playback(order, row, mode);
+    }
+
+    /// Plays from `row` of the order entry `order`, the channels first go through the earlier
+    /// rows of the song so their notes and effects are the ones the song would be playing
+    fn start_playback(&mut self, order: usize, row: usize, mode: Mode) {
         assert_log!(self.song_playback.is_some());
+        if self.song_playback.is_none() {
+            return;
+        }
+        assert_log!(self.patterns.channel_count as usize == self.channels.len());
+
+        self.clear_channels();
+        // Rows are played from their pattern, which may not be the one of the order entry
+        let played_pattern = match &mode {
+            Mode::Song => self.patterns.order().get(order).copied(),
+            Mode::Rows { pattern, .. } => Some(*pattern),
+        };
+        let chased_patterns = self.patterns.order()[..order.min(self.patterns.order().len())]
+            .iter()
+            .map(|&pattern_index| (pattern_index, self.patterns.channel_len as usize))
+            .chain(played_pattern.map(|pattern_index| (pattern_index, row)));
+        for (pattern_index, row_count) in chased_patterns {
+            for chased_row in 0..row_count {
+                for (line, channel) in self
+                    .patterns
+                    .pattern_row(pattern_index, chased_row)
+                    .zip(self.channels.iter_mut())
+                {
+                    channel.setup_line(line);
+                    for tick in 1..self.tempo.ticks_per_row {
+                        channel.perform_tick(tick);
+                    }
+                }
+            }
+        }
+
         let Some(song_playback) = self.song_playback.as_mut() else {
             return;
         };
-        song_playback.current_line = 0;
+        song_playback.mode = mode;
+        song_playback.current_order = order;
+        song_playback.current_line = row;
+        song_playback.current_tick = 0;
         song_playback.is_playing = true;
-        song_playback.current_line_duration = Duration::ZERO;
+        song_playback.current_tick_duration = Duration::ZERO;
 
+        let Some(pattern_index) = song_playback.current_pattern(self.patterns.order()) else {
+            song_playback.is_playing = false;
+            return;
+        };
         if self.follow_playing {
-            self.patterns.current_row = 0;
+            self.patterns.current_row = row as i32;
+            self.patterns.current_order = order;
+            self.patterns.current_pattern = pattern_index;
         }
 
-        assert_log!(self.patterns.channel_count as usize == self.channels.len());
-
         // Init first line
         for (line, channel) in self
             .patterns
-            .current_pattern_row(0)
+            .pattern_row(pattern_index, row)
             .zip(self.channels.iter_mut())
         {
             channel.setup_line(line);
@@ -176,10 +560,18 @@ impl model::State {
             return;
         };
         song_playback.is_playing = false;
-        song_playback.current_line_duration = Duration::ZERO;
+        song_playback.current_tick_duration = Duration::ZERO;
         self.clear_channels();
     }
 
+    fn stop_looped_rows(&mut self) {
+        if let Some(song_playback) = self.song_playback.as_ref() {
+            if song_playback.is_playing && matches!(song_playback.mode, Mode::Rows { .. }) {
+                self.stop_song_playback();
+            }
+        }
+    }
+
     fn audio_stream_sample_count_changed(&mut self, sample_count: usize) {
         assert_log!(self.song_playback.is_some());
         let Some(song_playback) = self.song_playback.as_mut() else {
@@ -197,6 +589,7 @@ impl model::State {
 
         song_playback.line_signal = signal::Owned::from_sample_count(sample_count, frame_rate);
         self.step_output = Some(signal::Owned::from_sample_count(sample_count, frame_rate));
+        self.resize_channel_step_outputs();
     }
 
     fn initialize_audio(&mut self, frame_rate: f32) {
@@ -204,13 +597,38 @@ impl model::State {
         assert_log!(frame_rate > 0.0);
         self.song_playback = Some(model::playback::song::Playback {
             line_signal: signal::Owned::new(frame_rate),
+            mode: Mode::Song,
+            current_order: 0,
             current_line: 0,
-            current_line_duration: Duration::ZERO,
-            line_duration: Duration::from_secs_f32(1.0 / self.line_per_second),
+            current_tick: 0,
+            current_tick_duration: Duration::ZERO,
             is_playing: false,
         });
 
         self.step_output = Some(signal::Owned::new(frame_rate));
+        self.resize_channel_step_outputs();
+    }
+
+    fn capture_channel_outputs(&mut self, enabled: bool) {
+        self.channel_step_outputs = enabled.then(Vec::new);
+        self.resize_channel_step_outputs();
+    }
+
+    /// Keeps one captured output per channel, of the step output size
+    fn resize_channel_step_outputs(&mut self) {
+        let (Some(channel_step_outputs), Some(step_output)) = (
+            self.channel_step_outputs.as_mut(),
+            self.step_output.as_ref(),
+        ) else {
+            return;
+        };
+        *channel_step_outputs = vec![
+            signal::Owned::from_sample_count(
+                step_output.as_ref().sample_count(),
+                step_output.frame_rate
+            );
+            self.channels.len()
+        ];
     }
 
     fn perform_playbacks_step(&mut self) {
@@ -222,6 +640,9 @@ impl model::State {
         };
 
         step_output.fill(Frame::default());
+        for channel_step_output in self.channel_step_outputs.iter_mut().flatten() {
+            channel_step_output.fill(Frame::default());
+        }
 
         assert_log!(self.song_playback.is_some());
         let Some(song_playback) = self.song_playback.as_mut() else {
@@ -229,52 +650,75 @@ impl model::State {
         };
 
         if !song_playback.is_playing {
-            for channel in self.channels.iter_mut() {
-                channel.collect_mix_in(step_output.as_mut(), &self.instruments, self.global_volume);
-            }
+            mix_channels_in(
+                &mut self.channels,
+                self.channel_step_outputs.as_deref_mut(),
+                step_output,
+                0..step_output.len(),
+                &self.instruments,
+                self.global_volume,
+                &self.mute_solo,
+            );
             self.computed_frame_count = step_output.as_ref().frame_count();
         } else {
-            if song_playback.current_line as i32 >= self.patterns.channel_len {
+            if song_playback
+                .current_pattern(self.patterns.order())
+                .is_none()
+            {
                 self.stop_song_playback();
                 return;
             }
 
+            // The tempo is read on every step so it can change while playing. Ticks never last
+            // less than a microsecond, a null tick would never end the step.
+            let tick_duration = self.tempo.tick_duration().max(Duration::from_micros(1));
             let step_duration = step_output.as_ref().duration();
             let mut sub_step_start_duration = Duration::ZERO;
 
             while sub_step_start_duration < step_duration {
-                let sub_step_duration = (song_playback.line_duration
-                    - song_playback.current_line_duration)
+                let sub_step_duration = tick_duration
+                    .saturating_sub(song_playback.current_tick_duration)
                     .min(step_duration - sub_step_start_duration);
 
                 let sub_step_end_duration = sub_step_start_duration + sub_step_duration;
 
-                for channel in self.channels.iter_mut() {
-                    channel.collect_mix_in(
-                        step_output
-                            .sub_signal_from_duration_mut(
-                                sub_step_start_duration,
-                                sub_step_end_duration,
-                            )
-                            .unwrap(),
-                        &self.instruments,
-                        self.global_volume,
-                    );
-                }
+                // Rounded as accumulated tick durations can fall a hair short of a frame
+                let frame_index = |duration: Duration| {
+                    (duration.as_secs_f32() * step_output.frame_rate).round() as usize
+                };
+                mix_channels_in(
+                    &mut self.channels,
+                    self.channel_step_outputs.as_deref_mut(),
+                    step_output,
+                    frame_index(sub_step_start_duration)..frame_index(sub_step_end_duration),
+                    &self.instruments,
+                    self.global_volume,
+                    &self.mute_solo,
+                );
 
                 sub_step_start_duration = sub_step_end_duration;
 
-                song_playback.current_line_duration += sub_step_duration;
-                if song_playback.current_line_duration >= song_playback.line_duration {
-                    song_playback.current_line += 1;
-                    if song_playback.current_line as i32 >= self.patterns.channel_len {
-                        break;
+                song_playback.current_tick_duration += sub_step_duration;
+                if song_playback.current_tick_duration < tick_duration {
+                    continue;
+                }
+                song_playback.current_tick_duration -= tick_duration;
+                song_playback.current_tick += 1;
+                if song_playback.current_tick < self.tempo.ticks_per_row {
+                    for channel in self.channels.iter_mut() {
+                        channel.perform_tick(song_playback.current_tick);
                     }
+                } else {
+                    song_playback.current_tick = 0;
+                    song_playback.advance_line(self.patterns.channel_len as usize);
+                    let Some(pattern_index) = song_playback.current_pattern(self.patterns.order())
+                    else {
+                        break;
+                    };
 
-                    song_playback.current_line_duration -= song_playback.line_duration;
                     for (line, channel) in self
                         .patterns
-                        .current_pattern_row(song_playback.current_line)
+                        .pattern_row(pattern_index, song_playback.current_line)
                         .zip(&mut self.channels)
                     {
                         channel.setup_line(line);
@@ -282,13 +726,24 @@ impl model::State {
                 }
             }
 
-            if self.follow_playing {
+            if let Some(pattern_index) = song_playback
+                .current_pattern(self.patterns.order())
+                .filter(|_| self.follow_playing)
+            {
                 self.patterns.current_row = song_playback.current_line as i32;
+                self.patterns.current_order = song_playback.current_order;
+                self.patterns.current_pattern = pattern_index;
             }
 
             self.computed_frame_count =
                 (sub_step_start_duration.as_secs_f32() * step_output.frame_rate) as usize;
         }
+
+        // Jamming goes on whether the song plays or not
+        if let Ok(output) = step_output.sub_signal_mut(0, self.computed_frame_count) {
+            self.jam
+                .collect_mix_in(output, &self.instruments, self.global_volume);
+        }
     }
 
     fn clear_channels(&mut self) {
@@ -297,7 +752,298 @@ impl model::State {
         }
     }
 
+    fn load_song(&mut self, song: model::Song) {
+        if let Some(song_playback) = self.song_playback.as_mut() {
+            song_playback.is_playing = false;
+            song_playback.mode = Mode::Song;
+            song_playback.current_order = 0;
+            song_playback.current_line = 0;
+            song_playback.current_tick = 0;
+            song_playback.current_tick_duration = Duration::ZERO;
+        }
+
+        self.channels = vec![Channel::new(); song.patterns.channel_count as usize];
+        self.resize_channel_step_outputs();
+        self.patterns = song.patterns;
+        self.instruments = song.instruments;
+        self.global_octave = song.global_octave;
+        self.global_volume = song.global_volume;
+        self.tempo = song.tempo;
+        self.mute_solo = song.mute_solo;
+        // A song whose patterns were replaced keeps the switches of its former channels
+        self.mute_solo.resize(self.patterns.channel_count as usize);
+        self.history.clear();
+        self.selection = None;
+    }
+
     fn change_selected_instrument(&mut self, increment: i32) {
         self.instruments.increment_selected(increment);
     }
 }
+
+/// Mixes every channel in the given frames of the step output. When channel outputs are
+/// captured, each channel is first rendered in its own output then summed with the global volume.
+/// Silenced channels still play at a null volume so they stay in time when heard again.
+fn mix_channels_in(
+    channels: &mut [Channel],
+    mut channel_step_outputs: Option<&mut [signal::stereo::Owned]>,
+    step_output: &mut signal::stereo::Owned,
+    frame_range: Range<usize>,
+    instruments: &Instruments,
+    global_volume: Volume,
+    mute_solo: &MuteSolo,
+) {
+    let Range { start, end } = frame_range;
+    for (index, channel) in channels.iter_mut().enumerate() {
+        let channel_volume = if mute_solo.is_audible(index) {
+            Volume::MAX
+        } else {
+            Volume::MIN
+        };
+        match channel_step_outputs
+            .as_deref_mut()
+            .and_then(|outputs| outputs.get_mut(index))
+        {
+            Some(channel_step_output) => {
+                channel.collect_mix_in(
+                    channel_step_output.sub_signal_mut(start, end).unwrap(),
+                    instruments,
+                    channel_volume,
+                );
+                for (output, channel_frame) in step_output
+                    .sub_signal_mut(start, end)
+                    .unwrap()
+                    .iter_mut()
+                    .zip(channel_step_output.sub_signal(start, end).unwrap().iter())
+                {
+                    *output += *channel_frame * global_volume;
+                }
+            }
+            None => channel.collect_mix_in(
+                step_output.sub_signal_mut(start, end).unwrap(),
+                instruments,
+                channel_volume * global_volume,
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        format::import,
+        model::{
+            instrument::{Instrument, Kind},
+            pattern::{Pattern, Patterns},
+            tempo::Tempo,
+            Song,
+        },
+    };
+
+    use super::*;
+
+    const CHANNEL_LEN: i32 = 8;
+    const LINE_PER_SECOND: f32 = 10.0;
+
+    /// 2 channels song playing `patterns` in order, given as (channel, row, line)
+    fn song(patterns: &[&[(usize, usize, &str)]]) -> Song {
+        let channel_count = 2;
+        let patterns = patterns
+            .iter()
+            .map(|lines| {
+                let mut pattern = Pattern::new(channel_count, CHANNEL_LEN);
+                for (channel, row, line) in lines.iter() {
+                    pattern.lines[channel * CHANNEL_LEN as usize + row] = line.parse().unwrap();
+                }
+                pattern
+            })
+            .collect();
+        let patterns = Patterns::from_patterns(channel_count, CHANNEL_LEN, patterns).unwrap();
+        let mut instruments = Instruments::empty();
+        instruments.set(0, Instrument::from(Kind::Sine)).unwrap();
+        instruments.set(1, Instrument::from(Kind::Square)).unwrap();
+        import::song(
+            patterns,
+            instruments,
+            Tempo::from_line_per_second(LINE_PER_SECOND, 4, 6).unwrap(),
+        )
+    }
+
+    /// State playing `song` from `start` with the cursor on `row`, one line per step
+    fn start_playback(song: Song, start: PlaybackStart, row: i32) -> model::State {
+        let mut state = model::State::default();
+        state.handle_command(model::Command::LoadSong(Box::new(song)));
+        state.handle_command(model::Command::InitializeAudio { frame_rate: 8000.0 });
+        state.handle_command(model::Command::UpdatePlaybackSampleCount(
+            2 * (8000.0 / LINE_PER_SECOND) as usize,
+        ));
+        state.patterns.current_row = row;
+        state.handle_command(model::Command::StartPlayback(start));
+        state
+    }
+
+    fn play_line(state: &mut model::State) -> bool {
+        state.handle_command(model::Command::PerformPlaybacksStep);
+        state
+            .output_samples()
+            .unwrap()
+            .iter()
+            .any(|frame| frame.0 != [0.0, 0.0])
+    }
+
+    #[test]
+    fn test_added_channels_can_be_muted() {
+        let mut state = model::State::default();
+        let mut song = state.song();
+        let channel_count = song.patterns.channel_count + 4;
+        song.patterns = Patterns::new(channel_count, CHANNEL_LEN, 1);
+        state.handle_command(model::Command::LoadSong(Box::new(song)));
+        state.patterns.current_channel = channel_count - 1;
+        state.handle_command(model::Command::ToggleMute);
+
+        assert!(state.mute_solo.is_muted(channel_count as usize - 1));
+        assert!(!state.mute_solo.is_audible(channel_count as usize - 1));
+    }
+
+    #[test]
+    fn test_play_from_cursor_chases_earlier_notes() {
+        let song = song(&[&[(0, 1, "A-5 .. 00"), (0, 6, "CUT .. ..")]]);
+        let mut state = start_playback(song, PlaybackStart::FromCursor, 4);
+
+        assert_eq!(Some(4), state.currently_played_line());
+        assert!(play_line(&mut state));
+        assert!(play_line(&mut state));
+        assert!(!play_line(&mut state));
+        play_line(&mut state);
+        play_line(&mut state);
+        assert!(!state.is_song_playing());
+    }
+
+    #[test]
+    fn test_looped_pattern_starts_over() {
+        let song = song(&[&[(0, 0, "A-5 .. 00"), (0, 1, "CUT .. ..")]]);
+        let mut state = start_playback(song, PlaybackStart::LoopPattern, 5);
+
+        assert_eq!(Some(0), state.currently_played_line());
+        for _ in 0..3 * CHANNEL_LEN {
+            play_line(&mut state);
+        }
+        assert!(state.is_song_playing());
+        assert_eq!(Some(0), state.currently_played_line());
+        assert!(play_line(&mut state));
+        assert!(!play_line(&mut state));
+    }
+
+    #[test]
+    fn test_looped_pattern_chases_its_own_notes() {
+        let song = song(&[&[(0, 0, "CUT .. ..")], &[(0, 0, "A-5 .. 00")]]);
+        let mut state = start_playback(song, PlaybackStart::FromCursor, 0);
+        // Shows the second pattern without moving in the order list
+        state.handle_command(model::Command::GoToNextPattern);
+        state.patterns.current_row = 3;
+        state.handle_command(model::Command::StartPlayback(PlaybackStart::CurrentRow));
+
+        assert!(play_line(&mut state));
+    }
+
+    #[test]
+    fn test_deleting_the_looped_pattern_stops_the_playback() {
+        let song = song(&[&[], &[(0, 0, "A-5 .. 00")]]);
+        let mut state = start_playback(song, PlaybackStart::FromCursor, 0);
+        state.handle_command(model::Command::GoToNextPattern);
+        state.handle_command(model::Command::StartPlayback(PlaybackStart::LoopPattern));
+        assert!(play_line(&mut state));
+
+        state.handle_command(model::Command::DeletePattern);
+        assert!(!state.is_song_playing());
+        assert!(!play_line(&mut state));
+    }
+
+    #[test]
+    fn test_undoing_the_looped_pattern_creation_stops_the_playback() {
+        let mut state = start_playback(song(&[&[]]), PlaybackStart::FromCursor, 0);
+        state.handle_command(model::Command::DuplicatePattern);
+        state.handle_command(model::Command::StartPlayback(PlaybackStart::LoopPattern));
+        play_line(&mut state);
+        assert!(state.is_song_playing());
+
+        state.handle_command(model::Command::Undo);
+        assert!(!state.is_song_playing());
+        play_line(&mut state);
+    }
+
+    #[test]
+    fn test_current_row_is_played_once() {
+        let song = song(&[&[(0, 3, "A-5 .. 00")]]);
+        let mut state = start_playback(song, PlaybackStart::CurrentRow, 3);
+
+        assert!(play_line(&mut state));
+        play_line(&mut state);
+        assert!(!state.is_song_playing());
+    }
+
+    #[test]
+    fn test_notes_are_recorded_at_the_played_line() {
+        let mut state = start_playback(song(&[&[]]), PlaybackStart::LoopPattern, 0);
+        state.handle_command(model::Command::ToggleRecord);
+        state.handle_command(model::Command::ToggleRecordPolyphony);
+        play_line(&mut state);
+        play_line(&mut state);
+        // The audio player's copy of the state runs ahead of the UI
+        let mut player_state = state.clone();
+        play_line(&mut player_state);
+
+        for note in [NoteName::C, NoteName::E] {
+            let command = state.record_note(note, 0).unwrap();
+            state.handle_command(command.clone());
+            player_state.handle_command(command);
+        }
+        for state in [&state, &player_state] {
+            assert_eq!("C-5 .. 00 ...", state.patterns.line(0, 2).to_string());
+            assert_eq!("E-5 .. 00 ...", state.patterns.line(1, 2).to_string());
+            assert_eq!("... .. .. ...", state.patterns.line(0, 3).to_string());
+        }
+        assert_eq!(0, state.patterns.current_row);
+        assert!(play_line(&mut state));
+
+        state.handle_command(model::Command::ToggleRecord);
+        assert!(state.record_note(NoteName::G, 0).is_none());
+        state.handle_command(model::Command::SetNoteField {
+            note: NoteName::G,
+            octave_modifier: 0,
+        });
+        assert_eq!("G-5 .. 00 ...", state.patterns.line(0, 0).to_string());
+    }
+
+    fn lines(state: &model::State) -> Vec<String> {
+        (0..state.patterns.channel_len as usize)
+            .map(|row| state.patterns.line(0, row).to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_humanize_is_the_same_on_every_copy() {
+        let mut state = model::State::default();
+        for row in 0..4 {
+            state
+                .patterns
+                .edit_line(0, row, |line| *line = "C-5 80 00 ...".parse().unwrap());
+        }
+        let mut selection = Selection::new(FieldPosition { column: 1, row: 0 });
+        selection.extend_to(FieldPosition { column: 1, row: 3 });
+        state.selection = Some(selection);
+        // The UI and the audio player each have their copy
+        let mut player_state = state.clone();
+
+        let command = model::Command::HumanizeVelocity {
+            spread: 0x40,
+            seed: 7,
+        };
+        state.handle_command(command.clone());
+        player_state.handle_command(command);
+        assert_eq!(lines(&state), lines(&player_state));
+        assert!(lines(&state)[..4]
+            .iter()
+            .any(|line| line != "C-5 80 00 ..."));
+    }
+}
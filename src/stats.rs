@@ -88,28 +88,66 @@ impl Statistics {
                 note: _,
                 octave_modifier: _,
             } => String::from("SetNoteField"),
+            model::Command::SetLine { .. } => String::from("SetLine"),
             model::Command::MoveCursor(_) => String::from("MoveCursor"),
             model::Command::SetNoteCut => String::from("SetNoteCut"),
+            model::Command::SetNoteOff => String::from("SetNoteOff"),
             model::Command::ClearField => String::from("ClearField"),
             model::Command::SetOctaveField(_) => String::from("SetOctaveField"),
             model::Command::SetHexField(_) => String::from("SetHexField"),
             model::Command::CreateNewPattern => String::from("CreateNewPattern"),
+            model::Command::DuplicatePattern => String::from("DuplicatePattern"),
+            model::Command::DeletePattern => String::from("DeletePattern"),
             model::Command::GoToNextPattern => String::from("GoToNextPattern"),
             model::Command::GoToPreviousPattern => String::from("GoToPreviousPattern"),
+            model::Command::ChangeCurrentOrder { .. } => String::from("ChangeCurrentOrder"),
+            model::Command::InsertOrderEntry => String::from("InsertOrderEntry"),
+            model::Command::RemoveOrderEntry => String::from("RemoveOrderEntry"),
+            model::Command::ExtendSelection(_) => String::from("ExtendSelection"),
+            model::Command::ClearSelection => String::from("ClearSelection"),
+            model::Command::Copy => String::from("Copy"),
+            model::Command::Cut => String::from("Cut"),
+            model::Command::Paste(_) => String::from("Paste"),
+            model::Command::Transpose { .. } => String::from("Transpose"),
+            model::Command::InterpolateVelocity(_) => String::from("InterpolateVelocity"),
+            model::Command::HumanizeVelocity { .. } => String::from("HumanizeVelocity"),
+            model::Command::SetTempo(_) => String::from("SetTempo"),
+            model::Command::SetEnvelope(_) => String::from("SetEnvelope"),
+            model::Command::SetSampleLoops(_) => String::from("SetSampleLoops"),
+            model::Command::SetInterpolation(_) => String::from("SetInterpolation"),
+            model::Command::SetResampleQuality(_) => String::from("SetResampleQuality"),
+            model::Command::ToggleResampleOnDeviceChange => {
+                String::from("ToggleResampleOnDeviceChange")
+            }
+            model::Command::ToggleMute => String::from("ToggleMute"),
+            model::Command::ToggleSolo => String::from("ToggleSolo"),
+            model::Command::Undo => String::from("Undo"),
+            model::Command::Redo => String::from("Redo"),
             model::Command::StartSongPlaybackFromBeginning => {
                 String::from("StartSongPlaybackFromBeginning")
             }
+            model::Command::StartPlayback(_) => String::from("StartPlayback"),
             model::Command::StopSongPlayback => String::from("StopSongPlayback"),
+            model::Command::ToggleFollowPlaying => String::from("ToggleFollowPlaying"),
+            model::Command::ToggleRecord => String::from("ToggleRecord"),
+            model::Command::ToggleRecordQuantize => String::from("ToggleRecordQuantize"),
+            model::Command::ToggleRecordPolyphony => String::from("ToggleRecordPolyphony"),
+            model::Command::ToggleJam => String::from("ToggleJam"),
+            model::Command::JamNoteOn { .. } => String::from("JamNoteOn"),
+            model::Command::JamNoteOff { .. } => String::from("JamNoteOff"),
             model::Command::InitializeAudio { frame_rate: _ } => String::from("InitializeAudio"),
             model::Command::UpdatePlaybackSampleCount(_) => {
                 String::from("UpdatePlaybackSampleCount")
             }
             model::Command::PerformPlaybacksStep => String::from("PerformPlaybacksStep"),
+            model::Command::CaptureChannelOutputs(_) => String::from("CaptureChannelOutputs"),
             model::Command::ClearChannels => String::from("ClearChannels"),
             model::Command::ChangeGlobalVolume { .. } => String::from("ChangeGlobalVolume"),
+            model::Command::LoadSong(_) => String::from("LoadSong"),
         };
         let event_str = match event {
             Event::KeyPressed(_, _) => String::from("KeyPressed"),
+            Event::KeyReleased(_) => String::from("KeyReleased"),
             Event::Text(_) => String::from("Text"),
             Event::State(c) => format!("State({})", command_str_mapper(c)),
             Event::AudioCallback(c) => format!("AudioCallback({})", command_str_mapper(c)),
@@ -126,6 +164,16 @@ impl Statistics {
             Event::StopAudioPlayer(_) => String::from("StopAudioPlayer"),
             Event::RequestRedraw => String::from("RequestRedraw"),
             Event::ExitApp => String::from("ExitApp"),
+            Event::SaveSong(_) => String::from("SaveSong"),
+            Event::OpenSong(_) => String::from("OpenSong"),
+            Event::ExportPatternText(_) => String::from("ExportPatternText"),
+            Event::ImportPatternText(_) => String::from("ImportPatternText"),
+            Event::ExportXm(_) => String::from("ExportXm"),
+            Event::ExportMidi(_) => String::from("ExportMidi"),
+            Event::ChooseStemsGlobalVolume(_) => String::from("ChooseStemsGlobalVolume"),
+            Event::ChooseWavSampleFormat(..) => String::from("ChooseWavSampleFormat"),
+            Event::RenderWav(..) => String::from("RenderWav"),
+            Event::RenderProgress(_) => String::from("RenderProgress"),
             Event::ChangeScreen(screen) => format!(
                 "ChangeScreen({})",
                 match screen {
@@ -1,6 +1,9 @@
-use std::sync::mpsc::{channel, Sender};
+use std::{
+    path::PathBuf,
+    sync::mpsc::{channel, Sender},
+};
 
-use log::error;
+use log::{error, info};
 
 use crate::{
     audio::{
@@ -8,7 +11,8 @@ use crate::{
         player::{AudioPlayer, AudioPlayerBuilder},
     },
     event::{Event, HandleAction},
-    keybindings::Keybindings,
+    format,
+    keybindings::{InputContext, Keybindings},
     model::{self, Command},
     stats::Statistics,
     view::{popup::Popup, screen::Screen},
@@ -30,6 +34,7 @@ pub struct Tracky {
     pub loader_count: usize,
     pub audio_state: Option<AudioState>,
     pub stats: Statistics,
+    pub song_path: Option<PathBuf>,
 }
 
 impl Tracky {
@@ -37,11 +42,55 @@ impl Tracky {
         Self::default()
     }
 
-    pub fn input_context(&self) -> crate::keybindings::InputContext {
+    pub fn input_context(&self) -> InputContext {
+        if self.is_jamming() {
+            return InputContext::Note;
+        }
         self.current_popup
             .as_ref()
             .map(Popup::input_context)
-            .unwrap_or(self.current_screen.input_context())
+            .unwrap_or_else(|| match self.current_screen {
+                Screen::SongEditor => self.state.patterns.current_input_context(),
+                ref screen => screen.input_context(),
+            })
+    }
+
+    /// Whether the note keys play the selected instrument, only on the screens selecting one
+    pub fn is_jamming(&self) -> bool {
+        self.state.jam.enabled
+            && self.current_popup.is_none()
+            && matches!(self.current_screen, Screen::SongEditor)
+    }
+
+    pub fn song_path_or_default(&self) -> String {
+        self.song_path
+            .as_ref()
+            .map(|path| path.to_string_lossy().into_owned())
+            .unwrap_or_else(|| format!("song.{}", format::native::EXTENSION))
+    }
+
+    pub fn pattern_text_path_or_default(&self) -> String {
+        self.song_path_with_extension_or_default(format::text::EXTENSION, "patterns")
+    }
+
+    pub fn xm_path_or_default(&self) -> String {
+        self.song_path_with_extension_or_default(format::xm::EXTENSION, "song")
+    }
+
+    pub fn midi_path_or_default(&self) -> String {
+        self.song_path_with_extension_or_default(format::smf::EXTENSION, "song")
+    }
+
+    pub fn wav_path_or_default(&self) -> String {
+        self.song_path_with_extension_or_default(format::wav::EXTENSION, "song")
+    }
+
+    fn song_path_with_extension_or_default(&self, extension: &str, default_stem: &str) -> String {
+        self.song_path
+            .as_ref()
+            .map(|path| path.with_extension(extension))
+            .map(|path| path.to_string_lossy().into_owned())
+            .unwrap_or_else(|| format!("{default_stem}.{extension}"))
     }
 
     pub fn open_popup(&mut self, popup: Popup) {
@@ -62,8 +111,27 @@ impl Tracky {
         }
     }
 
+    /// Frame rate of the running audio player
+    pub fn frame_rate(&self) -> Option<f32> {
+        self.audio_state.as_ref()?;
+        self.state
+            .step_output
+            .as_ref()
+            .map(|step_output| step_output.frame_rate)
+    }
+
     pub fn start_audio_player(&mut self, event_tx: EventSender) {
         if let Some(selected_output_device) = self.selected_output_device.clone() {
+            if self.state.resample_on_device_change {
+                let frame_rate = selected_output_device.config.sample_rate.0 as f32;
+                let count = self
+                    .state
+                    .instruments
+                    .resample(frame_rate, self.state.resample_quality);
+                if count > 0 {
+                    info!("Resampled {count} sample(s) to {frame_rate} Hz");
+                }
+            }
             let (state_event_tx, state_event_rx) = channel();
             match AudioPlayerBuilder::new()
                 .device(selected_output_device)
@@ -101,11 +101,36 @@ pub fn render_root(app: &mut Tracky, frame: &mut Frame) {
         },
     ]);
 
-    let playback_state_text = Line::from(if app.state.is_song_playing() {
-        "Playing"
-    } else {
-        "Not playing"
-    });
+    let record = &app.state.record;
+    let playback_state_text = Line::from_iter([
+        if app.state.is_song_playing() {
+            "Playing".to_span()
+        } else {
+            "Not playing".to_span()
+        },
+        if app.state.follow_playing {
+            " Follow".to_span()
+        } else {
+            "".to_span()
+        },
+        if app.state.jam.enabled {
+            " Jam".to_span()
+        } else {
+            "".to_span()
+        },
+        if record.armed {
+            " • Rec".fg(THEME.danger)
+        } else {
+            "".to_span()
+        },
+        // Shown before arming so the recording is set up first
+        match (record.quantized, record.polyphonic) {
+            (false, false) => "".to_span(),
+            (true, false) => " [Q]".to_span(),
+            (false, true) => " [P]".to_span(),
+            (true, true) => " [QP]".to_span(),
+        },
+    ]);
 
     frame.render_widget(
         Header::new([
@@ -136,6 +161,8 @@ pub fn render_root(app: &mut Tracky, frame: &mut Frame) {
         match popup {
             // TODO: use frame instead of buffer
             popup::Popup::ChangeVolume(popup) => popup.render(area, frame.buffer_mut()),
+            popup::Popup::TextInput(popup) => popup.render(area, frame.buffer_mut()),
+            popup::Popup::Progress(popup) => popup.render(area, frame.buffer_mut()),
         }
     }
 
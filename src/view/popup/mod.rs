@@ -6,9 +6,13 @@ use crate::{
 
 pub mod change_volume;
 pub mod loading;
+pub mod progress;
+pub mod text_input;
 
 pub enum Popup {
     ChangeVolume(change_volume::Popup),
+    TextInput(text_input::Popup),
+    Progress(progress::Popup),
 }
 
 // TODO: Use macro to auto impl those methods
@@ -18,12 +22,20 @@ impl Popup {
             Popup::ChangeVolume(popup) => {
                 popup.handle_action(action, event_tx);
             }
+            Popup::TextInput(popup) => {
+                popup.handle_action(action, event_tx);
+            }
+            Popup::Progress(popup) => {
+                popup.handle_action(action, event_tx);
+            }
         }
     }
 
     pub fn input_context(&self) -> InputContext {
         match self {
             Popup::ChangeVolume(popup) => popup.input_context(),
+            Popup::TextInput(popup) => popup.input_context(),
+            Popup::Progress(popup) => popup.input_context(),
         }
     }
 }
@@ -0,0 +1,71 @@
+use crate::{
+    event::{Action, HandleAction},
+    keybindings::InputContext,
+    view::{
+        centered_line, render_block_and_get_inner, responsive_centered_rect, widget::slider::Slider,
+    },
+    EventSender,
+};
+use ratatui::{
+    buffer::Buffer,
+    layout::{Constraint, Layout, Rect},
+    text::ToLine,
+    widgets::{Block, Widget},
+};
+
+/// Shows the progress of a background task, it is closed by the task when done
+pub struct Popup {
+    title: &'static str,
+    progress: f32,
+}
+
+impl Popup {
+    pub fn new(title: &'static str) -> Popup {
+        Self {
+            title,
+            progress: 0.0,
+        }
+    }
+
+    pub fn set_progress(&mut self, progress: f32) {
+        self.progress = progress.clamp(0.0, 1.0);
+    }
+}
+
+pub enum PopupAction {}
+
+impl HandleAction<PopupAction> for Popup {
+    fn map_action(&self, _: &Action) -> Option<PopupAction> {
+        None
+    }
+
+    fn update(&mut self, action: PopupAction, _: EventSender) {
+        match action {}
+    }
+
+    fn input_context(&self) -> InputContext {
+        InputContext::Global
+    }
+}
+
+impl Popup {
+    pub fn render(&self, area: Rect, buf: &mut Buffer) {
+        let area = responsive_centered_rect(
+            area,
+            Constraint::Percentage(30),
+            Constraint::Length(30),
+            Constraint::Length(50),
+            Constraint::Length(3),
+        );
+
+        let area = render_block_and_get_inner(Block::bordered().title(self.title), area, buf);
+
+        let area = centered_line(area);
+        let [slider_area, text_area] =
+            Layout::horizontal([Constraint::Fill(1), Constraint::Length(5)]).areas(area);
+        Slider::new(0.0, 1.0, self.progress).render(slider_area, buf);
+
+        let value_text = format!("{:.0}%", self.progress * 100.0);
+        value_text.to_line().right_aligned().render(text_area, buf);
+    }
+}
@@ -0,0 +1,103 @@
+use crate::{
+    event::{self, Action, Event, HandleAction},
+    keybindings::InputContext,
+    view::{centered_line, render_block_and_get_inner, responsive_centered_rect, theme::THEME},
+    EventSender,
+};
+use ratatui::{
+    buffer::Buffer,
+    layout::{Constraint, Rect},
+    text::ToLine,
+    widgets::{Block, Widget},
+};
+use tui_input::{Input, InputRequest};
+
+pub struct Popup {
+    title: &'static str,
+    input: Input,
+    on_submit: Box<dyn Fn(String, EventSender)>,
+}
+
+impl Popup {
+    pub fn new<OnSubmitFn>(
+        title: &'static str,
+        initial_value: String,
+        on_submit: OnSubmitFn,
+    ) -> Popup
+    where
+        OnSubmitFn: Fn(String, EventSender) + 'static,
+    {
+        Self {
+            title,
+            input: Input::new(initial_value),
+            on_submit: Box::new(on_submit),
+        }
+    }
+}
+
+pub enum PopupAction {
+    Close,
+    Submit,
+    Input(event::Text),
+}
+
+impl HandleAction<PopupAction> for Popup {
+    fn map_action(&self, action: &Action) -> Option<PopupAction> {
+        match action {
+            Action::Cancel => Some(PopupAction::Close),
+            Action::Confirm => Some(PopupAction::Submit),
+            Action::Text(text_event) => Some(PopupAction::Input(text_event.clone())),
+            _ => None,
+        }
+    }
+
+    fn update(&mut self, action: PopupAction, event_sender: EventSender) {
+        match action {
+            PopupAction::Close => event_sender.send_event(Event::ClosePopup).unwrap(),
+            PopupAction::Submit => (self.on_submit)(self.input.value().to_string(), event_sender),
+            PopupAction::Input(text_event) => {
+                self.input.handle(match text_event {
+                    event::Text::WriteDataAtCursor(c) => InputRequest::InsertChar(c),
+                    event::Text::RemoveCharAtCursor => InputRequest::DeletePrevChar,
+                    event::Text::MoveCursorLeft => InputRequest::GoToPrevChar,
+                    event::Text::MoveCursorRight => InputRequest::GoToNextChar,
+                });
+            }
+        }
+    }
+
+    fn input_context(&self) -> InputContext {
+        InputContext::Text
+    }
+}
+
+impl Popup {
+    pub fn render(&self, area: Rect, buf: &mut Buffer) {
+        let area = responsive_centered_rect(
+            area,
+            Constraint::Percentage(50),
+            Constraint::Length(30),
+            Constraint::Length(100),
+            Constraint::Length(3),
+        );
+
+        let area = render_block_and_get_inner(Block::bordered().title(self.title), area, buf);
+        let area = centered_line(area);
+
+        let scroll = self
+            .input
+            .visual_scroll(area.width.saturating_sub(1) as usize);
+        self.input
+            .value()
+            .chars()
+            .skip(scroll)
+            .collect::<String>()
+            .to_line()
+            .render(area, buf);
+
+        let cursor_x = area.x + (self.input.visual_cursor().saturating_sub(scroll)) as u16;
+        if let Some(cell) = buf.cell_mut((cursor_x.min(area.right().saturating_sub(1)), area.y)) {
+            cell.set_style(THEME.primary_cursor);
+        }
+    }
+}
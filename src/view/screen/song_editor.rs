@@ -17,6 +17,8 @@ const CHANNEL_HORIZONTAL_PADDING: u16 = 1;
 const CHANNEL_TOTAL_HORIZONTAL_PADDING: u16 = CHANNEL_HORIZONTAL_PADDING * 2; // Left + Right
 const CHANNEL_CONTENT_WIDTH: u16 = PatternLineView::LINE_WIDTH;
 const CHANNEL_TOTAL_WIDTH: u16 = CHANNEL_CONTENT_WIDTH + CHANNEL_TOTAL_HORIZONTAL_PADDING;
+/// Order entries are displayed as "<order index>:<pattern index>"
+const ORDER_LIST_WIDTH: u16 = 7;
 
 fn channel_layout() -> Layout {
     Layout::vertical([
@@ -45,7 +47,53 @@ fn compute_three_states_scrolling(
     }
 }
 
+fn render_order_list(
+    frame: &mut Frame,
+    area: Rect,
+    state: &model::State,
+    currently_playing_order: Option<usize>,
+) {
+    let [header_area, _, entries_area] = channel_layout().areas(area);
+    frame.render_widget(Line::raw("Order").centered(), header_area);
+
+    let order = state.patterns.order();
+    let current_order = state.patterns.current_order;
+    let vertical_offset =
+        compute_three_states_scrolling(entries_area.height as usize, order.len(), current_order);
+
+    for ((order_index, pattern_index), entry_area) in order
+        .iter()
+        .enumerate()
+        .skip(vertical_offset)
+        .zip(entries_area.rows())
+    {
+        let style = if order_index == current_order {
+            THEME.primary_cursor
+        } else if currently_playing_order == Some(order_index) {
+            THEME.secondary_cursor
+        } else {
+            Style::reset()
+        };
+        frame.render_widget(
+            Line::raw(format!("{order_index:03}:{pattern_index:03}"))
+                .centered()
+                .style(style),
+            entry_area,
+        );
+    }
+}
+
 pub fn render(frame: &mut Frame, area: Rect, state: &model::State) {
+    let currently_playing_order = state
+        .currently_played_order()
+        .filter(|_| state.is_song_playing());
+
+    let [order_list_area, area] =
+        Layout::horizontal([Constraint::Length(ORDER_LIST_WIDTH), Constraint::Fill(1)])
+            .spacing(1)
+            .areas(area);
+    render_order_list(frame, order_list_area, state, currently_playing_order);
+
     let [line_numbers_area, pattern_area] = Layout::horizontal([
         Constraint::Length(state.patterns.channel_len.to_string().len() as u16),
         Constraint::Fill(1),
@@ -64,9 +112,14 @@ pub fn render(frame: &mut Frame, area: Rect, state: &model::State) {
         state.patterns.current_row as usize,
     );
 
-    let currently_playing_row = state
-        .currently_played_line()
-        .filter(|_| state.is_song_playing());
+    // The played line is only highlighted when the displayed pattern is the one being played
+    let currently_playing_row = state.currently_played_line().filter(|_| {
+        state.is_song_playing()
+            && state.currently_played_pattern() == Some(state.patterns.current_pattern)
+    });
+
+    let is_beat =
+        |line_number: usize| line_number.is_multiple_of(state.tempo.rows_per_beat as usize);
 
     (vertical_offset..channel_len)
         .map(|line_number| {
@@ -75,6 +128,8 @@ pub fn render(frame: &mut Frame, area: Rect, state: &model::State) {
                     .is_some_and(|current_playing_row| current_playing_row == line_number)
                 {
                     THEME.secondary_cursor
+                } else if is_beat(line_number) {
+                    THEME.elevated_1
                 } else {
                     Style::reset()
                 },
@@ -116,8 +171,24 @@ pub fn render(frame: &mut Frame, area: Rect, state: &model::State) {
 
         let [header_area, debug_area, lines_area] = channel_layout.areas(*channel_area);
 
+        let is_muted = state.mute_solo.is_muted(channel_index);
+        let is_soloed = state.mute_solo.is_soloed(channel_index);
+        let switches = match (is_muted, is_soloed) {
+            (false, false) => String::new(),
+            (true, false) => " [M]".to_string(),
+            (false, true) => " [S]".to_string(),
+            (true, true) => " [MS]".to_string(),
+        };
         frame.render_widget(
-            Line::raw(format!("Track {}", channel_index + 1)).centered(),
+            Line::raw(format!("Track {}{switches}", channel_index + 1))
+                .centered()
+                .style(if !state.mute_solo.is_audible(channel_index) {
+                    Style::new().fg(THEME.secondary)
+                } else if is_soloed {
+                    Style::new().fg(THEME.primary)
+                } else {
+                    Style::reset()
+                }),
             header_area,
         );
 
@@ -179,6 +250,11 @@ pub fn render(frame: &mut Frame, area: Rect, state: &model::State) {
                     is_line_selected: state.patterns.current_row as usize == line_index,
                     is_line_played: currently_playing_row
                         .is_some_and(|current_playing_row| line_index == current_playing_row),
+                    is_beat: is_beat(line_index),
+                    selected_fields: state
+                        .selection
+                        .map(|selection| selection.selected_fields(channel_index, line_index))
+                        .unwrap_or_default(),
                 },
                 area,
             );
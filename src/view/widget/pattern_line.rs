@@ -1,3 +1,5 @@
+use std::ops::Range;
+
 use joy_macro::New;
 use ratatui::{
     prelude::{Buffer, Rect},
@@ -6,7 +8,7 @@ use ratatui::{
 };
 
 use crate::{
-    model::pattern::{NoteFieldValue, NoteName, PatternLine, PatternLineDescriptor},
+    model::pattern::{PatternLine, PatternLineDescriptor},
     view::theme::THEME,
 };
 
@@ -16,6 +18,10 @@ pub struct PatternLineView<'a> {
     pub is_line_selected: bool,
     pub current_field: Option<i32>,
     pub is_line_played: bool,
+    /// First line of a beat
+    pub is_beat: bool,
+    /// Indexes of the selected fields
+    pub selected_fields: Range<usize>,
 }
 
 impl PatternLineView<'_> {
@@ -25,89 +31,29 @@ impl PatternLineView<'_> {
 
 impl Widget for PatternLineView<'_> {
     fn render(self, area: Rect, buf: &mut Buffer) {
-        let (note_char_1, note_char_2, octave_char) =
-            if let Some(note_value) = self.line.note.value() {
-                match note_value {
-                    NoteFieldValue::Note(note, octave) => {
-                        let (note_1, note_2) = match note {
-                            NoteName::A => ('A', '-'),
-                            NoteName::B => ('B', '-'),
-                            NoteName::C => ('C', '-'),
-                            NoteName::D => ('D', '-'),
-                            NoteName::E => ('E', '-'),
-                            NoteName::F => ('F', '-'),
-                            NoteName::G => ('G', '-'),
-                            NoteName::CSharp => ('C', '#'),
-                            NoteName::DSharp => ('D', '#'),
-                            NoteName::FSharp => ('F', '#'),
-                            NoteName::GSharp => ('G', '#'),
-                            NoteName::ASharp => ('A', '#'),
-                        };
-                        let octave = octave.value();
-                        let octave_char = match octave {
-                            0 => '0',
-                            1 => '1',
-                            2 => '2',
-                            3 => '3',
-                            4 => '4',
-                            5 => '5',
-                            6 => '6',
-                            7 => '7',
-                            8 => '8',
-                            9 => '9',
-                            _ => panic!("Cannot happen"),
-                        };
-                        (note_1, note_2, octave_char)
-                    }
-                    NoteFieldValue::Cut => ('C', 'U', 'T'),
-                }
+        Paragraph::new(self.line.to_string())
+            .style(if self.is_line_played {
+                THEME.secondary_cursor
+            } else if self.is_beat {
+                THEME.elevated_1
             } else {
-                ('.', '.', '.')
-            };
+                Style::reset()
+            })
+            .render(area, buf);
 
-        let (vel_char_1, vel_char_2) = if let Some((first, second)) = self.line.velocity.value() {
-            (
-                char::from_digit(first.value() as u32, 16)
-                    .unwrap()
-                    .to_ascii_uppercase(),
-                char::from_digit(second.value() as u32, 16)
-                    .unwrap()
-                    .to_ascii_uppercase(),
-            )
-        } else {
-            ('.', '.')
-        };
-
-        let (instr_char_1, instr_char_2) =
-            if let Some((first, second)) = self.line.instrument.value() {
-                (
-                    char::from_digit(first.value() as u32, 16)
-                        .unwrap()
-                        .to_ascii_uppercase(),
-                    char::from_digit(second.value() as u32, 16)
-                        .unwrap()
-                        .to_ascii_uppercase(),
-                )
-            } else {
-                ('.', '.')
-            };
-
-        Paragraph::new(format!(
-            "{}{}{} {}{} {}{}",
-            note_char_1,
-            note_char_2,
-            octave_char,
-            vel_char_1,
-            vel_char_2,
-            instr_char_1,
-            instr_char_2,
-        ))
-        .style(if self.is_line_played {
-            THEME.secondary_cursor
-        } else {
-            Style::reset()
-        })
-        .render(area, buf);
+        if !self.selected_fields.is_empty() {
+            // Fields are separated by a space, the spaces between selected fields are selected
+            let start = PatternLineDescriptor::INDEX_BOUNDS[self.selected_fields.start].0
+                + self.selected_fields.start as i32;
+            let end = PatternLineDescriptor::INDEX_BOUNDS[self.selected_fields.end - 1].1
+                + self.selected_fields.end as i32
+                - 1;
+            for x in start..end {
+                if let Some(cell) = buf.cell_mut((area.x + x as u16, area.y)) {
+                    cell.set_style(THEME.elevated_2);
+                }
+            }
+        }
 
         if let Some(current_field) = self.current_field.filter(|_| self.is_line_selected) {
             let offset_x = PatternLineDescriptor::field_index_by_cursor(current_field);